@@ -0,0 +1,7 @@
+DECLARE FUNCTION strlen LIB "c" (s AS STRING) AS INTEGER
+DECLARE SUB puts LIB "c" (s AS STRING)
+
+VAR MSG = "Hello from libc"
+
+puts MSG
+PRINT strlen(MSG)
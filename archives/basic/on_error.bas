@@ -0,0 +1,13 @@
+ON ERROR GOTO HANDLER
+
+PRINT "Before error, "
+ERROR 53
+PRINT ", resumed"
+END
+
+HANDLER:
+PRINT "caught error "
+PRINT ERR
+PRINT " at line "
+PRINT ERL
+RESUME NEXT
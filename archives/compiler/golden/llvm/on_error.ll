@@ -0,0 +1,151 @@
+@str0 = private unnamed_addr constant [15 x i8] c"Before error, \00"
+@str1 = private unnamed_addr constant [10 x i8] c", resumed\00"
+@str2 = private unnamed_addr constant [14 x i8] c"caught error \00"
+@str3 = private unnamed_addr constant [10 x i8] c" at line \00"
+@msg_expected_str = private unnamed_addr constant [32 x i8] c"type mismatch (expected STRING)\00"
+@msg_expected_int = private unnamed_addr constant [33 x i8] c"type mismatch (expected INTEGER)\00"
+@msg_resume_without_error = private unnamed_addr constant [21 x i8] c"RESUME without error\00"
+@msg_error_stmt = private unnamed_addr constant [32 x i8] c"error raised by ERROR statement\00"
+@fmt_str = private unnamed_addr constant [3 x i8] c"%s\00"
+@fmt_int = private unnamed_addr constant [4 x i8] c"%ld\00"
+@fmt_error = private unnamed_addr constant [25 x i8] c"Runtime error at %s: %s\0A\00"
+
+@err_code = internal global i64 0
+@err_line = internal global i64 0
+@err_handler = internal global i32 0
+@in_handler = internal global i32 0
+@cur_stmt = internal global i32 0
+@resume_addr = internal global i32 0
+@resume_next_addr = internal global i32 0
+@pc = internal global i32 0
+@pending = internal global i32 0
+
+@rt_loc0 = private unnamed_addr constant [4 x i8] c"4:7\00"
+@rt_loc1 = private unnamed_addr constant [4 x i8] c"4:1\00"
+@rt_loc2 = private unnamed_addr constant [5 x i8] c"13:1\00"
+
+define i32 @main() {
+entry:
+    br label %dispatch
+dispatch:
+    %t1 = load i32, i32* @pc
+    switch i32 %t1, label %exit [ i32 0, label %stmt0 i32 1, label %stmt1 i32 2, label %stmt2 i32 3, label %stmt3 i32 4, label %stmt4 i32 5, label %stmt5 i32 6, label %stmt6 i32 7, label %stmt7 i32 8, label %stmt8 i32 9, label %stmt9 i32 10, label %stmt10 i32 11, label %stmt11 i32 12, label %user_label0 ]
+stmt0:
+    store i32 0, i32* @cur_stmt
+    store i32 12, i32* @err_handler
+    br label %stmt1
+stmt1:
+    store i32 1, i32* @cur_stmt
+    %t2 = ptrtoint i8* getelementptr inbounds ([15 x i8], [15 x i8]* @str0, i64 0, i64 0) to i64
+    call void @rt_print_value(i64 %t2, i64 1)
+    br label %stmt2
+stmt2:
+    store i32 2, i32* @cur_stmt
+    %t3 = icmp ne i64 2, 2
+    br i1 %t3, label %error4, label %ok5
+error4:
+    call void @rt_error(i64 13, i64 4, i8* getelementptr inbounds ([4 x i8], [4 x i8]* @rt_loc0, i64 0, i64 0), i8* getelementptr inbounds ([33 x i8], [33 x i8]* @msg_expected_int, i64 0, i64 0))
+    store i32 0, i32* @pending
+    br label %dispatch
+ok5:
+    call void @rt_error(i64 53, i64 4, i8* getelementptr inbounds ([4 x i8], [4 x i8]* @rt_loc1, i64 0, i64 0), i8* getelementptr inbounds ([32 x i8], [32 x i8]* @msg_error_stmt, i64 0, i64 0))
+    store i32 0, i32* @pending
+    br label %dispatch
+stmt3:
+    store i32 3, i32* @cur_stmt
+    %t6 = ptrtoint i8* getelementptr inbounds ([10 x i8], [10 x i8]* @str1, i64 0, i64 0) to i64
+    call void @rt_print_value(i64 %t6, i64 1)
+    br label %stmt4
+stmt4:
+    store i32 4, i32* @cur_stmt
+    br label %exit
+stmt5:
+    store i32 5, i32* @cur_stmt
+    br label %user_label0
+user_label0:
+    br label %stmt6
+stmt6:
+    store i32 6, i32* @cur_stmt
+    %t7 = ptrtoint i8* getelementptr inbounds ([14 x i8], [14 x i8]* @str2, i64 0, i64 0) to i64
+    call void @rt_print_value(i64 %t7, i64 1)
+    br label %stmt7
+stmt7:
+    store i32 7, i32* @cur_stmt
+    %t8 = load i64, i64* @err_code
+    call void @rt_print_value(i64 %t8, i64 2)
+    br label %stmt8
+stmt8:
+    store i32 8, i32* @cur_stmt
+    %t9 = ptrtoint i8* getelementptr inbounds ([10 x i8], [10 x i8]* @str3, i64 0, i64 0) to i64
+    call void @rt_print_value(i64 %t9, i64 1)
+    br label %stmt9
+stmt9:
+    store i32 9, i32* @cur_stmt
+    %t10 = load i64, i64* @err_line
+    call void @rt_print_value(i64 %t10, i64 2)
+    br label %stmt10
+stmt10:
+    store i32 10, i32* @cur_stmt
+    %t11 = load i32, i32* @in_handler
+    %t12 = icmp eq i32 %t11, 0
+    br i1 %t12, label %error13, label %ok14
+error13:
+    call void @rt_error(i64 20, i64 13, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @rt_loc2, i64 0, i64 0), i8* getelementptr inbounds ([21 x i8], [21 x i8]* @msg_resume_without_error, i64 0, i64 0))
+    store i32 0, i32* @pending
+    br label %dispatch
+ok14:
+    store i32 0, i32* @in_handler
+    store i64 0, i64* @err_code
+    store i64 0, i64* @err_line
+    %t15 = load i32, i32* @resume_next_addr
+    store i32 %t15, i32* @pc
+    br label %dispatch
+stmt11:
+    br label %exit
+exit:
+    ret i32 0
+}
+
+define internal void @rt_print_value(i64 %value, i64 %tag) {
+entry:
+    %is_str = icmp eq i64 %tag, 1
+    br i1 %is_str, label %str, label %int
+str:
+    %ptr = inttoptr i64 %value to i8*
+    call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([3 x i8], [3 x i8]* @fmt_str, i64 0, i64 0), i8* %ptr)
+    ret void
+int:
+    call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @fmt_int, i64 0, i64 0), i64 %value)
+    ret void
+}
+
+define internal void @rt_error(i64 %code, i64 %line, i8* %loc, i8* %msg) {
+entry:
+    %handler = load i32, i32* @err_handler
+    %no_handler = icmp eq i32 %handler, 0
+    %in_handler = load i32, i32* @in_handler
+    %nested = icmp ne i32 %in_handler, 0
+    %uncaught = or i1 %no_handler, %nested
+    br i1 %uncaught, label %report, label %catch
+catch:
+    store i64 %code, i64* @err_code
+    store i64 %line, i64* @err_line
+    %cur = load i32, i32* @cur_stmt
+    store i32 %cur, i32* @resume_addr
+    %next = add i32 %cur, 1
+    store i32 %next, i32* @resume_next_addr
+    store i32 1, i32* @in_handler
+    store i32 %handler, i32* @pc
+    store i32 1, i32* @pending
+    ret void
+report:
+    call i32 @fflush(i8* null)
+    call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([25 x i8], [25 x i8]* @fmt_error, i64 0, i64 0), i8* %loc, i8* %msg)
+    call void @exit(i32 1)
+    unreachable
+}
+
+declare i32 @printf(i8*, ...)
+declare i32 @dprintf(i32, i8*, ...)
+declare i32 @fflush(i8*)
+declare void @exit(i32)
@@ -0,0 +1,90 @@
+@str0 = private unnamed_addr constant [8 x i8] c"Before \00"
+@str1 = private unnamed_addr constant [6 x i8] c"After\00"
+@msg_expected_str = private unnamed_addr constant [32 x i8] c"type mismatch (expected STRING)\00"
+@msg_expected_int = private unnamed_addr constant [33 x i8] c"type mismatch (expected INTEGER)\00"
+@msg_resume_without_error = private unnamed_addr constant [21 x i8] c"RESUME without error\00"
+@msg_error_stmt = private unnamed_addr constant [32 x i8] c"error raised by ERROR statement\00"
+@fmt_str = private unnamed_addr constant [3 x i8] c"%s\00"
+@fmt_int = private unnamed_addr constant [4 x i8] c"%ld\00"
+@fmt_error = private unnamed_addr constant [25 x i8] c"Runtime error at %s: %s\0A\00"
+
+@global0 = internal global i64 0
+@global0.tag = internal global i64 0
+@global1 = internal global i64 0
+@global1.tag = internal global i64 0
+@err_code = internal global i64 0
+@err_line = internal global i64 0
+@err_handler = internal global i32 0
+@in_handler = internal global i32 0
+@cur_stmt = internal global i32 0
+@resume_addr = internal global i32 0
+@resume_next_addr = internal global i32 0
+@pc = internal global i32 0
+@pending = internal global i32 0
+
+
+define i32 @main() {
+entry:
+    br label %stmt0
+stmt0:
+    %t1 = ptrtoint i8* getelementptr inbounds ([8 x i8], [8 x i8]* @str0, i64 0, i64 0) to i64
+    store i64 %t1, i64* @global0
+    store i64 1, i64* @global0.tag
+    br label %stmt1
+stmt1:
+    %t2 = load i64, i64* @global0
+    %t3 = load i64, i64* @global0.tag
+    store i64 %t2, i64* @global1
+    store i64 %t3, i64* @global1.tag
+    br label %stmt2
+stmt2:
+    %t4 = load i64, i64* @global1
+    %t5 = load i64, i64* @global1.tag
+    call void @rt_print_value(i64 %t4, i64 %t5)
+    br label %stmt3
+stmt3:
+    %t6 = ptrtoint i8* getelementptr inbounds ([6 x i8], [6 x i8]* @str1, i64 0, i64 0) to i64
+    store i64 %t6, i64* @global1
+    store i64 1, i64* @global1.tag
+    br label %stmt4
+stmt4:
+    %t7 = load i64, i64* @global0
+    %t8 = load i64, i64* @global0.tag
+    call void @rt_print_value(i64 %t7, i64 %t8)
+    br label %stmt5
+stmt5:
+    %t9 = load i64, i64* @global1
+    %t10 = load i64, i64* @global1.tag
+    call void @rt_print_value(i64 %t9, i64 %t10)
+    br label %stmt6
+stmt6:
+    br label %exit
+exit:
+    ret i32 0
+}
+
+define internal void @rt_print_value(i64 %value, i64 %tag) {
+entry:
+    %is_str = icmp eq i64 %tag, 1
+    br i1 %is_str, label %str, label %int
+str:
+    %ptr = inttoptr i64 %value to i8*
+    call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([3 x i8], [3 x i8]* @fmt_str, i64 0, i64 0), i8* %ptr)
+    ret void
+int:
+    call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @fmt_int, i64 0, i64 0), i64 %value)
+    ret void
+}
+
+define internal void @rt_error(i64 %code, i64 %line, i8* %loc, i8* %msg) {
+entry:
+    call i32 @fflush(i8* null)
+    call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([25 x i8], [25 x i8]* @fmt_error, i64 0, i64 0), i8* %loc, i8* %msg)
+    call void @exit(i32 1)
+    unreachable
+}
+
+declare i32 @printf(i8*, ...)
+declare i32 @dprintf(i32, i8*, ...)
+declare i32 @fflush(i8*)
+declare void @exit(i32)
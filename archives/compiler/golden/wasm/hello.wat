@@ -0,0 +1,154 @@
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32) (param i32) (param i32) (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory 1)
+  (export "memory" (memory 0))
+  (global $err_code (mut i64) (i64.const 0))
+  (global $err_line (mut i64) (i64.const 0))
+  (global $err_handler (mut i32) (i32.const 0))
+  (global $in_handler (mut i32) (i32.const 0))
+  (global $cur_stmt (mut i32) (i32.const 0))
+  (global $resume_addr (mut i32) (i32.const 0))
+  (global $resume_next_addr (mut i32) (i32.const 0))
+  (global $pc (mut i32) (i32.const 0))
+  (global $pending (mut i32) (i32.const 0))
+  (data (i32.const 64) "Hello, world!\00Runtime error at \00: \00\0a\00type mismatch (expected STRING)\00type mismatch (expected INTEGER)\00RESUME without error\00error raised by ERROR statement\00")
+  (func $_start (export "_start")
+    (local $tag i32)
+    (local $a0 i64)
+    (local $a1 i64)
+    (local $a2 i64)
+    (local $a3 i64)
+    (local $a4 i64)
+    (local $a5 i64)
+    block $exit
+      i64.const 64
+      i32.const 1
+      call $rt_print_value
+    end
+  )
+  (func $rt_strlen (param $ptr i32) (result i32)
+    (local $len i32)
+    block $done
+      loop $next
+        local.get $ptr
+        local.get $len
+        i32.add
+        i32.load8_u
+        i32.eqz
+        br_if $done
+        local.get $len
+        i32.const 1
+        i32.add
+        local.set $len
+        br $next
+      end
+    end
+    local.get $len
+  )
+  (func $rt_write (param $fd i32) (param $ptr i32) (param $len i32)
+    i32.const 0
+    local.get $ptr
+    i32.store
+    i32.const 4
+    local.get $len
+    i32.store
+    local.get $fd
+    i32.const 0
+    i32.const 1
+    i32.const 8
+    call $fd_write
+    drop
+  )
+  (func $rt_write_str (param $fd i32) (param $ptr i32)
+    local.get $fd
+    local.get $ptr
+    local.get $ptr
+    call $rt_strlen
+    call $rt_write
+  )
+  (func $rt_print_int (param $value i64)
+    (local $ptr i32)
+    (local $neg i32)
+    i32.const 48
+    local.set $ptr
+    local.get $value
+    i64.const 0
+    i64.lt_s
+    local.tee $neg
+    if
+      i64.const 0
+      local.get $value
+      i64.sub
+      local.set $value
+    end
+    loop $digit
+      local.get $ptr
+      i32.const 1
+      i32.sub
+      local.tee $ptr
+      local.get $value
+      i64.const 10
+      i64.rem_u
+      i32.wrap_i64
+      i32.const 48
+      i32.add
+      i32.store8
+      local.get $value
+      i64.const 10
+      i64.div_u
+      local.tee $value
+      i64.const 0
+      i64.ne
+      br_if $digit
+    end
+    local.get $neg
+    if
+      local.get $ptr
+      i32.const 1
+      i32.sub
+      local.tee $ptr
+      i32.const 45
+      i32.store8
+    end
+    i32.const 1
+    local.get $ptr
+    i32.const 48
+    local.get $ptr
+    i32.sub
+    call $rt_write
+  )
+  (func $rt_print_value (param $value i64) (param $tag i32)
+    local.get $tag
+    i32.const 1
+    i32.ne
+    if
+      local.get $value
+      call $rt_print_int
+      return
+    end
+    i32.const 1
+    local.get $value
+    i32.wrap_i64
+    call $rt_write_str
+  )
+  (func $rt_error (param $code i64) (param $line i64) (param $loc i32) (param $msg i32)
+    i32.const 2
+    i32.const 78
+    call $rt_write_str
+    i32.const 2
+    local.get $loc
+    call $rt_write_str
+    i32.const 2
+    i32.const 96
+    call $rt_write_str
+    i32.const 2
+    local.get $msg
+    call $rt_write_str
+    i32.const 2
+    i32.const 99
+    call $rt_write_str
+    i32.const 1
+    call $proc_exit
+  )
+)
@@ -0,0 +1,293 @@
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32) (param i32) (param i32) (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory 1)
+  (export "memory" (memory 0))
+  (global $err_code (mut i64) (i64.const 0))
+  (global $err_line (mut i64) (i64.const 0))
+  (global $err_handler (mut i32) (i32.const 0))
+  (global $in_handler (mut i32) (i32.const 0))
+  (global $cur_stmt (mut i32) (i32.const 0))
+  (global $resume_addr (mut i32) (i32.const 0))
+  (global $resume_next_addr (mut i32) (i32.const 0))
+  (global $pc (mut i32) (i32.const 0))
+  (global $pending (mut i32) (i32.const 0))
+  (data (i32.const 64) "Before error, \00, resumed\00caught error \00 at line \00Runtime error at \00: \00\0a\00type mismatch (expected STRING)\00type mismatch (expected INTEGER)\00RESUME without error\00error raised by ERROR statement\004:7\004:1\0013:1\00")
+  (func $_start (export "_start")
+    (local $tag i32)
+    (local $a0 i64)
+    (local $a1 i64)
+    (local $a2 i64)
+    (local $a3 i64)
+    (local $a4 i64)
+    (local $a5 i64)
+    loop $dispatch
+      block $exit
+        block $stmt11
+          block $stmt10
+            block $stmt9
+              block $stmt8
+                block $stmt7
+                  block $stmt6
+                    block $user_label0
+                      block $stmt5
+                        block $stmt4
+                          block $stmt3
+                            block $stmt2
+                              block $stmt1
+                                block $stmt0
+                                  global.get $pc
+                                  br_table $stmt0 $stmt1 $stmt2 $stmt3 $stmt4 $stmt5 $stmt6 $stmt7 $stmt8 $stmt9 $stmt10 $stmt11 $user_label0 $exit
+                                end
+                                i32.const 0
+                                global.set $cur_stmt
+                                i32.const 12
+                                global.set $err_handler
+                              end
+                              i32.const 1
+                              global.set $cur_stmt
+                              i64.const 64
+                              i32.const 1
+                              call $rt_print_value
+                            end
+                            i32.const 2
+                            global.set $cur_stmt
+                            i64.const 53
+                            i32.const 2
+                            local.tee $tag
+                            i32.const 2
+                            i32.ne
+                            if
+                              i64.const 13
+                              i64.const 4
+                              i32.const 254
+                              i32.const 168
+                              call $rt_error
+                              i32.const 0
+                              global.set $pending
+                              br $dispatch
+                            end
+                            local.get $tag
+                            drop
+                            i64.const 4
+                            i32.const 258
+                            i32.const 222
+                            call $rt_error
+                            i32.const 0
+                            global.set $pending
+                            br $dispatch
+                          end
+                          i32.const 3
+                          global.set $cur_stmt
+                          i64.const 79
+                          i32.const 1
+                          call $rt_print_value
+                        end
+                        i32.const 4
+                        global.set $cur_stmt
+                        br $exit
+                      end
+                      i32.const 5
+                      global.set $cur_stmt
+                    end
+                  end
+                  i32.const 6
+                  global.set $cur_stmt
+                  i64.const 89
+                  i32.const 1
+                  call $rt_print_value
+                end
+                i32.const 7
+                global.set $cur_stmt
+                global.get $err_code
+                i32.const 2
+                call $rt_print_value
+              end
+              i32.const 8
+              global.set $cur_stmt
+              i64.const 103
+              i32.const 1
+              call $rt_print_value
+            end
+            i32.const 9
+            global.set $cur_stmt
+            global.get $err_line
+            i32.const 2
+            call $rt_print_value
+          end
+          i32.const 10
+          global.set $cur_stmt
+          global.get $in_handler
+          i32.eqz
+          if
+            i64.const 20
+            i64.const 13
+            i32.const 262
+            i32.const 201
+            call $rt_error
+            i32.const 0
+            global.set $pending
+            br $dispatch
+          end
+          i32.const 0
+          global.set $in_handler
+          i64.const 0
+          global.set $err_code
+          i64.const 0
+          global.set $err_line
+          global.get $resume_next_addr
+          global.set $pc
+          br $dispatch
+        end
+      end
+    end
+  )
+  (func $rt_strlen (param $ptr i32) (result i32)
+    (local $len i32)
+    block $done
+      loop $next
+        local.get $ptr
+        local.get $len
+        i32.add
+        i32.load8_u
+        i32.eqz
+        br_if $done
+        local.get $len
+        i32.const 1
+        i32.add
+        local.set $len
+        br $next
+      end
+    end
+    local.get $len
+  )
+  (func $rt_write (param $fd i32) (param $ptr i32) (param $len i32)
+    i32.const 0
+    local.get $ptr
+    i32.store
+    i32.const 4
+    local.get $len
+    i32.store
+    local.get $fd
+    i32.const 0
+    i32.const 1
+    i32.const 8
+    call $fd_write
+    drop
+  )
+  (func $rt_write_str (param $fd i32) (param $ptr i32)
+    local.get $fd
+    local.get $ptr
+    local.get $ptr
+    call $rt_strlen
+    call $rt_write
+  )
+  (func $rt_print_int (param $value i64)
+    (local $ptr i32)
+    (local $neg i32)
+    i32.const 48
+    local.set $ptr
+    local.get $value
+    i64.const 0
+    i64.lt_s
+    local.tee $neg
+    if
+      i64.const 0
+      local.get $value
+      i64.sub
+      local.set $value
+    end
+    loop $digit
+      local.get $ptr
+      i32.const 1
+      i32.sub
+      local.tee $ptr
+      local.get $value
+      i64.const 10
+      i64.rem_u
+      i32.wrap_i64
+      i32.const 48
+      i32.add
+      i32.store8
+      local.get $value
+      i64.const 10
+      i64.div_u
+      local.tee $value
+      i64.const 0
+      i64.ne
+      br_if $digit
+    end
+    local.get $neg
+    if
+      local.get $ptr
+      i32.const 1
+      i32.sub
+      local.tee $ptr
+      i32.const 45
+      i32.store8
+    end
+    i32.const 1
+    local.get $ptr
+    i32.const 48
+    local.get $ptr
+    i32.sub
+    call $rt_write
+  )
+  (func $rt_print_value (param $value i64) (param $tag i32)
+    local.get $tag
+    i32.const 1
+    i32.ne
+    if
+      local.get $value
+      call $rt_print_int
+      return
+    end
+    i32.const 1
+    local.get $value
+    i32.wrap_i64
+    call $rt_write_str
+  )
+  (func $rt_error (param $code i64) (param $line i64) (param $loc i32) (param $msg i32)
+    global.get $err_handler
+    i32.eqz
+    global.get $in_handler
+    i32.or
+    i32.eqz
+    if
+      local.get $code
+      global.set $err_code
+      local.get $line
+      global.set $err_line
+      global.get $cur_stmt
+      global.set $resume_addr
+      global.get $cur_stmt
+      i32.const 1
+      i32.add
+      global.set $resume_next_addr
+      i32.const 1
+      global.set $in_handler
+      global.get $err_handler
+      global.set $pc
+      i32.const 1
+      global.set $pending
+      return
+    end
+    i32.const 2
+    i32.const 113
+    call $rt_write_str
+    i32.const 2
+    local.get $loc
+    call $rt_write_str
+    i32.const 2
+    i32.const 131
+    call $rt_write_str
+    i32.const 2
+    local.get $msg
+    call $rt_write_str
+    i32.const 2
+    i32.const 134
+    call $rt_write_str
+    i32.const 1
+    call $proc_exit
+  )
+)
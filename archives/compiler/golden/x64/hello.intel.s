@@ -0,0 +1,180 @@
+bits 64
+global _start
+
+%define EXIT_FAILURE 1
+%define FD_STDOUT 1
+%define FD_STDERR 2
+%define SYS_EXIT 60
+%define SYS_WRITE 1
+%define TYPE_STR 1
+%define TYPE_INT 2
+%define ERR_TYPE_MISMATCH 13
+%define ERR_RESUME_WITHOUT_ERROR 20
+
+section .data
+    err_prefix db 'Runtime error at ', 0
+    err_sep db ': ', 0
+    err_newline db 10, 0
+    bt_prefix db '    at ', 0
+    bt_open db ' (', 0
+    bt_close db ')', 10, 0
+    msg_expected_str db 'type mismatch (expected STRING)', 0
+    msg_expected_int db 'type mismatch (expected INTEGER)', 0
+    msg_resume_without_error db 'RESUME without error', 0
+    msg_error_stmt db 'error raised by ERROR statement', 0
+    str0 db 'Hello, world!', 0
+    err_handler dq 0
+    err_code dq 0
+    err_line dq 0
+    in_handler dq 0
+    cur_stmt dq 0
+    next_stmt dq 0
+    resume_addr dq 0
+    resume_next_addr dq 0
+    main_rsp dq 0
+    rt_name_main db '<main>', 0
+    call_sites dq 0
+
+section .text
+_start:
+    xor rbp, rbp
+    mov [main_rsp], rsp
+stmt0:
+    push str0
+    push TYPE_STR
+    pop rsi
+    pop rdi
+    call print_value
+stmt1:
+program_exit:
+    mov rax, SYS_EXIT
+    xor rdi, rdi  ; exit code
+    syscall
+print_value:
+    cmp rsi, TYPE_STR
+    je print_string
+    jmp print_int
+print_string:
+    mov rsi, FD_STDOUT
+    jmp write_string
+eprint_string:
+    mov rsi, FD_STDERR
+write_string:
+    call string_length
+    mov rdx, rax  ; length
+    mov rax, SYS_WRITE
+    xchg rsi, rdi  ; address, file descriptor
+    syscall
+    ret
+string_length:
+    xor rax, rax
+.loop:
+    cmp byte[rdi+rax], 0
+    je .end
+    inc rax
+    jmp .loop
+.end:
+    ret
+print_int:
+    sub rsp, 24
+    mov rax, rdi
+    lea rsi, [rsp+24]  ; バッファの末尾
+    mov rcx, 10
+    xor r8, r8  ; 負数かどうか
+    test rax, rax
+    jns .convert
+    neg rax
+    mov r8, 1
+.convert:
+    xor rdx, rdx
+    div rcx
+    add dl, '0'
+    dec rsi
+    mov [rsi], dl
+    test rax, rax
+    jnz .convert
+    test r8, r8
+    jz .write
+    dec rsi
+    mov byte[rsi], '-'
+.write:
+    lea rdx, [rsp+24]
+    sub rdx, rsi  ; length
+    mov rax, SYS_WRITE
+    mov rdi, FD_STDOUT
+    syscall
+    add rsp, 24
+    ret
+runtime_error:
+    cmp qword[err_handler], 0
+    je .report
+    cmp qword[in_handler], 0
+    jne .report
+    mov [err_code], rcx
+    mov [err_line], r8
+    mov rax, [cur_stmt]
+    mov [resume_addr], rax
+    mov rax, [next_stmt]
+    mov [resume_next_addr], rax
+    mov qword[in_handler], 1
+    mov rsp, [main_rsp]
+    xor rbp, rbp
+    jmp qword[err_handler]
+.report:
+    mov r12, rdx
+    mov r13, rdi
+    push rsi
+    push rdi
+    mov rdi, err_prefix
+    call eprint_string
+    pop rdi
+    call eprint_string
+    mov rdi, err_sep
+    call eprint_string
+    pop rdi
+    call eprint_string
+    mov rdi, err_newline
+    call eprint_string
+    mov rdi, r12
+    mov rsi, r13
+    call print_frame
+    mov rbx, rbp
+.walk:
+    test rbx, rbx
+    jz .exit
+    mov rax, [rbx+8]  ; 戻りアドレス
+    mov rcx, call_sites
+.search:
+    mov rdx, [rcx]
+    test rdx, rdx
+    jz .exit  ; BASIC 以外から呼び出された
+    cmp rdx, rax
+    je .found
+    add rcx, 24
+    jmp .search
+.found:
+    mov rsi, [rcx+8]
+    mov rdi, [rcx+16]
+    call print_frame
+    mov rbx, [rbx]
+    jmp .walk
+.exit:
+    mov rax, SYS_EXIT
+    mov rdi, EXIT_FAILURE
+    syscall
+print_frame:
+    push rsi
+    push rdi
+    mov rdi, bt_prefix
+    call eprint_string
+    pop rdi
+    call eprint_string
+    mov rdi, bt_open
+    call eprint_string
+    pop rdi
+    call eprint_string
+    mov rdi, bt_close
+    call eprint_string
+    ret
+
+section .note.GNU-stack noalloc noexec nowrite progbits
@@ -0,0 +1,232 @@
+.globl _start
+
+.set EXIT_FAILURE, 1
+.set FD_STDOUT, 1
+.set FD_STDERR, 2
+.set SYS_EXIT, 60
+.set SYS_WRITE, 1
+.set TYPE_STR, 1
+.set TYPE_INT, 2
+.set ERR_TYPE_MISMATCH, 13
+.set ERR_RESUME_WITHOUT_ERROR, 20
+
+.section .data
+err_prefix:
+    .asciz "Runtime error at "
+err_sep:
+    .asciz ": "
+err_newline:
+    .asciz "\012"
+bt_prefix:
+    .asciz "    at "
+bt_open:
+    .asciz " ("
+bt_close:
+    .asciz ")\012"
+msg_expected_str:
+    .asciz "type mismatch (expected STRING)"
+msg_expected_int:
+    .asciz "type mismatch (expected INTEGER)"
+msg_resume_without_error:
+    .asciz "RESUME without error"
+msg_error_stmt:
+    .asciz "error raised by ERROR statement"
+str0:
+    .asciz "Before "
+str1:
+    .asciz "After"
+globals:
+    .fill 4, 8, 0
+err_handler:
+    .quad 0
+err_code:
+    .quad 0
+err_line:
+    .quad 0
+in_handler:
+    .quad 0
+cur_stmt:
+    .quad 0
+next_stmt:
+    .quad 0
+resume_addr:
+    .quad 0
+resume_next_addr:
+    .quad 0
+main_rsp:
+    .quad 0
+rt_name_main:
+    .asciz "<main>"
+call_sites:
+    .quad 0
+
+.section .text
+_start:
+    xorq %rbp, %rbp
+    movq %rsp, main_rsp
+stmt0:
+    pushq $str0
+    pushq $TYPE_STR
+    popq globals+8
+    popq globals
+stmt1:
+    pushq globals
+    pushq globals+8
+    popq globals+24
+    popq globals+16
+stmt2:
+    pushq globals+16
+    pushq globals+24
+    popq %rsi
+    popq %rdi
+    call print_value
+stmt3:
+    pushq $str1
+    pushq $TYPE_STR
+    popq globals+24
+    popq globals+16
+stmt4:
+    pushq globals
+    pushq globals+8
+    popq %rsi
+    popq %rdi
+    call print_value
+stmt5:
+    pushq globals+16
+    pushq globals+24
+    popq %rsi
+    popq %rdi
+    call print_value
+stmt6:
+program_exit:
+    movq $SYS_EXIT, %rax
+    xorq %rdi, %rdi  # exit code
+    syscall
+print_value:
+    cmpq $TYPE_STR, %rsi
+    je print_string
+    jmp print_int
+print_string:
+    movq $FD_STDOUT, %rsi
+    jmp write_string
+eprint_string:
+    movq $FD_STDERR, %rsi
+write_string:
+    call string_length
+    movq %rax, %rdx  # length
+    movq $SYS_WRITE, %rax
+    xchgq %rdi, %rsi  # address, file descriptor
+    syscall
+    ret
+string_length:
+    xorq %rax, %rax
+string_length.loop:
+    cmpb $0, (%rdi,%rax)
+    je string_length.end
+    incq %rax
+    jmp string_length.loop
+string_length.end:
+    ret
+print_int:
+    subq $24, %rsp
+    movq %rdi, %rax
+    leaq 24(%rsp), %rsi  # バッファの末尾
+    movq $10, %rcx
+    xorq %r8, %r8  # 負数かどうか
+    testq %rax, %rax
+    jns print_int.convert
+    negq %rax
+    movq $1, %r8
+print_int.convert:
+    xorq %rdx, %rdx
+    divq %rcx
+    addb $48, %dl
+    decq %rsi
+    movb %dl, (%rsi)
+    testq %rax, %rax
+    jnz print_int.convert
+    testq %r8, %r8
+    jz print_int.write
+    decq %rsi
+    movb $45, (%rsi)
+print_int.write:
+    leaq 24(%rsp), %rdx
+    subq %rsi, %rdx  # length
+    movq $SYS_WRITE, %rax
+    movq $FD_STDOUT, %rdi
+    syscall
+    addq $24, %rsp
+    ret
+runtime_error:
+    cmpq $0, err_handler
+    je runtime_error.report
+    cmpq $0, in_handler
+    jne runtime_error.report
+    movq %rcx, err_code
+    movq %r8, err_line
+    movq cur_stmt, %rax
+    movq %rax, resume_addr
+    movq next_stmt, %rax
+    movq %rax, resume_next_addr
+    movq $1, in_handler
+    movq main_rsp, %rsp
+    xorq %rbp, %rbp
+    jmp *err_handler
+runtime_error.report:
+    movq %rdx, %r12
+    movq %rdi, %r13
+    pushq %rsi
+    pushq %rdi
+    movq $err_prefix, %rdi
+    call eprint_string
+    popq %rdi
+    call eprint_string
+    movq $err_sep, %rdi
+    call eprint_string
+    popq %rdi
+    call eprint_string
+    movq $err_newline, %rdi
+    call eprint_string
+    movq %r12, %rdi
+    movq %r13, %rsi
+    call print_frame
+    movq %rbp, %rbx
+runtime_error.walk:
+    testq %rbx, %rbx
+    jz runtime_error.exit
+    movq 8(%rbx), %rax  # 戻りアドレス
+    movq $call_sites, %rcx
+runtime_error.search:
+    movq (%rcx), %rdx
+    testq %rdx, %rdx
+    jz runtime_error.exit  # BASIC 以外から呼び出された
+    cmpq %rax, %rdx
+    je runtime_error.found
+    addq $24, %rcx
+    jmp runtime_error.search
+runtime_error.found:
+    movq 8(%rcx), %rsi
+    movq 16(%rcx), %rdi
+    call print_frame
+    movq (%rbx), %rbx
+    jmp runtime_error.walk
+runtime_error.exit:
+    movq $SYS_EXIT, %rax
+    movq $EXIT_FAILURE, %rdi
+    syscall
+print_frame:
+    pushq %rsi
+    pushq %rdi
+    movq $bt_prefix, %rdi
+    call eprint_string
+    popq %rdi
+    call eprint_string
+    movq $bt_open, %rdi
+    call eprint_string
+    popq %rdi
+    call eprint_string
+    movq $bt_close, %rdi
+    call eprint_string
+    ret
+
+.section .note.GNU-stack,"",@progbits
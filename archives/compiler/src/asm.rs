@@ -1,17 +1,312 @@
-pub enum DataSectionItem {
-    Data {
-        name: String,
-        size: String,
-        values: String,
-    },
-    DefineMacro {
-        name: String,
-        replacement: String,
-    },
-    StrlenMacro {
-        name: String,
-        str: String,
-    },
+use std::{convert::TryFrom, fmt::Write};
+
+/// ``%define`` (GAS では ``.set`` ) で定義される定数
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Constant {
+    ExitFailure,
+    FdStdout,
+    FdStderr,
+    SysExit,
+    SysWrite,
+    TypeStr,
+    TypeInt,
+    ErrTypeMismatch,
+    ErrResumeWithoutError,
+}
+
+pub static CONSTANTS: [Constant; 9] = [
+    Constant::ExitFailure,
+    Constant::FdStdout,
+    Constant::FdStderr,
+    Constant::SysExit,
+    Constant::SysWrite,
+    Constant::TypeStr,
+    Constant::TypeInt,
+    Constant::ErrTypeMismatch,
+    Constant::ErrResumeWithoutError,
+];
+
+impl Constant {
+    pub fn name(self) -> &'static str {
+        match self {
+            Constant::ExitFailure => "EXIT_FAILURE",
+            Constant::FdStdout => "FD_STDOUT",
+            Constant::FdStderr => "FD_STDERR",
+            Constant::SysExit => "SYS_EXIT",
+            Constant::SysWrite => "SYS_WRITE",
+            Constant::TypeStr => "TYPE_STR",
+            Constant::TypeInt => "TYPE_INT",
+            Constant::ErrTypeMismatch => "ERR_TYPE_MISMATCH",
+            Constant::ErrResumeWithoutError => "ERR_RESUME_WITHOUT_ERROR",
+        }
+    }
+
+    pub fn value(self) -> i64 {
+        match self {
+            Constant::ExitFailure => 1,
+            Constant::FdStdout => 1,
+            Constant::FdStderr => 2,
+            Constant::SysExit => 60,
+            Constant::SysWrite => 1,
+            Constant::TypeStr => 1,
+            Constant::TypeInt => 2,
+            Constant::ErrTypeMismatch => 13,
+            Constant::ErrResumeWithoutError => 20,
+        }
+    }
+}
+
+/// ランタイムが使用する、データセクションの先頭に置かれる 0 終端の文字列 (名前, 内容)
+pub static RUNTIME_DATA: [(&str, &str); 10] = [
+    ("err_prefix", "Runtime error at "),
+    ("err_sep", ": "),
+    ("err_newline", "\n"),
+    ("bt_prefix", "    at "),
+    ("bt_open", " ("),
+    ("bt_close", ")\n"),
+    ("msg_expected_str", "type mismatch (expected STRING)"),
+    ("msg_expected_int", "type mismatch (expected INTEGER)"),
+    ("msg_resume_without_error", "RESUME without error"),
+    ("msg_error_stmt", "error raised by ERROR statement"),
+];
+
+/// x86-64 の汎用レジスタ (値はレジスタ番号)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Register {
+    /// ModR/M バイトなどに埋め込むレジスタ番号
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// 64 ビットレジスタとしての名前
+    pub fn name(self) -> &'static str {
+        [
+            "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11",
+            "r12", "r13", "r14", "r15",
+        ][self as usize]
+    }
+
+    /// 下位 8 ビットのレジスタとしての名前
+    pub fn name8(self) -> &'static str {
+        [
+            "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b",
+            "r12b", "r13b", "r14b", "r15b",
+        ][self as usize]
+    }
+}
+
+/// メモリオペランドのアクセスするサイズ
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Size {
+    Byte,
+    Qword,
+}
+
+/// 即値
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Imm {
+    Int(i64),
+    /// 文字定数 ( ``'0'`` など)
+    Char(u8),
+    Const(Constant),
+    /// ラベルのアドレス
+    Label(String),
+}
+
+/// メモリオペランド ``[base + index + label + disp]`` (アクセスするサイズは命令やオペランドの組み合わせで決まる)
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Memory {
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    pub label: Option<String>,
+    pub disp: i64,
+}
+
+impl Memory {
+    /// ベースレジスタからの相対位置
+    pub fn base(base: Register, disp: i64) -> Self {
+        Memory {
+            base: Some(base),
+            index: None,
+            label: None,
+            disp,
+        }
+    }
+
+    /// ラベルからの相対位置
+    pub fn label<L: Into<String>>(label: L, disp: i64) -> Self {
+        Memory {
+            base: None,
+            index: None,
+            label: Some(label.into()),
+            disp,
+        }
+    }
+
+    pub fn index(self, index: Register) -> Self {
+        Memory {
+            index: Some(index),
+            ..self
+        }
+    }
+}
+
+/// 1 つのオペランド ( ``push`` / ``pop`` の対象で、メモリは 64 ビット)
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Operand {
+    Reg(Register),
+    Mem(Memory),
+    Imm(Imm),
+}
+
+impl From<Register> for Operand {
+    fn from(reg: Register) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<Memory> for Operand {
+    fn from(mem: Memory) -> Self {
+        Operand::Mem(mem)
+    }
+}
+
+impl From<Imm> for Operand {
+    fn from(imm: Imm) -> Self {
+        Operand::Imm(imm)
+    }
+}
+
+/// ``pop`` の転送先 (即値には書き込めない)
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PopDst {
+    Reg(Register),
+    Mem(Memory),
+}
+
+impl From<Register> for PopDst {
+    fn from(reg: Register) -> Self {
+        PopDst::Reg(reg)
+    }
+}
+
+impl From<Memory> for PopDst {
+    fn from(mem: Memory) -> Self {
+        PopDst::Mem(mem)
+    }
+}
+
+impl From<PopDst> for Operand {
+    fn from(dst: PopDst) -> Self {
+        match dst {
+            PopDst::Reg(reg) => Operand::Reg(reg),
+            PopDst::Mem(mem) => Operand::Mem(mem),
+        }
+    }
+}
+
+/// 2 つのオペランドの組み合わせ (転送先, 転送元) で、x86-64 の命令が受け付けるもののみを表せる
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Operands {
+    RegReg(Register, Register),
+    RegMem(Register, Memory),
+    MemReg(Memory, Register),
+    RegImm(Register, Imm),
+    /// 64 ビットのメモリと即値
+    MemImm(Memory, Imm),
+    /// 8 ビットのメモリと即値
+    MemImm8(Memory, Imm),
+    /// 下位 8 ビットのレジスタと即値
+    Reg8Imm(Register, Imm),
+    /// メモリと下位 8 ビットのレジスタ
+    MemReg8(Memory, Register),
+}
+
+/// 条件分岐の条件
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cond {
+    E,
+    Ne,
+    Z,
+    Nz,
+    S,
+    Ns,
+}
+
+impl Cond {
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::Z => "z",
+            Cond::Nz => "nz",
+            Cond::S => "s",
+            Cond::Ns => "ns",
+        }
+    }
+}
+
+/// コンパイラが使用する x86-64 の命令
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    Mov(Operands),
+    Add(Operands),
+    Sub(Operands),
+    Cmp(Operands),
+    Xor(Operands),
+    And(Operands),
+    /// 2 つのレジスタの論理積でフラグを設定する
+    Test(Register, Register),
+    Xchg(Register, Register),
+    Lea(Register, Memory),
+    Push(Operand),
+    Pop(PopDst),
+    Inc(Register),
+    Dec(Register),
+    Neg(Register),
+    Div(Register),
+    Jmp(String),
+    /// メモリに格納された 64 ビットのアドレスへの間接ジャンプ
+    JmpMem(Memory),
+    Jcc(Cond, String),
+    Call(String),
+    Ret,
+    Syscall,
+}
+
+/// データセクションに置かれるデータ
+#[derive(Clone)]
+pub enum Data {
+    /// 0 終端の文字列
+    Str(String),
+    /// 8 バイト値の列
+    Quads(Vec<Imm>),
+    /// 0 で初期化された 8 バイト値の領域 (個数)
+    Zeroed(usize),
+}
+
+pub struct DataSectionItem {
+    pub name: String,
+    pub data: Data,
 }
 
 /// データセクションの内部表現
@@ -21,45 +316,46 @@ pub struct DataSection {
 }
 
 impl DataSection {
-    pub fn append<N, S, V>(&mut self, name: N, size: S, values: V)
+    /// 0 終端の文字列を置く
+    pub fn string<N, S>(&mut self, name: N, str: S)
     where
         N: Into<String>,
         S: Into<String>,
-        V: Into<String>,
     {
-        self.items.push(DataSectionItem::Data {
+        self.items.push(DataSectionItem {
             name: name.into(),
-            size: size.into(),
-            values: values.into(),
+            data: Data::Str(str.into()),
         });
     }
 
-    pub fn define<N, R>(&mut self, name: N, replacement: R)
-    where
-        N: Into<String>,
-        R: Into<String>,
-    {
-        self.items.push(DataSectionItem::DefineMacro {
+    /// 8 バイト値の列を置く
+    pub fn quads<N: Into<String>>(&mut self, name: N, values: Vec<Imm>) {
+        self.items.push(DataSectionItem {
             name: name.into(),
-            replacement: replacement.into(),
+            data: Data::Quads(values),
         });
     }
 
-    pub fn strlen<N, S>(&mut self, name: N, str: S)
-    where
-        N: Into<String>,
-        S: Into<String>,
-    {
-        self.items.push(DataSectionItem::StrlenMacro {
+    /// 0 で初期化された 8 バイト値の領域を確保する
+    pub fn zeroed<N: Into<String>>(&mut self, name: N, count: usize) {
+        self.items.push(DataSectionItem {
             name: name.into(),
-            str: str.into(),
+            data: Data::Zeroed(count),
         });
     }
+
+    pub fn items(&self) -> &[DataSectionItem] {
+        &self.items
+    }
 }
 
 pub enum TextSectionItem {
     Label(String),
-    Instruction(String),
+    Instruction {
+        inst: Instruction,
+        /// 出力されるアセンブリに付けるコメント
+        comment: Option<String>,
+    },
 }
 
 /// テキストセクションの内部表現
@@ -73,8 +369,27 @@ impl TextSection {
         self.items.push(TextSectionItem::Label(name.into()));
     }
 
-    pub fn inst<I: Into<String>>(&mut self, inst: I) {
-        self.items.push(TextSectionItem::Instruction(inst.into()));
+    pub fn inst(&mut self, inst: Instruction) {
+        self.items.push(TextSectionItem::Instruction {
+            inst,
+            comment: None,
+        });
+    }
+
+    /// コメント付きの命令を追加する
+    pub fn inst_with_comment<C: Into<String>>(&mut self, inst: Instruction, comment: C) {
+        self.items.push(TextSectionItem::Instruction {
+            inst,
+            comment: Some(comment.into()),
+        });
+    }
+
+    pub fn items(&self) -> &[TextSectionItem] {
+        &self.items
+    }
+
+    pub fn items_mut(&mut self) -> &mut Vec<TextSectionItem> {
+        &mut self.items
     }
 
     pub fn extend(&mut self, other: TextSection) {
@@ -84,53 +399,415 @@ impl TextSection {
 
 /// アセンブリの内部表現
 pub struct Asm {
+    /// 外部に公開するシンボル
+    pub exports: Vec<String>,
+    /// 外部ライブラリから参照するシンボル
+    pub externs: Vec<String>,
     pub data: DataSection,
     pub text: TextSection,
 }
 
 impl Asm {
-    /// 出力の ``.s`` (アセンブリ) ファイルに書き込まれる文字列を生成する
+    /// 出力の ``.s`` (アセンブリ) ファイルに書き込まれる、NASM の構文の文字列を生成する
     pub fn stringify(&self) -> String {
         let mut result = String::from("bits 64\n");
-        result.push_str("global _start\n\n");
-        result.push_str("%define ERR_MSG 'Type Error', 0\n");
-        result.push_str("%strlen ERR_MSG_CNT ERR_MSG\n\n");
-        result.push_str("%define EXIT_FAILURE 1\n\n");
-        result.push_str("%define FD_STDOUT 1\n");
-        result.push_str("%define FD_STDERR 2\n\n");
-        result.push_str("%define SYS_EXIT 60\n");
-        result.push_str("%define SYS_WRITE 1\n\n");
-        result.push_str("%define TYPE_STR 1\n\n");
-        result.push_str("section .data\n");
-        result.push_str("    err_msg db ERR_MSG\n");
+        for name in self.exports.iter() {
+            writeln!(result, "global {}", name).unwrap();
+        }
+
+        for name in self.externs.iter() {
+            writeln!(result, "extern {}", name).unwrap();
+        }
+
+        result.push('\n');
+        for constant in CONSTANTS.iter() {
+            writeln!(result, "%define {} {}", constant.name(), constant.value()).unwrap();
+        }
+
+        result.push_str("\nsection .data\n");
+        for (name, str) in RUNTIME_DATA.iter() {
+            writeln!(result, "    {} db {}", name, nasm_string(str)).unwrap();
+        }
 
         for item in self.data.items.iter() {
+            let data = match &item.data {
+                Data::Str(str) => format!("db {}", nasm_string(str)),
+                Data::Quads(values) => format!(
+                    "dq {}",
+                    values.iter().map(nasm_imm).collect::<Vec<_>>().join(", ")
+                ),
+                Data::Zeroed(count) => format!("times {} dq 0", count),
+            };
+            writeln!(result, "    {} {}", item.name, data).unwrap();
+        }
+
+        result.push_str("\nsection .text\n");
+
+        for item in self.text.items.iter() {
             match item {
-                DataSectionItem::Data { name, size, values } => {
-                    result.push_str(format!("    {} {} {}\n", name, size, values).as_str());
-                }
-                DataSectionItem::DefineMacro { name, replacement } => {
-                    result.push_str(format!("    %define {} {}\n", name, replacement).as_str());
+                TextSectionItem::Label(label_name) => {
+                    writeln!(result, "{}:", label_name).unwrap();
                 }
-                DataSectionItem::StrlenMacro { name, str } => {
-                    result.push_str(format!("    %strlen {} {}\n", name, str).as_str());
+                TextSectionItem::Instruction { inst, comment } => {
+                    write!(result, "    {}", nasm_instruction(inst)).unwrap();
+                    if let Some(comment) = comment {
+                        write!(result, "  ; {}", comment).unwrap();
+                    }
+                    result.push('\n');
                 }
             }
         }
 
-        result.push_str("\nsection .text\n");
+        // スタックを実行可能にする必要がないことをリンカに伝える
+        result.push_str("\nsection .note.GNU-stack noalloc noexec nowrite progbits\n");
+        result
+    }
+
+    /// GNU アセンブラ (AT&T 構文) で変換できる文字列を生成する
+    ///
+    /// GAS には NASM のようなスコープを持つローカルラベルがないため、 ``.`` で始まるラベルには直前のラベルの名前を付ける
+    pub fn stringify_gas(&self) -> String {
+        let mut result = String::new();
+        for name in self.exports.iter() {
+            writeln!(result, ".globl {}", name).unwrap();
+        }
 
+        for name in self.externs.iter() {
+            writeln!(result, ".extern {}", name).unwrap();
+        }
+
+        result.push('\n');
+        for constant in CONSTANTS.iter() {
+            writeln!(result, ".set {}, {}", constant.name(), constant.value()).unwrap();
+        }
+
+        result.push_str("\n.section .data\n");
+        for (name, str) in RUNTIME_DATA.iter() {
+            writeln!(result, "{}:\n    .asciz {}", name, gas_string(str)).unwrap();
+        }
+
+        for item in self.data.items.iter() {
+            writeln!(result, "{}:", item.name).unwrap();
+            match &item.data {
+                Data::Str(str) => writeln!(result, "    .asciz {}", gas_string(str)).unwrap(),
+                Data::Quads(values) => writeln!(
+                    result,
+                    "    .quad {}",
+                    values
+                        .iter()
+                        .map(gas_imm_value)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .unwrap(),
+                Data::Zeroed(count) => writeln!(result, "    .fill {}, 8, 0", count).unwrap(),
+            }
+        }
+
+        result.push_str("\n.section .text\n");
+
+        let mut scope = "";
         for item in self.text.items.iter() {
             match item {
                 TextSectionItem::Label(label_name) => {
-                    result.push_str(format!("{}:\n", label_name).as_str());
+                    if !label_name.starts_with('.') {
+                        scope = label_name;
+                    }
+                    writeln!(result, "{}:", qualify_label(scope, label_name)).unwrap();
                 }
-                TextSectionItem::Instruction(inst) => {
-                    result.push_str(format!("    {}\n", inst).as_str());
+                TextSectionItem::Instruction { inst, comment } => {
+                    write!(result, "    {}", gas_instruction(inst, scope)).unwrap();
+                    if let Some(comment) = comment {
+                        write!(result, "  # {}", comment).unwrap();
+                    }
+                    result.push('\n');
                 }
             }
         }
 
+        // スタックを実行可能にする必要がないことをリンカに伝える
+        result.push_str("\n.section .note.GNU-stack,\"\",@progbits\n");
         result
     }
 }
+
+/// ``.`` で始まるローカルラベルを、直前の ``.`` で始まらないラベルを付けた名前にする
+pub fn qualify_label(scope: &str, name: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// NASM の ``db`` に渡す 0 終端の文字列 ( ``'abc', 10, 0`` の形式)
+fn nasm_string(str: &str) -> String {
+    let mut parts = Vec::<String>::new();
+    let mut quoted = String::new();
+    for c in str.chars() {
+        if c == '\'' || c.is_control() {
+            if !quoted.is_empty() {
+                parts.push(format!("'{}'", quoted));
+                quoted.clear();
+            }
+            let mut buf = [0; 4];
+            parts.extend(c.encode_utf8(&mut buf).bytes().map(|b| b.to_string()));
+        } else {
+            quoted.push(c);
+        }
+    }
+    if !quoted.is_empty() {
+        parts.push(format!("'{}'", quoted));
+    }
+    parts.push("0".to_owned());
+    parts.join(", ")
+}
+
+/// GAS の ``.asciz`` に渡す文字列
+fn gas_string(str: &str) -> String {
+    let mut result = String::from("\"");
+    for c in str.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    write!(result, "\\{:03o}", b).unwrap();
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn nasm_imm(imm: &Imm) -> String {
+    match imm {
+        Imm::Int(value) => value.to_string(),
+        Imm::Char(c) => format!("'{}'", *c as char),
+        Imm::Const(constant) => constant.name().to_owned(),
+        Imm::Label(label) => label.clone(),
+    }
+}
+
+fn nasm_memory(mem: &Memory) -> String {
+    let mut terms = Vec::<String>::new();
+    terms.extend(mem.base.map(|reg| reg.name().to_owned()));
+    terms.extend(mem.index.map(|reg| reg.name().to_owned()));
+    terms.extend(mem.label.clone());
+    let mut address = terms.join("+");
+    if mem.disp != 0 || address.is_empty() {
+        if mem.disp >= 0 && !address.is_empty() {
+            address.push('+');
+        }
+        write!(address, "{}", mem.disp).unwrap();
+    }
+    format!("[{}]", address)
+}
+
+/// サイズを明示したメモリオペランド
+fn nasm_sized_memory(size: Size, mem: &Memory) -> String {
+    let size = match size {
+        Size::Byte => "byte",
+        Size::Qword => "qword",
+    };
+    format!("{}{}", size, nasm_memory(mem))
+}
+
+fn nasm_operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Reg(reg) => reg.name().to_owned(),
+        Operand::Mem(mem) => nasm_sized_memory(Size::Qword, mem),
+        Operand::Imm(imm) => nasm_imm(imm),
+    }
+}
+
+fn nasm_operands(operands: &Operands) -> String {
+    let (dst, src) = match operands {
+        Operands::RegReg(dst, src) => (dst.name().to_owned(), src.name().to_owned()),
+        Operands::RegMem(dst, src) => (dst.name().to_owned(), nasm_memory(src)),
+        Operands::MemReg(dst, src) => (nasm_memory(dst), src.name().to_owned()),
+        Operands::RegImm(dst, src) => (dst.name().to_owned(), nasm_imm(src)),
+        Operands::MemImm(dst, src) => (nasm_sized_memory(Size::Qword, dst), nasm_imm(src)),
+        Operands::MemImm8(dst, src) => (nasm_sized_memory(Size::Byte, dst), nasm_imm(src)),
+        Operands::Reg8Imm(dst, src) => (dst.name8().to_owned(), nasm_imm(src)),
+        Operands::MemReg8(dst, src) => (nasm_memory(dst), src.name8().to_owned()),
+    };
+    format!("{}, {}", dst, src)
+}
+
+/// 命令を NASM の構文で表す
+pub fn nasm_instruction(inst: &Instruction) -> String {
+    match inst {
+        Instruction::Mov(operands) => format!("mov {}", nasm_operands(operands)),
+        Instruction::Add(operands) => format!("add {}", nasm_operands(operands)),
+        Instruction::Sub(operands) => format!("sub {}", nasm_operands(operands)),
+        Instruction::Cmp(operands) => format!("cmp {}", nasm_operands(operands)),
+        Instruction::Xor(operands) => format!("xor {}", nasm_operands(operands)),
+        Instruction::And(operands) => format!("and {}", nasm_operands(operands)),
+        Instruction::Test(reg1, reg2) => format!("test {}, {}", reg1.name(), reg2.name()),
+        Instruction::Xchg(dst, src) => format!("xchg {}, {}", dst.name(), src.name()),
+        Instruction::Lea(dst, src) => format!("lea {}, {}", dst.name(), nasm_memory(src)),
+        Instruction::Push(operand) => format!("push {}", nasm_operand(operand)),
+        Instruction::Pop(dst) => format!("pop {}", nasm_operand(&dst.clone().into())),
+        Instruction::Inc(reg) => format!("inc {}", reg.name()),
+        Instruction::Dec(reg) => format!("dec {}", reg.name()),
+        Instruction::Neg(reg) => format!("neg {}", reg.name()),
+        Instruction::Div(reg) => format!("div {}", reg.name()),
+        Instruction::Jmp(label) => format!("jmp {}", label),
+        Instruction::JmpMem(mem) => format!("jmp {}", nasm_sized_memory(Size::Qword, mem)),
+        Instruction::Jcc(cond, label) => format!("j{} {}", cond.name(), label),
+        Instruction::Call(label) => format!("call {}", label),
+        Instruction::Ret => "ret".to_owned(),
+        Instruction::Syscall => "syscall".to_owned(),
+    }
+}
+
+/// 即値を ``$`` を付けずに GAS の構文で表す
+fn gas_imm_value(imm: &Imm) -> String {
+    match imm {
+        Imm::Int(value) => value.to_string(),
+        Imm::Char(c) => c.to_string(),
+        Imm::Const(constant) => constant.name().to_owned(),
+        Imm::Label(label) => label.clone(),
+    }
+}
+
+fn gas_memory(mem: &Memory) -> String {
+    let mut result = mem.label.clone().unwrap_or_default();
+    if mem.disp != 0 || (mem.label.is_none() && mem.base.is_none()) {
+        if mem.disp >= 0 && mem.label.is_some() {
+            result.push('+');
+        }
+        write!(result, "{}", mem.disp).unwrap();
+    }
+    match (mem.base, mem.index) {
+        (Some(base), Some(index)) => write!(result, "(%{},%{})", base.name(), index.name()),
+        (Some(base), None) => write!(result, "(%{})", base.name()),
+        (None, Some(index)) => write!(result, "(,%{})", index.name()),
+        (None, None) => Ok(()),
+    }
+    .unwrap();
+    result
+}
+
+fn gas_operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Reg(reg) => format!("%{}", reg.name()),
+        Operand::Mem(mem) => gas_memory(mem),
+        Operand::Imm(imm) => format!("${}", gas_imm_value(imm)),
+    }
+}
+
+/// オペランドのサイズを表す接尾辞とオペランド ( AT&T 構文のため転送元, 転送先の順)
+fn gas_operands(operands: &Operands) -> (&'static str, String) {
+    let (suffix, src, dst) = match operands {
+        Operands::RegReg(dst, src) => ("q", format!("%{}", src.name()), format!("%{}", dst.name())),
+        Operands::RegMem(dst, src) => ("q", gas_memory(src), format!("%{}", dst.name())),
+        Operands::MemReg(dst, src) => ("q", format!("%{}", src.name()), gas_memory(dst)),
+        Operands::RegImm(dst, src) => (
+            "q",
+            format!("${}", gas_imm_value(src)),
+            format!("%{}", dst.name()),
+        ),
+        Operands::MemImm(dst, src) => ("q", format!("${}", gas_imm_value(src)), gas_memory(dst)),
+        Operands::MemImm8(dst, src) => ("b", format!("${}", gas_imm_value(src)), gas_memory(dst)),
+        Operands::Reg8Imm(dst, src) => (
+            "b",
+            format!("${}", gas_imm_value(src)),
+            format!("%{}", dst.name8()),
+        ),
+        Operands::MemReg8(dst, src) => ("b", format!("%{}", src.name8()), gas_memory(dst)),
+    };
+    (suffix, format!("{}, {}", src, dst))
+}
+
+/// 命令を GAS の AT&T 構文で表す ( ``scope`` はローカルラベルを完全な名前にするために用いる)
+pub fn gas_instruction(inst: &Instruction, scope: &str) -> String {
+    let binary = |mnemonic: &str, operands: &Operands| {
+        let (suffix, operands) = gas_operands(operands);
+        format!("{}{} {}", mnemonic, suffix, operands)
+    };
+    match inst {
+        // 32 ビットの符号付き整数に収まらない即値は movabs で転送する
+        Instruction::Mov(Operands::RegImm(dst, Imm::Int(value)))
+            if i32::try_from(*value).is_err() =>
+        {
+            format!("movabsq ${}, %{}", value, dst.name())
+        }
+        Instruction::Mov(operands) => binary("mov", operands),
+        Instruction::Add(operands) => binary("add", operands),
+        Instruction::Sub(operands) => binary("sub", operands),
+        Instruction::Cmp(operands) => binary("cmp", operands),
+        Instruction::Xor(operands) => binary("xor", operands),
+        Instruction::And(operands) => binary("and", operands),
+        Instruction::Test(reg1, reg2) => format!("testq %{}, %{}", reg2.name(), reg1.name()),
+        Instruction::Xchg(dst, src) => format!("xchgq %{}, %{}", src.name(), dst.name()),
+        Instruction::Lea(dst, src) => format!("leaq {}, %{}", gas_memory(src), dst.name()),
+        Instruction::Push(operand) => format!("pushq {}", gas_operand(operand)),
+        Instruction::Pop(dst) => format!("popq {}", gas_operand(&dst.clone().into())),
+        Instruction::Inc(reg) => format!("incq %{}", reg.name()),
+        Instruction::Dec(reg) => format!("decq %{}", reg.name()),
+        Instruction::Neg(reg) => format!("negq %{}", reg.name()),
+        Instruction::Div(reg) => format!("divq %{}", reg.name()),
+        Instruction::Jmp(label) => format!("jmp {}", qualify_label(scope, label)),
+        Instruction::JmpMem(mem) => format!("jmp *{}", gas_memory(mem)),
+        Instruction::Jcc(cond, label) => {
+            format!("j{} {}", cond.name(), qualify_label(scope, label))
+        }
+        Instruction::Call(label) => format!("call {}", qualify_label(scope, label)),
+        Instruction::Ret => "ret".to_owned(),
+        Instruction::Syscall => "syscall".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Register::*;
+
+    /// NASM と GAS の構文で表した命令
+    fn syntaxes(inst: Instruction) -> (String, String) {
+        (nasm_instruction(&inst), gas_instruction(&inst, ""))
+    }
+
+    #[test]
+    fn memory_access_size() {
+        let strs = |nasm: &str, gas: &str| (nasm.to_string(), gas.to_string());
+        assert_eq!(
+            syntaxes(Instruction::Mov(Operands::RegMem(
+                Rax,
+                Memory::base(Rbp, -8)
+            ))),
+            strs("mov rax, [rbp-8]", "movq -8(%rbp), %rax")
+        );
+        assert_eq!(
+            syntaxes(Instruction::Mov(Operands::MemImm(
+                Memory::base(Rbp, -8),
+                Imm::Int(1)
+            ))),
+            strs("mov qword[rbp-8], 1", "movq $1, -8(%rbp)")
+        );
+        assert_eq!(
+            syntaxes(Instruction::Cmp(Operands::MemImm8(
+                Memory::base(Rdi, 0).index(Rax),
+                Imm::Char(b'0')
+            ))),
+            strs("cmp byte[rdi+rax], '0'", "cmpb $48, (%rdi,%rax)")
+        );
+        assert_eq!(
+            syntaxes(Instruction::Push(Memory::label("err_code", 0).into())),
+            strs("push qword[err_code]", "pushq err_code")
+        );
+        assert_eq!(
+            syntaxes(Instruction::Pop(Memory::label("globals", 16).into())),
+            strs("pop qword[globals+16]", "popq globals+16")
+        );
+        assert_eq!(
+            syntaxes(Instruction::JmpMem(Memory::label("err_handler", 0))),
+            strs("jmp qword[err_handler]", "jmp *err_handler")
+        );
+    }
+}
@@ -0,0 +1,839 @@
+use super::asm::{
+    qualify_label, Asm, Cond, Data, Imm, Instruction, Memory, Operand, Operands, PopDst, Register,
+    Size, TextSectionItem, RUNTIME_DATA,
+};
+use super::elf::{data_offset, write_static_exec, BASE_ADDR, TEXT_OFFSET};
+use std::collections::HashMap;
+
+/// ラベルのアドレスの表 (``None`` の場合はアドレスが未確定で、命令の長さを求めるために仮の値を用いる)
+struct Symbols<'a>(Option<&'a HashMap<String, u64>>);
+
+impl Symbols<'_> {
+    fn address(&self, label: &str) -> Result<i64, String> {
+        match self.0 {
+            Some(symbols) => symbols
+                .get(label)
+                .map(|addr| *addr as i64)
+                .ok_or_else(|| format!("Undefined symbol `{}`", label)),
+            None => Ok(0),
+        }
+    }
+
+    fn resolve(&self, imm: &Imm) -> Result<i64, String> {
+        match imm {
+            Imm::Int(value) => Ok(*value),
+            Imm::Char(c) => Ok(*c as i64),
+            Imm::Const(constant) => Ok(constant.value()),
+            Imm::Label(label) => self.address(label),
+        }
+    }
+
+    fn displacement(&self, mem: &Memory) -> Result<i64, String> {
+        match &mem.label {
+            Some(label) => Ok(self.address(label)? + mem.disp),
+            None => Ok(mem.disp),
+        }
+    }
+}
+
+/// テキストセクションの要素 (ローカルラベルは完全な名前にし、命令にはローカルラベルのスコープを添える)
+enum TextItem<'a> {
+    Label(String),
+    Instruction(&'a Instruction, &'a str),
+}
+
+/// アセンブリの内部表現を機械語に変換し、静的リンクされた ELF64 の実行可能ファイルを生成する
+pub fn assemble(asm: &Asm) -> Result<Vec<u8>, String> {
+    if let Some(name) = asm.externs.first() {
+        return Err(format!(
+            "External symbol `{}` is not supported by the built-in assembler",
+            name
+        ));
+    }
+
+    let mut scope = "";
+    let mut text = Vec::<TextItem>::new();
+    for item in asm.text.items().iter() {
+        match item {
+            TextSectionItem::Label(name) => {
+                if !name.starts_with('.') {
+                    scope = name;
+                }
+                text.push(TextItem::Label(qualify_label(scope, name)));
+            }
+            TextSectionItem::Instruction { inst, .. } => {
+                text.push(TextItem::Instruction(inst, scope));
+            }
+        }
+    }
+
+    let mut data = Vec::<(&str, Data)>::new();
+    for (name, str) in RUNTIME_DATA.iter() {
+        data.push((name, Data::Str(str.to_string())));
+    }
+    for item in asm.data.items().iter() {
+        data.push((&item.name, item.data.clone()));
+    }
+
+    // 1 パス目: 命令の長さからラベルのアドレスを求める
+    let text_addr = BASE_ADDR + TEXT_OFFSET;
+    let mut symbols = HashMap::<String, u64>::new();
+    let mut text_len = 0;
+    for item in text.iter() {
+        match item {
+            TextItem::Label(label) => define_symbol(&mut symbols, label, text_addr + text_len)?,
+            TextItem::Instruction(inst, scope) => {
+                let addr = text_addr + text_len;
+                text_len += encode_instruction(inst, scope, addr, &Symbols(None))?.len() as u64;
+            }
+        }
+    }
+
+    let data_addr = BASE_ADDR + data_offset(text_len as usize);
+    let mut data_len = 0;
+    for (name, item_data) in data.iter() {
+        define_symbol(&mut symbols, name, data_addr + data_len)?;
+        data_len += encode_data(item_data, &Symbols(None))?.len() as u64;
+    }
+
+    // 2 パス目: ラベルのアドレスを埋め込んで機械語に変換する
+    let mut text_bytes = Vec::new();
+    for item in text.iter() {
+        if let TextItem::Instruction(inst, scope) = item {
+            let addr = text_addr + text_bytes.len() as u64;
+            text_bytes.extend(encode_instruction(
+                inst,
+                scope,
+                addr,
+                &Symbols(Some(&symbols)),
+            )?);
+        }
+    }
+    let mut data_bytes = Vec::new();
+    for (_, item_data) in data.iter() {
+        data_bytes.extend(encode_data(item_data, &Symbols(Some(&symbols)))?);
+    }
+
+    let entry = *symbols
+        .get("_start")
+        .ok_or("The entry point `_start` is not defined")?;
+    Ok(write_static_exec(&text_bytes, &data_bytes, entry))
+}
+
+fn define_symbol(symbols: &mut HashMap<String, u64>, name: &str, addr: u64) -> Result<(), String> {
+    if symbols.insert(name.to_owned(), addr).is_some() {
+        return Err(format!("Symbol `{}` is defined more than once", name));
+    }
+    Ok(())
+}
+
+fn fits_i8(value: i64) -> bool {
+    (-128..=127).contains(&value)
+}
+
+fn fits_i32(value: i64) -> bool {
+    (i32::MIN as i64..=i32::MAX as i64).contains(&value)
+}
+
+/// ラベルを含まず、符号付き 8 ビットに収まる即値かどうか
+fn is_imm8(imm: &Imm, symbols: &Symbols) -> Result<bool, String> {
+    Ok(!matches!(imm, Imm::Label(_)) && fits_i8(symbols.resolve(imm)?))
+}
+
+/// 命令の ModR/M バイトの ``r/m`` 側のオペランド
+enum Rm<'a> {
+    Reg(Register),
+    /// 下位 8 ビットのレジスタ
+    Reg8(Register),
+    Mem(&'a Memory),
+}
+
+/// REX プレフィックス・オペコード・ModR/M バイト (と SIB バイト、変位) を出力する
+///
+/// ``reg8`` は ``reg`` フィールドが下位 8 ビットのレジスタを表すかどうか
+fn emit_modrm(
+    out: &mut Vec<u8>,
+    rex_w: bool,
+    opcode: &[u8],
+    reg: u8,
+    reg8: bool,
+    rm: Rm,
+    symbols: &Symbols,
+) -> Result<(), String> {
+    // spl, bpl, sil, dil は REX プレフィックスがなければ ah, ch, dh, bh になる
+    let needs_rex = |code: u8| (4..8).contains(&code);
+
+    let (rex_x, rex_b, force_rex) = match &rm {
+        Rm::Reg(r) => (false, r.code() >= 8, false),
+        Rm::Reg8(r) => (false, r.code() >= 8, needs_rex(r.code())),
+        Rm::Mem(mem) => (
+            mem.index.is_some_and(|r| r.code() >= 8),
+            mem.base.is_some_and(|r| r.code() >= 8),
+            false,
+        ),
+    };
+    let rex = 0x40
+        | if rex_w { 0x08 } else { 0 }
+        | if reg >= 8 { 0x04 } else { 0 }
+        | if rex_x { 0x02 } else { 0 }
+        | if rex_b { 0x01 } else { 0 };
+    if rex != 0x40 || force_rex || (reg8 && needs_rex(reg)) {
+        out.push(rex);
+    }
+    out.extend_from_slice(opcode);
+
+    let reg = (reg & 7) << 3;
+    match rm {
+        Rm::Reg(r) | Rm::Reg8(r) => out.push(0xc0 | reg | (r.code() & 7)),
+        Rm::Mem(mem) => {
+            let disp = symbols.displacement(mem)?;
+            match mem.base {
+                // ベースレジスタがなければ SIB バイトで絶対アドレスを指定する
+                None => {
+                    let index = mem.index.map_or(4, |r| r.code() & 7);
+                    out.push(reg | 0x04);
+                    out.push((index << 3) | 0x05);
+                    push_i32(out, disp)?;
+                }
+                Some(base) => {
+                    let base_code = base.code() & 7;
+                    let mod_ = if mem.label.is_some() {
+                        0x80
+                    } else if disp == 0 && base_code != 5 {
+                        0x00
+                    } else if fits_i8(disp) {
+                        0x40
+                    } else {
+                        0x80
+                    };
+                    if mem.index.is_some() || base_code == 4 {
+                        let index = mem.index.map_or(4, |r| r.code() & 7);
+                        out.push(mod_ | reg | 0x04);
+                        out.push((index << 3) | base_code);
+                    } else {
+                        out.push(mod_ | reg | base_code);
+                    }
+                    match mod_ {
+                        0x40 => out.push(disp as i8 as u8),
+                        0x80 => push_i32(out, disp)?,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn push_i32(out: &mut Vec<u8>, value: i64) -> Result<(), String> {
+    if !fits_i32(value) {
+        return Err(format!("`{}` does not fit in 32 bits", value));
+    }
+    out.extend_from_slice(&(value as i32).to_le_bytes());
+    Ok(())
+}
+
+/// ``0f 8x`` の ``x``
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::E | Cond::Z => 0x4,
+        Cond::Ne | Cond::Nz => 0x5,
+        Cond::S => 0x8,
+        Cond::Ns => 0x9,
+    }
+}
+
+/// 算術演算命令 (``add`` / ``sub`` / ``cmp`` / ``xor``) を変換する
+///
+/// ``ext`` は ``81 /digit`` などのオペコード拡張で、 ``ext * 8 + 1`` が ``r/m, reg`` の形式のオペコードになる
+fn encode_arithmetic(
+    out: &mut Vec<u8>,
+    ext: u8,
+    operands: &Operands,
+    symbols: &Symbols,
+) -> Result<(), String> {
+    let op = ext * 8 + 1;
+    let (size, rm, imm) = match operands {
+        Operands::RegReg(dst, src) => {
+            return emit_modrm(out, true, &[op], src.code(), false, Rm::Reg(*dst), symbols)
+        }
+        Operands::RegMem(dst, src) => {
+            return emit_modrm(
+                out,
+                true,
+                &[op + 2],
+                dst.code(),
+                false,
+                Rm::Mem(src),
+                symbols,
+            )
+        }
+        Operands::MemReg(dst, src) => {
+            return emit_modrm(out, true, &[op], src.code(), false, Rm::Mem(dst), symbols)
+        }
+        Operands::MemReg8(dst, src) => {
+            return emit_modrm(
+                out,
+                false,
+                &[op - 1],
+                src.code(),
+                true,
+                Rm::Mem(dst),
+                symbols,
+            )
+        }
+        Operands::RegImm(dst, imm) => (Size::Qword, Rm::Reg(*dst), imm),
+        Operands::Reg8Imm(dst, imm) => (Size::Byte, Rm::Reg8(*dst), imm),
+        Operands::MemImm(dst, imm) => (Size::Qword, Rm::Mem(dst), imm),
+        Operands::MemImm8(dst, imm) => (Size::Byte, Rm::Mem(dst), imm),
+    };
+
+    let value = symbols.resolve(imm)?;
+    if size == Size::Byte {
+        emit_modrm(out, false, &[0x80], ext, false, rm, symbols)?;
+        out.push(value as u8);
+    } else if is_imm8(imm, symbols)? {
+        emit_modrm(out, true, &[0x83], ext, false, rm, symbols)?;
+        out.push(value as u8);
+    } else {
+        emit_modrm(out, true, &[0x81], ext, false, rm, symbols)?;
+        push_i32(out, value)?;
+    }
+    Ok(())
+}
+
+/// 命令を機械語に変換する (``addr`` は命令の先頭のアドレス、 ``scope`` はローカルラベルのスコープ)
+fn encode_instruction(
+    inst: &Instruction,
+    scope: &str,
+    addr: u64,
+    symbols: &Symbols,
+) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+
+    // 相対アドレスで分岐する命令 (``opcode`` の後に 32 ビットの相対アドレスが続く)
+    let relative = |opcode: &[u8], label: &str| -> Result<Vec<u8>, String> {
+        let mut out = opcode.to_vec();
+        let next = addr as i64 + opcode.len() as i64 + 4;
+        let rel = match symbols.0 {
+            Some(_) => symbols.address(&qualify_label(scope, label))? - next,
+            None => 0,
+        };
+        push_i32(&mut out, rel)?;
+        Ok(out)
+    };
+
+    match inst {
+        Instruction::Add(operands) => encode_arithmetic(&mut out, 0, operands, symbols)?,
+        Instruction::And(operands) => encode_arithmetic(&mut out, 4, operands, symbols)?,
+        Instruction::Sub(operands) => encode_arithmetic(&mut out, 5, operands, symbols)?,
+        Instruction::Xor(operands) => encode_arithmetic(&mut out, 6, operands, symbols)?,
+        Instruction::Cmp(operands) => encode_arithmetic(&mut out, 7, operands, symbols)?,
+        Instruction::Test(reg1, reg2) => emit_modrm(
+            &mut out,
+            true,
+            &[0x85],
+            reg2.code(),
+            false,
+            Rm::Reg(*reg1),
+            symbols,
+        )?,
+        Instruction::Mov(Operands::RegReg(dst, src)) => emit_modrm(
+            &mut out,
+            true,
+            &[0x89],
+            src.code(),
+            false,
+            Rm::Reg(*dst),
+            symbols,
+        )?,
+        Instruction::Mov(Operands::RegMem(dst, src)) => emit_modrm(
+            &mut out,
+            true,
+            &[0x8b],
+            dst.code(),
+            false,
+            Rm::Mem(src),
+            symbols,
+        )?,
+        Instruction::Mov(Operands::MemReg(dst, src)) => emit_modrm(
+            &mut out,
+            true,
+            &[0x89],
+            src.code(),
+            false,
+            Rm::Mem(dst),
+            symbols,
+        )?,
+        Instruction::Mov(Operands::MemReg8(dst, src)) => emit_modrm(
+            &mut out,
+            false,
+            &[0x88],
+            src.code(),
+            true,
+            Rm::Mem(dst),
+            symbols,
+        )?,
+        Instruction::Mov(Operands::RegImm(dst, imm)) => {
+            let value = symbols.resolve(imm)?;
+            let is_label = matches!(imm, Imm::Label(_));
+            let rex_b = if dst.code() >= 8 { 0x01 } else { 0 };
+            if !is_label && (0..=u32::MAX as i64).contains(&value) {
+                // 32 ビットレジスタへの mov は上位 32 ビットを 0 にする
+                if rex_b != 0 {
+                    out.push(0x40 | rex_b);
+                }
+                out.push(0xb8 + (dst.code() & 7));
+                out.extend_from_slice(&(value as u32).to_le_bytes());
+            } else if !is_label && fits_i32(value) {
+                emit_modrm(&mut out, true, &[0xc7], 0, false, Rm::Reg(*dst), symbols)?;
+                push_i32(&mut out, value)?;
+            } else {
+                out.push(0x48 | rex_b);
+                out.push(0xb8 + (dst.code() & 7));
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Instruction::Mov(Operands::MemImm(dst, imm)) => {
+            emit_modrm(&mut out, true, &[0xc7], 0, false, Rm::Mem(dst), symbols)?;
+            push_i32(&mut out, symbols.resolve(imm)?)?;
+        }
+        Instruction::Mov(Operands::MemImm8(dst, imm)) => {
+            emit_modrm(&mut out, false, &[0xc6], 0, false, Rm::Mem(dst), symbols)?;
+            out.push(symbols.resolve(imm)? as u8);
+        }
+        Instruction::Mov(Operands::Reg8Imm(dst, imm)) => {
+            emit_modrm(&mut out, false, &[0xc6], 0, false, Rm::Reg8(*dst), symbols)?;
+            out.push(symbols.resolve(imm)? as u8);
+        }
+        Instruction::Lea(dst, src) => emit_modrm(
+            &mut out,
+            true,
+            &[0x8d],
+            dst.code(),
+            false,
+            Rm::Mem(src),
+            symbols,
+        )?,
+        Instruction::Xchg(dst, src) => emit_modrm(
+            &mut out,
+            true,
+            &[0x87],
+            src.code(),
+            false,
+            Rm::Reg(*dst),
+            symbols,
+        )?,
+        Instruction::Inc(reg) => {
+            emit_modrm(&mut out, true, &[0xff], 0, false, Rm::Reg(*reg), symbols)?
+        }
+        Instruction::Dec(reg) => {
+            emit_modrm(&mut out, true, &[0xff], 1, false, Rm::Reg(*reg), symbols)?
+        }
+        Instruction::Neg(reg) => {
+            emit_modrm(&mut out, true, &[0xf7], 3, false, Rm::Reg(*reg), symbols)?
+        }
+        Instruction::Div(reg) => {
+            emit_modrm(&mut out, true, &[0xf7], 6, false, Rm::Reg(*reg), symbols)?
+        }
+        Instruction::Push(Operand::Reg(reg)) => {
+            if reg.code() >= 8 {
+                out.push(0x41);
+            }
+            out.push(0x50 + (reg.code() & 7));
+        }
+        Instruction::Push(Operand::Imm(imm)) => {
+            let value = symbols.resolve(imm)?;
+            if is_imm8(imm, symbols)? {
+                out.push(0x6a);
+                out.push(value as u8);
+            } else {
+                out.push(0x68);
+                push_i32(&mut out, value)?;
+            }
+        }
+        Instruction::Push(Operand::Mem(mem)) => {
+            emit_modrm(&mut out, false, &[0xff], 6, false, Rm::Mem(mem), symbols)?
+        }
+        Instruction::Pop(PopDst::Reg(reg)) => {
+            if reg.code() >= 8 {
+                out.push(0x41);
+            }
+            out.push(0x58 + (reg.code() & 7));
+        }
+        Instruction::Pop(PopDst::Mem(mem)) => {
+            emit_modrm(&mut out, false, &[0x8f], 0, false, Rm::Mem(mem), symbols)?
+        }
+        Instruction::Jmp(label) => return relative(&[0xe9], label),
+        Instruction::JmpMem(mem) => {
+            emit_modrm(&mut out, false, &[0xff], 4, false, Rm::Mem(mem), symbols)?
+        }
+        Instruction::Jcc(cond, label) => return relative(&[0x0f, 0x80 | cond_code(*cond)], label),
+        Instruction::Call(label) => return relative(&[0xe8], label),
+        Instruction::Ret => out.push(0xc3),
+        Instruction::Syscall => out.extend_from_slice(&[0x0f, 0x05]),
+    }
+    Ok(out)
+}
+
+/// データをバイト列に変換する
+fn encode_data(data: &Data, symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    match data {
+        Data::Str(str) => {
+            bytes.extend_from_slice(str.as_bytes());
+            bytes.push(0);
+        }
+        Data::Quads(values) => {
+            for value in values.iter() {
+                bytes.extend_from_slice(&symbols.resolve(value)?.to_le_bytes());
+            }
+        }
+        Data::Zeroed(count) => bytes.resize(count * 8, 0),
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{DataSection, Register::*, TextSection};
+    use crate::elf::data_offset;
+
+    /// 命令の先頭のアドレス
+    const ADDR: u64 = 0x1000;
+
+    fn symbols() -> HashMap<String, u64> {
+        [
+            ("main".to_string(), 0x1100),
+            ("main.loop".to_string(), 0x0f00),
+            ("table".to_string(), 0x12_3456),
+        ]
+        .iter()
+        .cloned()
+        .collect()
+    }
+
+    /// 2 パス目の変換結果 (1 パス目の変換結果と長さが一致することも確認する)
+    fn encode(inst: Instruction) -> Vec<u8> {
+        let first = encode_instruction(&inst, "main", ADDR, &Symbols(None)).unwrap();
+        let second = encode_instruction(&inst, "main", ADDR, &Symbols(Some(&symbols()))).unwrap();
+        assert_eq!(first.len(), second.len(), "{:?}", inst);
+        second
+    }
+
+    fn mov(operands: Operands) -> Vec<u8> {
+        encode(Instruction::Mov(operands))
+    }
+
+    #[test]
+    fn register_operands() {
+        assert_eq!(mov(Operands::RegReg(Rax, Rbx)), [0x48, 0x89, 0xd8]);
+        assert_eq!(mov(Operands::RegReg(R8, R9)), [0x4d, 0x89, 0xc8]);
+        assert_eq!(mov(Operands::RegReg(Rcx, R15)), [0x4c, 0x89, 0xf9]);
+        assert_eq!(encode(Instruction::Test(Rax, Rax)), [0x48, 0x85, 0xc0]);
+        assert_eq!(encode(Instruction::Xchg(Rdx, Rsi)), [0x48, 0x87, 0xf2]);
+        assert_eq!(encode(Instruction::Inc(R10)), [0x49, 0xff, 0xc2]);
+        assert_eq!(encode(Instruction::Dec(Rax)), [0x48, 0xff, 0xc8]);
+        assert_eq!(encode(Instruction::Neg(Rax)), [0x48, 0xf7, 0xd8]);
+        assert_eq!(encode(Instruction::Div(Rcx)), [0x48, 0xf7, 0xf1]);
+    }
+
+    #[test]
+    fn memory_operands() {
+        let load = |mem| mov(Operands::RegMem(Rax, mem));
+        assert_eq!(load(Memory::base(Rax, 0)), [0x48, 0x8b, 0x00]);
+        assert_eq!(load(Memory::base(Rbp, -8)), [0x48, 0x8b, 0x45, 0xf8]);
+        // rbp と r13 は変位がなくても 8 ビットの変位を付ける
+        assert_eq!(load(Memory::base(Rbp, 0)), [0x48, 0x8b, 0x45, 0x00]);
+        assert_eq!(load(Memory::base(R13, 0)), [0x49, 0x8b, 0x45, 0x00]);
+        assert_eq!(
+            load(Memory::base(Rbp, 0x100)),
+            [0x48, 0x8b, 0x85, 0x00, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(
+            load(Memory::base(Rbp, -129)),
+            [0x48, 0x8b, 0x85, 0x7f, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            mov(Operands::MemReg(Memory::base(Rbp, -16), R9)),
+            [0x4c, 0x89, 0x4d, 0xf0]
+        );
+        assert_eq!(
+            encode(Instruction::Lea(Rdi, Memory::base(Rbp, -8))),
+            [0x48, 0x8d, 0x7d, 0xf8]
+        );
+    }
+
+    #[test]
+    fn sib_operands() {
+        let load = |mem| mov(Operands::RegMem(Rax, mem));
+        // rsp と r12 をベースにすると SIB バイトが必要になる
+        assert_eq!(load(Memory::base(Rsp, 8)), [0x48, 0x8b, 0x44, 0x24, 0x08]);
+        assert_eq!(load(Memory::base(R12, 0)), [0x49, 0x8b, 0x04, 0x24]);
+        assert_eq!(
+            load(Memory::base(Rbx, 0).index(Rcx)),
+            [0x48, 0x8b, 0x04, 0x0b]
+        );
+        assert_eq!(
+            load(Memory::base(Rbx, 0).index(R9)),
+            [0x4a, 0x8b, 0x04, 0x0b]
+        );
+        assert_eq!(
+            load(Memory::base(R11, 16).index(Rsi)),
+            [0x49, 0x8b, 0x44, 0x33, 0x10]
+        );
+        // ベースレジスタがなければ SIB バイトと 32 ビットの絶対アドレスになる
+        assert_eq!(
+            load(Memory::label("table", 8)),
+            [0x48, 0x8b, 0x04, 0x25, 0x5e, 0x34, 0x12, 0x00]
+        );
+        assert_eq!(
+            load(Memory::label("table", 0).index(Rcx)),
+            [0x48, 0x8b, 0x04, 0x0d, 0x56, 0x34, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn label_displacement_is_always_32_bits() {
+        // 1 パス目ではラベルのアドレスが 0 なので、変位の大きさで形式を選ぶと 2 パス目と長さが変わってしまう
+        let mem = Memory {
+            label: Some("table".to_string()),
+            ..Memory::base(Rbx, 0)
+        };
+        assert_eq!(
+            mov(Operands::RegMem(Rax, mem)),
+            [0x48, 0x8b, 0x83, 0x56, 0x34, 0x12, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::Add(Operands::RegImm(
+                Rax,
+                Imm::Label("main".to_string())
+            ))),
+            [0x48, 0x81, 0xc0, 0x00, 0x11, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn arithmetic_immediates() {
+        let add = |imm| encode(Instruction::Add(Operands::RegImm(Rax, Imm::Int(imm))));
+        assert_eq!(add(1), [0x48, 0x83, 0xc0, 0x01]);
+        assert_eq!(add(127), [0x48, 0x83, 0xc0, 0x7f]);
+        assert_eq!(add(-128), [0x48, 0x83, 0xc0, 0x80]);
+        assert_eq!(add(128), [0x48, 0x81, 0xc0, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(add(-129), [0x48, 0x81, 0xc0, 0x7f, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            encode(Instruction::Sub(Operands::RegImm(R12, Imm::Int(8)))),
+            [0x49, 0x83, 0xec, 0x08]
+        );
+        assert_eq!(
+            encode(Instruction::And(Operands::RegImm(Rsp, Imm::Int(-16)))),
+            [0x48, 0x83, 0xe4, 0xf0]
+        );
+        assert_eq!(
+            encode(Instruction::Cmp(Operands::MemImm(
+                Memory::base(Rbp, -8),
+                Imm::Int(0)
+            ))),
+            [0x48, 0x83, 0x7d, 0xf8, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::Cmp(Operands::MemImm8(
+                Memory::base(Rax, 0),
+                Imm::Char(b'0')
+            ))),
+            [0x80, 0x38, 0x30]
+        );
+        assert_eq!(
+            encode(Instruction::Cmp(Operands::Reg8Imm(Rdi, Imm::Int(0)))),
+            [0x40, 0x80, 0xff, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::Xor(Operands::RegReg(Rdx, Rdx))),
+            [0x48, 0x31, 0xd2]
+        );
+        assert_eq!(
+            encode(Instruction::Sub(Operands::RegMem(
+                Rax,
+                Memory::base(Rbp, -8)
+            ))),
+            [0x48, 0x2b, 0x45, 0xf8]
+        );
+    }
+
+    #[test]
+    fn mov_immediates() {
+        let mov_imm = |dst, imm| mov(Operands::RegImm(dst, imm));
+        assert_eq!(mov_imm(Rax, Imm::Int(1)), [0xb8, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            mov_imm(R8, Imm::Int(0xffff_ffff)),
+            [0x41, 0xb8, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            mov_imm(Rax, Imm::Int(-1)),
+            [0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            mov_imm(Rax, Imm::Int(1 << 40)),
+            [0x48, 0xb8, 0, 0, 0, 0, 0, 0x01, 0, 0]
+        );
+        assert_eq!(
+            mov_imm(Rsi, Imm::Label("table".to_string())),
+            [0x48, 0xbe, 0x56, 0x34, 0x12, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            mov(Operands::MemImm(Memory::base(Rbp, -8), Imm::Int(-1))),
+            [0x48, 0xc7, 0x45, 0xf8, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            mov(Operands::MemImm8(Memory::base(Rdi, 0), Imm::Char(b'\n'))),
+            [0xc6, 0x07, 0x0a]
+        );
+        assert_eq!(
+            mov(Operands::Reg8Imm(Rsi, Imm::Char(b'-'))),
+            [0x40, 0xc6, 0xc6, 0x2d]
+        );
+        assert_eq!(
+            mov(Operands::Reg8Imm(Rax, Imm::Char(b'-'))),
+            [0xc6, 0xc0, 0x2d]
+        );
+        // spl, bpl, sil, dil には REX プレフィックスが必要
+        assert_eq!(
+            mov(Operands::MemReg8(Memory::base(Rax, 0), Rsi)),
+            [0x40, 0x88, 0x30]
+        );
+        assert_eq!(
+            mov(Operands::MemReg8(Memory::base(R8, 0), Rdx)),
+            [0x41, 0x88, 0x10]
+        );
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let push = |operand: Operand| encode(Instruction::Push(operand));
+        assert_eq!(push(Rbx.into()), [0x53]);
+        assert_eq!(push(R12.into()), [0x41, 0x54]);
+        assert_eq!(push(Imm::Int(1).into()), [0x6a, 0x01]);
+        assert_eq!(
+            push(Imm::Int(0x1000).into()),
+            [0x68, 0x00, 0x10, 0x00, 0x00]
+        );
+        assert_eq!(
+            push(Imm::Label("main".to_string()).into()),
+            [0x68, 0x00, 0x11, 0x00, 0x00]
+        );
+        assert_eq!(push(Memory::base(Rbp, -8).into()), [0xff, 0x75, 0xf8]);
+        assert_eq!(encode(Instruction::Pop(Rbx.into())), [0x5b]);
+        assert_eq!(encode(Instruction::Pop(R15.into())), [0x41, 0x5f]);
+        assert_eq!(
+            encode(Instruction::Pop(Memory::base(Rsp, 8).into())),
+            [0x8f, 0x44, 0x24, 0x08]
+        );
+    }
+
+    #[test]
+    fn relative_branches() {
+        // 前方 (main = 0x1100) と後方 (main.loop = 0x0f00) への分岐
+        assert_eq!(
+            encode(Instruction::Jmp("main".to_string())),
+            [0xe9, 0xfb, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::Jmp(".loop".to_string())),
+            [0xe9, 0xfb, 0xfe, 0xff, 0xff]
+        );
+        assert_eq!(
+            encode(Instruction::Jcc(Cond::Ne, "main".to_string())),
+            [0x0f, 0x85, 0xfa, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::Jcc(Cond::S, ".loop".to_string())),
+            [0x0f, 0x88, 0xfa, 0xfe, 0xff, 0xff]
+        );
+        assert_eq!(
+            encode(Instruction::Call("main".to_string())),
+            [0xe8, 0xfb, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode(Instruction::JmpMem(Memory::label("table", 0))),
+            [0xff, 0x24, 0x25, 0x56, 0x34, 0x12, 0x00]
+        );
+        assert_eq!(encode(Instruction::Ret), [0xc3]);
+        assert_eq!(encode(Instruction::Syscall), [0x0f, 0x05]);
+    }
+
+    #[test]
+    fn undefined_label() {
+        let inst = Instruction::Jmp(".missing".to_string());
+        assert_eq!(
+            encode_instruction(&inst, "main", ADDR, &Symbols(Some(&symbols()))),
+            Err("Undefined symbol `main.missing`".to_string())
+        );
+    }
+
+    fn program(text: TextSection, data: DataSection) -> Asm {
+        Asm {
+            exports: Vec::new(),
+            externs: Vec::new(),
+            data,
+            text,
+        }
+    }
+
+    #[test]
+    fn assemble_program() {
+        let mut text = TextSection::default();
+        text.label("_start");
+        text.inst(Instruction::Jmp(".next".to_string()));
+        text.label(".next");
+        text.inst(Instruction::Mov(Operands::RegMem(
+            Rax,
+            Memory::label("msg", 0),
+        )));
+        text.inst(Instruction::Ret);
+        let mut data = DataSection::default();
+        data.string("msg", "hi");
+
+        let exec = assemble(&program(text, data)).unwrap();
+        let text_addr = BASE_ADDR + TEXT_OFFSET;
+        let runtime_len = RUNTIME_DATA
+            .iter()
+            .map(|(_, s)| s.len() as u64 + 1)
+            .sum::<u64>();
+        let msg_addr = (BASE_ADDR + data_offset(13) + runtime_len) as u32;
+
+        let mut expected = vec![0xe9, 0, 0, 0, 0, 0x48, 0x8b, 0x04, 0x25];
+        expected.extend(msg_addr.to_le_bytes());
+        expected.push(0xc3);
+        let text_start = TEXT_OFFSET as usize;
+        assert_eq!(
+            &exec[text_start..text_start + expected.len()],
+            &expected[..]
+        );
+        // エントリポイントは _start
+        assert_eq!(&exec[24..32], &text_addr.to_le_bytes());
+        assert!(exec.ends_with(b"hi\0"));
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let mut asm = program(TextSection::default(), DataSection::default());
+        assert_eq!(
+            assemble(&asm).unwrap_err(),
+            "The entry point `_start` is not defined"
+        );
+
+        asm.text.label("_start");
+        asm.text.label("_start");
+        assert_eq!(
+            assemble(&asm).unwrap_err(),
+            "Symbol `_start` is defined more than once"
+        );
+
+        asm.externs.push("puts".to_string());
+        assert_eq!(
+            assemble(&asm).unwrap_err(),
+            "External symbol `puts` is not supported by the built-in assembler"
+        );
+    }
+}
@@ -1,12 +1,36 @@
+use super::diagnostic::Diagnostic;
 use super::location::{Locatable, Location};
-use super::token::{Identifier, StringLiteral};
-use serde::Serialize;
+use super::token::{Identifier, IntegerLiteral, StringLiteral};
+use serde::{Deserialize, Serialize};
+
+/// 値の型
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Type {
+    String,
+    Integer,
+}
+
+/// 型注釈の抽象構文木
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TypeAst {
+    pub ty: Type,
+    pub location: Location,
+}
+
+impl Locatable for TypeAst {
+    fn locate(&self) -> Location {
+        self.location
+    }
+}
 
 /// 式の抽象構文木
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ExprAst {
     Ident(Identifier),
     StrLit(StringLiteral),
+    IntLit(IntegerLiteral),
+    /// 関数呼び出し
+    Call(Identifier, Vec<ExprAst>),
 }
 
 impl Locatable for ExprAst {
@@ -14,14 +38,271 @@ impl Locatable for ExprAst {
         match self {
             ExprAst::Ident(ident) => ident.locate(),
             ExprAst::StrLit(str_lit) => str_lit.locate(),
+            ExprAst::IntLit(int_lit) => int_lit.locate(),
+            ExprAst::Call(func, _) => func.locate(),
         }
     }
 }
 
+/// 仮引数の抽象構文木
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParamAst {
+    pub name: Identifier,
+    pub ty: TypeAst,
+}
+
+/// 外部ライブラリの手続きの宣言 ( ``DECLARE FUNCTION ... LIB ...`` )
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExternDeclAst {
+    pub name: Identifier,
+    pub lib: StringLiteral,
+    pub params: Vec<ParamAst>,
+    /// 戻り値の型 ( ``SUB`` の場合は ``None`` )
+    pub ret: Option<TypeAst>,
+}
+
+/// BASIC で記述された手続きの定義 ( ``SUB ... END SUB`` / ``FUNCTION ... END FUNCTION`` )
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProcDefAst {
+    pub name: Identifier,
+    pub params: Vec<ParamAst>,
+    /// 戻り値の型 ( ``SUB`` の場合は ``None`` )
+    pub ret: Option<TypeAst>,
+    pub body: Vec<StmtAst>,
+}
+
+/// ``RESUME`` で実行を再開する位置
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResumeTarget {
+    /// エラーが発生した文 ( ``RESUME`` )
+    Retry,
+    /// エラーが発生した文の次の文 ( ``RESUME NEXT`` )
+    Next,
+}
+
 /// 文の抽象構文木
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum StmtAst {
     VarDecl(Identifier, ExprAst),
     VarAssign(Identifier, ExprAst),
     ProcCall(Identifier, Vec<ExprAst>),
+    ExternDecl(ExternDeclAst),
+    ProcDef(ProcDefAst),
+    /// ラベル ( ``name:`` )
+    Label(Identifier),
+    /// ``ON ERROR GOTO label`` (``ON ERROR GOTO 0`` の場合は ``None``)
+    OnErrorGoto(Location, Option<Identifier>),
+    Resume(Location, ResumeTarget),
+    /// ``ERROR n``
+    Error(Location, ExprAst),
+    /// プログラムの終了
+    End(Location),
+}
+
+impl Locatable for StmtAst {
+    fn locate(&self) -> Location {
+        match self {
+            StmtAst::VarDecl(ident, _) => ident.locate(),
+            StmtAst::VarAssign(ident, _) => ident.locate(),
+            StmtAst::ProcCall(proc, _) => proc.locate(),
+            StmtAst::ExternDecl(decl) => decl.name.locate(),
+            StmtAst::ProcDef(def) => def.name.locate(),
+            StmtAst::Label(label) => label.locate(),
+            StmtAst::OnErrorGoto(location, _) => *location,
+            StmtAst::Resume(location, _) => *location,
+            StmtAst::Error(location, _) => *location,
+            StmtAst::End(location) => *location,
+        }
+    }
+}
+
+/// JSON で表された抽象構文木を読み込む
+///
+/// BASIC のソースコードから生成される抽象構文木と同じ範囲に収まるように、
+/// 識別子と文字列リテラル、整数リテラルがトークンとして表せるものであることと、
+/// 位置が負でなく終わりが始まりより前にないことも検査する
+pub fn from_json(src: &str) -> Result<Vec<StmtAst>, Diagnostic> {
+    let stmts: Vec<StmtAst> = serde_json::from_str(src)
+        .map_err(|e| Diagnostic::error("E0301", format!("Invalid AST: {}", e)))?;
+    stmts.iter().try_for_each(check_stmt)?;
+    Ok(stmts)
+}
+
+fn check_stmt(stmt: &StmtAst) -> Result<(), Diagnostic> {
+    match stmt {
+        StmtAst::VarDecl(ident, expr) | StmtAst::VarAssign(ident, expr) => {
+            check_ident(ident)?;
+            check_expr(expr)
+        }
+        StmtAst::ProcCall(proc, args) => {
+            check_ident(proc)?;
+            args.iter().try_for_each(check_expr)
+        }
+        StmtAst::ExternDecl(decl) => {
+            check_ident(&decl.name)?;
+            check_str_lit(&decl.lib)?;
+            check_params(&decl.params, &decl.ret)
+        }
+        StmtAst::ProcDef(def) => {
+            check_ident(&def.name)?;
+            check_params(&def.params, &def.ret)?;
+            def.body.iter().try_for_each(check_stmt)
+        }
+        StmtAst::Label(label) => check_ident(label),
+        StmtAst::OnErrorGoto(location, label) => {
+            check_location(*location)?;
+            label.iter().try_for_each(check_ident)
+        }
+        StmtAst::Error(location, expr) => {
+            check_location(*location)?;
+            check_expr(expr)
+        }
+        StmtAst::Resume(location, _) | StmtAst::End(location) => check_location(*location),
+    }
+}
+
+fn check_params(params: &[ParamAst], ret: &Option<TypeAst>) -> Result<(), Diagnostic> {
+    for param in params.iter() {
+        check_ident(&param.name)?;
+        check_location(param.ty.location)?;
+    }
+    ret.iter().try_for_each(|ret| check_location(ret.location))
+}
+
+/// 位置が負でなく、終わりが始まりより前にないか検査する
+///
+/// 範囲外の位置はそのまま診断に表示できないため、ラベルは付けずに JSON 上の値 (0 始まり) を示す
+fn check_location(location: Location) -> Result<(), Diagnostic> {
+    let Location { start, end } = location;
+    let negative = [start.line(), start.column(), end.line(), end.column()]
+        .iter()
+        .any(|n| *n < 0);
+    let inverted = (end.line(), end.column()) < (start.line(), start.column());
+    if negative || inverted {
+        return Err(Diagnostic::error(
+            "E0305",
+            format!(
+                "Invalid AST: Location from line {} column {} to line {} column {} (0-based) is {}",
+                start.line(),
+                start.column(),
+                end.line(),
+                end.column(),
+                if negative {
+                    "negative"
+                } else {
+                    "inverted (its end precedes its start)"
+                }
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check_expr(expr: &ExprAst) -> Result<(), Diagnostic> {
+    match expr {
+        ExprAst::Ident(ident) => check_ident(ident),
+        ExprAst::StrLit(str_lit) => check_str_lit(str_lit),
+        ExprAst::IntLit(int_lit) => {
+            check_location(int_lit.location)?;
+            if int_lit.value < 0 {
+                return Err(Diagnostic::error(
+                    "E0302",
+                    format!(
+                        "Invalid AST: Integer literal `{}` is negative",
+                        int_lit.value
+                    ),
+                )
+                .with_label(int_lit.location, "negative integer"));
+            }
+            Ok(())
+        }
+        ExprAst::Call(func, args) => {
+            check_ident(func)?;
+            args.iter().try_for_each(check_expr)
+        }
+    }
+}
+
+/// トークナイザが識別子として切り出す文字列であるか検査する
+fn check_ident(ident: &Identifier) -> Result<(), Diagnostic> {
+    check_location(ident.location)?;
+    let name = &ident.name;
+    if name.is_empty()
+        || name.chars().all(|c| c.is_ascii_digit())
+        || name
+            .chars()
+            .any(|c| c.is_whitespace() || "\",=:()".contains(c))
+    {
+        return Err(Diagnostic::error(
+            "E0303",
+            format!("Invalid AST: `{}` is not a valid identifier", name),
+        )
+        .with_label(ident.location, "invalid identifier"));
+    }
+    Ok(())
+}
+
+fn check_str_lit(str_lit: &StringLiteral) -> Result<(), Diagnostic> {
+    check_location(str_lit.location)?;
+    if str_lit.value.contains(['"', '\n']) {
+        return Err(Diagnostic::error(
+            "E0304",
+            format!(
+                "Invalid AST: String literal {:?} cannot be written in BASIC",
+                str_lit.value
+            ),
+        )
+        .with_label(str_lit.location, "contains `\"` or a line break"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ``PRINT 1`` の抽象構文木で、 ``1`` の位置を置き換えたもの
+    fn print_one(start: (i32, i32), end: (i32, i32)) -> String {
+        format!(
+            r#"[{{"ProcCall": [
+                {{"name": "PRINT", "location": {{"start": {{"line": 0, "column": 0}}, "end": {{"line": 0, "column": 4}}}}}},
+                [{{"IntLit": {{"value": 1, "location": {{"start": {{"line": {}, "column": {}}}, "end": {{"line": {}, "column": {}}}}}}}}}]
+            ]}}]"#,
+            start.0, start.1, end.0, end.1
+        )
+    }
+
+    fn error_of(src: &str) -> Diagnostic {
+        match from_json(src) {
+            Ok(_) => panic!("invalid AST is accepted"),
+            Err(diag) => diag,
+        }
+    }
+
+    #[test]
+    fn valid_location() {
+        assert!(from_json(&print_one((0, 6), (0, 6))).is_ok());
+    }
+
+    #[test]
+    fn negative_location() {
+        let diag = error_of(&print_one((0, -1), (0, 6)));
+        assert_eq!(diag.code, Some("E0305"));
+        assert_eq!(
+            diag.message,
+            "Invalid AST: Location from line 0 column -1 to line 0 column 6 (0-based) is negative"
+        );
+        assert!(diag.labels.is_empty());
+    }
+
+    #[test]
+    fn inverted_location() {
+        let diag = error_of(&print_one((1, 0), (0, 6)));
+        assert_eq!(diag.code, Some("E0305"));
+        assert_eq!(
+            diag.message,
+            "Invalid AST: Location from line 1 column 0 to line 0 column 6 (0-based) is inverted (its end precedes its start)"
+        );
+        assert_eq!(error_of(&print_one((0, 6), (0, 5))).code, Some("E0305"));
+    }
 }
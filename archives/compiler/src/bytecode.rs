@@ -0,0 +1,625 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{validate, ExternProc, Ir, IrInst, Proc};
+use super::location::{Location, Point};
+use std::convert::TryFrom;
+
+/// ``.basc`` ファイルの先頭に置かれるマジックナンバー
+pub static MAGIC: [u8; 4] = *b"BASC";
+
+/// バイトコード形式のバージョン (形式を変更した場合は更新する)
+pub const VERSION: u16 = 1;
+
+/// バイトコード形式の命令のオペコード
+mod opcode {
+    pub const GET_STATIC_STR: u8 = 0x01;
+    pub const GET_IMM_INT: u8 = 0x02;
+    pub const GET_GLOBAL: u8 = 0x03;
+    pub const SET_GLOBAL: u8 = 0x04;
+    pub const GET_LOCAL: u8 = 0x05;
+    pub const SET_LOCAL: u8 = 0x06;
+    pub const ASSERT_TYPE: u8 = 0x07;
+    pub const CALL_EXTERN: u8 = 0x08;
+    pub const CALL_PROC: u8 = 0x09;
+    pub const POP: u8 = 0x0a;
+    pub const PRINT: u8 = 0x0b;
+    pub const BEGIN_STMT: u8 = 0x0c;
+    pub const LABEL: u8 = 0x0d;
+    pub const ON_ERROR_GOTO: u8 = 0x0e;
+    pub const RESUME: u8 = 0x0f;
+    pub const RAISE_ERROR: u8 = 0x10;
+    pub const GET_ERR_CODE: u8 = 0x11;
+    pub const GET_ERR_LINE: u8 = 0x12;
+    pub const END: u8 = 0x13;
+}
+
+/// 中間表現をバイトコード形式 ( ``.basc`` ) に変換する
+///
+/// 整数はすべてリトルエンディアンで、次の順に並べる。
+///
+/// - ヘッダ: マジックナンバー ``BASC`` 、バージョン (u16)、予約領域 (u16、0)
+/// - グローバル変数の個数 (u32、命令列が参照する最大の番号 + 1)
+/// - 文字列プール: 個数 (u32) と、各文字列 (長さ (u32) と UTF-8 のバイト列)
+/// - 外部ライブラリの手続き: 個数 (u32) と、各手続きの名前、ライブラリ名、引数の型、戻り値の型
+/// - BASIC で定義された手続き: 個数 (u32) と、各手続きの名前、仮引数の名前と型、戻り値の型、ローカル変数の個数 (u32、仮引数と戻り値を含めて参照する最大の番号 + 1)、命令列
+/// - トップレベルの命令列: 命令の個数 (u32) と、各命令 (オペコード (u8) とオペランド)
+pub fn encode(ir: &Ir) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(&MAGIC);
+    writer.u16(VERSION);
+    writer.u16(0);
+    writer.u32(num_used_globals(ir) as u32);
+
+    writer.u32(ir.string_pool.len() as u32);
+    for s in ir.string_pool.iter() {
+        writer.string(s);
+    }
+
+    writer.u32(ir.externs.len() as u32);
+    for ext in ir.externs.iter() {
+        writer.string(&ext.name);
+        writer.string(&ext.lib);
+        writer.u32(ext.params.len() as u32);
+        for ty in ext.params.iter() {
+            writer.u8(type_code(*ty));
+        }
+        writer.ret_type(ext.ret);
+    }
+
+    writer.u32(ir.procs.len() as u32);
+    for proc in ir.procs.iter() {
+        writer.string(&proc.name);
+        writer.u32(proc.params.len() as u32);
+        for (name, ty) in proc.param_names.iter().zip(proc.params.iter()) {
+            writer.string(name);
+            writer.u8(type_code(*ty));
+        }
+        writer.ret_type(proc.ret);
+        writer.u32(num_used_locals(proc) as u32);
+        writer.insts(&proc.insts);
+    }
+
+    writer.insts(&ir.insts);
+    writer.bytes
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /// 戻り値の型 (0 は戻り値なし)
+    fn ret_type(&mut self, ty: Option<Type>) {
+        self.u8(ty.map_or(0, type_code));
+    }
+
+    fn location(&mut self, location: &Location) {
+        for point in [location.start, location.end] {
+            self.u32(point.line() as u32);
+            self.u32(point.column() as u32);
+        }
+    }
+
+    fn insts(&mut self, insts: &[IrInst]) {
+        self.u32(insts.len() as u32);
+        for inst in insts.iter() {
+            match inst {
+                IrInst::GetStaticStr(index) => {
+                    self.u8(opcode::GET_STATIC_STR);
+                    self.u32(*index as u32);
+                }
+                IrInst::GetImmInt(value) => {
+                    self.u8(opcode::GET_IMM_INT);
+                    self.i64(*value);
+                }
+                IrInst::GetGlobal(index) => {
+                    self.u8(opcode::GET_GLOBAL);
+                    self.u32(*index as u32);
+                }
+                IrInst::SetGlobal(index) => {
+                    self.u8(opcode::SET_GLOBAL);
+                    self.u32(*index as u32);
+                }
+                IrInst::GetLocal(index) => {
+                    self.u8(opcode::GET_LOCAL);
+                    self.u32(*index as u32);
+                }
+                IrInst::SetLocal(index) => {
+                    self.u8(opcode::SET_LOCAL);
+                    self.u32(*index as u32);
+                }
+                IrInst::AssertType(ty, location) => {
+                    self.u8(opcode::ASSERT_TYPE);
+                    self.u8(type_code(*ty));
+                    self.location(location);
+                }
+                IrInst::CallExtern(index) => {
+                    self.u8(opcode::CALL_EXTERN);
+                    self.u32(*index as u32);
+                }
+                IrInst::CallProc(index, location) => {
+                    self.u8(opcode::CALL_PROC);
+                    self.u32(*index as u32);
+                    self.location(location);
+                }
+                IrInst::Pop => self.u8(opcode::POP),
+                IrInst::Print => self.u8(opcode::PRINT),
+                IrInst::BeginStmt(index) => {
+                    self.u8(opcode::BEGIN_STMT);
+                    self.u32(*index as u32);
+                }
+                IrInst::Label(index) => {
+                    self.u8(opcode::LABEL);
+                    self.u32(*index as u32);
+                }
+                IrInst::OnErrorGoto(label) => {
+                    // 0 はエラーハンドラの解除を表し、ラベルの番号は 1 を足して格納する
+                    self.u8(opcode::ON_ERROR_GOTO);
+                    self.u32(label.map_or(0, |index| index as u32 + 1));
+                }
+                IrInst::Resume(target, location) => {
+                    self.u8(opcode::RESUME);
+                    self.u8(match target {
+                        ResumeTarget::Retry => 0,
+                        ResumeTarget::Next => 1,
+                    });
+                    self.location(location);
+                }
+                IrInst::RaiseError(location) => {
+                    self.u8(opcode::RAISE_ERROR);
+                    self.location(location);
+                }
+                IrInst::GetErrCode => self.u8(opcode::GET_ERR_CODE),
+                IrInst::GetErrLine => self.u8(opcode::GET_ERR_LINE),
+                IrInst::End => self.u8(opcode::END),
+            }
+        }
+    }
+}
+
+/// 命令列が参照するグローバル変数の個数 (参照する最大の番号 + 1)
+fn num_used_globals(ir: &Ir) -> i32 {
+    let insts = ir
+        .insts
+        .iter()
+        .chain(ir.procs.iter().flat_map(|proc| &proc.insts));
+    insts
+        .filter_map(|inst| match inst {
+            IrInst::GetGlobal(index) | IrInst::SetGlobal(index) => Some(index + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// 手続きが使うローカル変数の個数 (仮引数と戻り値、命令列が参照する最大の番号 + 1)
+fn num_used_locals(proc: &Proc) -> i32 {
+    let min_locals = (proc.params.len() + proc.ret.map_or(0, |_| 1)) as i32;
+    proc.insts
+        .iter()
+        .filter_map(|inst| match inst {
+            IrInst::GetLocal(index) | IrInst::SetLocal(index) => Some(index + 1),
+            _ => None,
+        })
+        .fold(min_locals, i32::max)
+}
+
+fn type_code(ty: Type) -> u8 {
+    match ty {
+        Type::String => 1,
+        Type::Integer => 2,
+    }
+}
+
+/// バイトコード形式 ( ``.basc`` ) を読み込み、検査した上で中間表現に変換する
+///
+/// ファイルが途中で終わっている場合や、範囲外の番号を参照している場合など、
+/// 実行できない内容であればその理由を含むエラーを返す。
+/// 変数の個数は実行時にそのまま確保されるため、命令列が参照する分を超える個数も受け付けない
+pub fn decode(bytes: &[u8]) -> Result<Ir, String> {
+    let ir = Reader { bytes, pos: 0 }
+        .module()
+        .map_err(|e| format!("Invalid bytecode: {}", e))?;
+    validate(&ir)
+        .and_then(|_| validate_counts(&ir))
+        .map_err(|e| format!("Invalid bytecode: {}", e))?;
+    Ok(ir)
+}
+
+fn validate_counts(ir: &Ir) -> Result<(), String> {
+    let used = num_used_globals(ir);
+    if ir.num_globals > used {
+        return Err(format!(
+            "global count {} exceeds the {} global variable(s) referenced by the instructions",
+            ir.num_globals, used
+        ));
+    }
+    for (i, proc) in ir.procs.iter().enumerate() {
+        let used = num_used_locals(proc);
+        if proc.num_locals > used {
+            return Err(format!(
+                "local count {} of procedure #{} (`{}`) exceeds the {} local variable(s) it uses",
+                proc.num_locals, i, proc.name, used
+            ));
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize, what: &str) -> Result<&[u8], String> {
+        if self.bytes.len() - self.pos < len {
+            return Err(format!(
+                "unexpected end of file while reading {} at offset {}",
+                what, self.pos
+            ));
+        }
+        self.pos += len;
+        Ok(&self.bytes[self.pos - len..self.pos])
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, String> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, String> {
+        let bytes = self.take(2, what)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, String> {
+        let bytes = self.take(4, what)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// 中間表現で ``i32`` として扱う番号や個数
+    fn index(&mut self, what: &str) -> Result<i32, String> {
+        let pos = self.pos;
+        let value = self.u32(what)?;
+        i32::try_from(value).map_err(|_| format!("{} at offset {} is too large", what, pos))
+    }
+
+    /// 個数を読み込む (残りのバイト数を超える個数は、ファイルが途中で終わっているものとして扱う)
+    fn count(&mut self, what: &str, min_item_size: usize) -> Result<usize, String> {
+        let count = self.index(what)? as usize;
+        if count * min_item_size > self.bytes.len() - self.pos {
+            return Err(format!(
+                "unexpected end of file while reading {} (expected {})",
+                what, count
+            ));
+        }
+        Ok(count)
+    }
+
+    fn i64(&mut self, what: &str) -> Result<i64, String> {
+        let bytes = self.take(8, what)?;
+        let mut buf = [0; 8];
+        buf.copy_from_slice(bytes);
+        Ok(i64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, String> {
+        let len = self.u32(what)? as usize;
+        let pos = self.pos;
+        let bytes = self.take(len, what)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| format!("{} at offset {} is not valid UTF-8", what, pos))
+    }
+
+    fn ty(&mut self, what: &str) -> Result<Type, String> {
+        match self.ret_type(what)? {
+            Some(ty) => Ok(ty),
+            None => Err(format!("invalid type code 0 for {}", what)),
+        }
+    }
+
+    fn ret_type(&mut self, what: &str) -> Result<Option<Type>, String> {
+        let pos = self.pos;
+        match self.u8(what)? {
+            0 => Ok(None),
+            1 => Ok(Some(Type::String)),
+            2 => Ok(Some(Type::Integer)),
+            code => Err(format!(
+                "invalid type code {} for {} at offset {}",
+                code, what, pos
+            )),
+        }
+    }
+
+    fn location(&mut self) -> Result<Location, String> {
+        let mut point = || -> Result<Point, String> {
+            let line = self.index("source location")?;
+            let column = self.index("source location")?;
+            Ok(Point::new(line, column))
+        };
+        Ok(Location {
+            start: point()?,
+            end: point()?,
+        })
+    }
+
+    fn module(&mut self) -> Result<Ir, String> {
+        let magic = self.take(MAGIC.len(), "header")?;
+        if magic != MAGIC {
+            return Err("not a BASIC bytecode file (wrong magic number)".to_owned());
+        }
+        let version = self.u16("header")?;
+        if version != VERSION {
+            return Err(format!(
+                "unsupported version {} (expected {})",
+                version, VERSION
+            ));
+        }
+        if self.u16("header")? != 0 {
+            return Err("reserved header field is not zero".to_owned());
+        }
+
+        let num_globals = self.index("global count")?;
+
+        let num_strings = self.count("string pool", 4)?;
+        let string_pool = (0..num_strings)
+            .map(|_| self.string("string pool"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let num_externs = self.count("external procedures", 13)?;
+        let mut externs = Vec::with_capacity(num_externs);
+        for _ in 0..num_externs {
+            let name = self.string("external procedure name")?;
+            let lib = self.string("library name")?;
+            let num_params = self.count("external procedure parameters", 1)?;
+            let params = (0..num_params)
+                .map(|_| self.ty("external procedure parameter"))
+                .collect::<Result<Vec<_>, _>>()?;
+            let ret = self.ret_type("external procedure return type")?;
+            externs.push(ExternProc {
+                name,
+                lib,
+                params,
+                ret,
+            });
+        }
+
+        let num_procs = self.count("procedures", 17)?;
+        let mut procs = Vec::with_capacity(num_procs);
+        for _ in 0..num_procs {
+            let name = self.string("procedure name")?;
+            let num_params = self.count("procedure parameters", 5)?;
+            let mut param_names = Vec::with_capacity(num_params);
+            let mut params = Vec::with_capacity(num_params);
+            for _ in 0..num_params {
+                param_names.push(self.string("parameter name")?);
+                params.push(self.ty("procedure parameter")?);
+            }
+            let ret = self.ret_type("procedure return type")?;
+            let num_locals = self.index("local count")?;
+            let insts = self.insts()?;
+            procs.push(Proc {
+                name,
+                param_names,
+                params,
+                ret,
+                num_locals,
+                insts,
+            });
+        }
+
+        let insts = self.insts()?;
+
+        if self.pos != self.bytes.len() {
+            return Err(format!(
+                "{} trailing byte(s) after the instruction stream",
+                self.bytes.len() - self.pos
+            ));
+        }
+
+        Ok(Ir {
+            num_globals,
+            string_pool,
+            externs,
+            procs,
+            insts,
+        })
+    }
+
+    fn insts(&mut self) -> Result<Vec<IrInst>, String> {
+        let num_insts = self.count("instruction stream", 1)?;
+        let mut insts = Vec::with_capacity(num_insts);
+        for _ in 0..num_insts {
+            let pos = self.pos;
+            let inst = match self.u8("instruction")? {
+                opcode::GET_STATIC_STR => IrInst::GetStaticStr(self.index("operand")?),
+                opcode::GET_IMM_INT => IrInst::GetImmInt(self.i64("operand")?),
+                opcode::GET_GLOBAL => IrInst::GetGlobal(self.index("operand")?),
+                opcode::SET_GLOBAL => IrInst::SetGlobal(self.index("operand")?),
+                opcode::GET_LOCAL => IrInst::GetLocal(self.index("operand")?),
+                opcode::SET_LOCAL => IrInst::SetLocal(self.index("operand")?),
+                opcode::ASSERT_TYPE => IrInst::AssertType(self.ty("operand")?, self.location()?),
+                opcode::CALL_EXTERN => IrInst::CallExtern(self.index("operand")?),
+                opcode::CALL_PROC => IrInst::CallProc(self.index("operand")?, self.location()?),
+                opcode::POP => IrInst::Pop,
+                opcode::PRINT => IrInst::Print,
+                opcode::BEGIN_STMT => IrInst::BeginStmt(self.index("operand")?),
+                opcode::LABEL => IrInst::Label(self.index("operand")?),
+                opcode::ON_ERROR_GOTO => {
+                    let label = self.index("operand")?;
+                    IrInst::OnErrorGoto(if label == 0 { None } else { Some(label - 1) })
+                }
+                opcode::RESUME => {
+                    let target = match self.u8("operand")? {
+                        0 => ResumeTarget::Retry,
+                        1 => ResumeTarget::Next,
+                        target => {
+                            return Err(format!(
+                                "invalid RESUME target {} at offset {}",
+                                target, pos
+                            ))
+                        }
+                    };
+                    IrInst::Resume(target, self.location()?)
+                }
+                opcode::RAISE_ERROR => IrInst::RaiseError(self.location()?),
+                opcode::GET_ERR_CODE => IrInst::GetErrCode,
+                opcode::GET_ERR_LINE => IrInst::GetErrLine,
+                opcode::END => IrInst::End,
+                op => return Err(format!("unknown opcode 0x{:02x} at offset {}", op, pos)),
+            };
+            insts.push(inst);
+        }
+        Ok(insts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::frontend;
+
+    const SRC: &str = "VAR a = 1
+SUB Proc1 ()
+  VAR x = 2
+  PRINT x
+END SUB
+Proc1
+PRINT a
+";
+
+    fn sample() -> Vec<u8> {
+        encode(&frontend(SRC))
+    }
+
+    /// ``Proc1`` のローカル変数の個数が格納されている位置
+    fn local_count_offset(bytes: &[u8]) -> usize {
+        let name = bytes.windows(5).position(|w| w == b"Proc1").unwrap();
+        // 名前、仮引数の個数 (u32)、戻り値の型 (u8) の後に置かれる
+        name + 5 + 4 + 1
+    }
+
+    fn decode_err(bytes: &[u8]) -> String {
+        match decode(bytes) {
+            Ok(_) => panic!("invalid bytecode is accepted"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = sample();
+        assert_eq!(encode(&decode(&bytes).unwrap()), bytes);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = sample();
+        for len in 0..bytes.len() {
+            assert!(
+                decode_err(&bytes[..len]).contains("unexpected end of file"),
+                "truncated at {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = sample();
+        bytes[0] = b'X';
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: not a BASIC bytecode file (wrong magic number)"
+        );
+    }
+
+    #[test]
+    fn bad_version() {
+        let mut bytes = sample();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            format!(
+                "Invalid bytecode: unsupported version {} (expected {})",
+                VERSION + 1,
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn bad_opcode() {
+        let mut bytes = sample();
+        let last = bytes.len() - 1;
+        assert_eq!(bytes[last], opcode::PRINT);
+        bytes[last] = 0xff;
+        assert_eq!(
+            decode_err(&bytes),
+            format!("Invalid bytecode: unknown opcode 0xff at offset {}", last)
+        );
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let mut bytes = sample();
+        bytes[8..12].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: global variable #0 does not exist in the main program"
+        );
+
+        let mut bytes = sample();
+        let offset = local_count_offset(&bytes);
+        bytes[offset..offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: local variable #0 does not exist in procedure #0 (`Proc1`)"
+        );
+    }
+
+    #[test]
+    fn oversized_counts() {
+        let mut bytes = sample();
+        bytes[8..12].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: global count 100 exceeds the 1 global variable(s) referenced by the instructions"
+        );
+
+        let mut bytes = sample();
+        bytes[8..12].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: global count 2147483647 is out of range (0 to 65536)"
+        );
+
+        let mut bytes = sample();
+        let offset = local_count_offset(&bytes);
+        bytes[offset..offset + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            decode_err(&bytes),
+            "Invalid bytecode: local count 2 of procedure #0 (`Proc1`) exceeds the 1 local variable(s) it uses"
+        );
+    }
+}
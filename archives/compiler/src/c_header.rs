@@ -0,0 +1,143 @@
+use super::ast::Type;
+use super::ir::Ir;
+
+/// 静的ライブラリとして公開される手続きを C から呼び出すためのヘッダファイルを生成する
+pub fn gen_c_header(ir: &Ir) -> String {
+    let mut result = String::from("#pragma once\n\n");
+    // ライブラリは位置独立コードではないため、PIE としてリンクできない
+    result.push_str("/* This library is not position-independent: link it with `-no-pie`. */\n\n");
+    result.push_str("#include <stdint.h>\n\n");
+
+    for proc in ir.procs.iter() {
+        let params = if proc.params.is_empty() {
+            "void".to_owned()
+        } else {
+            proc.param_names
+                .iter()
+                .zip(proc.params.iter())
+                .map(|(name, ty)| declarator(Some(*ty), name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let func = format!("{}({})", proc.name, params);
+        result.push_str(format!("{};\n", declarator(proc.ret, &func)).as_str());
+    }
+
+    result
+}
+
+/// 型に対応する C の宣言子を生成する ( ``None`` は ``void`` を表す)
+fn declarator(ty: Option<Type>, name: &str) -> String {
+    match ty {
+        Some(Type::String) => format!("const char *{}", name),
+        Some(Type::Integer) => format!("int64_t {}", name),
+        None => format!("void {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{require_tool, run_command, TempDir};
+    use crate::{
+        compile_ir, gen_ir, AsmSyntax, CompileConfig, CrateType, InputFormat, OptLevel, Target,
+    };
+    use std::fs;
+
+    const SRC: &str = "FUNCTION Greeting () AS STRING
+  Greeting = \"Hello from BASIC\"
+END FUNCTION
+FUNCTION Answer () AS INTEGER
+  Answer = 42
+END FUNCTION
+SUB PrintTwice (msg AS STRING, n AS INTEGER)
+  PRINT msg
+  PRINT msg
+  PRINT n
+END SUB
+";
+
+    fn library_ir() -> Ir {
+        gen_ir(SRC, InputFormat::Basic, CrateType::StaticLib)
+            .unwrap_or_else(|_| panic!("failed to compile the library"))
+    }
+
+    #[test]
+    fn declares_procedures() {
+        assert_eq!(
+            gen_c_header(&library_ir()),
+            "#pragma once
+
+/* This library is not position-independent: link it with `-no-pie`. */
+
+#include <stdint.h>
+
+const char *Greeting(void);
+int64_t Answer(void);
+void PrintTwice(const char *msg, int64_t n);
+"
+        );
+    }
+
+    /// 静的ライブラリを C から呼び出し、ヘッダの宣言どおりに引数と戻り値を受け渡せる
+    #[test]
+    #[ignore = "requires as, ar and cc"]
+    fn called_from_c() {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return;
+        }
+        let dir = TempDir::new("staticlib");
+        let config = CompileConfig {
+            crate_type: CrateType::StaticLib,
+            target: Target::X64Linux,
+            use_external_assembler: true,
+            asm_syntax: AsmSyntax::Att,
+            opt_level: OptLevel::O0,
+        };
+        let output = compile_ir(library_ir(), "greet", &config).unwrap();
+        let asm_path = dir.join("greet.s");
+        let obj_path = dir.join("greet.o");
+        let lib_path = dir.join("libgreet.a");
+        let main_path = dir.join("main.c");
+        let bin_path = dir.join("main.bin");
+        fs::write(&asm_path, output.asm).unwrap();
+        fs::write(dir.join("greet.h"), output.header.unwrap()).unwrap();
+        fs::write(
+            &main_path,
+            "#include <stdio.h>
+#include \"greet.h\"
+
+int main(void) {
+    puts(Greeting());
+    printf(\"%ld\\n\", (long)Answer());
+    PrintTwice(\"BASIC \", 3);
+    return 0;
+}
+",
+        )
+        .unwrap();
+        let obj = obj_path.to_str().unwrap();
+        require_tool("as", &["-o", obj, asm_path.to_str().unwrap()]);
+        require_tool("ar", &["rcs", lib_path.to_str().unwrap(), obj]);
+        let lib_dir = format!("-L{}", dir.join("").display());
+        require_tool(
+            "cc",
+            &[
+                "-no-pie",
+                "-o",
+                bin_path.to_str().unwrap(),
+                main_path.to_str().unwrap(),
+                &lib_dir,
+                "-lgreet",
+            ],
+        );
+        assert_eq!(
+            run_command(&bin_path, &[]).unwrap(),
+            (
+                0,
+                "Hello from BASIC\n42\nBASIC BASIC 3".to_owned(),
+                String::new()
+            )
+        );
+    }
+}
@@ -0,0 +1,760 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+
+/// ランタイムが定義しているため、外部ライブラリの手続きの名前として使えない関数名
+static RUNTIME_FUNCS: [&str; 6] = [
+    "main",
+    "rt_str",
+    "rt_int",
+    "rt_print",
+    "rt_expect",
+    "rt_error",
+];
+
+/// ランタイムがインクルードする標準ヘッダで宣言されている関数
+///
+/// これらの関数はプロトタイプ宣言を出力せず、ヘッダの宣言を通して呼び出す
+static HEADER_FUNCS: [&str; 110] = [
+    // <stdio.h>
+    "remove",
+    "rename",
+    "tmpfile",
+    "tmpnam",
+    "fclose",
+    "fflush",
+    "fopen",
+    "freopen",
+    "setbuf",
+    "setvbuf",
+    "fprintf",
+    "fscanf",
+    "printf",
+    "scanf",
+    "snprintf",
+    "sprintf",
+    "sscanf",
+    "vfprintf",
+    "vfscanf",
+    "vprintf",
+    "vscanf",
+    "vsnprintf",
+    "vsprintf",
+    "vsscanf",
+    "fgetc",
+    "fgets",
+    "fputc",
+    "fputs",
+    "getc",
+    "getchar",
+    "gets",
+    "putc",
+    "putchar",
+    "puts",
+    "ungetc",
+    "fread",
+    "fwrite",
+    "fgetpos",
+    "fseek",
+    "fsetpos",
+    "ftell",
+    "rewind",
+    "clearerr",
+    "feof",
+    "ferror",
+    "perror",
+    // <stdlib.h>
+    "atof",
+    "atoi",
+    "atol",
+    "atoll",
+    "strtod",
+    "strtof",
+    "strtold",
+    "strtol",
+    "strtoll",
+    "strtoul",
+    "strtoull",
+    "rand",
+    "srand",
+    "calloc",
+    "free",
+    "malloc",
+    "realloc",
+    "abort",
+    "atexit",
+    "exit",
+    "_Exit",
+    "getenv",
+    "system",
+    "bsearch",
+    "qsort",
+    "abs",
+    "labs",
+    "llabs",
+    "div",
+    "ldiv",
+    "lldiv",
+    "mblen",
+    "mbtowc",
+    "wctomb",
+    "mbstowcs",
+    "wcstombs",
+    // <string.h>
+    "memcpy",
+    "memmove",
+    "strcpy",
+    "strncpy",
+    "strcat",
+    "strncat",
+    "memcmp",
+    "strcmp",
+    "strcoll",
+    "strncmp",
+    "strxfrm",
+    "memchr",
+    "strchr",
+    "strcspn",
+    "strpbrk",
+    "strrchr",
+    "strspn",
+    "strstr",
+    "strtok",
+    "memset",
+    "strerror",
+    "strlen",
+    // <inttypes.h>
+    "imaxabs",
+    "imaxdiv",
+    "strtoimax",
+    "strtoumax",
+    // <setjmp.h>
+    "longjmp",
+    "setjmp",
+];
+
+/// 関数本体の生成に用いる状態
+struct FuncBuilder<'a> {
+    ir: &'a Ir,
+    lines: Vec<String>,
+    /// スタックに積まれている値 (``Value`` 型の C の式)
+    stack: Vec<String>,
+    /// 一時変数の連番
+    num_temps: i32,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    traps_errors: bool,
+}
+
+impl<'a> FuncBuilder<'a> {
+    fn stmt<S: Into<String>>(&mut self, stmt: S) {
+        self.lines.push(format!("    {}", stmt.into()));
+    }
+
+    fn label<N: AsRef<str>>(&mut self, name: N) {
+        // ラベルの直後に宣言を置けないため、空文を付ける
+        self.lines.push(format!("{}:;", name.as_ref()));
+    }
+
+    /// 値を計算して一時変数に代入し、その一時変数をスタックに積む
+    ///
+    /// 引数の評価順序が規定されていない C でも、 BASIC の評価順序を保つために用いる
+    fn push_temp<E: AsRef<str>>(&mut self, expr: E) {
+        self.num_temps += 1;
+        let temp = format!("t{}", self.num_temps);
+        self.stmt(format!("Value {} = {};", temp, expr.as_ref()));
+        self.stack.push(temp);
+    }
+
+    fn pop(&mut self) -> String {
+        self.stack.pop().expect("IR stack underflow")
+    }
+}
+
+/// 中間表現から、小さなランタイムを埋め込んだ単一の C99 のソースファイルを生成する
+///
+/// スタックの各要素は型タグ付きの ``Value`` 構造体として扱い、
+/// エラーの捕捉には ``setjmp`` / ``longjmp`` を用いる
+pub fn gen_c_source(ir: &Ir) -> Result<String, String> {
+    for ext in ir.externs.iter() {
+        if RUNTIME_FUNCS.contains(&ext.name.as_str()) {
+            return Err(format!(
+                "`{}` conflicts with a function used by the runtime",
+                ext.name
+            ));
+        }
+    }
+
+    let traps_errors = ir
+        .insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_))));
+
+    let mut result = runtime(traps_errors);
+
+    // 文字列プール
+    if !ir.string_pool.is_empty() {
+        for (i, s) in ir.string_pool.iter().enumerate() {
+            result.push_str(format!("static const char str{}[] = {};\n", i, literal(s)).as_str());
+        }
+        result.push('\n');
+    }
+
+    if ir.num_globals > 0 {
+        for i in 0..ir.num_globals {
+            result.push_str(format!("static Value global{};\n", i).as_str());
+        }
+        result.push('\n');
+    }
+
+    // 外部ライブラリの手続き
+    let mut declared_externs = false;
+    for ext in ir.externs.iter() {
+        if HEADER_FUNCS.contains(&ext.name.as_str()) {
+            continue;
+        }
+        let params = param_list(
+            ext.params
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| declarator(Some(*ty), &format!("arg{}", i))),
+        );
+        result.push_str(
+            format!(
+                "extern {};\n",
+                declarator(ext.ret, &format!("{}({})", ext.name, params))
+            )
+            .as_str(),
+        );
+        declared_externs = true;
+    }
+    if declared_externs {
+        result.push('\n');
+    }
+
+    // BASIC で定義された手続き (互いに呼び出せるように先に宣言しておく)
+    if !ir.procs.is_empty() {
+        for proc in ir.procs.iter() {
+            result.push_str(format!("{};\n", proc_signature(proc)).as_str());
+        }
+        result.push('\n');
+    }
+    for proc in ir.procs.iter() {
+        result.push_str(gen_proc(ir, proc, traps_errors).as_str());
+    }
+
+    result.push_str(gen_main(ir, traps_errors).as_str());
+
+    Ok(result)
+}
+
+/// エントリポイント ``main`` を生成する
+///
+/// エラーを捕捉する場合は ``dispatch`` で ``rt_pc`` の値に従って
+/// 文の先頭かエラーハンドラのラベルへ移る
+fn gen_main(ir: &Ir, traps_errors: bool) -> String {
+    let mut builder = FuncBuilder {
+        ir,
+        lines: Vec::new(),
+        stack: Vec::new(),
+        num_temps: 0,
+        traps_errors,
+    };
+
+    let num_stmts = count_stmts(ir);
+
+    if traps_errors {
+        let num_labels = ir
+            .insts
+            .iter()
+            .filter(|inst| matches!(inst, IrInst::Label(_)))
+            .count() as i32;
+
+        // エラーが捕捉されると rt_error から戻ってくる
+        builder.stmt("if (setjmp(rt_jmp) != 0) {");
+        builder.stmt("    goto dispatch;");
+        builder.stmt("}");
+        builder.stmt("goto stmt0;");
+
+        // rt_pc の値と移動先の対応 (文の番号, 最後の文の次の位置, ラベルの番号の順)
+        builder.label("dispatch");
+        builder.stmt("switch (rt_pc) {");
+        for i in 0..=num_stmts {
+            builder.stmt(format!("case {}: goto stmt{};", i, i));
+        }
+        for i in 0..num_labels {
+            builder.stmt(format!("case {}: goto user_label{};", num_stmts + 1 + i, i));
+        }
+        builder.stmt("default: goto program_exit;");
+        builder.stmt("}");
+    }
+
+    gen_insts(&ir.insts, &mut builder);
+    if traps_errors {
+        builder.label(format!("stmt{}", num_stmts));
+    }
+    if traps_errors || ir.insts.iter().any(|inst| matches!(inst, IrInst::End)) {
+        builder.label("program_exit");
+    }
+    builder.stmt("return 0;");
+
+    format!("int main(void) {{\n{}\n}}\n", builder.lines.join("\n"))
+}
+
+/// BASIC で定義された手続きを関数として生成する
+///
+/// 引数と戻り値は C の型で受け渡しする (型タグは呼び出し側で検査・付与する)
+fn gen_proc(ir: &Ir, proc: &Proc, traps_errors: bool) -> String {
+    let mut builder = FuncBuilder {
+        ir,
+        lines: Vec::new(),
+        stack: Vec::new(),
+        num_temps: 0,
+        traps_errors,
+    };
+
+    // ローカル変数を確保し、引数を格納する
+    for (i, ty) in proc.params.iter().enumerate() {
+        builder.stmt(format!(
+            "Value local{} = {};",
+            i,
+            tagged(*ty, &format!("arg{}", i))
+        ));
+    }
+    for i in proc.params.len() as i32..proc.num_locals {
+        builder.stmt(format!("Value local{} = {{0, {{0}}}};", i));
+    }
+    // 代入されるだけのローカル変数で -Wall の警告が出ないように、参照しておく
+    for i in 0..proc.num_locals {
+        builder.stmt(format!("(void)local{};", i));
+    }
+
+    gen_insts(&proc.insts, &mut builder);
+
+    if let (Some(ty), Some(ret_slot)) = (proc.ret, proc.ret_slot()) {
+        builder.stmt(format!(
+            "return {};",
+            untagged(ty, &format!("local{}", ret_slot))
+        ));
+    }
+
+    format!(
+        "{} {{\n{}\n}}\n\n",
+        proc_signature(proc),
+        builder.lines.join("\n")
+    )
+}
+
+/// 命令列を生成する
+fn gen_insts(insts: &[IrInst], builder: &mut FuncBuilder) {
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                builder.stack.push(format!("rt_str(str{})", index));
+            }
+            IrInst::GetImmInt(value) => {
+                let value = if *value == i64::MIN {
+                    "INT64_MIN".to_owned()
+                } else {
+                    format!("INT64_C({})", value)
+                };
+                builder.stack.push(format!("rt_int({})", value));
+            }
+            IrInst::GetGlobal(index) => {
+                builder.push_temp(format!("global{}", index));
+            }
+            IrInst::SetGlobal(index) => {
+                let value = builder.pop();
+                builder.stmt(format!("global{} = {};", index, value));
+            }
+            IrInst::GetLocal(index) => {
+                builder.push_temp(format!("local{}", index));
+            }
+            IrInst::SetLocal(index) => {
+                let value = builder.pop();
+                builder.stmt(format!("local{} = {};", index, value));
+            }
+            IrInst::AssertType(ty, location) => {
+                let (tag, message) = match ty {
+                    Type::String => ("TYPE_STR", "MSG_EXPECTED_STR"),
+                    Type::Integer => ("TYPE_INT", "MSG_EXPECTED_INT"),
+                };
+                let value = builder.stack.last().unwrap().clone();
+                builder.stmt(format!(
+                    "rt_expect({}, {}, {}, {});",
+                    value,
+                    tag,
+                    location_args(location),
+                    message
+                ));
+            }
+            IrInst::CallExtern(index) => {
+                let ext = &builder.ir.externs[*index as usize];
+                let args = pop_args(&ext.params, builder);
+                let call = format!("{}({})", ext.name, args.join(", "));
+                // 標準ヘッダで宣言されている関数は、戻り値を BASIC の型に変換する
+                let call = match ext.ret {
+                    Some(ty) if HEADER_FUNCS.contains(&ext.name.as_str()) => {
+                        format!("({}){}", c_type(ty), call)
+                    }
+                    _ => call,
+                };
+                match ext.ret {
+                    Some(ty) => builder.push_temp(tagged(ty, &call)),
+                    None => builder.stmt(format!("{};", call)),
+                }
+            }
+            IrInst::CallProc(index, _) => {
+                let proc = &builder.ir.procs[*index as usize];
+                let args = pop_args(&proc.params, builder);
+                let call = format!("basic_{}({})", proc.name, args.join(", "));
+                match proc.ret {
+                    Some(ty) => builder.push_temp(tagged(ty, &call)),
+                    None => builder.stmt(format!("{};", call)),
+                }
+            }
+            IrInst::Pop => {
+                // 一時変数が使われないことによる警告が出ないように、捨てる値を参照しておく
+                let value = builder.pop();
+                builder.stmt(format!("(void){};", value));
+            }
+            IrInst::Print => {
+                let value = builder.pop();
+                builder.stmt(format!("rt_print({});", value));
+            }
+            IrInst::BeginStmt(index) => {
+                if builder.traps_errors {
+                    builder.label(format!("stmt{}", index));
+                    builder.stmt(format!("rt_cur_stmt = {};", index));
+                }
+            }
+            IrInst::Label(index) => {
+                if builder.traps_errors {
+                    builder.label(format!("user_label{}", index));
+                }
+            }
+            IrInst::OnErrorGoto(handler) => {
+                // エラーを捕捉しない場合は、エラー処理の状態を持たない
+                if builder.traps_errors {
+                    // 0 はエラーハンドラが設定されていないことを表す
+                    let pc = handler.map_or(0, |index| count_stmts(builder.ir) + 1 + index);
+                    builder.stmt(format!("rt_err_handler = {};", pc));
+                }
+            }
+            IrInst::Resume(target, location) => {
+                // エラーを捕捉しない場合は、 RESUME は常にエラーになる
+                if builder.traps_errors {
+                    builder.stmt("if (!rt_in_handler) {");
+                    builder.stmt(format!(
+                        "    rt_error(ERR_RESUME_WITHOUT_ERROR, {}, MSG_RESUME_WITHOUT_ERROR);",
+                        location_args(location)
+                    ));
+                    builder.stmt("}");
+                    builder.stmt("rt_in_handler = 0;");
+                    builder.stmt("rt_err_code = 0;");
+                    builder.stmt("rt_err_line = 0;");
+                    builder.stmt(match target {
+                        ResumeTarget::Retry => "rt_pc = rt_resume_addr;",
+                        ResumeTarget::Next => "rt_pc = rt_resume_next_addr;",
+                    });
+                    builder.stmt("goto dispatch;");
+                } else {
+                    builder.stmt(format!(
+                        "rt_error(ERR_RESUME_WITHOUT_ERROR, {}, MSG_RESUME_WITHOUT_ERROR);",
+                        location_args(location)
+                    ));
+                }
+            }
+            IrInst::RaiseError(location) => {
+                let code = builder.pop();
+                builder.stmt(format!(
+                    "rt_error({}.as.int_, {}, MSG_ERROR_STMT);",
+                    code,
+                    location_args(location)
+                ));
+            }
+            IrInst::GetErrCode if builder.traps_errors => {
+                builder.push_temp("rt_int(rt_err_code)");
+            }
+            IrInst::GetErrLine if builder.traps_errors => {
+                builder.push_temp("rt_int(rt_err_line)");
+            }
+            // エラーを捕捉しない場合、 ERR と ERL は常に 0
+            IrInst::GetErrCode | IrInst::GetErrLine => {
+                builder.stack.push("rt_int(INT64_C(0))".to_owned());
+            }
+            IrInst::End => {
+                builder.stmt("goto program_exit;");
+            }
+        }
+    }
+}
+
+/// スタックに積まれた引数の値を、先頭の引数から順に C の型に変換して取り出す
+fn pop_args(params: &[Type], builder: &mut FuncBuilder) -> Vec<String> {
+    let mut args = params
+        .iter()
+        .rev()
+        .map(|ty| untagged(*ty, &builder.pop()))
+        .collect::<Vec<_>>();
+    args.reverse();
+    args
+}
+
+/// ``rt_expect`` / ``rt_error`` に渡す行番号と位置の引数
+fn location_args(location: &Location) -> String {
+    format!("{}, \"{}\"", location.start.line() + 1, location.start)
+}
+
+/// トップレベルの文の個数
+fn count_stmts(ir: &Ir) -> i32 {
+    ir.insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+        .count() as i32
+}
+
+/// C の値に型タグを付けて ``Value`` にする
+fn tagged(ty: Type, expr: &str) -> String {
+    match ty {
+        Type::String => format!("rt_str({})", expr),
+        Type::Integer => format!("rt_int({})", expr),
+    }
+}
+
+/// ``Value`` から C の値を取り出す
+fn untagged(ty: Type, expr: &str) -> String {
+    match ty {
+        Type::String => format!("{}.as.str", expr),
+        Type::Integer => format!("{}.as.int_", expr),
+    }
+}
+
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::String => "const char *",
+        Type::Integer => "int64_t",
+    }
+}
+
+/// 型に対応する C の宣言子を生成する ( ``None`` は ``void`` を表す)
+fn declarator(ty: Option<Type>, name: &str) -> String {
+    match ty {
+        Some(Type::String) => format!("const char *{}", name),
+        Some(Type::Integer) => format!("int64_t {}", name),
+        None => format!("void {}", name),
+    }
+}
+
+/// 仮引数の並び (引数がなければ ``void``)
+fn param_list<I: Iterator<Item = String>>(params: I) -> String {
+    let params = params.collect::<Vec<_>>();
+    if params.is_empty() {
+        "void".to_owned()
+    } else {
+        params.join(", ")
+    }
+}
+
+/// BASIC で定義された手続きのシグネチャ
+///
+/// 標準ライブラリの関数と衝突しないように、名前に ``basic_`` を付ける
+fn proc_signature(proc: &Proc) -> String {
+    let params = param_list(
+        proc.params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| declarator(Some(*ty), &format!("arg{}", i))),
+    );
+    format!(
+        "static {}",
+        declarator(proc.ret, &format!("basic_{}({})", proc.name, params))
+    )
+}
+
+/// C の文字列リテラル
+fn literal(s: &str) -> String {
+    let escaped = s
+        .bytes()
+        .map(|b| match b {
+            // `?` はトライグラフにならないようにエスケープする
+            b'"' | b'\\' | b'?' => format!("\\{}", b as char),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => format!("\\{:03o}", b),
+        })
+        .collect::<String>();
+    format!("\"{}\"", escaped)
+}
+
+/// 埋め込むランタイム (型タグ付きの値と出力、ランタイムエラーの報告)
+fn runtime(traps_errors: bool) -> String {
+    let mut result = String::from(
+        "#include <inttypes.h>
+#include <setjmp.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define TYPE_STR 1
+#define TYPE_INT 2
+
+#define ERR_TYPE_MISMATCH 13
+#define ERR_RESUME_WITHOUT_ERROR 20
+
+#define MSG_EXPECTED_STR \"type mismatch (expected STRING)\"
+#define MSG_EXPECTED_INT \"type mismatch (expected INTEGER)\"
+#define MSG_RESUME_WITHOUT_ERROR \"RESUME without error\"
+#define MSG_ERROR_STMT \"error raised by ERROR statement\"
+
+typedef struct {
+    int64_t tag;
+    union {
+        const char *str;
+        int64_t int_;
+    } as;
+} Value;
+",
+    );
+    if traps_errors {
+        result.push_str(
+            "
+/* ON ERROR GOTO によるエラー処理の状態 */
+static int64_t rt_err_code;
+static int64_t rt_err_line;
+static int rt_err_handler;
+static int rt_in_handler;
+static int rt_cur_stmt;
+static int rt_resume_addr;
+static int rt_resume_next_addr;
+static int rt_pc;
+static jmp_buf rt_jmp;
+",
+        );
+    }
+    // ランタイムの関数は、使われない場合に警告が出ないように inline にする
+    result.push_str(
+        "
+static inline Value rt_str(const char *str) {
+    Value value;
+    value.tag = TYPE_STR;
+    value.as.str = str;
+    return value;
+}
+
+static inline Value rt_int(int64_t int_) {
+    Value value;
+    value.tag = TYPE_INT;
+    value.as.int_ = int_;
+    return value;
+}
+
+static inline void rt_print(Value value) {
+    if (value.tag == TYPE_STR) {
+        fputs(value.as.str, stdout);
+    } else {
+        printf(\"%\" PRId64, value.as.int_);
+    }
+}
+
+",
+    );
+
+    // エラーハンドラが設定されていれば状態を記録して main へ戻り、そうでなければエラーを報告して終了する
+    result.push_str(
+        "static inline void rt_error(int64_t code, int64_t line, const char *loc, const char *msg) {\n",
+    );
+    if traps_errors {
+        result.push_str(
+            "    if (rt_err_handler != 0 && !rt_in_handler) {
+        rt_err_code = code;
+        rt_err_line = line;
+        rt_resume_addr = rt_cur_stmt;
+        rt_resume_next_addr = rt_cur_stmt + 1;
+        rt_in_handler = 1;
+        rt_pc = rt_err_handler;
+        longjmp(rt_jmp, 1);
+    }
+",
+        );
+    } else {
+        result.push_str("    (void)code;\n    (void)line;\n");
+    }
+    result.push_str(
+        "    fflush(stdout);
+    fprintf(stderr, \"Runtime error at %s: %s\\n\", loc, msg);
+    exit(1);
+}
+
+static inline void rt_expect(Value value, int64_t tag, int64_t line, const char *loc, const char *msg) {
+    if (value.tag != tag) {
+        rt_error(ERR_TYPE_MISMATCH, line, loc, msg);
+    }
+}
+
+",
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{
+        frontend, require_tool, run_command, run_x64, sample_ir, sample_names, TempDir,
+    };
+    use std::fs;
+
+    /// C のソースコードを警告をエラーとしてコンパイルする
+    fn compile_c(ir: &Ir, dir: &TempDir, name: &str) {
+        let c_path = dir.join(&format!("{}.c", name));
+        let bin_path = dir.join(&format!("{}.bin", name));
+        fs::write(&c_path, gen_c_source(ir).unwrap()).unwrap();
+        let libs: Vec<String> = ir
+            .linked_libs()
+            .iter()
+            .map(|lib| format!("-l{}", lib))
+            .collect();
+        let mut args = vec![
+            "-std=c99",
+            "-Wall",
+            "-Wextra",
+            "-Werror",
+            "-o",
+            bin_path.to_str().unwrap(),
+            c_path.to_str().unwrap(),
+        ];
+        args.extend(libs.iter().map(String::as_str));
+        require_tool("cc", &args);
+    }
+
+    /// C コンパイラでコンパイルしたサンプルプログラムの出力が、x86-64 のバックエンドと一致するか
+    #[test]
+    #[ignore = "requires cc"]
+    fn samples_behave_like_x64_backend() {
+        let dir = TempDir::new("c");
+        for name in sample_names() {
+            compile_c(&sample_ir(&name), &dir, &name);
+            let actual = run_command(dir.join(&format!("{}.bin", name)), &[]).unwrap();
+            if let Some(expected) = run_x64(sample_ir(&name), &name, &dir) {
+                assert_eq!(actual, expected, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "requires cc"]
+    fn unused_locals_and_values_compile_without_warnings() {
+        let src = "FUNCTION Id (s AS STRING) AS STRING
+  VAR unused = 1
+  Id = s
+END FUNCTION
+SUB Ignore (n AS INTEGER)
+  VAR x = n
+END SUB
+Id(\"x\")
+Ignore 3
+PRINT ERR
+";
+        compile_c(&frontend(src), &TempDir::new("c-warnings"), "unused");
+    }
+}
@@ -0,0 +1,505 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use super::sem_analysis::MAX_PARAMS;
+
+/// ランタイムが使用するため、手続きの名前として使えないメソッド名
+static RUNTIME_METHODS: [&str; 2] = ["Main", "RuntimeError"];
+
+/// ランタイムエラーを表す例外 ( ``ON ERROR GOTO`` で捕捉される)
+static ERROR_EXCEPTION: &str = "[mscorlib]System.ApplicationException";
+
+/// メソッド本体の生成に用いる状態
+struct MethodBody<'a> {
+    ir: &'a Ir,
+    lines: Vec<String>,
+    /// 評価スタックに積まれている値の個数
+    depth: i32,
+    /// 評価スタックに積まれる値の個数の最大値 ( ``.maxstack`` に用いる)
+    max_depth: i32,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    traps_errors: bool,
+    /// 連番のラベルを生成するためのカウンタ
+    num_labels: i32,
+}
+
+impl<'a> MethodBody<'a> {
+    fn new(ir: &'a Ir, traps_errors: bool) -> Self {
+        MethodBody {
+            ir,
+            lines: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+            traps_errors,
+            num_labels: 0,
+        }
+    }
+
+    fn inst<I: Into<String>>(&mut self, inst: I) {
+        self.lines.push(format!("    {}", inst.into()));
+    }
+
+    fn label<N: AsRef<str>>(&mut self, name: N) {
+        self.lines.push(format!("{}:", name.as_ref()));
+    }
+
+    fn fresh_label(&mut self) -> String {
+        self.num_labels += 1;
+        format!("L{}", self.num_labels)
+    }
+
+    /// 評価スタックに積まれている値の個数を増減する
+    fn grow(&mut self, n: i32) {
+        self.depth += n;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+}
+
+/// 中間表現から CIL アセンブリ ( ``ilasm`` の入力) を生成する
+///
+/// 評価スタックは CIL の評価スタックに対応し、値は ``object`` (文字列または boxing された ``int64``) として扱う
+pub fn gen_cil(ir: &Ir, name: &str) -> Result<String, String> {
+    for proc in ir.procs.iter() {
+        if RUNTIME_METHODS.contains(&proc.name.as_str()) {
+            return Err(format!(
+                "`{}` conflicts with a method used by the runtime",
+                proc.name
+            ));
+        }
+    }
+
+    let traps_errors = ir
+        .insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_))));
+
+    let mut result = format!("// ilasm {}.il\n\n", name);
+    result.push_str(format!(".assembly '{}' {{}}\n", name).as_str());
+    result.push_str(".assembly extern mscorlib {}\n\n");
+
+    // グローバル変数
+    for i in 0..ir.num_globals {
+        result.push_str(format!(".field static object global{}\n", i).as_str());
+    }
+
+    // ON ERROR GOTO によるエラー処理の状態
+    result.push_str(".field static int64 err_code\n");
+    result.push_str(".field static int64 err_line\n");
+    result.push_str(".field static int32 err_handler\n");
+    result.push_str(".field static int32 in_handler\n");
+    result.push_str(".field static int32 cur_stmt\n");
+    result.push_str(".field static int32 resume_addr\n");
+    result.push_str(".field static int32 resume_next_addr\n");
+    result.push_str(".field static int32 entry\n\n");
+
+    // 外部ライブラリの手続きは P/Invoke で呼び出す
+    for ext in ir.externs.iter() {
+        let params = ext
+            .params
+            .iter()
+            .map(|ty| cil_type(Some(*ty)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        result.push_str(
+            format!(
+                ".method static pinvokeimpl(\"lib{}\" ansi cdecl) {} '{}'({}) preservesig {{}}\n\n",
+                ext.lib,
+                cil_type(ext.ret),
+                ext.name,
+                params
+            )
+            .as_str(),
+        );
+    }
+
+    result.push_str(gen_main(ir, traps_errors).as_str());
+
+    for proc in ir.procs.iter() {
+        result.push_str(gen_proc(ir, proc).as_str());
+    }
+
+    result.push_str(RUNTIME_ERROR_METHOD);
+
+    Ok(result)
+}
+
+/// エントリポイントを生成する
+///
+/// エラーを捕捉する場合は本体全体を ``try`` ブロックで囲み、
+/// ``catch`` ブロックから ``entry`` に従ってエラーハンドラのラベルへ分岐し直す
+fn gen_main(ir: &Ir, traps_errors: bool) -> String {
+    let mut body = MethodBody::new(ir, traps_errors);
+    let num_stmts = count_stmts(ir);
+    let num_labels = ir
+        .insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::Label(_)))
+        .count();
+
+    if traps_errors {
+        body.label("DISPATCH");
+        body.lines.push(".try {".to_owned());
+        // entry が 0 ならプログラムの先頭から、そうでなければエラーハンドラから実行する
+        body.inst("ldsfld int32 entry");
+        body.grow(1);
+        let targets = std::iter::once("stmt0".to_owned())
+            .chain((0..num_labels).map(|i| format!("user_label{}", i)))
+            .collect::<Vec<_>>();
+        body.inst(format!("switch ({})", targets.join(", ")));
+        body.depth -= 1;
+    }
+
+    gen_insts(&ir.insts, &mut body);
+    body.label(format!("stmt{}", num_stmts));
+
+    if traps_errors {
+        body.inst("leave PROGRAM_EXIT");
+        body.lines.push("}".to_owned());
+        body.lines.push(format!("catch {} {{", ERROR_EXCEPTION));
+        // 評価スタックには捕捉した例外だけが積まれている
+        body.grow(1);
+        body.inst("pop");
+        body.depth -= 1;
+        body.inst("ldc.i4.1");
+        body.inst("stsfld int32 in_handler");
+        body.inst("ldsfld int32 cur_stmt");
+        body.inst("stsfld int32 resume_addr");
+        body.inst("ldsfld int32 cur_stmt");
+        body.inst("ldc.i4.1");
+        body.grow(2);
+        body.inst("add");
+        body.inst("stsfld int32 resume_next_addr");
+        body.depth -= 2;
+        body.inst("ldsfld int32 err_handler");
+        body.inst("stsfld int32 entry");
+        body.inst("leave DISPATCH");
+        body.lines.push("}".to_owned());
+    }
+
+    body.label("PROGRAM_EXIT");
+    body.inst("ret");
+
+    format!(
+        ".method static void Main()\n{{\n    .entrypoint\n    .maxstack {}\n{}{}\n}}\n\n",
+        max_stack(&body),
+        locals(&[]),
+        body.lines.join("\n")
+    )
+}
+
+/// BASIC で定義された手続きを静的メソッドとして生成する
+fn gen_proc(ir: &Ir, proc: &Proc) -> String {
+    let mut body = MethodBody::new(ir, false);
+
+    // 引数をローカル変数に格納する
+    for (i, ty) in proc.params.iter().enumerate() {
+        body.inst(format!("ldarg {}", i));
+        body.grow(1);
+        if *ty == Type::Integer {
+            body.inst("box int64");
+        }
+        body.inst(format!("stloc V_{}", i));
+        body.depth -= 1;
+    }
+
+    gen_insts(&proc.insts, &mut body);
+
+    if let (Some(ret_slot), Some(ret)) = (proc.ret_slot(), proc.ret) {
+        body.inst(format!("ldloc V_{}", ret_slot));
+        body.grow(1);
+        body.inst(unbox(ret));
+    }
+    body.inst("ret");
+
+    let params = proc
+        .param_names
+        .iter()
+        .zip(proc.params.iter())
+        .map(|(name, ty)| format!("{} '{}'", cil_type(Some(*ty)), name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let local_names = (0..proc.num_locals)
+        .map(|i| format!("V_{}", i))
+        .collect::<Vec<_>>();
+
+    format!(
+        ".method public static {} '{}'({})\n{{\n    .maxstack {}\n{}{}\n}}\n\n",
+        cil_type(proc.ret),
+        proc.name,
+        params,
+        max_stack(&body),
+        locals(&local_names),
+        body.lines.join("\n")
+    )
+}
+
+/// 命令列を生成する
+fn gen_insts(insts: &[IrInst], body: &mut MethodBody) {
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                let value = escape(&body.ir.string_pool[*index as usize]);
+                body.inst(format!("ldstr \"{}\"", value));
+                body.grow(1);
+            }
+            IrInst::GetImmInt(value) => {
+                body.inst(format!("ldc.i8 {}", value));
+                body.inst("box int64");
+                body.grow(1);
+            }
+            IrInst::GetGlobal(index) => {
+                body.inst(format!("ldsfld object global{}", index));
+                body.grow(1);
+            }
+            IrInst::SetGlobal(index) => {
+                body.inst(format!("stsfld object global{}", index));
+                body.depth -= 1;
+            }
+            IrInst::GetLocal(index) => {
+                body.inst(format!("ldloc V_{}", index));
+                body.grow(1);
+            }
+            IrInst::SetLocal(index) => {
+                body.inst(format!("stloc V_{}", index));
+                body.depth -= 1;
+            }
+            IrInst::AssertType(ty, location) => {
+                let (message, class) = match ty {
+                    Type::String => ("type mismatch (expected STRING)", "string"),
+                    Type::Integer => ("type mismatch (expected INTEGER)", "int64"),
+                };
+                let ok = body.fresh_label();
+                body.inst("dup");
+                body.grow(1);
+                body.inst(format!("isinst {}", class));
+                body.inst(format!("brtrue {}", ok));
+                body.depth -= 1;
+                body.inst("ldc.i8 13");
+                body.grow(1);
+                gen_runtime_error(location, message, body);
+                body.label(ok);
+            }
+            IrInst::CallExtern(index) => {
+                let ext = &body.ir.externs[*index as usize];
+                let (name, params, ret) = (ext.name.clone(), ext.params.clone(), ext.ret);
+                gen_call(&name, &params, ret, body);
+            }
+            IrInst::CallProc(index, _) => {
+                let proc = &body.ir.procs[*index as usize];
+                let (name, params, ret) = (proc.name.clone(), proc.params.clone(), proc.ret);
+                gen_call(&name, &params, ret, body);
+            }
+            IrInst::Pop => {
+                body.inst("pop");
+                body.depth -= 1;
+            }
+            IrInst::Print => {
+                body.inst("call void [mscorlib]System.Console::Write(object)");
+                body.depth -= 1;
+            }
+            IrInst::BeginStmt(index) => {
+                body.label(format!("stmt{}", index));
+                if body.traps_errors {
+                    body.inst(format!("ldc.i4 {}", index));
+                    body.grow(1);
+                    body.inst("stsfld int32 cur_stmt");
+                    body.depth -= 1;
+                }
+            }
+            IrInst::Label(index) => {
+                body.label(format!("user_label{}", index));
+            }
+            IrInst::OnErrorGoto(handler) => {
+                // 0 はエラーハンドラが設定されていないことを表す
+                let entry = handler.map_or(0, |index| index + 1);
+                body.inst(format!("ldc.i4 {}", entry));
+                body.grow(1);
+                body.inst("stsfld int32 err_handler");
+                body.depth -= 1;
+            }
+            IrInst::Resume(target, location) => {
+                let ok = body.fresh_label();
+                body.inst("ldsfld int32 in_handler");
+                body.grow(1);
+                body.inst(format!("brtrue {}", ok));
+                body.depth -= 1;
+                body.inst("ldc.i8 20");
+                body.grow(1);
+                gen_runtime_error(location, "RESUME without error", body);
+                body.label(ok);
+                body.inst("ldc.i4.0");
+                body.inst("stsfld int32 in_handler");
+                body.inst("ldc.i8 0");
+                body.inst("stsfld int64 err_code");
+                body.inst("ldc.i8 0");
+                body.inst("stsfld int64 err_line");
+                body.inst(match target {
+                    ResumeTarget::Retry => "ldsfld int32 resume_addr",
+                    ResumeTarget::Next => "ldsfld int32 resume_next_addr",
+                });
+                body.grow(1);
+                let targets = (0..=count_stmts(body.ir))
+                    .map(|i| format!("stmt{}", i))
+                    .collect::<Vec<_>>();
+                body.inst(format!("switch ({})", targets.join(", ")));
+                body.depth -= 1;
+            }
+            IrInst::RaiseError(location) => {
+                body.inst("unbox.any int64");
+                gen_runtime_error(location, "error raised by ERROR statement", body);
+            }
+            IrInst::GetErrCode => {
+                body.inst("ldsfld int64 err_code");
+                body.inst("box int64");
+                body.grow(1);
+            }
+            IrInst::GetErrLine => {
+                body.inst("ldsfld int64 err_line");
+                body.inst("box int64");
+                body.grow(1);
+            }
+            IrInst::End => {
+                body.inst(if body.traps_errors {
+                    "leave PROGRAM_EXIT"
+                } else {
+                    "br PROGRAM_EXIT"
+                });
+            }
+        }
+    }
+}
+
+/// 評価スタックに積まれたエラー番号と、位置とメッセージを渡して ``RuntimeError`` を呼び出す
+fn gen_runtime_error(location: &Location, message: &str, body: &mut MethodBody) {
+    body.inst(format!("ldc.i8 {}", location.start.line() + 1));
+    body.inst(format!("ldstr \"{}\"", location.start));
+    body.inst(format!("ldstr \"{}\"", message));
+    body.grow(3);
+    body.inst("call void RuntimeError(int64, int64, string, string)");
+    body.depth -= 4;
+}
+
+/// 評価スタックに積まれた引数を型に応じて unboxing してから、手続きを呼び出す
+fn gen_call(name: &str, params: &[Type], ret: Option<Type>, body: &mut MethodBody) {
+    // 最後の引数からポップして一時変数に退避し、先頭から順に積み直す
+    for i in (0..params.len()).rev() {
+        body.inst(format!("stloc T_{}", i));
+        body.depth -= 1;
+    }
+    for (i, ty) in params.iter().enumerate() {
+        body.inst(format!("ldloc T_{}", i));
+        body.grow(1);
+        body.inst(unbox(*ty));
+    }
+
+    let param_types = params
+        .iter()
+        .map(|ty| cil_type(Some(*ty)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    body.inst(format!(
+        "call {} '{}'({})",
+        cil_type(ret),
+        name,
+        param_types
+    ));
+    body.depth -= params.len() as i32;
+
+    match ret {
+        Some(Type::Integer) => {
+            body.inst("box int64");
+            body.grow(1);
+        }
+        Some(Type::String) => body.grow(1),
+        None => {}
+    }
+}
+
+/// トップレベルの文の個数
+fn count_stmts(ir: &Ir) -> usize {
+    ir.insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+        .count()
+}
+
+/// 型に対応する CIL の型 ( ``None`` は ``void`` を表す)
+fn cil_type(ty: Option<Type>) -> &'static str {
+    match ty {
+        Some(Type::String) => "string",
+        Some(Type::Integer) => "int64",
+        None => "void",
+    }
+}
+
+/// ``object`` として積まれた値を、型に応じた CIL の値に変換する命令
+fn unbox(ty: Type) -> &'static str {
+    match ty {
+        Type::String => "castclass string",
+        Type::Integer => "unbox.any int64",
+    }
+}
+
+/// ``.maxstack`` に指定する値
+fn max_stack(body: &MethodBody) -> i32 {
+    body.max_depth.max(1)
+}
+
+/// ``.locals`` 宣言を生成する (手続き呼び出しのための一時変数を含む)
+fn locals(names: &[String]) -> String {
+    let mut decls = names
+        .iter()
+        .map(|name| format!("object {}", name))
+        .collect::<Vec<_>>();
+    decls.extend((0..MAX_PARAMS).map(|i| format!("object T_{}", i)));
+    format!("    .locals init ({})\n", decls.join(", "))
+}
+
+/// ``ldstr`` のオペランドとして使えるように文字列をエスケープする
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// エラーハンドラが設定されていればエラーハンドラへ移るための例外を投げ、
+/// そうでなければエラーを報告して終了するメソッド
+static RUNTIME_ERROR_METHOD: &str = r#".method static void RuntimeError(int64 code, int64 line, string location, string message)
+{
+    .maxstack 4
+    ldsfld int32 err_handler
+    brfalse REPORT
+    ldsfld int32 in_handler
+    brtrue REPORT
+    ldarg.0
+    stsfld int64 err_code
+    ldarg.1
+    stsfld int64 err_line
+    ldarg.3
+    newobj instance void [mscorlib]System.ApplicationException::.ctor(string)
+    throw
+REPORT:
+    call class [mscorlib]System.IO.TextWriter [mscorlib]System.Console::get_Error()
+    ldstr "Runtime error at "
+    ldarg.2
+    ldstr ": "
+    ldarg.3
+    call string [mscorlib]System.String::Concat(string, string, string, string)
+    callvirt instance void [mscorlib]System.IO.TextWriter::WriteLine(string)
+    ldc.i4.1
+    call void [mscorlib]System.Environment::Exit(int32)
+    ret
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{check_golden, sample_ir, sample_names};
+
+    #[test]
+    fn samples_match_golden_files() {
+        for name in sample_names() {
+            let il = gen_cil(&sample_ir(&name), &name).unwrap();
+            check_golden("cil", &format!("{}.il", name), &il);
+        }
+    }
+}
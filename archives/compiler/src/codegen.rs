@@ -1,88 +1,1112 @@
-use super::asm::{Asm, DataSection, TextSection};
-use super::ir::{Ir, IrInst};
+use super::asm::{
+    Asm, Cond, Constant, DataSection, Imm, Instruction::*, Memory, Operand, Operands::*,
+    Register::*, TextSection,
+};
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use super::regalloc::{allocate, Allocation, Loc, ARG_REGISTERS};
+use super::vcode::{lower_main, lower_proc, Place, VCode, VInst, VReg, VValue};
+use super::CrateType;
+use std::convert::TryFrom;
 
-/// 中間表現からアセンブリの内部表現を生成する
-pub fn gen_asm(ir: &Ir) -> Result<Asm, String> {
-    let max_stack_size = ir.num_globals * 8;
+/// ランタイムが使用するため、手続きの名前として使えないシンボル
+static RUNTIME_SYMBOLS: [&str; 33] = [
+    "_start",
+    "program_exit",
+    "globals",
+    "err_handler",
+    "err_code",
+    "err_line",
+    "in_handler",
+    "cur_stmt",
+    "next_stmt",
+    "resume_addr",
+    "resume_next_addr",
+    "main_rsp",
+    "call_sites",
+    "rt_name_main",
+    "err_prefix",
+    "err_sep",
+    "err_newline",
+    "bt_prefix",
+    "bt_open",
+    "bt_close",
+    "msg_expected_str",
+    "msg_expected_int",
+    "msg_resume_without_error",
+    "msg_error_stmt",
+    "print_value",
+    "print_string",
+    "eprint_string",
+    "write_string",
+    "string_length",
+    "print_int",
+    "runtime_error",
+    "print_frame",
+    "flush_stdio",
+];
+
+/// 連番が付与されて生成されるシンボルのプレフィックス
+static NUMBERED_SYMBOL_PREFIXES: [&str; 8] = [
+    "str",
+    "rt_error",
+    "rt_loc",
+    "rt_ret",
+    "rt_cloc",
+    "rt_name",
+    "stmt",
+    "user_label",
+];
+
+/// ランタイムエラーの報告に用いる情報
+#[derive(Default)]
+struct DebugInfo {
+    error_sites: Vec<ErrorSite>,
+    call_sites: Vec<CallSite>,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    /// (捕捉する場合は、 ``RESUME`` のために実行中の文の位置を記録する)
+    traps_errors: bool,
+}
 
+/// ランタイムエラーが発生しうる箇所
+struct ErrorSite {
+    /// エラーの原因となったソースコード上の位置
+    location: Location,
+    /// エラーメッセージのシンボル
+    message: &'static str,
+    /// エラー番号 ( ``None`` の場合は rcx に格納されている値をエラー番号とする)
+    code: Option<Constant>,
+    /// エラーが発生した手続きの名前のシンボル
+    block: String,
+}
+
+/// BASIC で定義された手続きの呼び出し箇所
+struct CallSite {
+    /// 呼び出し箇所のソースコード上の位置
+    location: Location,
+    /// 呼び出し元の手続きの名前のシンボル
+    block: String,
+}
+
+/// 中間表現からアセンブリの内部表現を生成する
+///
+/// ``allocate_registers`` が ``true`` の場合はレジスタ割り当てを行い、``false`` の場合はすべての値をスタックに積む
+pub fn gen_asm(ir: &Ir, crate_type: CrateType, allocate_registers: bool) -> Result<Asm, String> {
+    let mut exports = Vec::<String>::new();
+    let mut externs = Vec::<String>::new();
     let mut dat = DataSection::default();
     let mut txt = TextSection::default();
 
-    for (i, static_str) in ir.string_pool.iter().enumerate() {
-        dat.append(format!("str{}", i), "db", format!("'{}', 0", static_str));
+    for proc in ir.procs.iter() {
+        if RUNTIME_SYMBOLS.contains(&proc.name.as_str()) || is_numbered_symbol(&proc.name) {
+            return Err(format!(
+                "`{}` conflicts with a symbol used by the runtime",
+                proc.name
+            ));
+        }
     }
 
-    txt.label("_start");
-    txt.inst(format!("sub rsp, {}", max_stack_size));
-    txt.inst("mov rbp, rsp");
+    for ext in ir.externs.iter() {
+        if !externs.contains(&ext.name) {
+            externs.push(ext.name.clone());
+        }
+    }
 
-    for ir_inst in ir.insts.iter() {
-        match ir_inst {
-            IrInst::GetStaticStr(index) => {
-                txt.inst(format!("push str{}", index));
-                txt.inst("push TYPE_STR");
-            }
-            IrInst::GetGlobal(index) => {
-                txt.inst(format!("push qword[rbp+{}]", index * 8));
-                txt.inst("push TYPE_STR");
+    // libc とリンクされる場合 (外部ライブラリを呼び出す実行可能ファイルと、C から呼び出される静的ライブラリ) は、
+    // libc の stdio のバッファと出力の順序が入れ替わらないように、表示の前にフラッシュし、libc の exit で終了する
+    let links_libc = crate_type == CrateType::StaticLib || !externs.is_empty();
+    if links_libc {
+        for name in ["fflush", "exit"] {
+            if !externs.iter().any(|ext| ext == name) {
+                externs.push(name.to_owned());
             }
-            IrInst::SetGlobal(index) => {
-                txt.inst("add rsp, 8");
-                txt.inst("pop rax");
-                txt.inst(format!("mov qword[rbp+{}], rax", index * 8));
+        }
+    }
+
+    for (i, static_str) in ir.string_pool.iter().enumerate() {
+        dat.string(format!("str{}", i), static_str);
+    }
+
+    // グローバル変数1つにつき、値と型タグの 16 バイトを確保する
+    if ir.num_globals > 0 {
+        dat.zeroed("globals", ir.num_globals as usize * 2);
+    }
+
+    // バックトレースで表示する手続きの名前
+    for (i, proc) in ir.procs.iter().enumerate() {
+        dat.string(format!("rt_name{}", i), &proc.name);
+    }
+
+    // ON ERROR GOTO によるエラー処理の状態
+    for name in [
+        "err_handler",
+        "err_code",
+        "err_line",
+        "in_handler",
+        "cur_stmt",
+        "next_stmt",
+        "resume_addr",
+        "resume_next_addr",
+        "main_rsp",
+    ] {
+        dat.quads(name, vec![Imm::Int(0)]);
+    }
+
+    let mut debug_info = DebugInfo {
+        traps_errors: ir
+            .insts
+            .iter()
+            .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_)))),
+        ..DebugInfo::default()
+    };
+
+    match crate_type {
+        CrateType::Bin => {
+            exports.push("_start".to_owned());
+            dat.string("rt_name_main", "<main>");
+
+            txt.label("_start");
+            // フレームポインタの連鎖の終端
+            txt.inst(Xor(RegReg(Rbp, Rbp)));
+            if allocate_registers {
+                let code = lower_main(ir);
+                let alloc = allocate(&code);
+                // スピルした値の領域 (rsp は 16 バイト境界に揃えたままにする)
+                let frame_size = (alloc.num_spill_slots * 8).div_ceil(16) * 16;
+                if frame_size > 0 {
+                    txt.inst(Sub(RegImm(Rsp, Imm::Int(frame_size as i64))));
+                }
+                // エラーハンドラに移るときに復元するスタックポインタ
+                txt.inst(Mov(MemReg(Memory::label("main_rsp", 0), Rsp)));
+                gen_vcode(&code, &alloc, "rt_name_main", &mut debug_info, &mut txt);
+            } else {
+                // エラーハンドラに移るときに復元するスタックポインタ
+                txt.inst(Mov(MemReg(Memory::label("main_rsp", 0), Rsp)));
+                gen_insts(ir, &ir.insts, "rt_name_main", &mut debug_info, &mut txt);
             }
-            IrInst::Print => {
-                txt.inst("pop rax");
-                txt.inst("cmp rax, TYPE_STR");
-                txt.inst("jnz type_error");
-                txt.inst("pop rdi");
-                txt.inst("call print_string");
+
+            // 最後の文の次の位置 (RESUME NEXT で移る先)
+            let num_stmts = ir
+                .insts
+                .iter()
+                .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+                .count();
+            txt.label(format!("stmt{}", num_stmts));
+
+            // exit
+            txt.label("program_exit");
+            if links_libc {
+                txt.inst_with_comment(Xor(RegReg(Rdi, Rdi)), "exit code");
+                txt.inst(Call("exit".to_owned()));
+            } else {
+                txt.inst(Mov(RegImm(Rax, Imm::Const(Constant::SysExit))));
+                txt.inst_with_comment(Xor(RegReg(Rdi, Rdi)), "exit code");
+                txt.inst(Syscall);
             }
         }
+        CrateType::StaticLib => {
+            exports.extend(ir.procs.iter().map(|proc| proc.name.clone()));
+        }
+    }
+
+    for (i, proc) in ir.procs.iter().enumerate() {
+        let block = format!("rt_name{}", i);
+        if allocate_registers {
+            gen_allocated_proc(ir, proc, &block, &mut debug_info, &mut txt);
+        } else {
+            gen_proc(ir, proc, &block, &mut debug_info, &mut txt);
+        }
     }
 
-    // スタックを実行前の状態に戻す
-    txt.inst(format!("add rsp, {}", max_stack_size));
+    // ランタイムエラーの発生箇所ごとに、位置とメッセージを渡して runtime_error へ移る
+    for (i, site) in debug_info.error_sites.iter().enumerate() {
+        dat.string(format!("rt_loc{}", i), site.location.start.to_string());
+        txt.label(format!("rt_error{}", i));
+        txt.inst(Mov(RegImm(Rdi, Imm::Label(format!("rt_loc{}", i)))));
+        txt.inst(Mov(RegImm(Rsi, Imm::Label(site.message.to_owned()))));
+        txt.inst(Mov(RegImm(Rdx, Imm::Label(site.block.clone()))));
+        if let Some(code) = site.code {
+            txt.inst(Mov(RegImm(Rcx, Imm::Const(code))));
+        }
+        txt.inst(Mov(RegImm(
+            R8,
+            Imm::Int(site.location.start.line() as i64 + 1),
+        )));
+        txt.inst(Jmp("runtime_error".to_owned()));
+    }
+
+    // 戻りアドレスから呼び出し箇所を引くための表 (戻りアドレス, 位置, 呼び出し元の名前) で、0 で終端する
+    let mut call_site_entries = Vec::<Imm>::new();
+    for (i, site) in debug_info.call_sites.iter().enumerate() {
+        dat.string(format!("rt_cloc{}", i), site.location.start.to_string());
+        call_site_entries.push(Imm::Label(format!("rt_ret{}", i)));
+        call_site_entries.push(Imm::Label(format!("rt_cloc{}", i)));
+        call_site_entries.push(Imm::Label(site.block.clone()));
+    }
+    call_site_entries.push(Imm::Int(0));
+    dat.quads("call_sites", call_site_entries);
 
-    // exit
-    txt.inst("mov rax, SYS_EXIT");
-    txt.inst("xor rdi, rdi  ; exit code");
-    txt.inst("syscall");
+    gen_runtime(links_libc, &mut txt);
+
+    Ok(Asm {
+        exports,
+        externs,
+        data: dat,
+        text: txt,
+    })
+}
+
+/// 値の表示とランタイムエラーの報告を行うサブルーチンを生成する
+///
+/// ``links_libc`` が ``true`` の場合は、出力の前に libc の stdio のバッファをフラッシュし、libc の exit で終了する
+fn gen_runtime(links_libc: bool, txt: &mut TextSection) {
+    let label = |name: &str| name.to_owned();
+
+    // print_value
+    txt.label("print_value");
+    if links_libc {
+        txt.inst(Call(label("flush_stdio")));
+    }
+    txt.inst(Cmp(RegImm(Rsi, Imm::Const(Constant::TypeStr))));
+    txt.inst(Jcc(Cond::E, label("print_string")));
+    txt.inst(Jmp(label("print_int")));
 
     // print_string
     txt.label("print_string");
-    txt.inst("call string_length");
-    txt.inst("mov rdx, rax  ; length");
-    txt.inst("mov rax, SYS_WRITE");
-    txt.inst("mov rsi, rdi  ; address");
-    txt.inst("mov rdi, FD_STDOUT");
-    txt.inst("syscall");
-    txt.inst("ret");
+    txt.inst(Mov(RegImm(Rsi, Imm::Const(Constant::FdStdout))));
+    txt.inst(Jmp(label("write_string")));
+
+    // eprint_string
+    txt.label("eprint_string");
+    txt.inst(Mov(RegImm(Rsi, Imm::Const(Constant::FdStderr))));
+
+    // write_string
+    txt.label("write_string");
+    txt.inst(Call(label("string_length")));
+    txt.inst_with_comment(Mov(RegReg(Rdx, Rax)), "length");
+    txt.inst(Mov(RegImm(Rax, Imm::Const(Constant::SysWrite))));
+    txt.inst_with_comment(Xchg(Rsi, Rdi), "address, file descriptor");
+    txt.inst(Syscall);
+    txt.inst(Ret);
 
     // string_length
     txt.label("string_length");
-    txt.inst("xor rax, rax");
+    txt.inst(Xor(RegReg(Rax, Rax)));
     txt.label(".loop");
-    txt.inst("cmp byte[rdi+rax], 0");
-    txt.inst("je .end");
-    txt.inst("inc rax");
-    txt.inst("jmp .loop");
+    txt.inst(Cmp(MemImm8(Memory::base(Rdi, 0).index(Rax), Imm::Int(0))));
+    txt.inst(Jcc(Cond::E, label(".end")));
+    txt.inst(Inc(Rax));
+    txt.inst(Jmp(label(".loop")));
     txt.label(".end");
-    txt.inst("ret");
-
-    // type_error
-    txt.label("type_error");
-    txt.inst("mov rax, SYS_WRITE");
-    txt.inst("mov rdi, FD_STDERR");
-    txt.inst("mov rsi, err_msg");
-    txt.inst("mov rdx, ERR_MSG_CNT");
-    txt.inst("syscall");
-    txt.inst("mov rax, SYS_EXIT");
-    txt.inst("mov rdi, EXIT_FAILURE");
-    txt.inst("syscall");
+    txt.inst(Ret);
 
-    Ok(Asm {
-        data: dat,
-        text: txt,
+    // print_int (スタック上のバッファに下の桁から10進数の文字を詰めていく)
+    txt.label("print_int");
+    txt.inst(Sub(RegImm(Rsp, Imm::Int(24))));
+    txt.inst(Mov(RegReg(Rax, Rdi)));
+    txt.inst_with_comment(Lea(Rsi, Memory::base(Rsp, 24)), "バッファの末尾");
+    txt.inst(Mov(RegImm(Rcx, Imm::Int(10))));
+    txt.inst_with_comment(Xor(RegReg(R8, R8)), "負数かどうか");
+    txt.inst(Test(Rax, Rax));
+    txt.inst(Jcc(Cond::Ns, label(".convert")));
+    txt.inst(Neg(Rax));
+    txt.inst(Mov(RegImm(R8, Imm::Int(1))));
+    txt.label(".convert");
+    txt.inst(Xor(RegReg(Rdx, Rdx)));
+    txt.inst(Div(Rcx));
+    txt.inst(Add(Reg8Imm(Rdx, Imm::Char(b'0'))));
+    txt.inst(Dec(Rsi));
+    txt.inst(Mov(MemReg8(Memory::base(Rsi, 0), Rdx)));
+    txt.inst(Test(Rax, Rax));
+    txt.inst(Jcc(Cond::Nz, label(".convert")));
+    txt.inst(Test(R8, R8));
+    txt.inst(Jcc(Cond::Z, label(".write")));
+    txt.inst(Dec(Rsi));
+    txt.inst(Mov(MemImm8(Memory::base(Rsi, 0), Imm::Char(b'-'))));
+    txt.label(".write");
+    txt.inst(Lea(Rdx, Memory::base(Rsp, 24)));
+    txt.inst_with_comment(Sub(RegReg(Rdx, Rsi)), "length");
+    txt.inst(Mov(RegImm(Rax, Imm::Const(Constant::SysWrite))));
+    txt.inst(Mov(RegImm(Rdi, Imm::Const(Constant::FdStdout))));
+    txt.inst(Syscall);
+    txt.inst(Add(RegImm(Rsp, Imm::Int(24))));
+    txt.inst(Ret);
+
+    // runtime_error (rdi: 位置を表す文字列, rsi: メッセージ, rdx: 手続きの名前, rcx: エラー番号, r8: 行番号)
+    txt.label("runtime_error");
+    // エラーハンドラが設定されていて、エラーハンドラの実行中でなければ、エラーハンドラに移る
+    txt.inst(Cmp(MemImm(Memory::label("err_handler", 0), Imm::Int(0))));
+    txt.inst(Jcc(Cond::E, label(".report")));
+    txt.inst(Cmp(MemImm(Memory::label("in_handler", 0), Imm::Int(0))));
+    txt.inst(Jcc(Cond::Ne, label(".report")));
+    txt.inst(Mov(MemReg(Memory::label("err_code", 0), Rcx)));
+    txt.inst(Mov(MemReg(Memory::label("err_line", 0), R8)));
+    txt.inst(Mov(RegMem(Rax, Memory::label("cur_stmt", 0))));
+    txt.inst(Mov(MemReg(Memory::label("resume_addr", 0), Rax)));
+    txt.inst(Mov(RegMem(Rax, Memory::label("next_stmt", 0))));
+    txt.inst(Mov(MemReg(Memory::label("resume_next_addr", 0), Rax)));
+    txt.inst(Mov(MemImm(Memory::label("in_handler", 0), Imm::Int(1))));
+    txt.inst(Mov(RegMem(Rsp, Memory::label("main_rsp", 0))));
+    txt.inst(Xor(RegReg(Rbp, Rbp)));
+    txt.inst(JmpMem(Memory::label("err_handler", 0)));
+    txt.label(".report");
+    if links_libc {
+        txt.inst(Call(label("flush_stdio")));
+    }
+    txt.inst(Mov(RegReg(R12, Rdx)));
+    txt.inst(Mov(RegReg(R13, Rdi)));
+    txt.inst(Push(Rsi.into()));
+    txt.inst(Push(Rdi.into()));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("err_prefix")))));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Pop(Rdi.into()));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("err_sep")))));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Pop(Rdi.into()));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("err_newline")))));
+    txt.inst(Call(label("eprint_string")));
+    // エラーが発生したフレーム
+    txt.inst(Mov(RegReg(Rdi, R12)));
+    txt.inst(Mov(RegReg(Rsi, R13)));
+    txt.inst(Call(label("print_frame")));
+    // フレームポインタの連鎖を辿り、戻りアドレスに対応する呼び出し箇所を表示する
+    txt.inst(Mov(RegReg(Rbx, Rbp)));
+    txt.label(".walk");
+    txt.inst(Test(Rbx, Rbx));
+    txt.inst(Jcc(Cond::Z, label(".exit")));
+    txt.inst_with_comment(Mov(RegMem(Rax, Memory::base(Rbx, 8))), "戻りアドレス");
+    txt.inst(Mov(RegImm(Rcx, Imm::Label(label("call_sites")))));
+    txt.label(".search");
+    txt.inst(Mov(RegMem(Rdx, Memory::base(Rcx, 0))));
+    txt.inst(Test(Rdx, Rdx));
+    txt.inst_with_comment(Jcc(Cond::Z, label(".exit")), "BASIC 以外から呼び出された");
+    txt.inst(Cmp(RegReg(Rdx, Rax)));
+    txt.inst(Jcc(Cond::E, label(".found")));
+    txt.inst(Add(RegImm(Rcx, Imm::Int(24))));
+    txt.inst(Jmp(label(".search")));
+    txt.label(".found");
+    txt.inst(Mov(RegMem(Rsi, Memory::base(Rcx, 8))));
+    txt.inst(Mov(RegMem(Rdi, Memory::base(Rcx, 16))));
+    txt.inst(Call(label("print_frame")));
+    txt.inst(Mov(RegMem(Rbx, Memory::base(Rbx, 0))));
+    txt.inst(Jmp(label(".walk")));
+    txt.label(".exit");
+    if links_libc {
+        txt.inst(Mov(RegImm(Rdi, Imm::Const(Constant::ExitFailure))));
+        txt.inst(And(RegImm(Rsp, Imm::Int(-16))));
+        txt.inst(Call(label("exit")));
+    } else {
+        txt.inst(Mov(RegImm(Rax, Imm::Const(Constant::SysExit))));
+        txt.inst(Mov(RegImm(Rdi, Imm::Const(Constant::ExitFailure))));
+        txt.inst(Syscall);
+    }
+
+    if links_libc {
+        // flush_stdio (libc のすべての出力ストリームをフラッシュする、rdi, rsi, rdx, rcx, r8 は保存する)
+        txt.label("flush_stdio");
+        for reg in [Rdi, Rsi, Rdx, Rcx, R8, Rbx] {
+            txt.inst(Push(reg.into()));
+        }
+        txt.inst(Mov(RegReg(Rbx, Rsp)));
+        txt.inst_with_comment(
+            And(RegImm(Rsp, Imm::Int(-16))),
+            "関数呼び出しの前に 16 バイト境界に揃える",
+        );
+        txt.inst(Xor(RegReg(Rdi, Rdi)));
+        txt.inst(Call(label("fflush")));
+        txt.inst(Mov(RegReg(Rsp, Rbx)));
+        for reg in [Rbx, R8, Rcx, Rdx, Rsi, Rdi] {
+            txt.inst(Pop(reg.into()));
+        }
+        txt.inst(Ret);
+    }
+
+    // print_frame (rdi: 手続きの名前, rsi: 位置を表す文字列)
+    txt.label("print_frame");
+    txt.inst(Push(Rsi.into()));
+    txt.inst(Push(Rdi.into()));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("bt_prefix")))));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Pop(Rdi.into()));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("bt_open")))));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Pop(Rdi.into()));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Mov(RegImm(Rdi, Imm::Label(label("bt_close")))));
+    txt.inst(Call(label("eprint_string")));
+    txt.inst(Ret);
+}
+
+/// 型に対応する型タグ
+fn type_tag(ty: Type) -> Imm {
+    match ty {
+        Type::String => Imm::Const(Constant::TypeStr),
+        Type::Integer => Imm::Const(Constant::TypeInt),
+    }
+}
+
+/// 連番が付与されて生成されるシンボル ( ``str0`` など) かどうか
+fn is_numbered_symbol(name: &str) -> bool {
+    NUMBERED_SYMBOL_PREFIXES.iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
     })
 }
+
+/// BASIC で定義された手続きを System V AMD64 ABI に従う関数として生成する
+fn gen_proc(ir: &Ir, proc: &Proc, block: &str, debug_info: &mut DebugInfo, txt: &mut TextSection) {
+    txt.label(&proc.name);
+    txt.inst(Push(Rbp.into()));
+    txt.inst(Mov(RegReg(Rbp, Rsp)));
+    txt.inst(Sub(RegImm(Rsp, Imm::Int(proc.num_locals as i64 * 16))));
+
+    // レジスタで渡された引数をローカル変数に格納する
+    for (i, (reg, ty)) in ARG_REGISTERS.iter().zip(proc.params.iter()).enumerate() {
+        txt.inst(Mov(MemReg(local_value(i as i32), *reg)));
+        txt.inst(Mov(MemImm(local_tag(i as i32), type_tag(*ty))));
+    }
+
+    gen_insts(ir, &proc.insts, block, debug_info, txt);
+
+    if let Some(ret_slot) = proc.ret_slot() {
+        txt.inst(Mov(RegMem(Rax, local_value(ret_slot))));
+    }
+
+    txt.inst(Mov(RegReg(Rsp, Rbp)));
+    txt.inst(Pop(Rbp.into()));
+    txt.inst(Ret);
+}
+
+/// 命令列を生成する
+///
+/// rsp は命令列の実行開始時点で 16 バイト境界に揃っているものとする。
+/// ``block`` は命令列を含む手続きの名前のシンボルで、ランタイムエラーの報告に用いられる
+fn gen_insts(
+    ir: &Ir,
+    insts: &[IrInst],
+    block: &str,
+    debug_info: &mut DebugInfo,
+    txt: &mut TextSection,
+) {
+    // 命令列の実行開始時点からスタックに積まれている 8 バイト値の個数
+    // (手続きを呼び出す前に rsp を 16 バイト境界に揃えるために用いる)
+    let mut depth = 0;
+
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                txt.inst(Push(Imm::Label(format!("str{}", index)).into()));
+                txt.inst(Push(type_tag(Type::String).into()));
+                depth += 2;
+            }
+            IrInst::GetImmInt(value) => {
+                txt.inst(Mov(RegImm(Rax, Imm::Int(*value))));
+                txt.inst(Push(Rax.into()));
+                txt.inst(Push(type_tag(Type::Integer).into()));
+                depth += 2;
+            }
+            IrInst::GetGlobal(index) => {
+                txt.inst(Push(global_value(*index).into()));
+                txt.inst(Push(global_tag(*index).into()));
+                depth += 2;
+            }
+            IrInst::SetGlobal(index) => {
+                txt.inst(Pop(global_tag(*index).into()));
+                txt.inst(Pop(global_value(*index).into()));
+                depth -= 2;
+            }
+            IrInst::GetLocal(index) => {
+                txt.inst(Push(local_value(*index).into()));
+                txt.inst(Push(local_tag(*index).into()));
+                depth += 2;
+            }
+            IrInst::SetLocal(index) => {
+                txt.inst(Pop(local_tag(*index).into()));
+                txt.inst(Pop(local_value(*index).into()));
+                depth -= 2;
+            }
+            IrInst::AssertType(ty, location) => {
+                txt.inst(Cmp(MemImm(Memory::base(Rsp, 0), type_tag(*ty))));
+                let error_label = type_mismatch_site(*ty, *location, block, debug_info);
+                txt.inst(Jcc(Cond::Nz, error_label));
+            }
+            IrInst::CallExtern(index) => {
+                let ext = &ir.externs[*index as usize];
+                gen_call(&ext.name, ext.params.len(), ext.ret, None, &mut depth, txt);
+            }
+            IrInst::CallProc(index, location) => {
+                let proc = &ir.procs[*index as usize];
+                let ret_label = call_site(*location, block, debug_info);
+                gen_call(
+                    &proc.name,
+                    proc.params.len(),
+                    proc.ret,
+                    Some(ret_label),
+                    &mut depth,
+                    txt,
+                );
+            }
+            IrInst::Pop => {
+                txt.inst(Add(RegImm(Rsp, Imm::Int(16))));
+                depth -= 2;
+            }
+            IrInst::Print => {
+                txt.inst(Pop(Rsi.into()));
+                txt.inst(Pop(Rdi.into()));
+                txt.inst(Call("print_value".to_owned()));
+                depth -= 2;
+            }
+            IrInst::BeginStmt(index) => {
+                gen_begin_stmt(*index, debug_info, txt);
+            }
+            IrInst::Label(index) => {
+                txt.label(format!("user_label{}", index));
+            }
+            IrInst::OnErrorGoto(label) => {
+                gen_on_error_goto(*label, txt);
+            }
+            IrInst::Resume(target, location) => {
+                gen_resume(*target, *location, block, debug_info, txt);
+            }
+            IrInst::RaiseError(location) => {
+                txt.inst(Add(RegImm(Rsp, Imm::Int(8))));
+                txt.inst_with_comment(Pop(Rcx.into()), "error code");
+                let error_label = error_site(*location, "msg_error_stmt", None, block, debug_info);
+                txt.inst(Jmp(error_label));
+                depth -= 2;
+            }
+            IrInst::GetErrCode => {
+                txt.inst(Push(Memory::label("err_code", 0).into()));
+                txt.inst(Push(type_tag(Type::Integer).into()));
+                depth += 2;
+            }
+            IrInst::GetErrLine => {
+                txt.inst(Push(Memory::label("err_line", 0).into()));
+                txt.inst(Push(type_tag(Type::Integer).into()));
+                depth += 2;
+            }
+            IrInst::End => {
+                txt.inst(Jmp("program_exit".to_owned()));
+            }
+        }
+    }
+}
+
+/// トップレベルの文の開始位置のラベルを生成し、エラーを捕捉する場合は ``RESUME`` で再開する位置を記録する
+fn gen_begin_stmt(index: i32, debug_info: &DebugInfo, txt: &mut TextSection) {
+    txt.label(format!("stmt{}", index));
+    if debug_info.traps_errors {
+        txt.inst(Mov(MemImm(
+            Memory::label("cur_stmt", 0),
+            Imm::Label(format!("stmt{}", index)),
+        )));
+        txt.inst(Mov(MemImm(
+            Memory::label("next_stmt", 0),
+            Imm::Label(format!("stmt{}", index + 1)),
+        )));
+    }
+}
+
+fn gen_on_error_goto(label: Option<i32>, txt: &mut TextSection) {
+    let handler = match label {
+        Some(index) => Imm::Label(format!("user_label{}", index)),
+        None => Imm::Int(0),
+    };
+    txt.inst(Mov(MemImm(Memory::label("err_handler", 0), handler)));
+}
+
+fn gen_resume(
+    target: ResumeTarget,
+    location: Location,
+    block: &str,
+    debug_info: &mut DebugInfo,
+    txt: &mut TextSection,
+) {
+    txt.inst(Cmp(MemImm(Memory::label("in_handler", 0), Imm::Int(0))));
+    let error_label = error_site(
+        location,
+        "msg_resume_without_error",
+        Some(Constant::ErrResumeWithoutError),
+        block,
+        debug_info,
+    );
+    txt.inst(Jcc(Cond::E, error_label));
+    for name in ["in_handler", "err_code", "err_line"] {
+        txt.inst(Mov(MemImm(Memory::label(name, 0), Imm::Int(0))));
+    }
+    match target {
+        ResumeTarget::Retry => txt.inst(JmpMem(Memory::label("resume_addr", 0))),
+        ResumeTarget::Next => txt.inst(JmpMem(Memory::label("resume_next_addr", 0))),
+    }
+}
+
+/// ランタイムエラーの発生箇所を登録し、エラーを報告する処理のラベルを返す
+fn error_site(
+    location: Location,
+    message: &'static str,
+    code: Option<Constant>,
+    block: &str,
+    debug_info: &mut DebugInfo,
+) -> String {
+    debug_info.error_sites.push(ErrorSite {
+        location,
+        message,
+        code,
+        block: block.to_owned(),
+    });
+    format!("rt_error{}", debug_info.error_sites.len() - 1)
+}
+
+fn type_mismatch_site(
+    ty: Type,
+    location: Location,
+    block: &str,
+    debug_info: &mut DebugInfo,
+) -> String {
+    let message = match ty {
+        Type::String => "msg_expected_str",
+        Type::Integer => "msg_expected_int",
+    };
+    error_site(
+        location,
+        message,
+        Some(Constant::ErrTypeMismatch),
+        block,
+        debug_info,
+    )
+}
+
+/// BASIC で定義された手続きの呼び出し箇所を登録し、戻りアドレスに付けるラベルを返す
+fn call_site(location: Location, block: &str, debug_info: &mut DebugInfo) -> String {
+    debug_info.call_sites.push(CallSite {
+        location,
+        block: block.to_owned(),
+    });
+    format!("rt_ret{}", debug_info.call_sites.len() - 1)
+}
+
+/// BASIC で定義された手続きを、レジスタ割り当てを行って生成する
+fn gen_allocated_proc(
+    ir: &Ir,
+    proc: &Proc,
+    block: &str,
+    debug_info: &mut DebugInfo,
+    txt: &mut TextSection,
+) {
+    let code = lower_proc(ir, proc);
+    let alloc = allocate(&code);
+    let saved = alloc.used_callee_saved();
+    // 退避したレジスタと合わせて、rsp を 16 バイト境界に揃える
+    let frame_size =
+        ((saved.len() + alloc.num_spill_slots) * 8).div_ceil(16) * 16 - saved.len() * 8;
+
+    txt.label(&proc.name);
+    txt.inst(Push(Rbp.into()));
+    txt.inst(Mov(RegReg(Rbp, Rsp)));
+    for reg in saved.iter() {
+        txt.inst(Push((*reg).into()));
+    }
+    if frame_size > 0 {
+        txt.inst(Sub(RegImm(Rsp, Imm::Int(frame_size as i64))));
+    }
+
+    gen_vcode(&code, &alloc, block, debug_info, txt);
+
+    if frame_size > 0 {
+        txt.inst(Add(RegImm(Rsp, Imm::Int(frame_size as i64))));
+    }
+    for reg in saved.iter().rev() {
+        txt.inst(Pop((*reg).into()));
+    }
+    txt.inst(Pop(Rbp.into()));
+    txt.inst(Ret);
+}
+
+/// レジスタ割り当ての結果に従って、仮想レジスタを用いる命令列を生成する
+///
+/// スピルされた値は rsp からの相対位置に置かれるため、rsp は命令列の実行中に変化しないものとする
+fn gen_vcode(
+    code: &VCode,
+    alloc: &Allocation,
+    block: &str,
+    debug_info: &mut DebugInfo,
+    txt: &mut TextSection,
+) {
+    for inst in code.insts.iter() {
+        match inst {
+            VInst::Params(params) => {
+                let moves = params
+                    .iter()
+                    .zip(ARG_REGISTERS.iter())
+                    .map(|(param, reg)| (vreg_operand(alloc, *param), Operand::Reg(*reg)))
+                    .collect();
+                gen_parallel_move(moves, txt);
+            }
+            VInst::Imm(reg, imm) => {
+                gen_move(vreg_operand(alloc, *reg), imm.clone().into(), txt);
+            }
+            VInst::Load(reg, place) => {
+                gen_move(vreg_operand(alloc, *reg), place_memory(*place).into(), txt);
+            }
+            VInst::Store(place, value) => {
+                gen_move(
+                    place_memory(*place).into(),
+                    value_operand(alloc, value),
+                    txt,
+                );
+            }
+            VInst::AssertTag(tag, ty, location) => match value_operand(alloc, tag) {
+                Operand::Imm(tag) if tag == type_tag(*ty) => {}
+                Operand::Imm(_) => {
+                    let error_label = type_mismatch_site(*ty, *location, block, debug_info);
+                    txt.inst(Jmp(error_label));
+                }
+                Operand::Reg(reg) => {
+                    txt.inst(Cmp(RegImm(reg, type_tag(*ty))));
+                    let error_label = type_mismatch_site(*ty, *location, block, debug_info);
+                    txt.inst(Jcc(Cond::Nz, error_label));
+                }
+                Operand::Mem(mem) => {
+                    txt.inst(Cmp(MemImm(mem, type_tag(*ty))));
+                    let error_label = type_mismatch_site(*ty, *location, block, debug_info);
+                    txt.inst(Jcc(Cond::Nz, error_label));
+                }
+            },
+            VInst::Call {
+                name,
+                args,
+                ret,
+                location,
+            } => {
+                let moves = args
+                    .iter()
+                    .zip(ARG_REGISTERS.iter())
+                    .map(|(arg, reg)| (Operand::Reg(*reg), value_operand(alloc, arg)))
+                    .collect();
+                gen_parallel_move(moves, txt);
+                txt.inst(Call(name.clone()));
+                if let Some(location) = location {
+                    txt.label(call_site(*location, block, debug_info));
+                }
+                if let Some(ret) = ret {
+                    gen_move(vreg_operand(alloc, *ret), Rax.into(), txt);
+                }
+            }
+            VInst::Print(value, tag) => {
+                let moves = vec![
+                    (Rdi.into(), value_operand(alloc, value)),
+                    (Rsi.into(), value_operand(alloc, tag)),
+                ];
+                gen_parallel_move(moves, txt);
+                txt.inst(Call("print_value".to_owned()));
+            }
+            VInst::BeginStmt(index) => {
+                gen_begin_stmt(*index, debug_info, txt);
+            }
+            VInst::Label(index) => {
+                txt.label(format!("user_label{}", index));
+            }
+            VInst::OnErrorGoto(label) => {
+                gen_on_error_goto(*label, txt);
+            }
+            VInst::Resume(target, location) => {
+                gen_resume(*target, *location, block, debug_info, txt);
+            }
+            VInst::RaiseError(value, location) => {
+                gen_move(Rcx.into(), value_operand(alloc, value), txt);
+                let error_label = error_site(*location, "msg_error_stmt", None, block, debug_info);
+                txt.inst(Jmp(error_label));
+            }
+            VInst::End => {
+                txt.inst(Jmp("program_exit".to_owned()));
+            }
+            VInst::Return(value) => {
+                if let Some(value) = value {
+                    gen_move(Rax.into(), value_operand(alloc, value), txt);
+                }
+            }
+        }
+    }
+}
+
+/// 仮想レジスタの割り当て先
+fn vreg_operand(alloc: &Allocation, reg: VReg) -> Operand {
+    match alloc.loc(reg) {
+        Loc::Reg(reg) => Operand::Reg(reg),
+        Loc::Spill(slot) => Memory::base(Rsp, slot as i64 * 8).into(),
+    }
+}
+
+fn value_operand(alloc: &Allocation, value: &VValue) -> Operand {
+    match value {
+        VValue::Reg(reg) => vreg_operand(alloc, *reg),
+        VValue::Imm(imm) => imm.clone().into(),
+    }
+}
+
+fn place_memory(place: Place) -> Memory {
+    match place {
+        Place::GlobalValue(index) => global_value(index),
+        Place::GlobalTag(index) => global_tag(index),
+        Place::ErrCode => Memory::label("err_code", 0),
+        Place::ErrLine => Memory::label("err_line", 0),
+    }
+}
+
+/// 値を転送する (メモリどうしの転送と、32 ビットに収まらない即値のメモリへの転送は r11 を経由する)
+fn gen_move(dst: Operand, src: Operand, txt: &mut TextSection) {
+    if dst == src {
+        return;
+    }
+    match (dst, src) {
+        (Operand::Reg(dst), Operand::Reg(src)) => txt.inst(Mov(RegReg(dst, src))),
+        (Operand::Reg(dst), Operand::Mem(src)) => txt.inst(Mov(RegMem(dst, src))),
+        (Operand::Reg(dst), Operand::Imm(src)) => txt.inst(Mov(RegImm(dst, src))),
+        (Operand::Mem(dst), Operand::Reg(src)) => txt.inst(Mov(MemReg(dst, src))),
+        (Operand::Mem(dst), Operand::Imm(Imm::Int(value))) if i32::try_from(value).is_err() => {
+            txt.inst(Mov(RegImm(R11, Imm::Int(value))));
+            txt.inst(Mov(MemReg(dst, R11)));
+        }
+        (Operand::Mem(dst), Operand::Imm(src)) => txt.inst(Mov(MemImm(dst, src))),
+        (Operand::Mem(dst), Operand::Mem(src)) => {
+            txt.inst(Mov(RegMem(R11, src)));
+            txt.inst(Mov(MemReg(dst, R11)));
+        }
+        (Operand::Imm(_), _) => unreachable!(),
+    }
+}
+
+/// 転送先が互いに異なる複数の転送を、まだ読み出していない転送元を上書きしない順序で生成する
+///
+/// 転送が循環している場合は、転送元の 1 つを r11 に退避して循環を断ち切る
+fn gen_parallel_move(moves: Vec<(Operand, Operand)>, txt: &mut TextSection) {
+    let mut pending: Vec<(Operand, Operand)> =
+        moves.into_iter().filter(|(dst, src)| dst != src).collect();
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(dst, _)| pending.iter().all(|(_, src)| src != dst));
+        match ready {
+            Some(i) => {
+                let (dst, src) = pending.remove(i);
+                gen_move(dst, src, txt);
+            }
+            None => {
+                let blocked = pending[0].0.clone();
+                gen_move(R11.into(), blocked.clone(), txt);
+                for (_, src) in pending.iter_mut() {
+                    if *src == blocked {
+                        *src = R11.into();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// スタックに積まれた引数をレジスタに移して手続きを呼び出し、戻り値をスタックに積む
+///
+/// ``ret_label`` を指定すると、戻りアドレスにラベルを付ける
+fn gen_call(
+    name: &str,
+    num_params: usize,
+    ret: Option<Type>,
+    ret_label: Option<String>,
+    depth: &mut i32,
+    txt: &mut TextSection,
+) {
+    // 最後の引数からポップして、対応するレジスタに格納する
+    for reg in ARG_REGISTERS[..num_params].iter().rev() {
+        txt.inst(Add(RegImm(Rsp, Imm::Int(8))));
+        txt.inst(Pop((*reg).into()));
+        *depth -= 2;
+    }
+
+    let aligned = *depth % 2 == 0;
+
+    if !aligned {
+        txt.inst(Sub(RegImm(Rsp, Imm::Int(8))));
+    }
+
+    txt.inst(Call(name.to_owned()));
+
+    if let Some(ret_label) = ret_label {
+        txt.label(ret_label);
+    }
+
+    if !aligned {
+        txt.inst(Add(RegImm(Rsp, Imm::Int(8))));
+    }
+
+    if let Some(ret) = ret {
+        txt.inst(Push(Rax.into()));
+        txt.inst(Push(type_tag(ret).into()));
+        *depth += 2;
+    }
+}
+
+/// グローバル変数の値の格納場所
+fn global_value(index: i32) -> Memory {
+    Memory::label("globals", index as i64 * 16)
+}
+
+/// グローバル変数の型タグの格納場所
+fn global_tag(index: i32) -> Memory {
+    Memory::label("globals", index as i64 * 16 + 8)
+}
+
+/// ローカル変数の値の格納場所
+fn local_value(index: i32) -> Memory {
+    Memory::base(Rbp, -(index as i64 + 1) * 16)
+}
+
+/// ローカル変数の型タグの格納場所
+fn local_tag(index: i32) -> Memory {
+    Memory::base(Rbp, -(index as i64 + 1) * 16 + 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golden::{frontend, require_tool, run_x64_at, TempDir};
+    use crate::OptLevel;
+    use std::fs;
+
+    /// すべての最適化レベルで同じ終了コードと出力になることを確かめる
+    fn assert_runs(name: &str, src: &str, expected: (i32, &str, &str)) {
+        let dir = TempDir::new(name);
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            if let Some((code, out, err)) = run_x64_at(frontend(src), name, &dir, opt_level) {
+                assert_eq!(
+                    (code, out.as_str(), err.as_str()),
+                    expected,
+                    "{} at -O{}",
+                    name,
+                    opt_level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn type_error_reports_location() {
+        let src = "SUB Need (n AS INTEGER)
+  PRINT n
+END SUB
+VAR s = \"a\"
+PRINT \"start\"
+Need 1
+  Need s
+PRINT \"unreachable\"
+";
+        assert_runs(
+            "type-error",
+            src,
+            (
+                1,
+                "start1",
+                "Runtime error at 7:8: type mismatch (expected INTEGER)\n    at <main> (7:8)\n",
+            ),
+        );
+    }
+
+    /// 6 個の引数が System V AMD64 ABI のレジスタの順に渡される
+    #[test]
+    #[ignore = "requires cc, ar and as"]
+    fn extern_args_follow_abi_registers() {
+        let dir = TempDir::new("abi");
+        let c_path = dir.join("digits.c");
+        let obj_path = dir.join("digits.o");
+        fs::write(
+            &c_path,
+            "#include <string.h>
+long digits(long a, const char *b, long c, long d, long e, long f) {
+    return ((((a * 10 + (long)strlen(b)) * 10 + c) * 10 + d) * 10 + e) * 10 + f;
+}
+",
+        )
+        .unwrap();
+        let (c_path, obj_path) = (c_path.to_str().unwrap(), obj_path.to_str().unwrap());
+        require_tool("cc", &["-c", "-o", obj_path, c_path]);
+        let lib_path = dir.join("libdigits.a");
+        require_tool("ar", &["rcs", lib_path.to_str().unwrap(), obj_path]);
+
+        let src = "DECLARE FUNCTION digits LIB \"digits\" (a AS INTEGER, b AS STRING, c AS INTEGER, d AS INTEGER, e AS INTEGER, f AS INTEGER) AS INTEGER
+PRINT digits(1, \"ab\", 3, 4, 5, 6)
+";
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let name = format!("abi-{}", opt_level);
+            if let Some(actual) = run_x64_at(frontend(src), &name, &dir, opt_level) {
+                assert_eq!(actual, (0, "123456".to_owned(), String::new()), "{}", name);
+            }
+        }
+    }
+
+    /// 手続きの中で発生したエラーは、呼び出し元をたどるバックトレースとともに報告される
+    #[test]
+    fn runtime_error_backtrace() {
+        let src = "SUB Inner (n AS INTEGER)
+  PRINT n
+END SUB
+SUB Outer (s AS STRING)
+  VAR v = s
+  Inner v
+END SUB
+SUB Top ()
+  Outer \"x\"
+END SUB
+PRINT \"start\"
+Top
+";
+        assert_runs(
+            "backtrace",
+            src,
+            (
+                1,
+                "start",
+                "Runtime error at 6:9: type mismatch (expected INTEGER)
+    at Outer (6:9)
+    at Top (9:3)
+    at <main> (12:1)
+",
+            ),
+        );
+    }
+
+    /// ``ERR`` と ``ERL`` は、型の不一致 (13)、 ``ERROR`` で発生させたエラー、
+    /// エラーなしの ``RESUME`` (20) のエラーコードと、エラーが発生した文の行番号を返す
+    #[test]
+    fn err_and_erl() {
+        let src = "ON ERROR GOTO H
+VAR s = \"a\"
+SUB Need (n AS INTEGER)
+  PRINT n
+END SUB
+Need s
+ERROR 53
+RESUME
+PRINT \"|\"
+ON ERROR GOTO 0
+ERROR 7
+END
+H:
+PRINT ERR
+PRINT \",\"
+PRINT ERL
+PRINT \" \"
+RESUME NEXT
+";
+        assert_runs(
+            "err-erl",
+            src,
+            (
+                1,
+                "13,6 53,7 20,8 |",
+                "Runtime error at 11:1: error raised by ERROR statement\n    at <main> (11:1)\n",
+            ),
+        );
+    }
+}
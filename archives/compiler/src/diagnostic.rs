@@ -0,0 +1,416 @@
+use super::location::Location;
+use super::term_color::{blue_bold, bold, red_bold, yellow_bold};
+use serde::Serialize;
+use std::fmt;
+
+/// テキストに色を付ける関数 ( ``term_color`` )
+type Style = fn(&str) -> String;
+
+/// 診断の重大度
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity_name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}", severity_name)
+    }
+}
+
+/// 診断の対象となるソースコード上の範囲と、その説明
+#[derive(Clone, Debug, Serialize)]
+pub struct Label {
+    pub location: Location,
+    pub message: String,
+    /// 主な原因を指すラベルか (そうでなければ、関連する箇所を補足するラベル)
+    pub primary: bool,
+}
+
+/// コンパイラが報告するエラーなどの診断
+///
+/// 色は付けずに保持し、 ``render`` で出力する際に付ける
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// エラーコード (ソースコードに起因しないエラーの場合は ``None`` )
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(code: &'static str, message: S) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: Some(code),
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    /// 主な原因を指すラベルを追加する
+    pub fn with_label<S: Into<String>>(mut self, location: Location, message: S) -> Self {
+        self.labels.push(Label {
+            location,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    /// 関連する箇所を補足するラベルを追加する
+    pub fn with_secondary_label<S: Into<String>>(mut self, location: Location, message: S) -> Self {
+        self.labels.push(Label {
+            location,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// 主な原因の位置 (ラベルがなければ ``None`` )
+    pub fn primary_location(&self) -> Option<Location> {
+        self.labels
+            .iter()
+            .find(|label| label.primary)
+            .or_else(|| self.labels.first())
+            .map(|label| label.location)
+    }
+
+    /// JSON で出力する ( ``--error-format=json`` )
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// rustc と同様に、ソースコードの該当する行と範囲を示す印を付けて出力する
+    ///
+    /// ``src`` が ``None`` の場合や、ラベルの位置がソースコードの範囲外の場合は、位置のみを示す。
+    /// ``src`` が ``None`` の場合の位置は ``path`` のファイル上の位置ではなく、
+    /// 元の BASIC のソースコード上の位置として示す ( ``--from-ast`` で読み込んだ抽象構文木など)。
+    /// ``color`` が ``true`` の場合は ANSI エスケープシーケンスで色を付ける
+    pub fn render(&self, path: &str, src: Option<&str>, color: bool) -> String {
+        let severity_style: Style = match self.severity {
+            Severity::Error => red_bold,
+            Severity::Warning => yellow_bold,
+        };
+
+        let mut output = match self.code {
+            Some(code) => paint(
+                color,
+                severity_style,
+                &format!("{}[{}]", self.severity, code),
+            ),
+            None => paint(color, severity_style, &self.severity.to_string()),
+        };
+        output.push_str(&paint(color, bold, &format!(": {}", self.message)));
+        output.push('\n');
+
+        let lines: Vec<&str> = src.map_or(Vec::new(), |src| src.lines().collect());
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.location.start.line(), label.location.start.column()));
+        let shown_lines: Vec<i32> = {
+            let mut shown_lines: Vec<i32> = labels
+                .iter()
+                .map(|label| label.location.start.line())
+                .filter(|line| *line >= 0 && (*line as usize) < lines.len())
+                .collect();
+            shown_lines.dedup();
+            shown_lines
+        };
+        let gutter_width = shown_lines
+            .last()
+            .map_or(1, |line| (line + 1).to_string().len());
+        let gutter = |text: &str| paint(color, blue_bold, &format!("{:>1$} |", text, gutter_width));
+
+        if let Some(location) = self.primary_location() {
+            // ソースコードがなければ、位置はファイル上の位置ではなくソースコード上の位置であることを明示する
+            let position = match src {
+                Some(_) => format!("{}:{}", path, location.start),
+                None => format!("{} (source position {})", path, location.start),
+            };
+            output.push_str(&format!(
+                "{}{} {}\n",
+                " ".repeat(gutter_width),
+                paint(color, blue_bold, "-->"),
+                position
+            ));
+        }
+
+        if !shown_lines.is_empty() {
+            output.push_str(&gutter(""));
+            output.push('\n');
+            let mut prev_line = None;
+            for line in shown_lines.iter().copied() {
+                if prev_line.is_some_and(|prev| line > prev + 1) {
+                    output.push_str(&paint(color, blue_bold, "..."));
+                    output.push('\n');
+                }
+                prev_line = Some(line);
+
+                let text = lines[line as usize];
+                output.push_str(&format!("{} {}\n", gutter(&(line + 1).to_string()), text));
+                for label in labels
+                    .iter()
+                    .filter(|label| label.location.start.line() == line)
+                {
+                    output.push_str(&gutter(""));
+                    output.push(' ');
+                    output.push_str(&underline(label, text, color));
+                    output.push('\n');
+                }
+            }
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            if !shown_lines.is_empty() {
+                output.push_str(&gutter(""));
+                output.push('\n');
+            }
+            for note in self.notes.iter() {
+                output.push_str(&format!(
+                    "{} {} {}\n",
+                    " ".repeat(gutter_width),
+                    paint(color, blue_bold, "="),
+                    paint(color, bold, "note:") + " " + note
+                ));
+            }
+            if let Some(help) = &self.help {
+                output.push_str(&format!(
+                    "{} {} {}\n",
+                    " ".repeat(gutter_width),
+                    paint(color, blue_bold, "="),
+                    paint(color, bold, "help:") + " " + help
+                ));
+            }
+        }
+
+        output
+    }
+}
+
+/// ラベルの範囲の下に ``^`` (主な原因) または ``-`` (補足) を並べ、説明を続ける
+///
+/// 複数行にわたる範囲は、開始した行の末尾までを示す
+fn underline(label: &Label, text: &str, color: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let start = (label.location.start.column().max(0) as usize).min(chars.len());
+    let end = if label.location.end.line() == label.location.start.line() {
+        (label.location.end.column().max(0) as usize + 1).min(chars.len())
+    } else {
+        chars.len()
+    };
+    let width = end.saturating_sub(start).max(1);
+
+    // タブの幅がずれないように、空白はソースコードの文字をそのまま用いる
+    let padding: String = chars[..start]
+        .iter()
+        .map(|c| if *c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let (mark, style): (&str, Style) = if label.primary {
+        ("^", red_bold)
+    } else {
+        ("-", blue_bold)
+    };
+    let mut marks = mark.repeat(width);
+    if !label.message.is_empty() {
+        marks.push(' ');
+        marks.push_str(&label.message);
+    }
+    format!("{}{}", padding, paint(color, style, &marks))
+}
+
+/// ソースコードの位置を持たないエラー (ファイルの入出力やバックエンドのエラーなど)
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+}
+
+/// 色やソースコードを含めない1行の形式 (例: ``error[E0209] (1:7-1:7): `x` is not defined`` )
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(code) = self.code {
+            write!(f, "[{}]", code)?;
+        }
+        if let Some(location) = self.primary_location() {
+            write!(f, " ({})", location)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// ``color`` が ``true`` の場合のみ、テキストに色を付ける
+fn paint(color: bool, style: Style, text: &str) -> String {
+    if color {
+        style(text)
+    } else {
+        text.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::Point;
+
+    /// ``line`` 行目の ``start`` 列から ``end`` 列まで (すべて 0 始まり)
+    fn loc(line: i32, start: i32, end: i32) -> Location {
+        Location {
+            start: Point::new(line, start),
+            end: Point::new(line, end),
+        }
+    }
+
+    fn undefined_y() -> Diagnostic {
+        Diagnostic::error("E0209", "`y` is not defined").with_label(loc(1, 6, 6), "not defined")
+    }
+
+    /// ANSI エスケープシーケンスを取り除く
+    fn strip_color(text: &str) -> String {
+        let mut result = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn caret_placement() {
+        let src = "VAR x = 1\nPRINT y\n";
+        assert_eq!(
+            undefined_y().render("a.bas", Some(src), false),
+            "error[E0209]: `y` is not defined
+ --> a.bas:2:7
+  |
+2 | PRINT y
+  |       ^ not defined
+"
+        );
+
+        let diag = Diagnostic::error("E0209", "`Foo` is not defined").with_label(loc(0, 2, 4), "");
+        assert_eq!(
+            diag.render("a.bas", Some("\tx Foo\n"), false),
+            "error[E0209]: `Foo` is not defined
+ --> a.bas:1:3
+  |
+1 | \tx Foo
+  | \t ^^^
+"
+        );
+    }
+
+    #[test]
+    fn secondary_labels() {
+        let src = "VAR x = 1\nVAR x = 2\n";
+        let diag = Diagnostic::error("E0201", "`x` is already defined")
+            .with_label(loc(1, 4, 4), "redefined")
+            .with_secondary_label(loc(0, 4, 4), "first defined here")
+            .with_note("variables cannot be redefined")
+            .with_help("use assignment instead");
+        assert_eq!(
+            diag.render("a.bas", Some(src), false),
+            "error[E0201]: `x` is already defined
+ --> a.bas:2:5
+  |
+1 | VAR x = 1
+  |     - first defined here
+2 | VAR x = 2
+  |     ^ redefined
+  |
+  = note: variables cannot be redefined
+  = help: use assignment instead
+"
+        );
+    }
+
+    #[test]
+    fn elision() {
+        let src = "VAR x = 1\nPRINT x\nPRINT x\nVAR x = 2\n";
+        let diag = Diagnostic::error("E0201", "`x` is already defined")
+            .with_label(loc(3, 4, 4), "redefined")
+            .with_secondary_label(loc(0, 4, 4), "first defined here");
+        assert_eq!(
+            diag.render("a.bas", Some(src), false),
+            "error[E0201]: `x` is already defined
+ --> a.bas:4:5
+  |
+1 | VAR x = 1
+  |     - first defined here
+...
+4 | VAR x = 2
+  |     ^ redefined
+"
+        );
+    }
+
+    #[test]
+    fn without_source() {
+        assert_eq!(
+            undefined_y().render("a.ast.json", None, false),
+            "error[E0209]: `y` is not defined
+ --> a.ast.json (source position 2:7)
+"
+        );
+        assert_eq!(
+            Diagnostic::from("Failed to read the source file".to_owned()).render("", None, false),
+            "error: Failed to read the source file\n"
+        );
+    }
+
+    #[test]
+    fn color() {
+        let src = "VAR x = 1\nPRINT y\n";
+        let plain = undefined_y().render("a.bas", Some(src), false);
+        let colored = undefined_y().render("a.bas", Some(src), true);
+        assert!(!plain.contains('\x1b'));
+        assert!(colored.starts_with(&red_bold("error[E0209]")));
+        assert!(colored.contains(&red_bold("^ not defined")));
+        assert_eq!(strip_color(&colored), plain);
+    }
+
+    #[test]
+    fn to_json() {
+        assert_eq!(
+            undefined_y().to_json(),
+            r#"{"severity":"error","code":"E0209","message":"`y` is not defined","labels":[{"location":{"start":{"line":1,"column":6},"end":{"line":1,"column":6}},"message":"not defined","primary":true}],"notes":[],"help":null}"#
+        );
+        assert_eq!(
+            Diagnostic::from("Failed".to_owned()).to_json(),
+            r#"{"severity":"error","code":null,"message":"Failed","labels":[],"notes":[],"help":null}"#
+        );
+    }
+}
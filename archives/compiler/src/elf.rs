@@ -0,0 +1,146 @@
+/// 実行可能ファイルを読み込むアドレス
+pub const BASE_ADDR: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+/// テキストセグメントのファイル上の位置 (ELF ヘッダとプログラムヘッダの直後のページ)
+pub const TEXT_OFFSET: u64 = PAGE_SIZE;
+
+const ELF_HEADER_SIZE: u16 = 64;
+const PROGRAM_HEADER_SIZE: u16 = 56;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// データセグメントのファイル上の位置 (テキストセグメントの直後のページ)
+pub fn data_offset(text_len: usize) -> u64 {
+    (TEXT_OFFSET + text_len as u64).div_ceil(PAGE_SIZE) * PAGE_SIZE
+}
+
+/// 静的リンクされた ELF64 の実行可能ファイルを生成する
+///
+/// テキストセグメント (読み込み・実行可能) とデータセグメント (読み書き可能) の 2 つのみからなり、セクションヘッダは出力しない
+pub fn write_static_exec(text: &[u8], data: &[u8], entry: u64) -> Vec<u8> {
+    let data_offset = data_offset(text.len());
+    let mut bytes = Vec::new();
+
+    // ELF ヘッダ
+    bytes.extend_from_slice(b"\x7fELF");
+    bytes.push(2); // ELFCLASS64
+    bytes.push(1); // ELFDATA2LSB
+    bytes.push(1); // EV_CURRENT
+    bytes.push(0); // ELFOSABI_SYSV
+    bytes.resize(16, 0);
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    bytes.extend_from_slice(&0x3eu16.to_le_bytes()); // EM_X86_64
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&entry.to_le_bytes());
+    bytes.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    bytes.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    bytes.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    bytes.extend_from_slice(&PROGRAM_HEADER_SIZE.to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // e_phnum
+    bytes.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    bytes.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    bytes.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    // プログラムヘッダ (テキストセグメントは ELF ヘッダを含むファイルの先頭から読み込む)
+    write_program_header(&mut bytes, PF_R | PF_X, 0, TEXT_OFFSET + text.len() as u64);
+    write_program_header(&mut bytes, PF_R | PF_W, data_offset, data.len() as u64);
+
+    bytes.resize(TEXT_OFFSET as usize, 0);
+    bytes.extend_from_slice(text);
+    bytes.resize(data_offset as usize, 0);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn write_program_header(bytes: &mut Vec<u8>, flags: u32, offset: u64, size: u64) {
+    bytes.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&offset.to_le_bytes());
+    bytes.extend_from_slice(&(BASE_ADDR + offset).to_le_bytes()); // p_vaddr
+    bytes.extend_from_slice(&(BASE_ADDR + offset).to_le_bytes()); // p_paddr
+    bytes.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    bytes.extend_from_slice(&size.to_le_bytes()); // p_memsz
+    bytes.extend_from_slice(&PAGE_SIZE.to_le_bytes()); // p_align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(buf)
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(buf)
+    }
+
+    #[test]
+    fn data_follows_text_page() {
+        assert_eq!(data_offset(0), 0x1000);
+        assert_eq!(data_offset(1), 0x2000);
+        assert_eq!(data_offset(0x1000), 0x2000);
+        assert_eq!(data_offset(0x1001), 0x3000);
+    }
+
+    #[test]
+    fn headers() {
+        let text = [0x90; 0x1234];
+        let data = b"data";
+        let entry = BASE_ADDR + TEXT_OFFSET + 0x10;
+        let bytes = write_static_exec(&text, data, entry);
+
+        // ELF ヘッダ
+        assert_eq!(&bytes[..8], b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(u16_at(&bytes, 16), 2);
+        assert_eq!(u16_at(&bytes, 18), 0x3e);
+        assert_eq!(u32_at(&bytes, 20), 1);
+        assert_eq!(u64_at(&bytes, 24), entry);
+        assert_eq!(u64_at(&bytes, 32), ELF_HEADER_SIZE as u64);
+        assert_eq!(u64_at(&bytes, 40), 0);
+        assert_eq!(u16_at(&bytes, 52), ELF_HEADER_SIZE);
+        assert_eq!(u16_at(&bytes, 54), PROGRAM_HEADER_SIZE);
+        assert_eq!(u16_at(&bytes, 56), 2);
+        assert_eq!(u16_at(&bytes, 60), 0);
+
+        // プログラムヘッダ (p_type, p_flags, p_offset, p_vaddr, p_filesz, p_memsz, p_align)
+        let program_header = |i: usize| {
+            let start = ELF_HEADER_SIZE as usize + i * PROGRAM_HEADER_SIZE as usize;
+            (
+                u32_at(&bytes, start),
+                u32_at(&bytes, start + 4),
+                u64_at(&bytes, start + 8),
+                u64_at(&bytes, start + 16),
+                u64_at(&bytes, start + 32),
+                u64_at(&bytes, start + 40),
+                u64_at(&bytes, start + 48),
+            )
+        };
+        assert_eq!(
+            program_header(0),
+            (1, PF_R | PF_X, 0, BASE_ADDR, 0x2234, 0x2234, PAGE_SIZE)
+        );
+        assert_eq!(
+            program_header(1),
+            (1, PF_R | PF_W, 0x3000, BASE_ADDR + 0x3000, 4, 4, PAGE_SIZE)
+        );
+        // p_paddr
+        assert_eq!(u64_at(&bytes, ELF_HEADER_SIZE as usize + 24), BASE_ADDR);
+
+        // セグメントの中身はプログラムヘッダが示す位置に置かれる
+        assert!(bytes[0x1000..0x2234].iter().all(|b| *b == 0x90));
+        assert_eq!(&bytes[0x3000..], b"data");
+        assert_eq!(bytes.len(), 0x3004);
+    }
+}
@@ -0,0 +1,283 @@
+use super::ir::Ir;
+use super::{
+    compile_ir, gen_ir, AsmSyntax, CompileConfig, CrateType, InputFormat, OptLevel, Target,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+/// サンプルプログラムのディレクトリ ( ``archives/basic`` )
+fn samples_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../basic")
+}
+
+/// サンプルプログラムの名前 (拡張子を除いたファイル名) を名前順に返す
+pub fn sample_names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(samples_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bas"))
+        .map(|path| path.file_stem().unwrap().to_str().unwrap().to_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// BASIC のソースコードを実行可能ファイルとして意味解析まで行い、中間表現を返す
+pub fn frontend(src: &str) -> Ir {
+    gen_ir(src, InputFormat::Basic, CrateType::Bin).unwrap_or_else(|diags| {
+        let errors: Vec<String> = diags.iter().map(|diag| diag.to_string()).collect();
+        panic!("failed to compile:\n{}", errors.join("\n"))
+    })
+}
+
+/// サンプルプログラムを中間表現に変換する
+pub fn sample_ir(name: &str) -> Ir {
+    frontend(&fs::read_to_string(samples_dir().join(format!("{}.bas", name))).unwrap())
+}
+
+/// 出力を ``golden/<backend>/<file_name>`` に保存された期待される出力と比較する
+///
+/// 環境変数 ``UPDATE_GOLDEN`` が設定されている場合は、比較せずに保存された出力を書き換える
+pub fn check_golden(backend: &str, file_name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("golden")
+        .join(backend)
+        .join(file_name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("{}: {} (run with UPDATE_GOLDEN=1)", path.display(), err));
+    assert!(
+        expected == actual,
+        "output differs from {} (run with UPDATE_GOLDEN=1 to update it)\n{}",
+        path.display(),
+        actual
+    );
+}
+
+/// 削除されるときに中身ごと削除される一時ディレクトリ
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// テストごとに異なる ``name`` を与える
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("basic-{}-{}", name, process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn join(&self, file_name: &str) -> PathBuf {
+        self.0.join(file_name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// 外部のコマンドを実行して、終了コードと標準出力、標準エラー出力を返す
+///
+/// コマンドが見つからない場合は ``None`` を返す
+pub fn run_command<P: AsRef<Path>>(program: P, args: &[&str]) -> Option<(i32, String, String)> {
+    let output = Command::new(program.as_ref()).args(args).output().ok()?;
+    Some((
+        output.status.code().unwrap_or(-1),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    ))
+}
+
+/// 外部のコマンドを実行し、成功したことを確かめる (コマンドが見つからない場合は ``false`` を返す)
+fn run_tool(program: &str, args: &[&str]) -> bool {
+    match Command::new(program).args(args).output() {
+        Ok(output) => {
+            assert!(
+                output.status.success(),
+                "`{}` failed:\n{}",
+                program,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// 外部のコマンドを実行し、成功したことを確かめる
+///
+/// コマンドが見つからない場合も失敗とする (外部のコマンドを必要とするテストには ``#[ignore]`` を付ける)
+pub fn require_tool(program: &str, args: &[&str]) {
+    assert!(
+        run_tool(program, args),
+        "`{}` is not found; install it or run the tests without `--include-ignored`",
+        program
+    );
+}
+
+/// 中間表現を x86-64 のバックエンドでコンパイルして実行し、終了コードと標準出力、標準エラー出力を返す
+///
+/// x86-64 の Linux 以外で実行された場合は ``None`` を返す。
+/// 外部ライブラリを呼び出すプログラムは ``as`` と ``cc`` でリンクするため、それらが見つからなければ失敗する
+pub fn run_x64(ir: Ir, name: &str, dir: &TempDir) -> Option<(i32, String, String)> {
+    run_x64_at(ir, name, dir, OptLevel::O0)
+}
+
+/// ``run_x64`` と同様だが、最適化のレベルを指定する
+///
+/// 外部ライブラリは ``dir`` からも探す
+pub fn run_x64_at(
+    ir: Ir,
+    name: &str,
+    dir: &TempDir,
+    opt_level: OptLevel,
+) -> Option<(i32, String, String)> {
+    run_x64_with(ir, name, dir, opt_level, None)
+}
+
+/// ``run_x64`` と同様だが、組み込みのアセンブラを使わず、 ``asm_syntax`` の構文で出力して外部のアセンブラで変換する
+///
+/// Intel 構文は ``nasm`` 、AT&T 構文は ``as`` で変換し、 ``cc`` でリンクする
+pub fn run_x64_external(
+    ir: Ir,
+    name: &str,
+    dir: &TempDir,
+    asm_syntax: AsmSyntax,
+) -> Option<(i32, String, String)> {
+    run_x64_with(ir, name, dir, OptLevel::O0, Some(asm_syntax))
+}
+
+/// ``external_syntax`` が ``None`` の場合は、可能であれば組み込みのアセンブラを用いる
+fn run_x64_with(
+    ir: Ir,
+    name: &str,
+    dir: &TempDir,
+    opt_level: OptLevel,
+    external_syntax: Option<AsmSyntax>,
+) -> Option<(i32, String, String)> {
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        return None;
+    }
+    let asm_syntax = external_syntax.unwrap_or(AsmSyntax::Att);
+    let config = CompileConfig {
+        crate_type: CrateType::Bin,
+        target: Target::X64Linux,
+        use_external_assembler: external_syntax.is_some(),
+        asm_syntax,
+        opt_level,
+    };
+    let output = compile_ir(ir, name, &config).unwrap();
+    let stem = format!("{}.x64.{}", name, asm_syntax);
+    let bin_path = dir.join(&format!("{}.bin", stem));
+    if let Some(binary) = output.binary {
+        fs::write(&bin_path, binary).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&bin_path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    } else {
+        let asm_path = dir.join(&format!("{}.s", stem));
+        let obj_path = dir.join(&format!("{}.o", stem));
+        fs::write(&asm_path, output.asm).unwrap();
+        let (asm_path, obj_path) = (asm_path.to_str().unwrap(), obj_path.to_str().unwrap());
+        match asm_syntax {
+            // NASM は ``.s`` を ``.o`` に置き換えたファイルに出力する
+            AsmSyntax::Intel => require_tool("nasm", &["-f", "elf64", asm_path]),
+            AsmSyntax::Att => require_tool("as", &["-o", obj_path, asm_path]),
+        }
+        let libs: Vec<String> = output.libs.iter().map(|lib| format!("-l{}", lib)).collect();
+        let lib_dir = format!("-L{}", dir.join("").display());
+        let mut args = vec![
+            "-nostartfiles",
+            "-no-pie",
+            "-o",
+            bin_path.to_str().unwrap(),
+            obj_path,
+            &lib_dir,
+        ];
+        args.extend(libs.iter().map(String::as_str));
+        require_tool("cc", &args);
+    }
+    run_command(&bin_path, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret, Limits};
+
+    /// 外部ライブラリを呼び出さないサンプルプログラムは、x86-64 のバックエンドとインタプリタで出力が一致する
+    #[test]
+    fn x64_backend_matches_interpreter() {
+        let dir = TempDir::new("golden");
+        for name in sample_names() {
+            let ir = sample_ir(&name);
+            if !ir.externs.is_empty() {
+                continue;
+            }
+            let mut out = Vec::new();
+            let mut err = Vec::new();
+            let code = interpret(&ir, Limits::default(), &mut out, &mut err).unwrap();
+            let expected = (
+                code,
+                String::from_utf8(out).unwrap(),
+                String::from_utf8(err).unwrap(),
+            );
+            if let Some(actual) = run_x64(ir, &name, &dir) {
+                assert_eq!(actual, expected, "{}", name);
+            }
+        }
+    }
+
+    /// 外部のアセンブラに渡す Intel 構文と AT&T 構文のアセンブリ
+    #[test]
+    fn x64_asm_matches_golden_files() {
+        for name in sample_names() {
+            for asm_syntax in [AsmSyntax::Intel, AsmSyntax::Att] {
+                let config = CompileConfig {
+                    crate_type: CrateType::Bin,
+                    target: Target::X64Linux,
+                    use_external_assembler: true,
+                    asm_syntax,
+                    opt_level: OptLevel::O0,
+                };
+                let output = compile_ir(sample_ir(&name), &name, &config).unwrap();
+                check_golden("x64", &format!("{}.{}.s", name, asm_syntax), &output.asm);
+            }
+        }
+    }
+
+    /// Intel 構文 (NASM) と AT&T 構文 (GNU as) のどちらで出力しても、サンプルプログラムの動作は変わらない
+    #[test]
+    #[ignore = "requires nasm, as and cc"]
+    fn x64_asm_syntaxes_behave_identically() {
+        let dir = TempDir::new("syntax");
+        for name in sample_names() {
+            let ir = sample_ir(&name);
+            let intel = run_x64_external(sample_ir(&name), &name, &dir, AsmSyntax::Intel);
+            let att = run_x64_external(sample_ir(&name), &name, &dir, AsmSyntax::Att);
+            assert_eq!(intel, att, "{}", name);
+            if ir.externs.is_empty() {
+                let mut out = Vec::new();
+                let mut err = Vec::new();
+                let code = interpret(&ir, Limits::default(), &mut out, &mut err).unwrap();
+                let expected = (
+                    code,
+                    String::from_utf8(out).unwrap(),
+                    String::from_utf8(err).unwrap(),
+                );
+                if let Some(actual) = intel {
+                    assert_eq!(actual, expected, "{}", name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+
+/// 32 ビット汎用レジスタ
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Eax,
+    Ecx,
+    Edx,
+    Ebx,
+    Esp,
+    Ebp,
+    Esi,
+    Edi,
+}
+
+impl Register {
+    /// 機械語でのレジスタ番号
+    fn code(self) -> u8 {
+        self as u8
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Register::Eax => "eax",
+            Register::Ecx => "ecx",
+            Register::Edx => "edx",
+            Register::Ebx => "ebx",
+            Register::Esp => "esp",
+            Register::Ebp => "ebp",
+            Register::Esi => "esi",
+            Register::Edi => "edi",
+        }
+    }
+}
+
+/// 即値 (ラベルのアドレスにオフセットを加えた値も表せる)
+#[derive(Clone)]
+pub struct Imm {
+    pub label: Option<String>,
+    pub offset: i32,
+}
+
+impl Imm {
+    pub fn int(offset: i32) -> Self {
+        Imm {
+            label: None,
+            offset,
+        }
+    }
+
+    pub fn label<L: Into<String>>(label: L) -> Self {
+        Imm {
+            label: Some(label.into()),
+            offset: 0,
+        }
+    }
+
+    fn stringify(&self) -> String {
+        match &self.label {
+            Some(label) if self.offset > 0 => format!("{}+{}", label, self.offset),
+            Some(label) if self.offset < 0 => format!("{}{}", label, self.offset),
+            Some(label) => label.clone(),
+            None => self.offset.to_string(),
+        }
+    }
+}
+
+/// メモリオペランド ( ``[base + disp]`` 、ベースレジスタがなければ絶対アドレス)
+#[derive(Clone)]
+pub struct Memory {
+    pub base: Option<Register>,
+    pub disp: Imm,
+}
+
+impl Memory {
+    pub fn base(base: Register, disp: i32) -> Self {
+        Memory {
+            base: Some(base),
+            disp: Imm::int(disp),
+        }
+    }
+
+    pub fn label<L: Into<String>>(label: L, offset: i32) -> Self {
+        Memory {
+            base: None,
+            disp: Imm {
+                label: Some(label.into()),
+                offset,
+            },
+        }
+    }
+
+    fn stringify(&self) -> String {
+        match self.base {
+            Some(base) if self.disp.offset > 0 => {
+                format!("[{}+{}]", base.name(), self.disp.stringify())
+            }
+            Some(base) if self.disp.offset < 0 => {
+                format!("[{}{}]", base.name(), self.disp.stringify())
+            }
+            Some(base) => format!("[{}]", base.name()),
+            None => format!("[{}]", self.disp.stringify()),
+        }
+    }
+}
+
+/// 条件分岐の条件
+#[derive(Clone, Copy)]
+pub enum Cond {
+    E,
+    Ne,
+    Ge,
+}
+
+impl Cond {
+    fn name(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::Ge => "ge",
+        }
+    }
+
+    /// ``0f 8x`` の ``x``
+    fn code(self) -> u8 {
+        match self {
+            Cond::E => 0x4,
+            Cond::Ne => 0x5,
+            Cond::Ge => 0xd,
+        }
+    }
+}
+
+/// ``emulator`` クレートが実装している i386 の命令
+///
+/// 分岐命令はすべて 32 ビットの相対アドレスで符号化するため、命令の長さはオペランドの値に依存しない
+#[derive(Clone)]
+pub enum Inst {
+    MovRegImm(Register, Imm),
+    MovRegReg(Register, Register),
+    MovRegMem(Register, Memory),
+    MovMemReg(Memory, Register),
+    MovMemImm(Memory, Imm),
+    /// ``mov al, byte[...]``
+    MovAlMem8(Memory),
+    Push(Register),
+    PushImm(Imm),
+    Pop(Register),
+    AddRegImm(Register, i32),
+    SubRegImm(Register, i32),
+    CmpRegImm(Register, i32),
+    CmpMemImm(Memory, i32),
+    CmpAlImm(u8),
+    XorRegReg(Register, Register),
+    Inc(Register),
+    Dec(Register),
+    Neg(Register),
+    Div(Register),
+    Jmp(Imm),
+    JmpMem(Memory),
+    Jcc(Cond, String),
+    Call(String),
+    Ret,
+    /// ``out dx, al``
+    OutDxAl,
+}
+
+impl Inst {
+    fn stringify(&self) -> String {
+        match self {
+            Inst::MovRegImm(reg, imm) => format!("mov {}, {}", reg.name(), imm.stringify()),
+            Inst::MovRegReg(dst, src) => format!("mov {}, {}", dst.name(), src.name()),
+            Inst::MovRegMem(reg, mem) => format!("mov {}, {}", reg.name(), mem.stringify()),
+            Inst::MovMemReg(mem, reg) => format!("mov {}, {}", mem.stringify(), reg.name()),
+            Inst::MovMemImm(mem, imm) => {
+                format!("mov dword{}, {}", mem.stringify(), imm.stringify())
+            }
+            Inst::MovAlMem8(mem) => format!("mov al, byte{}", mem.stringify()),
+            Inst::Push(reg) => format!("push {}", reg.name()),
+            Inst::PushImm(imm) => format!("push dword {}", imm.stringify()),
+            Inst::Pop(reg) => format!("pop {}", reg.name()),
+            Inst::AddRegImm(reg, imm) => format!("add {}, dword {}", reg.name(), imm),
+            Inst::SubRegImm(reg, imm) => format!("sub {}, dword {}", reg.name(), imm),
+            Inst::CmpRegImm(reg, imm) => format!("cmp {}, dword {}", reg.name(), imm),
+            Inst::CmpMemImm(mem, imm) => format!("cmp dword{}, dword {}", mem.stringify(), imm),
+            Inst::CmpAlImm(imm) => format!("cmp al, {}", imm),
+            Inst::XorRegReg(dst, src) => format!("xor {}, {}", dst.name(), src.name()),
+            Inst::Inc(reg) => format!("inc {}", reg.name()),
+            Inst::Dec(reg) => format!("dec {}", reg.name()),
+            Inst::Neg(reg) => format!("neg {}", reg.name()),
+            Inst::Div(reg) => format!("div {}", reg.name()),
+            Inst::Jmp(target) => format!("jmp near {}", target.stringify()),
+            Inst::JmpMem(mem) => format!("jmp dword{}", mem.stringify()),
+            Inst::Jcc(cond, label) => format!("j{} near {}", cond.name(), label),
+            Inst::Call(label) => format!("call {}", label),
+            Inst::Ret => "ret".to_owned(),
+            Inst::OutDxAl => "out dx, al".to_owned(),
+        }
+    }
+
+    /// 命令の長さ (バイト数)
+    fn len(&self) -> u32 {
+        match self {
+            Inst::MovRegImm(..) | Inst::PushImm(_) | Inst::Jmp(_) | Inst::Call(_) => 5,
+            Inst::MovRegReg(..) | Inst::XorRegReg(..) | Inst::Neg(_) | Inst::Div(_) => 2,
+            Inst::MovRegMem(_, mem)
+            | Inst::MovMemReg(mem, _)
+            | Inst::MovAlMem8(mem)
+            | Inst::JmpMem(mem) => 1 + modrm_len(mem),
+            Inst::MovMemImm(mem, _) | Inst::CmpMemImm(mem, _) => 5 + modrm_len(mem),
+            Inst::AddRegImm(..) | Inst::SubRegImm(..) | Inst::CmpRegImm(..) => 6,
+            Inst::CmpAlImm(_) => 2,
+            Inst::Push(_) | Inst::Pop(_) | Inst::Inc(_) | Inst::Dec(_) => 1,
+            Inst::Jcc(..) => 6,
+            Inst::Ret | Inst::OutDxAl => 1,
+        }
+    }
+
+    /// 機械語に変換する (``addr`` は命令の先頭のアドレス)
+    fn encode(
+        &self,
+        addr: u32,
+        symbols: &HashMap<String, u32>,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let next = addr + self.len();
+        match self {
+            Inst::MovRegImm(reg, imm) => {
+                bytes.push(0xb8 + reg.code());
+                push_u32(resolve(imm, symbols)?, bytes);
+            }
+            Inst::MovRegReg(dst, src) => {
+                bytes.push(0x89);
+                bytes.push(0xc0 | (src.code() << 3) | dst.code());
+            }
+            Inst::MovRegMem(reg, mem) => {
+                bytes.push(0x8b);
+                encode_modrm(reg.code(), mem, symbols, bytes)?;
+            }
+            Inst::MovMemReg(mem, reg) => {
+                bytes.push(0x89);
+                encode_modrm(reg.code(), mem, symbols, bytes)?;
+            }
+            Inst::MovMemImm(mem, imm) => {
+                bytes.push(0xc7);
+                encode_modrm(0, mem, symbols, bytes)?;
+                push_u32(resolve(imm, symbols)?, bytes);
+            }
+            Inst::MovAlMem8(mem) => {
+                bytes.push(0x8a);
+                encode_modrm(0, mem, symbols, bytes)?;
+            }
+            Inst::Push(reg) => bytes.push(0x50 + reg.code()),
+            Inst::PushImm(imm) => {
+                bytes.push(0x68);
+                push_u32(resolve(imm, symbols)?, bytes);
+            }
+            Inst::Pop(reg) => bytes.push(0x58 + reg.code()),
+            Inst::AddRegImm(reg, imm) => encode_group81(0, *reg, *imm, bytes),
+            Inst::SubRegImm(reg, imm) => encode_group81(5, *reg, *imm, bytes),
+            Inst::CmpRegImm(reg, imm) => encode_group81(7, *reg, *imm, bytes),
+            Inst::CmpMemImm(mem, imm) => {
+                bytes.push(0x81);
+                encode_modrm(7, mem, symbols, bytes)?;
+                push_u32(*imm as u32, bytes);
+            }
+            Inst::CmpAlImm(imm) => {
+                bytes.push(0x3c);
+                bytes.push(*imm);
+            }
+            Inst::XorRegReg(dst, src) => {
+                bytes.push(0x31);
+                bytes.push(0xc0 | (src.code() << 3) | dst.code());
+            }
+            Inst::Inc(reg) => bytes.push(0x40 + reg.code()),
+            Inst::Dec(reg) => bytes.push(0x48 + reg.code()),
+            Inst::Neg(reg) => {
+                bytes.push(0xf7);
+                bytes.push(0xc0 | (3 << 3) | reg.code());
+            }
+            Inst::Div(reg) => {
+                bytes.push(0xf7);
+                bytes.push(0xc0 | (6 << 3) | reg.code());
+            }
+            Inst::Jmp(target) => {
+                bytes.push(0xe9);
+                push_u32(resolve(target, symbols)?.wrapping_sub(next), bytes);
+            }
+            Inst::JmpMem(mem) => {
+                bytes.push(0xff);
+                encode_modrm(4, mem, symbols, bytes)?;
+            }
+            Inst::Jcc(cond, label) => {
+                bytes.push(0x0f);
+                bytes.push(0x80 | cond.code());
+                push_u32(
+                    resolve(&Imm::label(label), symbols)?.wrapping_sub(next),
+                    bytes,
+                );
+            }
+            Inst::Call(label) => {
+                bytes.push(0xe8);
+                push_u32(
+                    resolve(&Imm::label(label), symbols)?.wrapping_sub(next),
+                    bytes,
+                );
+            }
+            Inst::Ret => bytes.push(0xc3),
+            Inst::OutDxAl => bytes.push(0xee),
+        }
+        Ok(())
+    }
+}
+
+/// ModR/M バイト以降 (SIB バイトと 32 ビットの変位を含む) の長さ
+fn modrm_len(mem: &Memory) -> u32 {
+    match mem.base {
+        Some(Register::Esp) => 6,
+        _ => 5,
+    }
+}
+
+/// メモリオペランドを ModR/M バイト (と SIB バイト、 32 ビットの変位) に変換する
+fn encode_modrm(
+    reg: u8,
+    mem: &Memory,
+    symbols: &HashMap<String, u32>,
+    bytes: &mut Vec<u8>,
+) -> Result<(), String> {
+    match mem.base {
+        // mod = 00, r/m = 101: 絶対アドレス
+        None => bytes.push((reg << 3) | 0x05),
+        // mod = 10, r/m = 100: SIB バイトでベースレジスタ esp を指定する
+        Some(Register::Esp) => {
+            bytes.push(0x80 | (reg << 3) | 0x04);
+            bytes.push(0x24);
+        }
+        // mod = 10: ベースレジスタ + 32 ビットの変位
+        Some(base) => bytes.push(0x80 | (reg << 3) | base.code()),
+    }
+    push_u32(resolve(&mem.disp, symbols)?, bytes);
+    Ok(())
+}
+
+/// ``81 /n id`` の形式の命令 (レジスタと 32 ビットの即値の演算)
+fn encode_group81(ext: u8, reg: Register, imm: i32, bytes: &mut Vec<u8>) {
+    bytes.push(0x81);
+    bytes.push(0xc0 | (ext << 3) | reg.code());
+    push_u32(imm as u32, bytes);
+}
+
+fn push_u32(value: u32, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// 即値をラベルのアドレスを解決して数値にする
+fn resolve(imm: &Imm, symbols: &HashMap<String, u32>) -> Result<u32, String> {
+    let base = match &imm.label {
+        Some(label) => *symbols
+            .get(label)
+            .ok_or_else(|| format!("Undefined label `{}`", label))?,
+        None => 0,
+    };
+    Ok(base.wrapping_add(imm.offset as u32))
+}
+
+pub enum TextItem {
+    Label(String),
+    Inst(Inst),
+}
+
+/// データ領域に置くデータ
+pub enum Data {
+    /// バイト列
+    Bytes(Vec<u8>),
+    /// 0 で初期化された 4 バイト値の領域
+    Zeroed(u32),
+}
+
+/// アドレス 0 に読み込まれるフラットバイナリの内部表現
+#[derive(Default)]
+pub struct Program {
+    text: Vec<TextItem>,
+    data: Vec<(String, Data)>,
+}
+
+impl Program {
+    /// ラベルを置く ( ``.`` で始まるラベルは直前の ``.`` で始まらないラベルのローカルラベルになり、
+    /// 分岐命令の分岐先としてのみ参照できる)
+    pub fn label<N: Into<String>>(&mut self, name: N) {
+        self.text.push(TextItem::Label(name.into()));
+    }
+
+    pub fn inst(&mut self, inst: Inst) {
+        self.text.push(TextItem::Inst(inst));
+    }
+
+    pub fn data<N: Into<String>>(&mut self, name: N, data: Data) {
+        self.data.push((name.into(), data));
+    }
+
+    /// ローカルラベルを完全な名前にしたテキスト領域の要素
+    fn resolved_text(&self) -> Vec<TextItem> {
+        let mut scope = String::new();
+        let qualify = |scope: &str, name: &str| {
+            if name.starts_with('.') {
+                format!("{}{}", scope, name)
+            } else {
+                name.to_owned()
+            }
+        };
+        let qualify_imm = |scope: &str, imm: &Imm| Imm {
+            label: imm.label.as_ref().map(|label| qualify(scope, label)),
+            offset: imm.offset,
+        };
+
+        let mut result = Vec::new();
+        for item in self.text.iter() {
+            match item {
+                TextItem::Label(name) => {
+                    if !name.starts_with('.') {
+                        scope = name.clone();
+                    }
+                    result.push(TextItem::Label(qualify(&scope, name)));
+                }
+                TextItem::Inst(inst) => {
+                    let inst = match inst {
+                        Inst::Jmp(target) => Inst::Jmp(qualify_imm(&scope, target)),
+                        Inst::Jcc(cond, label) => Inst::Jcc(*cond, qualify(&scope, label)),
+                        Inst::Call(label) => Inst::Call(qualify(&scope, label)),
+                        _ => inst.clone(),
+                    };
+                    result.push(TextItem::Inst(inst));
+                }
+            }
+        }
+        result
+    }
+
+    /// NASM (``nasm -f bin``) で機械語に変換できるアセンブリを生成する
+    pub fn stringify(&self) -> String {
+        let mut result = String::from("bits 32\norg 0\n\n");
+        for item in self.text.iter() {
+            match item {
+                TextItem::Label(name) => result.push_str(format!("{}:\n", name).as_str()),
+                TextItem::Inst(inst) => {
+                    result.push_str(format!("    {}\n", inst.stringify()).as_str())
+                }
+            }
+        }
+        result.push('\n');
+        for (name, data) in self.data.iter() {
+            match data {
+                Data::Bytes(bytes) => {
+                    let values = bytes
+                        .iter()
+                        .map(|b| b.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    result.push_str(format!("{} db {}\n", name, values).as_str());
+                }
+                Data::Zeroed(count) => {
+                    result.push_str(format!("{} times {} dd 0\n", name, count).as_str());
+                }
+            }
+        }
+        result
+    }
+
+    /// ラベルを解決してフラットバイナリを生成する
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let text = self.resolved_text();
+
+        // 1 パス目: ラベルのアドレスを求める
+        let mut symbols = HashMap::<String, u32>::new();
+        let mut addr = 0;
+        for item in text.iter() {
+            match item {
+                TextItem::Label(name) => {
+                    if symbols.insert(name.clone(), addr).is_some() {
+                        return Err(format!("Label `{}` is defined more than once", name));
+                    }
+                }
+                TextItem::Inst(inst) => addr += inst.len(),
+            }
+        }
+        for (name, data) in self.data.iter() {
+            if symbols.insert(name.clone(), addr).is_some() {
+                return Err(format!("Label `{}` is defined more than once", name));
+            }
+            addr += match data {
+                Data::Bytes(bytes) => bytes.len() as u32,
+                Data::Zeroed(count) => count * 4,
+            };
+        }
+
+        // 2 パス目: 機械語に変換する
+        let mut bytes = Vec::new();
+        for item in text.iter() {
+            if let TextItem::Inst(inst) = item {
+                let addr = bytes.len() as u32;
+                inst.encode(addr, &symbols, &mut bytes)?;
+            }
+        }
+        for (_, data) in self.data.iter() {
+            match data {
+                Data::Bytes(data_bytes) => bytes.extend_from_slice(data_bytes),
+                Data::Zeroed(count) => bytes.resize(bytes.len() + *count as usize * 4, 0),
+            }
+        }
+        Ok(bytes)
+    }
+}
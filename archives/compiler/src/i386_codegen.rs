@@ -0,0 +1,542 @@
+use super::ast::{ResumeTarget, Type};
+use super::i386::{Cond, Data, Imm, Inst, Memory, Program, Register::*};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use std::convert::TryFrom;
+
+/// プログラムを読み込むアドレスの直後から伸びていくスタックの底 (``emulator`` の ``esp`` の初期値)
+const STACK_BOTTOM: u32 = 0x7c00;
+
+/// スタックのために確保しておく領域の大きさ
+const STACK_SIZE: u32 = 0x1000;
+
+/// 文字を出力するシリアルポート (COM1)
+const SERIAL_PORT: i32 = 0x03f8;
+
+const TYPE_STR: i32 = 1;
+const TYPE_INT: i32 = 2;
+
+const ERR_TYPE_MISMATCH: i32 = 13;
+const ERR_RESUME_WITHOUT_ERROR: i32 = 20;
+
+/// ランタイムが使用するため、手続きの名前として使えないシンボル
+static RUNTIME_SYMBOLS: [&str; 23] = [
+    "_start",
+    "program_exit",
+    "globals",
+    "err_handler",
+    "err_code",
+    "err_line",
+    "in_handler",
+    "cur_stmt",
+    "next_stmt",
+    "resume_addr",
+    "resume_next_addr",
+    "main_esp",
+    "err_prefix",
+    "err_sep",
+    "err_newline",
+    "msg_expected_str",
+    "msg_expected_int",
+    "msg_resume_without_error",
+    "msg_error_stmt",
+    "print_value",
+    "print_string",
+    "print_int",
+    "runtime_error",
+];
+
+/// 連番が付与されて生成されるシンボルのプレフィックス
+static NUMBERED_SYMBOL_PREFIXES: [&str; 5] = ["str", "rt_error", "rt_loc", "stmt", "user_label"];
+
+/// ランタイムエラーが発生しうる箇所
+struct ErrorSite {
+    /// エラーの原因となったソースコード上の位置
+    location: Location,
+    /// エラーメッセージのシンボル
+    message: &'static str,
+    /// エラー番号 ( ``None`` の場合は ecx に格納されている値をエラー番号とする)
+    code: Option<i32>,
+}
+
+/// 命令列の生成に用いる状態
+struct Context {
+    error_sites: Vec<ErrorSite>,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    traps_errors: bool,
+}
+
+/// 中間表現から、アドレス 0 に読み込まれて ``emulator`` で実行される i386 のフラットバイナリを生成する
+///
+/// 値は x86-64 のバックエンドと同様に (値, 型タグ) の組としてスタックに積み、整数は 32 ビットとして扱う。
+/// ``PRINT`` やランタイムエラーの出力は、 ``out dx, al`` でシリアルポートに 1 文字ずつ書き込む
+pub fn gen_i386(ir: &Ir) -> Result<Program, String> {
+    if let Some(ext) = ir.externs.first() {
+        return Err(format!(
+            "`{}`: external procedures are not supported for the `i386-flat` target",
+            ext.name
+        ));
+    }
+
+    for proc in ir.procs.iter() {
+        if RUNTIME_SYMBOLS.contains(&proc.name.as_str()) || is_numbered_symbol(&proc.name) {
+            return Err(format!(
+                "`{}` conflicts with a symbol used by the runtime",
+                proc.name
+            ));
+        }
+    }
+
+    let mut prog = Program::default();
+    let mut context = Context {
+        error_sites: Vec::new(),
+        traps_errors: ir
+            .insts
+            .iter()
+            .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_)))),
+    };
+
+    prog.label("_start");
+    // エラーハンドラに移るときに復元するスタックポインタ
+    prog.inst(Inst::MovMemReg(Memory::label("main_esp", 0), Esp));
+    prog.inst(Inst::XorRegReg(Ebp, Ebp));
+    gen_insts(ir, &ir.insts, &mut context, &mut prog)?;
+
+    // 最後の文の次の位置 (RESUME NEXT で移る先)
+    let num_stmts = ir
+        .insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+        .count();
+    prog.label(format!("stmt{}", num_stmts));
+
+    // emulator は eip が 0 になると実行を終える
+    prog.label("program_exit");
+    prog.inst(Inst::Jmp(Imm::int(0)));
+
+    for proc in ir.procs.iter() {
+        gen_proc(ir, proc, &mut context, &mut prog)?;
+    }
+
+    // ランタイムエラーの発生箇所ごとに、位置とメッセージを渡して runtime_error へ移る
+    for (i, site) in context.error_sites.iter().enumerate() {
+        prog.data(
+            format!("rt_loc{}", i),
+            Data::Bytes(c_string(&site.location.start.to_string())),
+        );
+        prog.label(format!("rt_error{}", i));
+        prog.inst(Inst::MovRegImm(Esi, Imm::label(format!("rt_loc{}", i))));
+        prog.inst(Inst::MovRegImm(Edi, Imm::label(site.message)));
+        if let Some(code) = site.code {
+            prog.inst(Inst::MovRegImm(Ecx, Imm::int(code)));
+        }
+        prog.inst(Inst::MovRegImm(
+            Edx,
+            Imm::int(site.location.start.line() + 1),
+        ));
+        prog.inst(Inst::Jmp(Imm::label("runtime_error")));
+    }
+
+    gen_runtime(&mut prog);
+
+    for (i, static_str) in ir.string_pool.iter().enumerate() {
+        prog.data(format!("str{}", i), Data::Bytes(c_string(static_str)));
+    }
+
+    // グローバル変数1つにつき、値と型タグの 8 バイトを確保する
+    if ir.num_globals > 0 {
+        prog.data("globals", Data::Zeroed(ir.num_globals as u32 * 2));
+    }
+
+    // ON ERROR GOTO によるエラー処理の状態
+    for name in [
+        "err_handler",
+        "err_code",
+        "err_line",
+        "in_handler",
+        "cur_stmt",
+        "next_stmt",
+        "resume_addr",
+        "resume_next_addr",
+        "main_esp",
+    ] {
+        prog.data(name, Data::Zeroed(1));
+    }
+
+    for (name, s) in [
+        ("err_prefix", "Runtime error at "),
+        ("err_sep", ": "),
+        ("err_newline", "\n"),
+        ("msg_expected_str", "type mismatch (expected STRING)"),
+        ("msg_expected_int", "type mismatch (expected INTEGER)"),
+        ("msg_resume_without_error", "RESUME without error"),
+        ("msg_error_stmt", "error raised by ERROR statement"),
+    ] {
+        prog.data(name, Data::Bytes(c_string(s)));
+    }
+
+    Ok(prog)
+}
+
+/// フラットバイナリに変換し、スタックと重ならないことを確認する
+pub fn encode_flat_binary(prog: &Program) -> Result<Vec<u8>, String> {
+    let image = prog.encode()?;
+    if image.len() as u32 > STACK_BOTTOM - STACK_SIZE {
+        return Err(format!(
+            "The program ({} bytes) is too large for the `i386-flat` target",
+            image.len()
+        ));
+    }
+    Ok(image)
+}
+
+/// BASIC で定義された手続きを生成する
+///
+/// 引数は (値, 型タグ) の組としてスタックに積んで渡し、呼び出し元が取り除く。戻り値は eax で返す
+fn gen_proc(ir: &Ir, proc: &Proc, context: &mut Context, prog: &mut Program) -> Result<(), String> {
+    prog.label(&proc.name);
+    prog.inst(Inst::Push(Ebp));
+    prog.inst(Inst::MovRegReg(Ebp, Esp));
+    if proc.num_locals > 0 {
+        prog.inst(Inst::SubRegImm(Esp, proc.num_locals * 8));
+    }
+
+    // スタックで渡された引数をローカル変数に格納する
+    let num_params = proc.params.len() as i32;
+    for (i, ty) in proc.params.iter().enumerate() {
+        let i = i as i32;
+        // 最初の引数が最も高いアドレスにある (ebp+4 は戻りアドレス)
+        let arg_value = Memory::base(Ebp, 8 + (num_params - 1 - i) * 8 + 4);
+        prog.inst(Inst::MovRegMem(Eax, arg_value));
+        prog.inst(Inst::MovMemReg(local_value(i), Eax));
+        prog.inst(Inst::MovMemImm(local_tag(i), Imm::int(type_tag(*ty))));
+    }
+
+    gen_insts(ir, &proc.insts, context, prog)?;
+
+    if let Some(ret_slot) = proc.ret_slot() {
+        prog.inst(Inst::MovRegMem(Eax, local_value(ret_slot)));
+    }
+
+    prog.inst(Inst::MovRegReg(Esp, Ebp));
+    prog.inst(Inst::Pop(Ebp));
+    prog.inst(Inst::Ret);
+    Ok(())
+}
+
+/// 命令列を生成する
+fn gen_insts(
+    ir: &Ir,
+    insts: &[IrInst],
+    context: &mut Context,
+    prog: &mut Program,
+) -> Result<(), String> {
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                prog.inst(Inst::PushImm(Imm::label(format!("str{}", index))));
+                prog.inst(Inst::PushImm(Imm::int(TYPE_STR)));
+            }
+            IrInst::GetImmInt(value) => {
+                let value = i32::try_from(*value).map_err(|_| {
+                    format!(
+                        "Integer `{}` is out of range for the `i386-flat` target",
+                        value
+                    )
+                })?;
+                prog.inst(Inst::PushImm(Imm::int(value)));
+                prog.inst(Inst::PushImm(Imm::int(TYPE_INT)));
+            }
+            IrInst::GetGlobal(index) => {
+                prog.inst(Inst::MovRegMem(Eax, Memory::label("globals", index * 8)));
+                prog.inst(Inst::Push(Eax));
+                prog.inst(Inst::MovRegMem(
+                    Eax,
+                    Memory::label("globals", index * 8 + 4),
+                ));
+                prog.inst(Inst::Push(Eax));
+            }
+            IrInst::SetGlobal(index) => {
+                prog.inst(Inst::Pop(Eax));
+                prog.inst(Inst::MovMemReg(
+                    Memory::label("globals", index * 8 + 4),
+                    Eax,
+                ));
+                prog.inst(Inst::Pop(Eax));
+                prog.inst(Inst::MovMemReg(Memory::label("globals", index * 8), Eax));
+            }
+            IrInst::GetLocal(index) => {
+                prog.inst(Inst::MovRegMem(Eax, local_value(*index)));
+                prog.inst(Inst::Push(Eax));
+                prog.inst(Inst::MovRegMem(Eax, local_tag(*index)));
+                prog.inst(Inst::Push(Eax));
+            }
+            IrInst::SetLocal(index) => {
+                prog.inst(Inst::Pop(Eax));
+                prog.inst(Inst::MovMemReg(local_tag(*index), Eax));
+                prog.inst(Inst::Pop(Eax));
+                prog.inst(Inst::MovMemReg(local_value(*index), Eax));
+            }
+            IrInst::AssertType(ty, location) => {
+                let message = match ty {
+                    Type::String => "msg_expected_str",
+                    Type::Integer => "msg_expected_int",
+                };
+                prog.inst(Inst::CmpMemImm(Memory::base(Esp, 0), type_tag(*ty)));
+                prog.inst(Inst::Jcc(
+                    Cond::Ne,
+                    format!("rt_error{}", context.error_sites.len()),
+                ));
+                context.error_sites.push(ErrorSite {
+                    location: *location,
+                    message,
+                    code: Some(ERR_TYPE_MISMATCH),
+                });
+            }
+            IrInst::CallExtern(_) => unreachable!("external procedures are rejected beforehand"),
+            IrInst::CallProc(index, _) => {
+                let proc = &ir.procs[*index as usize];
+                prog.inst(Inst::Call(proc.name.clone()));
+                if !proc.params.is_empty() {
+                    prog.inst(Inst::AddRegImm(Esp, proc.params.len() as i32 * 8));
+                }
+                if let Some(ret) = proc.ret {
+                    prog.inst(Inst::Push(Eax));
+                    prog.inst(Inst::PushImm(Imm::int(type_tag(ret))));
+                }
+            }
+            IrInst::Pop => {
+                prog.inst(Inst::AddRegImm(Esp, 8));
+            }
+            IrInst::Print => {
+                prog.inst(Inst::Pop(Ecx));
+                prog.inst(Inst::Pop(Eax));
+                prog.inst(Inst::Call("print_value".to_owned()));
+            }
+            IrInst::BeginStmt(index) => {
+                prog.label(format!("stmt{}", index));
+                if context.traps_errors {
+                    prog.inst(Inst::MovMemImm(
+                        Memory::label("cur_stmt", 0),
+                        Imm::label(format!("stmt{}", index)),
+                    ));
+                    prog.inst(Inst::MovMemImm(
+                        Memory::label("next_stmt", 0),
+                        Imm::label(format!("stmt{}", index + 1)),
+                    ));
+                }
+            }
+            IrInst::Label(index) => {
+                prog.label(format!("user_label{}", index));
+            }
+            IrInst::OnErrorGoto(handler) => {
+                let handler = match handler {
+                    Some(index) => Imm::label(format!("user_label{}", index)),
+                    None => Imm::int(0),
+                };
+                prog.inst(Inst::MovMemImm(Memory::label("err_handler", 0), handler));
+            }
+            IrInst::Resume(target, location) => {
+                prog.inst(Inst::CmpMemImm(Memory::label("in_handler", 0), 0));
+                prog.inst(Inst::Jcc(
+                    Cond::E,
+                    format!("rt_error{}", context.error_sites.len()),
+                ));
+                context.error_sites.push(ErrorSite {
+                    location: *location,
+                    message: "msg_resume_without_error",
+                    code: Some(ERR_RESUME_WITHOUT_ERROR),
+                });
+                for name in ["in_handler", "err_code", "err_line"] {
+                    prog.inst(Inst::MovMemImm(Memory::label(name, 0), Imm::int(0)));
+                }
+                prog.inst(Inst::JmpMem(Memory::label(
+                    match target {
+                        ResumeTarget::Retry => "resume_addr",
+                        ResumeTarget::Next => "resume_next_addr",
+                    },
+                    0,
+                )));
+            }
+            IrInst::RaiseError(location) => {
+                prog.inst(Inst::AddRegImm(Esp, 4));
+                prog.inst(Inst::Pop(Ecx));
+                prog.inst(Inst::Jmp(Imm::label(format!(
+                    "rt_error{}",
+                    context.error_sites.len()
+                ))));
+                context.error_sites.push(ErrorSite {
+                    location: *location,
+                    message: "msg_error_stmt",
+                    code: None,
+                });
+            }
+            IrInst::GetErrCode => {
+                prog.inst(Inst::MovRegMem(Eax, Memory::label("err_code", 0)));
+                prog.inst(Inst::Push(Eax));
+                prog.inst(Inst::PushImm(Imm::int(TYPE_INT)));
+            }
+            IrInst::GetErrLine => {
+                prog.inst(Inst::MovRegMem(Eax, Memory::label("err_line", 0)));
+                prog.inst(Inst::Push(Eax));
+                prog.inst(Inst::PushImm(Imm::int(TYPE_INT)));
+            }
+            IrInst::End => {
+                prog.inst(Inst::Jmp(Imm::label("program_exit")));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// ランタイムのサブルーチンを生成する
+fn gen_runtime(prog: &mut Program) {
+    // print_value (eax: 値, ecx: 型タグ)
+    prog.label("print_value");
+    prog.inst(Inst::CmpRegImm(Ecx, TYPE_STR));
+    prog.inst(Inst::Jcc(Cond::E, "print_string".to_owned()));
+    prog.inst(Inst::Jmp(Imm::label("print_int")));
+
+    // print_string (eax: NUL 終端文字列のアドレス)
+    prog.label("print_string");
+    prog.inst(Inst::MovRegReg(Esi, Eax));
+    prog.inst(Inst::MovRegImm(Edx, Imm::int(SERIAL_PORT)));
+    prog.label(".loop");
+    prog.inst(Inst::MovAlMem8(Memory::base(Esi, 0)));
+    prog.inst(Inst::CmpAlImm(0));
+    prog.inst(Inst::Jcc(Cond::E, ".end".to_owned()));
+    prog.inst(Inst::OutDxAl);
+    prog.inst(Inst::Inc(Esi));
+    prog.inst(Inst::Jmp(Imm::label(".loop")));
+    prog.label(".end");
+    prog.inst(Inst::Ret);
+
+    // print_int (eax: 整数) 下の桁から順にスタックに積み、上の桁から出力する
+    prog.label("print_int");
+    prog.inst(Inst::MovRegImm(Edx, Imm::int(SERIAL_PORT)));
+    prog.inst(Inst::CmpRegImm(Eax, 0));
+    prog.inst(Inst::Jcc(Cond::Ge, ".convert".to_owned()));
+    prog.inst(Inst::MovRegReg(Ebx, Eax));
+    prog.inst(Inst::MovRegImm(Eax, Imm::int('-' as i32)));
+    prog.inst(Inst::OutDxAl);
+    prog.inst(Inst::MovRegReg(Eax, Ebx));
+    prog.inst(Inst::Neg(Eax));
+    prog.label(".convert");
+    prog.inst(Inst::MovRegImm(Ebx, Imm::int(10)));
+    prog.inst(Inst::XorRegReg(Ecx, Ecx));
+    prog.label(".digit");
+    prog.inst(Inst::XorRegReg(Edx, Edx));
+    prog.inst(Inst::Div(Ebx));
+    prog.inst(Inst::AddRegImm(Edx, '0' as i32));
+    prog.inst(Inst::Push(Edx));
+    prog.inst(Inst::Inc(Ecx));
+    prog.inst(Inst::CmpRegImm(Eax, 0));
+    prog.inst(Inst::Jcc(Cond::Ne, ".digit".to_owned()));
+    prog.inst(Inst::MovRegImm(Edx, Imm::int(SERIAL_PORT)));
+    prog.label(".write");
+    prog.inst(Inst::Pop(Eax));
+    prog.inst(Inst::OutDxAl);
+    prog.inst(Inst::Dec(Ecx));
+    prog.inst(Inst::Jcc(Cond::Ne, ".write".to_owned()));
+    prog.inst(Inst::Ret);
+
+    // runtime_error (esi: 位置を表す文字列, edi: メッセージ, ecx: エラー番号, edx: 行番号)
+    prog.label("runtime_error");
+    // エラーハンドラが設定されていて、エラーハンドラの実行中でなければ、エラーハンドラに移る
+    prog.inst(Inst::CmpMemImm(Memory::label("err_handler", 0), 0));
+    prog.inst(Inst::Jcc(Cond::E, ".report".to_owned()));
+    prog.inst(Inst::CmpMemImm(Memory::label("in_handler", 0), 0));
+    prog.inst(Inst::Jcc(Cond::Ne, ".report".to_owned()));
+    prog.inst(Inst::MovMemReg(Memory::label("err_code", 0), Ecx));
+    prog.inst(Inst::MovMemReg(Memory::label("err_line", 0), Edx));
+    prog.inst(Inst::MovRegMem(Eax, Memory::label("cur_stmt", 0)));
+    prog.inst(Inst::MovMemReg(Memory::label("resume_addr", 0), Eax));
+    prog.inst(Inst::MovRegMem(Eax, Memory::label("next_stmt", 0)));
+    prog.inst(Inst::MovMemReg(Memory::label("resume_next_addr", 0), Eax));
+    prog.inst(Inst::MovMemImm(Memory::label("in_handler", 0), Imm::int(1)));
+    prog.inst(Inst::MovRegMem(Esp, Memory::label("main_esp", 0)));
+    prog.inst(Inst::XorRegReg(Ebp, Ebp));
+    prog.inst(Inst::JmpMem(Memory::label("err_handler", 0)));
+    prog.label(".report");
+    prog.inst(Inst::Push(Edi));
+    prog.inst(Inst::Push(Esi));
+    prog.inst(Inst::MovRegImm(Eax, Imm::label("err_prefix")));
+    prog.inst(Inst::Call("print_string".to_owned()));
+    prog.inst(Inst::Pop(Eax));
+    prog.inst(Inst::Call("print_string".to_owned()));
+    prog.inst(Inst::MovRegImm(Eax, Imm::label("err_sep")));
+    prog.inst(Inst::Call("print_string".to_owned()));
+    prog.inst(Inst::Pop(Eax));
+    prog.inst(Inst::Call("print_string".to_owned()));
+    prog.inst(Inst::MovRegImm(Eax, Imm::label("err_newline")));
+    prog.inst(Inst::Call("print_string".to_owned()));
+    prog.inst(Inst::Jmp(Imm::label("program_exit")));
+}
+
+/// 型に対応する型タグの値
+fn type_tag(ty: Type) -> i32 {
+    match ty {
+        Type::String => TYPE_STR,
+        Type::Integer => TYPE_INT,
+    }
+}
+
+/// 連番が付与されて生成されるシンボル ( ``str0`` など) かどうか
+fn is_numbered_symbol(name: &str) -> bool {
+    NUMBERED_SYMBOL_PREFIXES.iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+/// NUL 終端文字列のバイト列
+fn c_string(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// ローカル変数の値の格納場所
+fn local_value(index: i32) -> Memory {
+    Memory::base(Ebp, -(index + 1) * 8)
+}
+
+/// ローカル変数の型タグの格納場所
+fn local_tag(index: i32) -> Memory {
+    Memory::base(Ebp, -(index + 1) * 8 + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{sample_ir, sample_names};
+    use crate::interp::{interpret, Limits};
+    use emulator::{get_inst_table, Emulator};
+
+    /// 外部ライブラリを呼び出さないサンプルプログラムは、エミュレータ上でインタプリタと同じ出力になる
+    #[test]
+    fn samples_run_in_emulator() {
+        for name in sample_names() {
+            let ir = sample_ir(&name);
+            if !ir.externs.is_empty() {
+                continue;
+            }
+            let mut out = Vec::new();
+            let mut err = Vec::new();
+            interpret(&ir, Limits::default(), &mut out, &mut err).unwrap();
+            out.extend(err);
+
+            let image = encode_flat_binary(&gen_i386(&ir).unwrap()).unwrap();
+            let mut emu = Emulator::new(1024 * 1024, 0, STACK_BOTTOM);
+            emu.capture_serial_output();
+            emu.store_bytes(&image);
+            emu.run(&get_inst_table());
+            assert_eq!(
+                String::from_utf8_lossy(emu.serial_output()),
+                String::from_utf8_lossy(&out),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst};
+use super::location::Location;
+use std::{collections::HashMap, io::Write, mem};
+
+/// エラー番号 (ネイティブのランタイムと同じ値)
+const ERR_TYPE_MISMATCH: i64 = 13;
+const ERR_RESUME_WITHOUT_ERROR: i64 = 20;
+
+/// インタプリタの実行に課す制限 ( ``None`` は無制限)
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    /// 実行する中間表現の命令の最大数
+    pub max_steps: Option<u64>,
+    /// スタックとローカル変数に用いるメモリの最大バイト数
+    pub max_memory: Option<usize>,
+}
+
+/// 型タグ付きの値 (ネイティブのランタイムと同様に、値と型タグの組を表す)
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    /// 文字列プールの文字列
+    Str(i32),
+    Int(i64),
+    /// 代入される前のローカル変数の値 (型タグが 0 で、どの型の検査にも失敗する)
+    Empty,
+}
+
+impl Value {
+    fn has_type(&self, ty: Type) -> bool {
+        matches!(
+            (self, ty),
+            (Value::Str(_), Type::String) | (Value::Int(_), Type::Integer)
+        )
+    }
+}
+
+/// 手続きの呼び出しごとの状態
+struct Frame<'a> {
+    /// 呼び出された手続きの番号 (トップレベルは ``None``)
+    proc: Option<usize>,
+    insts: &'a [IrInst],
+    pc: usize,
+    locals: Vec<Value>,
+    /// 呼び出し箇所 (バックトレースに用いる)
+    call_location: Option<Location>,
+}
+
+/// ``ON ERROR GOTO`` によるエラー処理の状態
+#[derive(Default)]
+struct ErrorState {
+    handler: Option<i32>,
+    in_handler: bool,
+    code: i64,
+    line: i64,
+    /// 実行中のトップレベルの文の番号
+    cur_stmt: i32,
+    resume_stmt: i32,
+}
+
+struct Interpreter<'a, 'w> {
+    ir: &'a Ir,
+    limits: Limits,
+    out: &'w mut dyn Write,
+    err: &'w mut dyn Write,
+    globals: Vec<Value>,
+    frames: Vec<Frame<'a>>,
+    stack: Vec<Value>,
+    /// すべてのフレームのローカル変数の個数
+    num_locals: usize,
+    steps: u64,
+    error: ErrorState,
+    /// トップレベルの文の番号から、その先頭の命令の位置への対応 (最後の文の次はプログラムの終了)
+    stmt_positions: HashMap<i32, usize>,
+    /// ラベルの番号から、その命令の位置への対応
+    label_positions: HashMap<i32, usize>,
+}
+
+/// 中間表現を直接実行し、プログラムの終了コードを返す
+///
+/// 出力は ``out`` に、ランタイムエラーの報告は ``err`` にネイティブのランタイムと同じ形式で書き込む。
+/// 制限を超えた場合や外部ライブラリの手続きを呼び出そうとした場合は、実行を中断して ``Err`` を返す
+pub fn interpret(
+    ir: &Ir,
+    limits: Limits,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, String> {
+    let mut stmt_positions = HashMap::new();
+    let mut label_positions = HashMap::new();
+    for (pos, inst) in ir.insts.iter().enumerate() {
+        match inst {
+            IrInst::BeginStmt(index) => {
+                stmt_positions.insert(*index, pos);
+            }
+            IrInst::Label(index) => {
+                label_positions.insert(*index, pos);
+            }
+            _ => {}
+        }
+    }
+    stmt_positions.insert(stmt_positions.len() as i32, ir.insts.len());
+
+    let mut interpreter = Interpreter {
+        ir,
+        limits,
+        out,
+        err,
+        globals: Vec::new(),
+        frames: vec![Frame {
+            proc: None,
+            insts: &ir.insts,
+            pc: 0,
+            locals: Vec::new(),
+            call_location: None,
+        }],
+        stack: Vec::new(),
+        num_locals: 0,
+        steps: 0,
+        error: ErrorState::default(),
+        stmt_positions,
+        label_positions,
+    };
+    let code = interpreter
+        .check_memory(ir.num_globals as usize)
+        .and_then(|_| {
+            interpreter.globals = vec![Value::Empty; ir.num_globals as usize];
+            interpreter.run()
+        });
+    interpreter
+        .out
+        .flush()
+        .map_err(|e| format!("{}\nError occurs while writing output", e))?;
+    code
+}
+
+impl<'a> Interpreter<'a, '_> {
+    fn frame(&mut self) -> &mut Frame<'a> {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("IR stack underflow")
+    }
+
+    fn run(&mut self) -> Result<i32, String> {
+        loop {
+            let frame = self.frames.last().unwrap();
+            let inst = match frame.insts.get(frame.pc) {
+                Some(inst) => inst,
+                None => {
+                    if self.frames.len() == 1 {
+                        return Ok(0);
+                    }
+                    self.return_from_proc();
+                    continue;
+                }
+            };
+            self.frame().pc += 1;
+
+            self.steps += 1;
+            if let Some(max_steps) = self.limits.max_steps {
+                if self.steps > max_steps {
+                    return Err(format!("Step limit exceeded ({} steps)", max_steps));
+                }
+            }
+
+            if let Some(exit_code) = self.step(inst)? {
+                return Ok(exit_code);
+            }
+
+            self.check_memory(0)?;
+        }
+    }
+
+    /// さらに ``additional`` 個の値を確保しても、メモリの制限を超えないか検査する
+    fn check_memory(&self, additional: usize) -> Result<(), String> {
+        if let Some(max_memory) = self.limits.max_memory {
+            let num_values = self.globals.len() + self.stack.len() + self.num_locals;
+            let used = (num_values as u64 + additional as u64) * mem::size_of::<Value>() as u64
+                + (self.frames.len() * mem::size_of::<Frame>()) as u64;
+            if used > max_memory as u64 {
+                return Err(format!("Memory limit exceeded ({} bytes)", max_memory));
+            }
+        }
+        Ok(())
+    }
+
+    /// 1 命令を実行し、プログラムが終了した場合は終了コードを返す
+    fn step(&mut self, inst: &'a IrInst) -> Result<Option<i32>, String> {
+        match inst {
+            IrInst::GetStaticStr(index) => {
+                self.stack.push(Value::Str(*index));
+            }
+            IrInst::GetImmInt(value) => {
+                self.stack.push(Value::Int(*value));
+            }
+            IrInst::GetGlobal(index) => {
+                let value = self.globals[*index as usize];
+                self.stack.push(value);
+            }
+            IrInst::SetGlobal(index) => {
+                let value = self.pop();
+                self.globals[*index as usize] = value;
+            }
+            IrInst::GetLocal(index) => {
+                let value = self.frame().locals[*index as usize];
+                self.stack.push(value);
+            }
+            IrInst::SetLocal(index) => {
+                let value = self.pop();
+                self.frame().locals[*index as usize] = value;
+            }
+            IrInst::AssertType(ty, location) => {
+                if !self.stack.last().unwrap().has_type(*ty) {
+                    let message = match ty {
+                        Type::String => "type mismatch (expected STRING)",
+                        Type::Integer => "type mismatch (expected INTEGER)",
+                    };
+                    return self.runtime_error(ERR_TYPE_MISMATCH, *location, message);
+                }
+            }
+            IrInst::CallExtern(index) => {
+                return Err(format!(
+                    "External procedure `{}` cannot be called by the interpreter",
+                    self.ir.externs[*index as usize].name
+                ));
+            }
+            IrInst::CallProc(index, location) => {
+                let proc = &self.ir.procs[*index as usize];
+                self.check_memory(proc.num_locals as usize)?;
+                let mut locals = self.stack.split_off(self.stack.len() - proc.params.len());
+                locals.resize(proc.num_locals as usize, Value::Empty);
+                self.num_locals += locals.len();
+                self.frames.push(Frame {
+                    proc: Some(*index as usize),
+                    insts: &proc.insts,
+                    pc: 0,
+                    locals,
+                    call_location: Some(*location),
+                });
+            }
+            IrInst::Pop => {
+                self.pop();
+            }
+            IrInst::Print => {
+                let result = match self.pop() {
+                    Value::Str(index) => self
+                        .out
+                        .write_all(self.ir.string_pool[index as usize].as_bytes()),
+                    Value::Int(value) => write!(self.out, "{}", value),
+                    Value::Empty => write!(self.out, "0"),
+                };
+                result.map_err(|e| format!("{}\nError occurs while writing output", e))?;
+            }
+            IrInst::BeginStmt(index) => {
+                self.error.cur_stmt = *index;
+            }
+            IrInst::Label(_) => {}
+            IrInst::OnErrorGoto(label) => {
+                self.error.handler = *label;
+            }
+            IrInst::Resume(target, location) => {
+                if !self.error.in_handler {
+                    return self.runtime_error(
+                        ERR_RESUME_WITHOUT_ERROR,
+                        *location,
+                        "RESUME without error",
+                    );
+                }
+                self.error.in_handler = false;
+                self.error.code = 0;
+                self.error.line = 0;
+                let stmt = match target {
+                    ResumeTarget::Retry => self.error.resume_stmt,
+                    ResumeTarget::Next => self.error.resume_stmt + 1,
+                };
+                self.frame().pc = self.stmt_positions[&stmt];
+            }
+            IrInst::RaiseError(location) => {
+                let code = match self.pop() {
+                    Value::Int(value) => value,
+                    _ => 0,
+                };
+                return self.runtime_error(code, *location, "error raised by ERROR statement");
+            }
+            IrInst::GetErrCode => {
+                self.stack.push(Value::Int(self.error.code));
+            }
+            IrInst::GetErrLine => {
+                self.stack.push(Value::Int(self.error.line));
+            }
+            IrInst::End => return Ok(Some(0)),
+        }
+        Ok(None)
+    }
+
+    /// 手続きから戻り、戻り値があればスタックに積む
+    fn return_from_proc(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        self.num_locals -= frame.locals.len();
+        let proc = &self.ir.procs[frame.proc.unwrap()];
+        if let (Some(ty), Some(slot)) = (proc.ret, proc.ret_slot()) {
+            // 戻り値は型タグを除いて受け渡され、呼び出し側で型タグが付けられる
+            let value = match (frame.locals[slot as usize], ty) {
+                (Value::Empty, Type::Integer) => Value::Int(0),
+                (value, _) => value,
+            };
+            self.stack.push(value);
+        }
+    }
+
+    /// ランタイムエラーを発生させる
+    ///
+    /// エラーハンドラが設定されていて、エラーハンドラの実行中でなければ、トップレベルまで巻き戻してエラーハンドラに移る。
+    /// そうでなければエラーとバックトレースを報告して、終了コード 1 で終了する
+    fn runtime_error(
+        &mut self,
+        code: i64,
+        location: Location,
+        message: &str,
+    ) -> Result<Option<i32>, String> {
+        if let (Some(handler), false) = (self.error.handler, self.error.in_handler) {
+            self.error.code = code;
+            self.error.line = location.start.line() as i64 + 1;
+            self.error.resume_stmt = self.error.cur_stmt;
+            self.error.in_handler = true;
+            for frame in self.frames.drain(1..) {
+                self.num_locals -= frame.locals.len();
+            }
+            self.stack.clear();
+            self.frames[0].pc = self.label_positions[&handler];
+            return Ok(None);
+        }
+
+        self.out
+            .flush()
+            .map_err(|e| format!("{}\nError occurs while writing output", e))?;
+        let mut report = format!("Runtime error at {}: {}\n", location.start, message);
+        // エラーが発生したフレームと、各呼び出し箇所
+        let mut frame_location = location;
+        for frame in self.frames.iter().rev() {
+            let name = frame
+                .proc
+                .map_or("<main>", |index| self.ir.procs[index].name.as_str());
+            report.push_str(&format!("    at {} ({})\n", name, frame_location.start));
+            if let Some(call_location) = frame.call_location {
+                frame_location = call_location;
+            }
+        }
+        self.err
+            .write_all(report.as_bytes())
+            .map_err(|e| format!("{}\nError occurs while reporting runtime error", e))?;
+        Ok(Some(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{frontend, run_x64, TempDir};
+
+    /// 終了コードと標準出力、標準エラー出力を返す
+    fn run(src: &str, limits: Limits) -> Result<(i32, String, String), String> {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let code = interpret(&frontend(src), limits, &mut out, &mut err)?;
+        Ok((
+            code,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        ))
+    }
+
+    /// インタプリタとネイティブのランタイムで、出力と終了コードが一致するか
+    fn assert_same_as_x64(name: &str, src: &str) {
+        let actual = run(src, Limits::default()).unwrap();
+        if let Some(expected) = run_x64(frontend(src), name, &TempDir::new(name)) {
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn max_steps() {
+        // エラーハンドラから失敗した文を再実行し続ける
+        let src = "ON ERROR GOTO H
+ERROR 1
+END
+H:
+RESUME
+";
+        let limits = Limits {
+            max_steps: Some(1000),
+            max_memory: None,
+        };
+        assert_eq!(
+            run(src, limits),
+            Err("Step limit exceeded (1000 steps)".to_owned())
+        );
+        assert!(run("PRINT 1\n", limits).is_ok());
+    }
+
+    #[test]
+    fn max_memory() {
+        let src = "SUB F (n AS INTEGER)
+  F n
+END SUB
+F 1
+";
+        let limits = Limits {
+            max_steps: None,
+            max_memory: Some(64 * 1024),
+        };
+        assert_eq!(
+            run(src, limits),
+            Err("Memory limit exceeded (65536 bytes)".to_owned())
+        );
+        assert!(run("PRINT 1\n", limits).is_ok());
+    }
+
+    #[test]
+    fn type_error_matches_native_runtime() {
+        let src = "SUB P (n AS INTEGER)
+  PRINT n
+END SUB
+FUNCTION Id (s AS STRING) AS STRING
+  P s
+  Id = s
+END FUNCTION
+PRINT \"before\"
+PRINT Id(\"x\")
+";
+        let (code, out, err) = run(src, Limits::default()).unwrap();
+        assert_eq!((code, out.as_str()), (1, "before"));
+        assert!(err.contains("type mismatch (expected INTEGER)"), "{}", err);
+        assert_same_as_x64("interp-type-error", src);
+    }
+
+    #[test]
+    fn on_error_and_resume() {
+        // RESUME は失敗した文を再実行し、 RESUME NEXT は次の文から再開する
+        let src = "SUB P (n AS INTEGER)
+  PRINT n
+END SUB
+VAR A = \"s\"
+ON ERROR GOTO H
+P A
+ERROR 5
+PRINT \"done\"
+END
+H:
+PRINT ERR
+PRINT ERL
+A = 7
+ON ERROR GOTO H2
+RESUME
+H2:
+PRINT ERR
+RESUME NEXT
+";
+        assert_eq!(
+            run(src, Limits::default()),
+            Ok((0, "13675done".to_owned(), String::new()))
+        );
+        assert_same_as_x64("interp-on-error", src);
+    }
+
+    #[test]
+    fn resume_without_error() {
+        let (code, out, err) = run("PRINT 1\nRESUME NEXT\n", Limits::default()).unwrap();
+        assert_eq!((code, out.as_str()), (1, "1"));
+        assert!(err.contains("RESUME without error"), "{}", err);
+        assert_same_as_x64("interp-resume", "PRINT 1\nRESUME NEXT\n");
+    }
+}
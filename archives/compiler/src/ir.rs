@@ -1,3 +1,7 @@
+use super::ast::{ResumeTarget, Type};
+use super::location::Location;
+use std::collections::HashSet;
+
 /// アーキテクチャに依存しない中間表現
 #[derive(Debug, Default)]
 pub struct Ir {
@@ -5,19 +9,252 @@ pub struct Ir {
     pub num_globals: i32,
     /// 文字列プール
     pub string_pool: Vec<String>,
+    /// 外部ライブラリの手続き
+    pub externs: Vec<ExternProc>,
+    /// BASIC で定義された手続き
+    pub procs: Vec<Proc>,
+    /// 命令列
+    pub insts: Vec<IrInst>,
+}
+
+impl Ir {
+    /// リンクする必要のあるライブラリ名の一覧を重複なく取得する
+    pub fn linked_libs(&self) -> Vec<String> {
+        let mut libs = Vec::<String>::new();
+        for ext in self.externs.iter() {
+            if !libs.contains(&ext.lib) {
+                libs.push(ext.lib.clone());
+            }
+        }
+        libs
+    }
+}
+
+/// 外部ライブラリの手続きのシグネチャ
+#[derive(Debug)]
+pub struct ExternProc {
+    /// シンボル名
+    pub name: String,
+    /// ライブラリ名 ( ``-l`` に渡す名前)
+    pub lib: String,
+    /// 引数の型
+    pub params: Vec<Type>,
+    /// 戻り値の型
+    pub ret: Option<Type>,
+}
+
+/// BASIC で定義された手続き
+#[derive(Debug)]
+pub struct Proc {
+    /// シンボル名
+    pub name: String,
+    /// 仮引数の名前
+    pub param_names: Vec<String>,
+    /// 引数の型 (ローカル変数の先頭から順に割り当てられる)
+    pub params: Vec<Type>,
+    /// 戻り値の型 (戻り値は仮引数の直後のローカル変数に格納される)
+    pub ret: Option<Type>,
+    /// ローカル変数 (仮引数と戻り値を含む) の個数
+    pub num_locals: i32,
     /// 命令列
     pub insts: Vec<IrInst>,
 }
 
+impl Proc {
+    /// 戻り値を格納するローカル変数の番号
+    pub fn ret_slot(&self) -> Option<i32> {
+        self.ret.map(|_| self.params.len() as i32)
+    }
+}
+
 /// 中間表現で用いられる命令
 #[derive(Debug)]
 pub enum IrInst {
     /// 文字列プールから指定した文字列のアドレスを取得する
     GetStaticStr(i32),
+    /// 整数の即値をスタックに積む
+    GetImmInt(i64),
     /// 指定したグローバル変数の値を取得してスタックに積む
     GetGlobal(i32),
     /// 指定したグローバル変数に、スタックからポップした値を代入する
     SetGlobal(i32),
+    /// 指定したローカル変数の値を取得してスタックに積む
+    GetLocal(i32),
+    /// 指定したローカル変数に、スタックからポップした値を代入する
+    SetLocal(i32),
+    /// スタックの先頭の値が指定した型であることを検査する
+    /// (失敗した場合は、指定した位置を含むランタイムエラーを報告する)
+    AssertType(Type, Location),
+    /// 引数をスタックからポップして外部ライブラリの手続きを呼び出し、戻り値があればスタックに積む
+    CallExtern(i32),
+    /// 引数をスタックからポップして BASIC で定義された手続きを呼び出し、戻り値があればスタックに積む
+    /// (呼び出し箇所の位置はバックトレースに用いられる)
+    CallProc(i32, Location),
+    /// スタックからポップした値を捨てる
+    Pop,
     /// スタックからポップした値を出力する
     Print,
+    /// トップレベルの文の開始位置 ( ``RESUME`` で実行を再開する位置になる)
+    BeginStmt(i32),
+    /// ラベル
+    Label(i32),
+    /// エラーハンドラとして指定したラベルを設定する ( ``None`` の場合は解除する)
+    OnErrorGoto(Option<i32>),
+    /// エラーハンドラから実行を再開する
+    Resume(ResumeTarget, Location),
+    /// スタックからポップした整数をエラー番号として、ランタイムエラーを発生させる
+    RaiseError(Location),
+    /// 直前に発生したエラーのエラー番号をスタックに積む
+    GetErrCode,
+    /// 直前に発生したエラーの行番号をスタックに積む
+    GetErrLine,
+    /// プログラムを終了する
+    End,
+}
+
+/// グローバル変数の個数と、1 つの手続きのローカル変数の個数の上限
+pub const MAX_VARIABLES: i32 = 1 << 16;
+
+/// 中間表現が実行できる形をしているか検査する
+///
+/// - 変数の個数が 0 以上 ``MAX_VARIABLES`` 以下である
+/// - 参照する文字列・変数・手続き・ラベルが存在する
+/// - トップレベルの文は 0 から順に番号が振られ、ラベルは 1 度だけ定義される
+/// - 手続きの命令列は直線的 (文の区切り・ラベル・ ``RESUME`` ・ ``END`` を含まない)
+/// - スタックが不足せず、文の境界と命令列の末尾でスタックが空になる
+pub fn validate(ir: &Ir) -> Result<(), String> {
+    if !(0..=MAX_VARIABLES).contains(&ir.num_globals) {
+        return Err(format!(
+            "global count {} is out of range (0 to {})",
+            ir.num_globals, MAX_VARIABLES
+        ));
+    }
+
+    for (i, proc) in ir.procs.iter().enumerate() {
+        if !(0..=MAX_VARIABLES).contains(&proc.num_locals) {
+            return Err(format!(
+                "local count {} of procedure #{} (`{}`) is out of range (0 to {})",
+                proc.num_locals, i, proc.name, MAX_VARIABLES
+            ));
+        }
+        let min_locals = proc.params.len() + proc.ret.map_or(0, |_| 1);
+        if (proc.num_locals as usize) < min_locals {
+            return Err(format!(
+                "procedure #{} (`{}`) has fewer locals than its parameters and return value",
+                i, proc.name
+            ));
+        }
+    }
+
+    let mut labels = HashSet::new();
+    let mut num_stmts = 0;
+    for inst in ir.insts.iter() {
+        match inst {
+            IrInst::BeginStmt(index) => {
+                if *index != num_stmts {
+                    return Err(format!(
+                        "statement #{} appears where #{} is expected",
+                        index, num_stmts
+                    ));
+                }
+                num_stmts += 1;
+            }
+            IrInst::Label(index) if !labels.insert(*index) => {
+                return Err(format!("label #{} is defined more than once", index));
+            }
+            _ => {}
+        }
+    }
+
+    validate_insts(ir, &ir.insts, None, &labels)
+        .map_err(|e| format!("{} in the main program", e))?;
+    for (i, proc) in ir.procs.iter().enumerate() {
+        validate_insts(ir, &proc.insts, Some(proc), &labels)
+            .map_err(|e| format!("{} in procedure #{} (`{}`)", e, i, proc.name))?;
+    }
+    Ok(())
+}
+
+fn validate_insts(
+    ir: &Ir,
+    insts: &[IrInst],
+    proc: Option<&Proc>,
+    labels: &HashSet<i32>,
+) -> Result<(), String> {
+    let check = |what: &str, index: i32, len: usize| {
+        if index < 0 || index as usize >= len {
+            Err(format!("{} #{} does not exist", what, index))
+        } else {
+            Ok(())
+        }
+    };
+    let num_locals = proc.map_or(0, |proc| proc.num_locals as usize);
+    let mut depth = 0usize;
+
+    for (pos, inst) in insts.iter().enumerate() {
+        let (pops, pushes) = match inst {
+            IrInst::GetStaticStr(index) => {
+                check("string", *index, ir.string_pool.len())?;
+                (0, 1)
+            }
+            IrInst::GetImmInt(_) | IrInst::GetErrCode | IrInst::GetErrLine => (0, 1),
+            IrInst::GetGlobal(index) => {
+                check("global variable", *index, ir.num_globals as usize)?;
+                (0, 1)
+            }
+            IrInst::SetGlobal(index) => {
+                check("global variable", *index, ir.num_globals as usize)?;
+                (1, 0)
+            }
+            IrInst::GetLocal(index) => {
+                check("local variable", *index, num_locals)?;
+                (0, 1)
+            }
+            IrInst::SetLocal(index) => {
+                check("local variable", *index, num_locals)?;
+                (1, 0)
+            }
+            IrInst::AssertType(_, _) => (1, 1),
+            IrInst::CallExtern(index) => {
+                check("external procedure", *index, ir.externs.len())?;
+                let ext = &ir.externs[*index as usize];
+                (ext.params.len(), ext.ret.map_or(0, |_| 1))
+            }
+            IrInst::CallProc(index, _) => {
+                check("procedure", *index, ir.procs.len())?;
+                let callee = &ir.procs[*index as usize];
+                (callee.params.len(), callee.ret.map_or(0, |_| 1))
+            }
+            IrInst::Pop | IrInst::Print | IrInst::RaiseError(_) => (1, 0),
+            IrInst::OnErrorGoto(label) => {
+                if let Some(label) = label {
+                    if !labels.contains(label) {
+                        return Err(format!("label #{} does not exist", label));
+                    }
+                }
+                (0, 0)
+            }
+            IrInst::BeginStmt(_) | IrInst::Label(_) | IrInst::Resume(_, _) | IrInst::End => {
+                if proc.is_some() {
+                    return Err(format!(
+                        "instruction {} may only appear in the main program",
+                        pos
+                    ));
+                }
+                if depth != 0 {
+                    return Err(format!("stack is not empty at instruction {}", pos));
+                }
+                (0, 0)
+            }
+        };
+        if depth < pops {
+            return Err(format!("stack underflow at instruction {}", pos));
+        }
+        depth = depth - pops + pushes;
+    }
+
+    if depth != 0 {
+        return Err("stack is not empty at the end".to_owned());
+    }
+    Ok(())
 }
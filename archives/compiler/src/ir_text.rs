@@ -0,0 +1,701 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{validate, ExternProc, Ir, IrInst, Proc};
+use super::location::{Location, Point};
+use std::fmt::Write;
+
+/// 中間表現をテキスト形式 ( ``.bir`` ) で出力する
+///
+/// ```text
+/// globals 1
+/// string 0 "Hello"
+/// extern 0 puts "c" (string)
+///
+/// proc 0 Twice(s: string) -> string locals 2 {
+///     get_local 0
+///     set_local 1
+/// }
+///
+/// main {
+///     begin_stmt 0
+///     get_static_str 0
+///     print
+/// }
+/// ```
+///
+/// ``;`` から行末まではコメントで、位置は ``@行:列-行:列`` (1 始まり) で表す
+pub fn print_ir(ir: &Ir) -> String {
+    let mut result = String::new();
+    writeln!(result, "globals {}", ir.num_globals).unwrap();
+    for (i, s) in ir.string_pool.iter().enumerate() {
+        writeln!(result, "string {} {}", i, quote(s)).unwrap();
+    }
+    for (i, ext) in ir.externs.iter().enumerate() {
+        let params: Vec<&str> = ext.params.iter().map(|ty| type_name(*ty)).collect();
+        write!(
+            result,
+            "extern {} {} {} ({})",
+            i,
+            ext.name,
+            quote(&ext.lib),
+            params.join(", ")
+        )
+        .unwrap();
+        if let Some(ty) = ext.ret {
+            write!(result, " -> {}", type_name(ty)).unwrap();
+        }
+        result.push('\n');
+    }
+
+    for (i, proc) in ir.procs.iter().enumerate() {
+        let params: Vec<String> = proc
+            .param_names
+            .iter()
+            .zip(proc.params.iter())
+            .map(|(name, ty)| format!("{}: {}", name, type_name(*ty)))
+            .collect();
+        write!(result, "\nproc {} {}({})", i, proc.name, params.join(", ")).unwrap();
+        if let Some(ty) = proc.ret {
+            write!(result, " -> {}", type_name(ty)).unwrap();
+        }
+        writeln!(result, " locals {} {{", proc.num_locals).unwrap();
+        print_insts(&proc.insts, &mut result);
+        result.push_str("}\n");
+    }
+
+    result.push_str("\nmain {\n");
+    print_insts(&ir.insts, &mut result);
+    result.push_str("}\n");
+    result
+}
+
+fn print_insts(insts: &[IrInst], result: &mut String) {
+    for inst in insts.iter() {
+        let line = match inst {
+            IrInst::GetStaticStr(index) => format!("get_static_str {}", index),
+            IrInst::GetImmInt(value) => format!("get_imm_int {}", value),
+            IrInst::GetGlobal(index) => format!("get_global {}", index),
+            IrInst::SetGlobal(index) => format!("set_global {}", index),
+            IrInst::GetLocal(index) => format!("get_local {}", index),
+            IrInst::SetLocal(index) => format!("set_local {}", index),
+            IrInst::AssertType(ty, location) => {
+                format!("assert_type {} @{}", type_name(*ty), location)
+            }
+            IrInst::CallExtern(index) => format!("call_extern {}", index),
+            IrInst::CallProc(index, location) => format!("call_proc {} @{}", index, location),
+            IrInst::Pop => "pop".to_owned(),
+            IrInst::Print => "print".to_owned(),
+            IrInst::BeginStmt(index) => format!("begin_stmt {}", index),
+            IrInst::Label(index) => format!("label {}", index),
+            IrInst::OnErrorGoto(Some(index)) => format!("on_error_goto {}", index),
+            IrInst::OnErrorGoto(None) => "on_error_goto none".to_owned(),
+            IrInst::Resume(ResumeTarget::Retry, location) => format!("resume @{}", location),
+            IrInst::Resume(ResumeTarget::Next, location) => format!("resume_next @{}", location),
+            IrInst::RaiseError(location) => format!("raise_error @{}", location),
+            IrInst::GetErrCode => "get_err_code".to_owned(),
+            IrInst::GetErrLine => "get_err_line".to_owned(),
+            IrInst::End => "end".to_owned(),
+        };
+        writeln!(result, "    {}", line).unwrap();
+    }
+}
+
+fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::String => "string",
+        Type::Integer => "integer",
+    }
+}
+
+/// 文字列をエスケープして ``"`` で囲む (制御文字は ``\u{..}`` で表す)
+fn quote(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => write!(result, "\\u{{{:x}}}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// テキスト形式の中間表現の字句
+#[derive(Clone, Debug)]
+enum Tok {
+    /// 識別子・キーワード・整数
+    Word(String),
+    Str(String),
+    /// ``@`` で始まる位置
+    Loc(Location),
+    /// ``(`` ``)`` ``,`` ``:`` ``{`` ``}``
+    Punct(char),
+    Arrow,
+}
+
+/// 1 行を字句に分割する
+fn tokenize_line(line: &str) -> Result<Vec<Tok>, String> {
+    let mut toks = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' | ':' | '{' | '}' => {
+                chars.next();
+                toks.push(Tok::Punct(c));
+            }
+            '-' if line[i..].starts_with("->") => {
+                chars.next();
+                chars.next();
+                toks.push(Tok::Arrow);
+            }
+            '"' => {
+                chars.next();
+                toks.push(Tok::Str(unquote(&mut chars.by_ref().map(|(_, c)| c))?));
+            }
+            '@' => {
+                chars.next();
+                let mut s = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                toks.push(Tok::Loc(parse_location(&s)?));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '-' => {
+                let mut s = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '-') {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                toks.push(Tok::Word(s));
+            }
+            c => return Err(format!("unexpected character `{}`", c)),
+        }
+    }
+    Ok(toks)
+}
+
+/// ``"`` の直後から文字列を読み込み、エスケープを解除する
+fn unquote<I: Iterator<Item = char>>(chars: &mut I) -> Result<String, String> {
+    let mut result = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_owned()),
+            Some('"') => return Ok(result),
+            Some('\\') => match chars.next() {
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('u') => {
+                    if chars.next() != Some('{') {
+                        return Err("expected `{` after `\\u`".to_owned());
+                    }
+                    let mut hex = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => hex.push(c),
+                            None => return Err("unterminated string".to_owned()),
+                        }
+                    }
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or(format!("invalid escape `\\u{{{}}}`", hex))?;
+                    result.push(c);
+                }
+                Some(c) => return Err(format!("unknown escape `\\{}`", c)),
+                None => return Err("unterminated string".to_owned()),
+            },
+            Some(c) => result.push(c),
+        }
+    }
+}
+
+/// ``行:列-行:列`` (1 始まり) を位置に変換する
+fn parse_location(s: &str) -> Result<Location, String> {
+    let point = |s: &str| -> Option<Point> {
+        let (line, column) = s.split_once(':')?;
+        let line = line.parse::<i32>().ok().filter(|n| *n >= 1)?;
+        let column = column.parse::<i32>().ok().filter(|n| *n >= 1)?;
+        Some(Point::new(line - 1, column - 1))
+    };
+    s.split_once('-')
+        .and_then(|(start, end)| {
+            Some(Location {
+                start: point(start)?,
+                end: point(end)?,
+            })
+        })
+        .ok_or(format!("invalid location `@{}`", s))
+}
+
+/// 1 行分の字句を先頭から読み進める
+struct Line {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl Line {
+    fn next(&mut self, expected: &str) -> Result<Tok, String> {
+        let tok = self
+            .toks
+            .get(self.pos)
+            .cloned()
+            .ok_or(format!("expected {}", expected))?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+
+    fn word(&mut self, expected: &str) -> Result<String, String> {
+        match self.next(expected)? {
+            Tok::Word(word) => Ok(word),
+            tok => Err(format!("expected {}, found {}", expected, describe(&tok))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.next(&format!("`{}`", keyword))? {
+            Tok::Word(word) if word == keyword => Ok(()),
+            tok => Err(format!("expected `{}`, found {}", keyword, describe(&tok))),
+        }
+    }
+
+    fn punct(&mut self, c: char) -> Result<(), String> {
+        match self.next(&format!("`{}`", c))? {
+            Tok::Punct(p) if p == c => Ok(()),
+            tok => Err(format!("expected `{}`, found {}", c, describe(&tok))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        match self.next("a string")? {
+            Tok::Str(s) => Ok(s),
+            tok => Err(format!("expected a string, found {}", describe(&tok))),
+        }
+    }
+
+    fn location(&mut self) -> Result<Location, String> {
+        match self.next("a location")? {
+            Tok::Loc(location) => Ok(location),
+            tok => Err(format!("expected a location, found {}", describe(&tok))),
+        }
+    }
+
+    fn int<T: std::str::FromStr>(&mut self, expected: &str) -> Result<T, String> {
+        let word = self.word(expected)?;
+        word.parse()
+            .map_err(|_| format!("expected {}, found `{}`", expected, word))
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        match self.word("a type")?.as_str() {
+            "string" => Ok(Type::String),
+            "integer" => Ok(Type::Integer),
+            word => Err(format!("unknown type `{}`", word)),
+        }
+    }
+
+    /// ``-> 型`` があれば読み込む
+    fn ret_type(&mut self) -> Result<Option<Type>, String> {
+        if matches!(self.peek(), Some(Tok::Arrow)) {
+            self.pos += 1;
+            Ok(Some(self.ty()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn end(&self) -> Result<(), String> {
+        match self.peek() {
+            Some(tok) => Err(format!("unexpected {}", describe(tok))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn describe(tok: &Tok) -> String {
+    match tok {
+        Tok::Word(word) => format!("`{}`", word),
+        Tok::Str(s) => format!("string {}", quote(s)),
+        Tok::Loc(location) => format!("location `@{}`", location),
+        Tok::Punct(c) => format!("`{}`", c),
+        Tok::Arrow => "`->`".to_owned(),
+    }
+}
+
+/// 命令列のブロックの種類
+enum Block {
+    Proc(Proc),
+    Main,
+}
+
+/// テキスト形式 ( ``.bir`` ) の中間表現を読み込み、検査した上で中間表現に変換する
+pub fn parse_ir(src: &str) -> Result<Ir, String> {
+    let mut ir = Ir::default();
+    let mut has_main = false;
+    let mut block: Option<Block> = None;
+    let mut insts = Vec::new();
+
+    for (i, text) in src.lines().enumerate() {
+        let error = |msg: String| format!("Invalid IR at line {}: {}", i + 1, msg);
+        let toks = tokenize_line(text).map_err(error)?;
+        if toks.is_empty() {
+            continue;
+        }
+        let mut line = Line { toks, pos: 0 };
+
+        if block.is_some() {
+            if matches!(line.peek(), Some(Tok::Punct('}'))) {
+                line.pos += 1;
+                line.end().map_err(error)?;
+                match block.take().unwrap() {
+                    Block::Proc(mut proc) => {
+                        proc.insts = std::mem::take(&mut insts);
+                        ir.procs.push(proc);
+                    }
+                    Block::Main => ir.insts = std::mem::take(&mut insts),
+                }
+            } else {
+                insts.push(parse_inst(&mut line).map_err(error)?);
+            }
+            continue;
+        }
+
+        let keyword = line.word("a declaration").map_err(error)?;
+        match keyword.as_str() {
+            "globals" => {
+                ir.num_globals = line.int("the number of globals").map_err(error)?;
+            }
+            "string" => {
+                expect_index(&mut line, ir.string_pool.len()).map_err(error)?;
+                ir.string_pool.push(line.string().map_err(error)?);
+            }
+            "extern" => {
+                expect_index(&mut line, ir.externs.len()).map_err(error)?;
+                let ext = parse_extern(&mut line).map_err(error)?;
+                ir.externs.push(ext);
+            }
+            "proc" => {
+                expect_index(&mut line, ir.procs.len()).map_err(error)?;
+                let proc = parse_proc_header(&mut line).map_err(error)?;
+                block = Some(Block::Proc(proc));
+            }
+            "main" => {
+                if has_main {
+                    return Err(error("`main` is defined more than once".to_owned()));
+                }
+                line.punct('{').map_err(error)?;
+                has_main = true;
+                block = Some(Block::Main);
+            }
+            _ => return Err(error(format!("unknown declaration `{}`", keyword))),
+        }
+        line.end().map_err(error)?;
+    }
+
+    if block.is_some() {
+        return Err("Invalid IR: unexpected end of file (missing `}`)".to_owned());
+    }
+    if !has_main {
+        return Err("Invalid IR: `main` is not defined".to_owned());
+    }
+    validate(&ir).map_err(|e| format!("Invalid IR: {}", e))?;
+    Ok(ir)
+}
+
+/// 文字列・外部ライブラリの手続き・手続きの番号が、定義順に振られていることを検査する
+fn expect_index(line: &mut Line, expected: usize) -> Result<(), String> {
+    let index: usize = line.int("an index")?;
+    if index != expected {
+        return Err(format!("expected index {}, found {}", expected, index));
+    }
+    Ok(())
+}
+
+/// ``名前 "ライブラリ名" (型, ...) -> 型``
+fn parse_extern(line: &mut Line) -> Result<ExternProc, String> {
+    let name = line.word("a procedure name")?;
+    let lib = line.string()?;
+    line.punct('(')?;
+    let mut params = Vec::new();
+    if !matches!(line.peek(), Some(Tok::Punct(')'))) {
+        loop {
+            params.push(line.ty()?);
+            if !matches!(line.peek(), Some(Tok::Punct(','))) {
+                break;
+            }
+            line.pos += 1;
+        }
+    }
+    line.punct(')')?;
+    let ret = line.ret_type()?;
+    Ok(ExternProc {
+        name,
+        lib,
+        params,
+        ret,
+    })
+}
+
+/// ``名前(引数: 型, ...) -> 型 locals 個数 {``
+fn parse_proc_header(line: &mut Line) -> Result<Proc, String> {
+    let name = line.word("a procedure name")?;
+    line.punct('(')?;
+    let mut param_names = Vec::new();
+    let mut params = Vec::new();
+    if !matches!(line.peek(), Some(Tok::Punct(')'))) {
+        loop {
+            param_names.push(line.word("a parameter name")?);
+            line.punct(':')?;
+            params.push(line.ty()?);
+            if !matches!(line.peek(), Some(Tok::Punct(','))) {
+                break;
+            }
+            line.pos += 1;
+        }
+    }
+    line.punct(')')?;
+    let ret = line.ret_type()?;
+    line.keyword("locals")?;
+    let num_locals = line.int("the number of locals")?;
+    line.punct('{')?;
+    Ok(Proc {
+        name,
+        param_names,
+        params,
+        ret,
+        num_locals,
+        insts: Vec::new(),
+    })
+}
+
+fn parse_inst(line: &mut Line) -> Result<IrInst, String> {
+    let mnemonic = line.word("an instruction")?;
+    let inst = match mnemonic.as_str() {
+        "get_static_str" => IrInst::GetStaticStr(line.int("an index")?),
+        "get_imm_int" => IrInst::GetImmInt(line.int("an integer")?),
+        "get_global" => IrInst::GetGlobal(line.int("an index")?),
+        "set_global" => IrInst::SetGlobal(line.int("an index")?),
+        "get_local" => IrInst::GetLocal(line.int("an index")?),
+        "set_local" => IrInst::SetLocal(line.int("an index")?),
+        "assert_type" => IrInst::AssertType(line.ty()?, line.location()?),
+        "call_extern" => IrInst::CallExtern(line.int("an index")?),
+        "call_proc" => IrInst::CallProc(line.int("an index")?, line.location()?),
+        "pop" => IrInst::Pop,
+        "print" => IrInst::Print,
+        "begin_stmt" => IrInst::BeginStmt(line.int("an index")?),
+        "label" => IrInst::Label(line.int("an index")?),
+        "on_error_goto" => match line.peek() {
+            Some(Tok::Word(word)) if word == "none" => {
+                line.pos += 1;
+                IrInst::OnErrorGoto(None)
+            }
+            _ => IrInst::OnErrorGoto(Some(line.int("a label index or `none`")?)),
+        },
+        "resume" => IrInst::Resume(ResumeTarget::Retry, line.location()?),
+        "resume_next" => IrInst::Resume(ResumeTarget::Next, line.location()?),
+        "raise_error" => IrInst::RaiseError(line.location()?),
+        "get_err_code" => IrInst::GetErrCode,
+        "get_err_line" => IrInst::GetErrLine,
+        "end" => IrInst::End,
+        _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+    };
+    line.end()?;
+    Ok(inst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        golden::frontend,
+        interp::{interpret, Limits},
+        opt::optimize,
+        OptLevel,
+    };
+
+    fn run(ir: &Ir) -> (i32, String, String) {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let code = interpret(ir, Limits::default(), &mut out, &mut err).unwrap();
+        (
+            code,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn round_trip_frontend_output() {
+        let ir = frontend(
+            "DECLARE FUNCTION strlen LIB \"c\" (s AS STRING) AS INTEGER
+FUNCTION Twice (s AS STRING, n AS INTEGER) AS STRING
+  Twice = s
+END FUNCTION
+VAR G = \"tab\there \\ back\"
+ON ERROR GOTO HANDLER
+PRINT Twice(G, 2)
+ERROR 42
+END
+
+HANDLER:
+PRINT ERR
+ON ERROR GOTO 0
+RESUME NEXT
+",
+        );
+        let text = print_ir(&ir);
+        let parsed = parse_ir(&text).unwrap();
+        assert_eq!(print_ir(&parsed), text);
+    }
+
+    #[test]
+    fn run_hand_written_ir() {
+        let ir = parse_ir(
+            "globals 1
+string 0 \"x = \"
+
+; 引数をそのまま返す
+proc 0 Id(n: integer) -> integer locals 2 {
+    get_local 0
+    set_local 1
+}
+
+main {
+    begin_stmt 0
+    get_imm_int 42
+    call_proc 0 @1:1-1:5
+    assert_type integer @1:1-1:5
+    set_global 0
+    begin_stmt 1
+    get_static_str 0
+    print
+    get_global 0
+    print
+}
+",
+        )
+        .unwrap();
+        assert_eq!(run(&ir), (0, "x = 42".to_owned(), String::new()));
+    }
+
+    #[test]
+    fn type_error_in_hand_written_ir() {
+        let ir = parse_ir(
+            "string 0 \"a\"
+main {
+    begin_stmt 0
+    get_static_str 0
+    assert_type integer @3:7-3:9
+    print
+}
+",
+        )
+        .unwrap();
+        assert_eq!(
+            run(&ir),
+            (
+                1,
+                String::new(),
+                "Runtime error at 3:7: type mismatch (expected INTEGER)\n    at <main> (3:7)\n"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn optimize_hand_written_ir() {
+        let mut ir = parse_ir(
+            "globals 1
+main {
+    begin_stmt 0
+    get_imm_int 1
+    assert_type integer @1:1-1:2
+    print
+}
+",
+        )
+        .unwrap();
+        optimize(&mut ir, OptLevel::O2);
+        assert_eq!(
+            print_ir(&ir),
+            "globals 0
+
+main {
+    begin_stmt 0
+    get_imm_int 1
+    print
+}
+"
+        );
+    }
+
+    #[test]
+    fn reject_malformed_ir() {
+        let cases = [
+            (
+                "main {\n    jump 0\n}\n",
+                "line 2: unknown instruction `jump`",
+            ),
+            ("string 1 \"a\"\n", "line 1: expected index 0, found 1"),
+            ("string 0 \"a\n", "line 1: unterminated string"),
+            ("main {\n    print\n", "missing `}`"),
+            ("globals 0\n", "`main` is not defined"),
+            ("main {\n    print\n}\n", "stack underflow"),
+            (
+                "main {\n    get_global 0\n}\n",
+                "global variable #0 does not exist",
+            ),
+            (
+                "globals -1\nmain {\n}\n",
+                "global count -1 is out of range (0 to 65536)",
+            ),
+            (
+                "globals 65537\nmain {\n}\n",
+                "global count 65537 is out of range (0 to 65536)",
+            ),
+            (
+                "proc 0 P() locals -1 {\n}\nmain {\n}\n",
+                "local count -1 of procedure #0 (`P`) is out of range (0 to 65536)",
+            ),
+            (
+                "proc 0 P() locals 4294967296 {\n}\nmain {\n}\n",
+                "line 1: expected the number of locals, found `4294967296`",
+            ),
+            (
+                "proc 0 P() locals 100000 {\n}\nmain {\n}\n",
+                "local count 100000 of procedure #0 (`P`) is out of range (0 to 65536)",
+            ),
+        ];
+        for (src, expected) in cases.iter() {
+            let err = parse_ir(src).unwrap_err();
+            assert!(
+                err.contains(expected),
+                "{:?} does not contain {:?}",
+                err,
+                expected
+            );
+        }
+    }
+}
@@ -1,20 +1,65 @@
-mod asm;
+pub mod asm;
+mod assembler;
 pub mod ast;
+mod bytecode;
+mod c_header;
+mod c_source;
+mod cil;
 pub mod codegen;
+pub mod diagnostic;
+mod elf;
+#[cfg(test)]
+mod golden;
+mod i386;
+mod i386_codegen;
+mod interp;
 mod ir;
-mod location;
+mod ir_text;
+mod llvm;
+pub mod location;
+mod opt;
 pub mod parser;
+mod peephole;
+mod regalloc;
 pub mod sem_analysis;
+pub mod ssa;
+pub mod symbols;
 pub mod term_color;
-mod token;
+pub mod token;
 pub mod tokenizer;
+mod vcode;
+mod wasm;
+mod wasm_codegen;
 
-use clap::{app_from_crate, Arg};
+use assembler::assemble;
+use bytecode::{decode, encode};
+use c_header::gen_c_header;
+use c_source::gen_c_source;
+use cil::gen_cil;
+use clap::{app_from_crate, App, AppSettings, Arg, ArgMatches};
 use codegen::gen_asm;
+use diagnostic::Diagnostic;
+use i386_codegen::{encode_flat_binary, gen_i386};
+use interp::interpret;
+pub use interp::Limits;
+use ir::Ir;
+use ir_text::{parse_ir, print_ir};
+use llvm::gen_llvm;
+use opt::optimize;
+pub use opt::PassStats;
 use parser::parse;
+use peephole::peephole;
 use sem_analysis::sem_analysis;
-use std::{fmt, path::PathBuf, str};
+use ssa::{build_ssa, verify};
+use std::{
+    ffi::OsString,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    str,
+};
 use tokenizer::tokenize;
+use wasm_codegen::gen_wasm;
 
 pub struct IOInfo {
     pub input: InputInfo,
@@ -23,37 +68,115 @@ pub struct IOInfo {
 
 pub struct InputInfo {
     pub src_path: PathBuf,
+    /// 拡張子を除いた入力ファイルの名前
+    pub name: String,
+    /// 入力ファイルの形式 (拡張子で判別する)
+    pub format: InputFormat,
 }
 
 pub struct OutputInfo {
+    pub tokens_path: PathBuf,
     pub ast_path: PathBuf,
     pub asm_path: PathBuf,
     pub obj_path: PathBuf,
     pub bin_path: PathBuf,
+    pub lib_path: PathBuf,
+    pub header_path: PathBuf,
+    pub il_path: PathBuf,
+    pub wat_path: PathBuf,
+    pub wasm_path: PathBuf,
+    pub ll_path: PathBuf,
+    pub c_path: PathBuf,
+    pub basc_path: PathBuf,
+    pub ir_path: PathBuf,
 }
 
-pub fn get_io_info<F: Into<PathBuf>>(filename: F) -> Result<IOInfo, String> {
+/// ``out_dir`` が指定された場合は、入力ファイルと同じディレクトリではなく ``out_dir`` に出力する
+pub fn get_io_info<F: Into<PathBuf>>(
+    filename: F,
+    out_dir: Option<PathBuf>,
+) -> Result<IOInfo, String> {
     let src_path = filename.into();
-    let src_dir = src_path.parent().ok_or("Failed to get directory info")?;
-    let mut src_dir = src_dir.to_path_buf();
+    let mut out_path = match out_dir {
+        Some(out_dir) => out_dir,
+        None => src_path
+            .parent()
+            .ok_or("Failed to get directory info")?
+            .to_path_buf(),
+    };
     let src_filename = src_path
         .file_name()
         .ok_or("Failed to get name of the source file")?;
+    // `*.ast.json` は `*.ast` として、 `*` を元に出力ファイル名を決める
+    let src_filename = match src_filename
+        .to_str()
+        .and_then(|filename| filename.strip_suffix(".json"))
+    {
+        Some(filename) if filename.ends_with(".ast") => OsString::from(filename),
+        _ => src_filename.to_os_string(),
+    };
+
+    out_path.push(&src_filename);
 
-    src_dir.push(src_filename);
+    let tokens_path = out_path.with_extension("tokens.json");
+    let ast_path = out_path.with_extension("ast.json");
+    let asm_path = out_path.with_extension("s");
+    let obj_path = out_path.with_extension("o");
+    let bin_path = out_path.with_extension("bin");
+    let header_path = out_path.with_extension("h");
+    let il_path = out_path.with_extension("il");
+    let wat_path = out_path.with_extension("wat");
+    let wasm_path = out_path.with_extension("wasm");
+    let ll_path = out_path.with_extension("ll");
+    let c_path = out_path.with_extension("c");
+    let basc_path = out_path.with_extension("basc");
+    let ir_path = out_path.with_extension("bir");
 
-    let ast_path = src_dir.with_extension("ast.json");
-    let asm_path = src_dir.with_extension("s");
-    let obj_path = src_dir.with_extension("o");
-    let bin_path = src_dir.with_extension("bin");
+    // `-l<name>` でリンクできるように `lib<name>.a` とする
+    let src_stem = Path::new(&src_filename)
+        .file_stem()
+        .ok_or("Failed to get name of the source file")?;
+    let mut lib_name = OsString::from("lib");
+    lib_name.push(src_stem);
+    lib_name.push(".a");
+    let lib_path = out_path.with_file_name(lib_name);
 
-    let input_info = InputInfo { src_path };
+    let name = src_stem
+        .to_str()
+        .ok_or("Failed to get name of the source file")?
+        .to_owned();
+
+    // `.bir` はテキスト形式の中間表現として、 `.ast.json` は JSON で表された抽象構文木として、
+    // トークナイザと構文解析器を通さずに読み込む
+    let format = match src_path.extension().and_then(|ext| ext.to_str()) {
+        Some("bir") => InputFormat::Ir,
+        Some("json") if Path::new(&src_filename).extension() == Some("ast".as_ref()) => {
+            InputFormat::Ast
+        }
+        _ => InputFormat::Basic,
+    };
+
+    let input_info = InputInfo {
+        src_path,
+        name,
+        format,
+    };
 
     let output_info = OutputInfo {
+        tokens_path,
         ast_path,
         asm_path,
         obj_path,
         bin_path,
+        lib_path,
+        header_path,
+        il_path,
+        wat_path,
+        wasm_path,
+        ll_path,
+        c_path,
+        basc_path,
+        ir_path,
     };
 
     Ok(IOInfo {
@@ -64,53 +187,565 @@ pub fn get_io_info<F: Into<PathBuf>>(filename: F) -> Result<IOInfo, String> {
 
 pub struct Options {
     pub input: String,
+    /// 入力ファイルを JSON で表された抽象構文木として読み込む ( ``--from-ast`` )
+    pub from_ast: bool,
     pub verbose: bool,
     pub target: Target,
+    pub crate_type: CrateType,
+    pub use_external_assembler: bool,
+    pub asm_syntax: AsmSyntax,
+    pub opt_level: OptLevel,
+    /// 出力する中間生成物 (空の場合は通常どおりコンパイルする)
+    pub emit: Vec<Emit>,
+    /// 出力先のディレクトリ (指定されなければ入力ファイルと同じディレクトリ)
+    pub out_dir: Option<String>,
+    /// エラーの出力形式
+    pub error_format: ErrorFormat,
+    /// 出力するエラーの最大個数 (0 の場合は制限しない)
+    pub max_errors: usize,
+    /// ``run`` サブコマンドが指定された場合の、インタプリタの実行に課す制限
+    pub run: Option<Limits>,
 }
 
 impl Options {
+    /// コード生成の設定
+    pub fn compile_config(&self) -> CompileConfig {
+        CompileConfig {
+            crate_type: self.crate_type,
+            target: self.target,
+            // オブジェクトファイルは外部のアセンブラでのみ生成できる
+            use_external_assembler: self.use_external_assembler || self.emit.contains(&Emit::Obj),
+            asm_syntax: self.asm_syntax,
+            opt_level: self.opt_level,
+        }
+    }
+
     pub fn parse() -> Self {
         let matches = app_from_crate!()
-            .arg(Arg::new("INPUT").required(true).about("Source file"))
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .arg(
+                Arg::new("INPUT")
+                    .required_unless_present("from-ast")
+                    .conflicts_with("from-ast")
+                    .about("Source file"),
+            )
+            .arg(
+                Arg::new("from-ast")
+                    .long("from-ast")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .about("Compiles the abstract syntax tree in JSON (e.g. hello.ast.json) instead of source code"),
+            )
             .arg(
                 Arg::new("verbose")
                     .long("verbose")
+                    .global(true)
                     .about("Outputs verbose messages on internal operations"),
             )
             .arg(
                 Arg::new("target")
                     .long("target")
                     .takes_value(true)
-                    .possible_values(&["x86_64-linux", "x86_64-darwin", "dotnet"])
+                    .possible_values(&[
+                        "x86_64-linux",
+                        "x86_64-darwin",
+                        "dotnet",
+                        "wasm32-wasi",
+                        "llvm",
+                        "c",
+                        "i386-flat",
+                        "bytecode",
+                    ])
                     .default_value(&Target::default().to_string())
                     .about("Builds for the target triple"),
             )
+            .arg(
+                Arg::new("crate-type")
+                    .long("crate-type")
+                    .takes_value(true)
+                    .possible_values(&["bin", "staticlib"])
+                    .default_value("bin")
+                    .about("Builds an executable or a static library with a C header"),
+            )
+            .arg(
+                Arg::new("asm-syntax")
+                    .long("asm-syntax")
+                    .takes_value(true)
+                    .possible_values(&["intel", "att"])
+                    .default_value("intel")
+                    .about("Outputs assembly for NASM (intel) or GNU as (att)"),
+            )
+            .arg(
+                Arg::new("opt-level")
+                    .short('O')
+                    .takes_value(true)
+                    .possible_values(&["0", "1", "2"])
+                    .default_value("0")
+                    .global(true)
+                    .about("Optimizes the intermediate representation (e.g. -O2)"),
+            )
+            .arg(
+                Arg::new("emit")
+                    .long("emit")
+                    .takes_value(true)
+                    .multiple(true)
+                    .use_delimiter(true)
+                    .require_delimiter(true)
+                    .possible_values(&["tokens", "ast", "ir", "asm", "obj", "bin"])
+                    .about("Outputs the given intermediate results and stops after the last of them (e.g. --emit=ast,asm)"),
+            )
+            .arg(
+                Arg::new("error-format")
+                    .long("error-format")
+                    .takes_value(true)
+                    .possible_values(&["human", "json"])
+                    .default_value("human")
+                    .global(true)
+                    .about("Outputs errors with source snippets (human) or as JSON lines (json)"),
+            )
+            .arg(
+                Arg::new("max-errors")
+                    .long("max-errors")
+                    .takes_value(true)
+                    .default_value("20")
+                    .global(true)
+                    .validator(|s| s.parse::<usize>())
+                    .about("Stops reporting errors after the given number of them (0 for no limit)"),
+            )
+            .arg(
+                Arg::new("out-dir")
+                    .short('o')
+                    .long("out-dir")
+                    .takes_value(true)
+                    .about("Writes the output files to the given directory"),
+            )
+            .arg(
+                Arg::new("use-external-assembler")
+                    .long("use-external-assembler")
+                    .about(
+                        "Assembles and links with NASM and ld instead of the built-in assembler",
+                    ),
+            )
+            .subcommand(
+                App::new("run")
+                    .about("Runs the program with the built-in interpreter")
+                    .arg(Arg::new("INPUT").required(true).about("Source file"))
+                    .arg(
+                        Arg::new("max-steps")
+                            .long("max-steps")
+                            .takes_value(true)
+                            .validator(|s| s.parse::<u64>())
+                            .about("Stops after executing the given number of IR instructions"),
+                    )
+                    .arg(
+                        Arg::new("max-memory")
+                            .long("max-memory")
+                            .takes_value(true)
+                            .validator(|s| s.parse::<usize>())
+                            .about("Stops when the stack and variables exceed the given number of bytes"),
+                    ),
+            )
             .get_matches();
 
-        let input = matches.value_of("INPUT").unwrap();
-        let verbose = matches.is_present("verbose");
+        let run_matches = matches.subcommand_matches("run");
+        let run = run_matches.map(|run_matches| Limits {
+            max_steps: run_matches
+                .value_of("max-steps")
+                .map(|s| s.parse().unwrap()),
+            max_memory: run_matches
+                .value_of("max-memory")
+                .map(|s| s.parse().unwrap()),
+        });
+        // 入力ファイルとグローバルな引数は、サブコマンドが指定されていればその引数から取得する
+        let sub_matches: &ArgMatches = run_matches.unwrap_or(&matches);
+
+        let from_ast = matches.is_present("from-ast");
+        let input = if from_ast {
+            matches.value_of("from-ast").unwrap()
+        } else {
+            sub_matches.value_of("INPUT").unwrap()
+        };
+        let verbose = sub_matches.is_present("verbose");
         let target: Target = matches.value_of("target").unwrap().parse().unwrap();
+        let crate_type: CrateType = matches.value_of("crate-type").unwrap().parse().unwrap();
+        let use_external_assembler = matches.is_present("use-external-assembler");
+        let asm_syntax: AsmSyntax = matches.value_of("asm-syntax").unwrap().parse().unwrap();
+        let opt_level: OptLevel = sub_matches.value_of("opt-level").unwrap().parse().unwrap();
+        let emit: Vec<Emit> = matches.values_of("emit").map_or(Vec::new(), |values| {
+            values.map(|value| value.parse().unwrap()).collect()
+        });
+        let out_dir = matches.value_of("out-dir").map(|s| s.to_owned());
+        let error_format: ErrorFormat = sub_matches
+            .value_of("error-format")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let max_errors: usize = sub_matches.value_of("max-errors").unwrap().parse().unwrap();
 
         Options {
             input: input.to_owned(),
+            from_ast,
             verbose,
             target,
+            crate_type,
+            use_external_assembler,
+            asm_syntax,
+            opt_level,
+            emit,
+            out_dir,
+            error_format,
+            max_errors,
+            run,
         }
     }
 }
 
-pub fn compile(src: &str) -> Result<String, String> {
-    let tokens = tokenize(src)?;
-    let ast = parse(&tokens)?;
-    let ir = sem_analysis(&ast)?;
-    let asm = gen_asm(&ir)?;
-    Ok(asm.stringify())
+/// コンパイル結果
+pub struct CompileOutput {
+    /// アセンブリプログラム ( ``dotnet`` ターゲットの場合は CIL アセンブリ、
+    /// ``wasm32-wasi`` ターゲットの場合は WebAssembly のテキスト形式、 ``llvm`` ターゲットの場合は LLVM IR、 ``c`` ターゲットの場合は C のソースコード、
+    /// ``i386-flat`` ターゲットの場合は NASM でも変換できるアセンブリ、 ``bytecode`` ターゲットの場合は空)
+    pub asm: String,
+    /// バイナリ形式のモジュール ( ``wasm32-wasi`` ターゲットの場合)、フラットバイナリ ( ``i386-flat`` ターゲットの場合)、
+    /// バイトコード ( ``bytecode`` ターゲットの場合)、
+    /// または内蔵のアセンブラで生成した ELF64 の実行可能ファイル ( ``x86_64-linux`` ターゲットの実行可能ファイルの場合)
+    pub binary: Option<Vec<u8>>,
+    /// リンクする必要のある外部ライブラリ
+    pub libs: Vec<String>,
+    /// C から呼び出すためのヘッダファイル (静的ライブラリの場合のみ)
+    pub header: Option<String>,
+    /// 中間表現に適用した最適化パスの統計情報
+    pub opt_stats: Vec<PassStats>,
+}
+
+/// コード生成の設定
+#[derive(Clone, Copy)]
+pub struct CompileConfig {
+    pub crate_type: CrateType,
+    pub target: Target,
+    /// ``false`` で、 ``x86_64-linux`` ターゲットの外部ライブラリを使わない実行可能ファイルの場合は、
+    /// 内蔵のアセンブラで実行可能ファイルまで生成する
+    pub use_external_assembler: bool,
+    /// ``x86_64-linux`` / ``x86_64-darwin`` ターゲットで出力するアセンブリの構文
+    pub asm_syntax: AsmSyntax,
+    /// 中間表現に適用する最適化のレベル
+    pub opt_level: OptLevel,
+}
+
+/// ``name`` は ``dotnet`` ターゲットで生成するアセンブリの名前
+pub fn compile(
+    src: &str,
+    input_format: InputFormat,
+    name: &str,
+    config: &CompileConfig,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    let ir = gen_ir(src, input_format, config.crate_type)?;
+    compile_ir(ir, name, config).map_err(|diag| vec![diag])
+}
+
+/// 意味解析を終えた中間表現を最適化して、ターゲットのコードを生成する
+fn compile_ir(mut ir: Ir, name: &str, config: &CompileConfig) -> Result<CompileOutput, Diagnostic> {
+    let CompileConfig {
+        crate_type,
+        target,
+        use_external_assembler,
+        asm_syntax,
+        opt_level,
+    } = *config;
+    let mut opt_stats = optimize(&mut ir, opt_level);
+
+    // 最適化後の中間表現から制御フローグラフと SSA 形式を構築し、正しい形であることを検査する
+    // (コンパイラ自身の不具合を検出するためのものなので、デバッグビルドでのみ行う)
+    if cfg!(debug_assertions) {
+        let module = build_ssa(&ir);
+        for func in std::iter::once(&module.main).chain(module.procs.iter()) {
+            verify(func).map_err(|e| Diagnostic::from(format!("Internal error: {}", e)))?;
+        }
+    }
+
+    // 静的ライブラリは x86-64 のターゲットでのみ生成できる
+    if crate_type == CrateType::StaticLib && !matches!(target, Target::X64Linux | Target::X64Darwin)
+    {
+        return Err(Diagnostic::from(format!(
+            "`staticlib` is not supported for the `{}` target",
+            target
+        )));
+    }
+
+    let (asm, binary, libs) = match target {
+        Target::Dotnet => (gen_cil(&ir, name)?, None, ir.linked_libs()),
+        Target::Wasm32Wasi => {
+            let module = gen_wasm(&ir)?;
+            (module.stringify(), Some(module.encode()?), ir.linked_libs())
+        }
+        Target::Llvm => (gen_llvm(&ir)?, None, ir.linked_libs()),
+        Target::C => (gen_c_source(&ir)?, None, ir.linked_libs()),
+        Target::I386Flat => {
+            let prog = gen_i386(&ir)?;
+            (
+                prog.stringify(),
+                Some(encode_flat_binary(&prog)?),
+                Vec::new(),
+            )
+        }
+        Target::Bytecode => (String::new(), Some(encode(&ir)), Vec::new()),
+        Target::X64Linux | Target::X64Darwin => {
+            let mut asm = gen_asm(&ir, crate_type, opt_level != OptLevel::O0)?;
+            if opt_level != OptLevel::O0 {
+                let eliminated = peephole(&mut asm.text);
+                opt_stats.push(PassStats {
+                    name: "peephole",
+                    changes: eliminated,
+                });
+            }
+            let binary = if target == Target::X64Linux
+                && crate_type == CrateType::Bin
+                && asm.externs.is_empty()
+                && !use_external_assembler
+            {
+                Some(assemble(&asm)?)
+            } else {
+                None
+            };
+            let asm = match asm_syntax {
+                AsmSyntax::Intel => asm.stringify(),
+                AsmSyntax::Att => asm.stringify_gas(),
+            };
+            (asm, binary, ir.linked_libs())
+        }
+    };
+    let header = match crate_type {
+        CrateType::Bin => None,
+        CrateType::StaticLib => Some(gen_c_header(&ir)),
+    };
+    Ok(CompileOutput {
+        asm,
+        binary,
+        libs,
+        header,
+        opt_stats,
+    })
+}
+
+/// 生成する成果物の種類
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CrateType {
+    /// 実行可能ファイル
+    Bin,
+    /// 手続きを公開する静的ライブラリ
+    StaticLib,
+}
+
+#[derive(Debug)]
+pub struct InvalidCrateTypeError;
+
+impl str::FromStr for CrateType {
+    type Err = InvalidCrateTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "bin" {
+            Ok(CrateType::Bin)
+        } else if s == "staticlib" {
+            Ok(CrateType::StaticLib)
+        } else {
+            Err(InvalidCrateTypeError)
+        }
+    }
+}
+
+impl fmt::Display for CrateType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let crate_type_name = match self {
+            CrateType::Bin => "bin",
+            CrateType::StaticLib => "staticlib",
+        };
+        write!(f, "{}", crate_type_name)
+    }
+}
+
+/// 入力ファイルの形式
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// BASIC のソースコード
+    Basic,
+    /// JSON で表された抽象構文木 ( ``.ast.json`` )
+    Ast,
+    /// テキスト形式の中間表現 ( ``.bir`` )
+    Ir,
 }
 
+/// ``--emit`` で出力する中間生成物
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// トークン列の JSON ( ``.tokens.json`` )
+    Tokens,
+    /// 抽象構文木の JSON ( ``.ast.json`` )
+    Ast,
+    /// 最適化後の中間表現 ( ``.bir`` )
+    Ir,
+    /// ターゲットのアセンブリやソースコード ( ``.s`` / ``.il`` / ``.wat`` / ``.ll`` / ``.c`` )
+    Asm,
+    /// オブジェクトファイル ( ``.o`` )
+    Obj,
+    /// 実行可能ファイルなどの最終的な生成物
+    Bin,
+}
+
+#[derive(Debug)]
+pub struct InvalidEmitError;
+
+impl str::FromStr for Emit {
+    type Err = InvalidEmitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "ir" => Ok(Emit::Ir),
+            "asm" => Ok(Emit::Asm),
+            "obj" => Ok(Emit::Obj),
+            "bin" => Ok(Emit::Bin),
+            _ => Err(InvalidEmitError),
+        }
+    }
+}
+
+impl fmt::Display for Emit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let emit_name = match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::Ir => "ir",
+            Emit::Asm => "asm",
+            Emit::Obj => "obj",
+            Emit::Bin => "bin",
+        };
+        write!(f, "{}", emit_name)
+    }
+}
+
+/// エラーの出力形式
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// ソースコードの該当箇所を示す、人が読むための形式
+    Human,
+    /// 1行に1つの診断を表す JSON
+    Json,
+}
+
+#[derive(Debug)]
+pub struct InvalidErrorFormatError;
+
+impl str::FromStr for ErrorFormat {
+    type Err = InvalidErrorFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(InvalidErrorFormatError),
+        }
+    }
+}
+
+impl fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_name = match self {
+            ErrorFormat::Human => "human",
+            ErrorFormat::Json => "json",
+        };
+        write!(f, "{}", format_name)
+    }
+}
+
+/// 中間表現に適用する最適化のレベル
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// 最適化しない
+    O0,
+    /// 定数とコピーの伝播、文字列プールの重複の除去
+    O1,
+    /// ``O1`` に加えて不要な代入とグローバル変数の除去
+    O2,
+}
+
+#[derive(Debug)]
+pub struct InvalidOptLevelError;
+
+impl str::FromStr for OptLevel {
+    type Err = InvalidOptLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "0" {
+            Ok(OptLevel::O0)
+        } else if s == "1" {
+            Ok(OptLevel::O1)
+        } else if s == "2" {
+            Ok(OptLevel::O2)
+        } else {
+            Err(InvalidOptLevelError)
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level_name = match self {
+            OptLevel::O0 => "0",
+            OptLevel::O1 => "1",
+            OptLevel::O2 => "2",
+        };
+        write!(f, "{}", level_name)
+    }
+}
+
+/// 出力するアセンブリの構文
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AsmSyntax {
+    /// NASM で変換する Intel 構文
+    Intel,
+    /// GNU アセンブラで変換する AT&T 構文
+    Att,
+}
+
+#[derive(Debug)]
+pub struct InvalidAsmSyntaxError;
+
+impl str::FromStr for AsmSyntax {
+    type Err = InvalidAsmSyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "intel" {
+            Ok(AsmSyntax::Intel)
+        } else if s == "att" {
+            Ok(AsmSyntax::Att)
+        } else {
+            Err(InvalidAsmSyntaxError)
+        }
+    }
+}
+
+impl fmt::Display for AsmSyntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let syntax_name = match self {
+            AsmSyntax::Intel => "intel",
+            AsmSyntax::Att => "att",
+        };
+        write!(f, "{}", syntax_name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Target {
     X64Darwin,
     X64Linux,
     Dotnet,
+    Wasm32Wasi,
+    Llvm,
+    C,
+    I386Flat,
+    /// ``vm`` で実行するバイトコード ( ``.basc`` )
+    Bytecode,
 }
 
 #[derive(Debug)]
@@ -136,6 +771,18 @@ impl str::FromStr for Target {
             Ok(Target::X64Linux)
         } else if s == "x86_64-darwin" {
             Ok(Target::X64Darwin)
+        } else if s == "dotnet" {
+            Ok(Target::Dotnet)
+        } else if s == "wasm32-wasi" {
+            Ok(Target::Wasm32Wasi)
+        } else if s == "llvm" {
+            Ok(Target::Llvm)
+        } else if s == "c" {
+            Ok(Target::C)
+        } else if s == "i386-flat" {
+            Ok(Target::I386Flat)
+        } else if s == "bytecode" {
+            Ok(Target::Bytecode)
         } else {
             Err(InvalidTargetError)
         }
@@ -148,7 +795,108 @@ impl fmt::Display for Target {
             Target::X64Linux => "x86_64-linux",
             Target::X64Darwin => "x86_64-darwin",
             Target::Dotnet => "dotnet",
+            Target::Wasm32Wasi => "wasm32-wasi",
+            Target::Llvm => "llvm",
+            Target::C => "c",
+            Target::I386Flat => "i386-flat",
+            Target::Bytecode => "bytecode",
         };
         write!(f, "{}", target_name)
     }
 }
+
+/// ソースコードを中間表現に変換して、内蔵のインタプリタで実行する
+///
+/// NASM やリンカを必要とせず、ネイティブのバックエンドと同じ意味 (型の検査とエラー処理) で実行する。
+/// 戻り値はプログラムの終了コード (ランタイムエラーを報告した場合は 1)
+pub fn run(
+    src: &str,
+    input_format: InputFormat,
+    opt_level: OptLevel,
+    limits: Limits,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, Vec<Diagnostic>> {
+    let mut ir = gen_ir(src, input_format, CrateType::Bin)?;
+    optimize(&mut ir, opt_level);
+    interpret(&ir, limits, out, err).map_err(|e| vec![Diagnostic::from(e)])
+}
+
+/// バイトコード ( ``.basc`` ) を検査して、内蔵のインタプリタで実行する
+///
+/// 戻り値はプログラムの終了コード (ランタイムエラーを報告した場合は 1)
+pub fn run_bytecode(
+    bytes: &[u8],
+    limits: Limits,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, String> {
+    let ir = decode(bytes)?;
+    interpret(&ir, limits, out, err)
+}
+
+/// 入力を中間表現に変換する
+///
+/// JSON で表された抽象構文木は意味解析から、テキスト形式の中間表現はフロントエンドを通さずに読み込む。
+/// 構文エラーがあった場合は、意味解析は行わずに構文エラーのみを返す
+fn gen_ir(
+    src: &str,
+    input_format: InputFormat,
+    crate_type: CrateType,
+) -> Result<Ir, Vec<Diagnostic>> {
+    match input_format {
+        InputFormat::Basic => {
+            let tokens = tokenize(src).map_err(|diag| vec![diag])?;
+            let ast = parse(&tokens)?;
+            sem_analysis(&ast, crate_type)
+        }
+        InputFormat::Ast => {
+            let ast = ast::from_json(src).map_err(|diag| vec![diag])?;
+            sem_analysis(&ast, crate_type)
+        }
+        InputFormat::Ir => parse_ir(src).map_err(|e| vec![Diagnostic::from(e)]),
+    }
+}
+
+/// ソースコードのトークン列を JSON で出力する
+pub fn emit_tokens(src: &str, input_format: InputFormat) -> Result<String, Vec<Diagnostic>> {
+    if input_format != InputFormat::Basic {
+        return Err(vec![Diagnostic::from(
+            "`--emit=tokens` requires BASIC source code as input".to_owned(),
+        )]);
+    }
+    let tokens = tokenize(src).map_err(|diag| vec![diag])?;
+    serde_json::to_string_pretty(&tokens).map_err(|e| {
+        vec![Diagnostic::from(format!(
+            "{}\nFailed to serialize tokens",
+            e
+        ))]
+    })
+}
+
+/// ソースコードの抽象構文木を JSON で出力する
+pub fn emit_ast(src: &str, input_format: InputFormat) -> Result<String, Vec<Diagnostic>> {
+    let ast = match input_format {
+        InputFormat::Basic => parse(&tokenize(src).map_err(|diag| vec![diag])?)?,
+        InputFormat::Ast => ast::from_json(src).map_err(|diag| vec![diag])?,
+        InputFormat::Ir => {
+            return Err(vec![Diagnostic::from(
+                "`--emit=ast` requires BASIC source code or AST as input".to_owned(),
+            )])
+        }
+    };
+    serde_json::to_string_pretty(&ast)
+        .map_err(|e| vec![Diagnostic::from(format!("{}\nFailed to serialize AST", e))])
+}
+
+/// 入力を最適化した中間表現に変換して、テキスト形式 ( ``.bir`` ) で出力する
+pub fn emit_ir(
+    src: &str,
+    input_format: InputFormat,
+    crate_type: CrateType,
+    opt_level: OptLevel,
+) -> Result<String, Vec<Diagnostic>> {
+    let mut ir = gen_ir(src, input_format, crate_type)?;
+    optimize(&mut ir, opt_level);
+    Ok(print_ir(&ir))
+}
@@ -0,0 +1,710 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+
+/// ランタイムが使用するため、手続きの名前として使えない関数名
+static RUNTIME_FUNCS: [&str; 7] = [
+    "main",
+    "printf",
+    "dprintf",
+    "fflush",
+    "exit",
+    "rt_print_value",
+    "rt_error",
+];
+
+/// ランタイムが出力するメッセージ
+static MESSAGES: [(&str, &str); 4] = [
+    ("msg_expected_str", "type mismatch (expected STRING)"),
+    ("msg_expected_int", "type mismatch (expected INTEGER)"),
+    ("msg_resume_without_error", "RESUME without error"),
+    ("msg_error_stmt", "error raised by ERROR statement"),
+];
+
+/// 関数本体の生成に用いる状態
+struct FuncBuilder<'a> {
+    ir: &'a Ir,
+    lines: Vec<String>,
+    /// スタックに積まれている値 (値と型タグを表す ``i64`` のオペランドの組)
+    stack: Vec<(String, String)>,
+    /// 一時変数やラベルの連番
+    num_temps: i32,
+    /// 現在の基本ブロックが終端命令で終わっているかどうか
+    terminated: bool,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    traps_errors: bool,
+    /// 生成中の関数が BASIC で定義された手続きの場合、その戻り値の有無
+    proc_ret: Option<bool>,
+    /// エラーが発生した位置を表す文字列定数
+    locations: &'a mut Vec<String>,
+}
+
+impl<'a> FuncBuilder<'a> {
+    fn inst<I: Into<String>>(&mut self, inst: I) {
+        // 終端命令の後に命令を置く場合は、新しい基本ブロックを始める
+        if self.terminated {
+            let label = self.fresh("b");
+            self.lines.push(format!("{}:", label));
+            self.terminated = false;
+        }
+        self.lines.push(format!("    {}", inst.into()));
+    }
+
+    /// 終端命令
+    fn terminator<I: Into<String>>(&mut self, inst: I) {
+        self.inst(inst);
+        self.terminated = true;
+    }
+
+    /// 基本ブロックを始める (直前のブロックが終端していなければ、そのブロックへ分岐する)
+    fn label<N: AsRef<str>>(&mut self, name: N) {
+        if !self.terminated {
+            self.lines.push(format!("    br label %{}", name.as_ref()));
+        }
+        self.lines.push(format!("{}:", name.as_ref()));
+        self.terminated = false;
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.num_temps += 1;
+        format!("{}{}", prefix, self.num_temps)
+    }
+
+    /// 値を計算して一時変数に代入し、その一時変数を返す
+    fn assign<I: AsRef<str>>(&mut self, expr: I) -> String {
+        let temp = format!("%{}", self.fresh("t"));
+        self.inst(format!("{} = {}", temp, expr.as_ref()));
+        temp
+    }
+
+    fn push(&mut self, value: String, tag: String) {
+        self.stack.push((value, tag));
+    }
+
+    fn pop(&mut self) -> (String, String) {
+        self.stack.pop().expect("IR stack underflow")
+    }
+}
+
+/// 中間表現から LLVM IR (``.ll``) を生成する
+///
+/// スタックの各要素は値と型タグを表す ``i64`` の SSA 値の組として扱い、
+/// 文字列の値は NUL 終端文字列のアドレスとなる
+pub fn gen_llvm(ir: &Ir) -> Result<String, String> {
+    for name in ir
+        .procs
+        .iter()
+        .map(|proc| &proc.name)
+        .chain(ir.externs.iter().map(|ext| &ext.name))
+    {
+        if RUNTIME_FUNCS.contains(&name.as_str()) {
+            return Err(format!(
+                "`{}` conflicts with a function used by the runtime",
+                name
+            ));
+        }
+    }
+
+    let traps_errors = ir
+        .insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_))));
+
+    let mut result = String::new();
+
+    // 文字列プール
+    for (i, s) in ir.string_pool.iter().enumerate() {
+        result.push_str(string_constant(&format!("str{}", i), s).as_str());
+    }
+    for (name, s) in MESSAGES.iter() {
+        result.push_str(string_constant(name, s).as_str());
+    }
+    result.push_str(string_constant("fmt_str", "%s").as_str());
+    result.push_str(string_constant("fmt_int", "%ld").as_str());
+    result.push_str(string_constant("fmt_error", "Runtime error at %s: %s\n").as_str());
+    result.push('\n');
+
+    // グローバル変数 1 つにつき、値と型タグの 2 つの変数を用意する
+    for i in 0..ir.num_globals {
+        result.push_str(format!("@global{} = internal global i64 0\n", i).as_str());
+        result.push_str(format!("@global{}.tag = internal global i64 0\n", i).as_str());
+    }
+
+    // ON ERROR GOTO によるエラー処理の状態
+    result.push_str("@err_code = internal global i64 0\n");
+    result.push_str("@err_line = internal global i64 0\n");
+    for name in [
+        "err_handler",
+        "in_handler",
+        "cur_stmt",
+        "resume_addr",
+        "resume_next_addr",
+        "pc",
+        "pending",
+    ] {
+        result.push_str(format!("@{} = internal global i32 0\n", name).as_str());
+    }
+    result.push('\n');
+
+    let mut locations = Vec::new();
+    let mut functions = gen_main(ir, traps_errors, &mut locations);
+    for proc in ir.procs.iter() {
+        functions.push_str(gen_proc(ir, proc, traps_errors, &mut locations).as_str());
+    }
+
+    for (i, location) in locations.iter().enumerate() {
+        result.push_str(string_constant(&format!("rt_loc{}", i), location).as_str());
+    }
+    result.push('\n');
+
+    result.push_str(functions.as_str());
+    result.push_str(runtime_funcs(traps_errors).as_str());
+
+    // 外部ライブラリの手続き
+    for ext in ir.externs.iter() {
+        let params = ext
+            .params
+            .iter()
+            .map(|ty| extern_type(Some(*ty)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        result.push_str(
+            format!(
+                "declare {} @\"{}\"({})\n",
+                extern_type(ext.ret),
+                ext.name,
+                params
+            )
+            .as_str(),
+        );
+    }
+    result.push_str("declare i32 @printf(i8*, ...)\n");
+    result.push_str("declare i32 @dprintf(i32, i8*, ...)\n");
+    result.push_str("declare i32 @fflush(i8*)\n");
+    result.push_str("declare void @exit(i32)\n");
+
+    Ok(result)
+}
+
+/// エントリポイント ``main`` を生成する
+///
+/// エラーを捕捉する場合は ``dispatch`` ブロックで ``pc`` の値に従って
+/// 文の先頭かエラーハンドラのラベルへ分岐する
+fn gen_main(ir: &Ir, traps_errors: bool, locations: &mut Vec<String>) -> String {
+    let mut builder = FuncBuilder {
+        ir,
+        lines: Vec::new(),
+        stack: Vec::new(),
+        num_temps: 0,
+        terminated: false,
+        traps_errors,
+        proc_ret: None,
+        locations,
+    };
+
+    let num_stmts = count_stmts(ir);
+
+    if traps_errors {
+        let num_labels = ir
+            .insts
+            .iter()
+            .filter(|inst| matches!(inst, IrInst::Label(_)))
+            .count() as i32;
+
+        // pc の値と分岐先の対応 (文の番号, 最後の文の次の位置, ラベルの番号の順)
+        let cases = (0..=num_stmts)
+            .map(|i| format!("i32 {}, label %stmt{}", i, i))
+            .chain(
+                (0..num_labels)
+                    .map(|i| format!("i32 {}, label %user_label{}", num_stmts + 1 + i, i)),
+            )
+            .collect::<Vec<_>>();
+
+        builder.label("dispatch");
+        let pc = builder.assign("load i32, i32* @pc");
+        builder.terminator(format!(
+            "switch i32 {}, label %exit [ {} ]",
+            pc,
+            cases.join(" ")
+        ));
+    }
+
+    gen_insts(&ir.insts, &mut builder);
+    builder.label(format!("stmt{}", num_stmts));
+    builder.label("exit");
+    builder.terminator("ret i32 0");
+
+    format!(
+        "define i32 @main() {{\nentry:\n{}\n}}\n\n",
+        builder.lines.join("\n")
+    )
+}
+
+/// BASIC で定義された手続きを関数として生成する
+///
+/// 引数と戻り値は ``i64`` の値のみで受け渡しする (型タグは呼び出し側で検査・付与する)
+fn gen_proc(ir: &Ir, proc: &Proc, traps_errors: bool, locations: &mut Vec<String>) -> String {
+    let mut builder = FuncBuilder {
+        ir,
+        lines: Vec::new(),
+        stack: Vec::new(),
+        num_temps: 0,
+        terminated: false,
+        traps_errors,
+        proc_ret: Some(proc.ret.is_some()),
+        locations,
+    };
+
+    // ローカル変数を確保し、引数を格納する
+    for i in 0..proc.num_locals {
+        builder.inst(format!("%local{} = alloca i64", i));
+        builder.inst(format!("%local{}.tag = alloca i64", i));
+    }
+    for (i, ty) in proc.params.iter().enumerate() {
+        builder.inst(format!("store i64 %arg{}, i64* %local{}", i, i));
+        builder.inst(format!("store i64 {}, i64* %local{}.tag", type_tag(*ty), i));
+    }
+
+    gen_insts(&proc.insts, &mut builder);
+
+    match proc.ret_slot() {
+        Some(ret_slot) => {
+            let ret = builder.assign(format!("load i64, i64* %local{}", ret_slot));
+            builder.terminator(format!("ret i64 {}", ret));
+        }
+        None => builder.terminator("ret void"),
+    }
+
+    let params = (0..proc.params.len())
+        .map(|i| format!("i64 %arg{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "define internal {} @\"{}\"({}) {{\nentry:\n{}\n}}\n\n",
+        if proc.ret.is_some() { "i64" } else { "void" },
+        proc.name,
+        params,
+        builder.lines.join("\n")
+    )
+}
+
+/// 命令列を生成する
+fn gen_insts(insts: &[IrInst], builder: &mut FuncBuilder) {
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                let value = builder.assign(format!(
+                    "ptrtoint i8* {} to i64",
+                    string_ptr(
+                        &format!("str{}", index),
+                        &builder.ir.string_pool[*index as usize]
+                    )
+                ));
+                builder.push(value, type_tag(Type::String).to_string());
+            }
+            IrInst::GetImmInt(value) => {
+                builder.push(value.to_string(), type_tag(Type::Integer).to_string());
+            }
+            IrInst::GetGlobal(index) => {
+                let value = builder.assign(format!("load i64, i64* @global{}", index));
+                let tag = builder.assign(format!("load i64, i64* @global{}.tag", index));
+                builder.push(value, tag);
+            }
+            IrInst::SetGlobal(index) => {
+                let (value, tag) = builder.pop();
+                builder.inst(format!("store i64 {}, i64* @global{}", value, index));
+                builder.inst(format!("store i64 {}, i64* @global{}.tag", tag, index));
+            }
+            IrInst::GetLocal(index) => {
+                let value = builder.assign(format!("load i64, i64* %local{}", index));
+                let tag = builder.assign(format!("load i64, i64* %local{}.tag", index));
+                builder.push(value, tag);
+            }
+            IrInst::SetLocal(index) => {
+                let (value, tag) = builder.pop();
+                builder.inst(format!("store i64 {}, i64* %local{}", value, index));
+                builder.inst(format!("store i64 {}, i64* %local{}.tag", tag, index));
+            }
+            IrInst::AssertType(ty, location) => {
+                let message = match ty {
+                    Type::String => "msg_expected_str",
+                    Type::Integer => "msg_expected_int",
+                };
+                let tag = builder.stack.last().unwrap().1.clone();
+                let mismatch = builder.assign(format!("icmp ne i64 {}, {}", tag, type_tag(*ty)));
+                gen_error_check(&mismatch, "13", location, message, builder);
+            }
+            IrInst::CallExtern(index) => {
+                let ext = &builder.ir.externs[*index as usize];
+                let args = pop_args(ext.params.len(), builder)
+                    .iter()
+                    .zip(ext.params.iter())
+                    .map(|(arg, ty)| match ty {
+                        Type::String => {
+                            let ptr = builder.assign(format!("inttoptr i64 {} to i8*", arg));
+                            format!("i8* {}", ptr)
+                        }
+                        Type::Integer => format!("i64 {}", arg),
+                    })
+                    .collect::<Vec<_>>();
+                let call = format!(
+                    "call {} @\"{}\"({})",
+                    extern_type(ext.ret),
+                    ext.name,
+                    args.join(", ")
+                );
+                match ext.ret {
+                    Some(Type::String) => {
+                        let ptr = builder.assign(call);
+                        let value = builder.assign(format!("ptrtoint i8* {} to i64", ptr));
+                        builder.push(value, type_tag(Type::String).to_string());
+                    }
+                    Some(Type::Integer) => {
+                        let value = builder.assign(call);
+                        builder.push(value, type_tag(Type::Integer).to_string());
+                    }
+                    None => builder.inst(call),
+                }
+            }
+            IrInst::CallProc(index, _) => {
+                let proc = &builder.ir.procs[*index as usize];
+                let args = pop_args(proc.params.len(), builder)
+                    .iter()
+                    .map(|arg| format!("i64 {}", arg))
+                    .collect::<Vec<_>>();
+                match proc.ret {
+                    Some(ret) => {
+                        let value = builder.assign(format!(
+                            "call i64 @\"{}\"({})",
+                            proc.name,
+                            args.join(", ")
+                        ));
+                        builder.push(value, type_tag(ret).to_string());
+                    }
+                    None => {
+                        builder.inst(format!("call void @\"{}\"({})", proc.name, args.join(", ")))
+                    }
+                }
+
+                // 呼び出し先でエラーが捕捉された
+                if builder.traps_errors {
+                    let pending = builder.assign("load i32, i32* @pending");
+                    let caught = builder.assign(format!("icmp ne i32 {}, 0", pending));
+                    let unwind = builder.fresh("unwind");
+                    let cont = builder.fresh("cont");
+                    builder.terminator(format!(
+                        "br i1 {}, label %{}, label %{}",
+                        caught, unwind, cont
+                    ));
+                    builder.label(unwind);
+                    gen_goto_handler(builder);
+                    builder.label(cont);
+                }
+            }
+            IrInst::Pop => {
+                builder.pop();
+            }
+            IrInst::Print => {
+                let (value, tag) = builder.pop();
+                builder.inst(format!(
+                    "call void @rt_print_value(i64 {}, i64 {})",
+                    value, tag
+                ));
+            }
+            IrInst::BeginStmt(index) => {
+                builder.label(format!("stmt{}", index));
+                if builder.traps_errors {
+                    builder.inst(format!("store i32 {}, i32* @cur_stmt", index));
+                }
+            }
+            IrInst::Label(index) => {
+                builder.label(format!("user_label{}", index));
+            }
+            IrInst::OnErrorGoto(handler) => {
+                // 0 はエラーハンドラが設定されていないことを表す
+                let pc = handler.map_or(0, |index| count_stmts(builder.ir) + 1 + index);
+                builder.inst(format!("store i32 {}, i32* @err_handler", pc));
+            }
+            IrInst::Resume(target, location) => {
+                let in_handler = builder.assign("load i32, i32* @in_handler");
+                let no_error = builder.assign(format!("icmp eq i32 {}, 0", in_handler));
+                gen_error_check(
+                    &no_error,
+                    "20",
+                    location,
+                    "msg_resume_without_error",
+                    builder,
+                );
+
+                // エラーを捕捉しない場合は、 RESUME は常にエラーになる
+                if builder.traps_errors {
+                    builder.inst("store i32 0, i32* @in_handler");
+                    builder.inst("store i64 0, i64* @err_code");
+                    builder.inst("store i64 0, i64* @err_line");
+                    let addr = builder.assign(match target {
+                        ResumeTarget::Retry => "load i32, i32* @resume_addr",
+                        ResumeTarget::Next => "load i32, i32* @resume_next_addr",
+                    });
+                    builder.inst(format!("store i32 {}, i32* @pc", addr));
+                    builder.terminator("br label %dispatch");
+                } else {
+                    builder.terminator("unreachable");
+                }
+            }
+            IrInst::RaiseError(location) => {
+                let (code, _) = builder.pop();
+                gen_runtime_error(&code, location, "msg_error_stmt", builder);
+            }
+            IrInst::GetErrCode => {
+                let value = builder.assign("load i64, i64* @err_code");
+                builder.push(value, type_tag(Type::Integer).to_string());
+            }
+            IrInst::GetErrLine => {
+                let value = builder.assign("load i64, i64* @err_line");
+                builder.push(value, type_tag(Type::Integer).to_string());
+            }
+            IrInst::End => {
+                builder.terminator("br label %exit");
+            }
+        }
+    }
+}
+
+/// 条件が成り立つ場合にランタイムエラーを発生させる
+fn gen_error_check(
+    cond: &str,
+    code: &str,
+    location: &Location,
+    message: &str,
+    builder: &mut FuncBuilder,
+) {
+    let error = builder.fresh("error");
+    let ok = builder.fresh("ok");
+    builder.terminator(format!("br i1 {}, label %{}, label %{}", cond, error, ok));
+    builder.label(error);
+    gen_runtime_error(code, location, message, builder);
+    builder.label(ok);
+}
+
+/// エラー番号と位置とメッセージを渡して ``rt_error`` を呼び出す
+///
+/// エラーが捕捉された場合はエラーハンドラへ移る
+fn gen_runtime_error(code: &str, location: &Location, message: &str, builder: &mut FuncBuilder) {
+    let loc_index = builder.locations.len();
+    let loc = location.start.to_string();
+    let loc_ptr = string_ptr(&format!("rt_loc{}", loc_index), &loc);
+    builder.locations.push(loc);
+
+    let msg = MESSAGES.iter().find(|(name, _)| *name == message).unwrap();
+    builder.inst(format!(
+        "call void @rt_error(i64 {}, i64 {}, i8* {}, i8* {})",
+        code,
+        location.start.line() + 1,
+        loc_ptr,
+        string_ptr(msg.0, msg.1)
+    ));
+
+    if builder.traps_errors {
+        gen_goto_handler(builder);
+    } else {
+        builder.terminator("unreachable");
+    }
+}
+
+/// エラーが捕捉されたとき、エラーハンドラへ移る (手続きの中では呼び出し元に戻る)
+fn gen_goto_handler(builder: &mut FuncBuilder) {
+    match builder.proc_ret {
+        Some(true) => builder.terminator("ret i64 0"),
+        Some(false) => builder.terminator("ret void"),
+        None => {
+            builder.inst("store i32 0, i32* @pending");
+            builder.terminator("br label %dispatch");
+        }
+    }
+}
+
+/// スタックに積まれた引数の値を、先頭の引数から順に取り出す
+fn pop_args(num_params: usize, builder: &mut FuncBuilder) -> Vec<String> {
+    let mut args = (0..num_params).map(|_| builder.pop().0).collect::<Vec<_>>();
+    args.reverse();
+    args
+}
+
+/// トップレベルの文の個数
+fn count_stmts(ir: &Ir) -> i32 {
+    ir.insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+        .count() as i32
+}
+
+/// 型タグの値
+fn type_tag(ty: Type) -> i64 {
+    match ty {
+        Type::String => 1,
+        Type::Integer => 2,
+    }
+}
+
+/// 外部ライブラリの手続きの引数・戻り値の型 ( ``None`` は ``void`` を表す)
+fn extern_type(ty: Option<Type>) -> &'static str {
+    match ty {
+        Some(Type::String) => "i8*",
+        Some(Type::Integer) => "i64",
+        None => "void",
+    }
+}
+
+/// NUL 終端文字列の定数を定義する
+fn string_constant(name: &str, s: &str) -> String {
+    format!(
+        "@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n",
+        name,
+        s.len() + 1,
+        escape(s)
+    )
+}
+
+/// 文字列定数の先頭を指すポインタ
+fn string_ptr(name: &str, s: &str) -> String {
+    let len = s.len() + 1;
+    format!(
+        "getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0)",
+        len, len, name
+    )
+}
+
+/// 文字列定数として使えるようにエスケープする
+fn escape(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'"' | b'\\' => format!("\\{:02X}", b),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => format!("\\{:02X}", b),
+        })
+        .collect()
+}
+
+/// ランタイムの関数を生成する
+fn runtime_funcs(traps_errors: bool) -> String {
+    let fmt_str = string_ptr("fmt_str", "%s");
+    let fmt_int = string_ptr("fmt_int", "%ld");
+    let fmt_error = string_ptr("fmt_error", "Runtime error at %s: %s\n");
+
+    let mut result = format!(
+        "define internal void @rt_print_value(i64 %value, i64 %tag) {{
+entry:
+    %is_str = icmp eq i64 %tag, 1
+    br i1 %is_str, label %str, label %int
+str:
+    %ptr = inttoptr i64 %value to i8*
+    call i32 (i8*, ...) @printf(i8* {}, i8* %ptr)
+    ret void
+int:
+    call i32 (i8*, ...) @printf(i8* {}, i64 %value)
+    ret void
+}}
+
+",
+        fmt_str, fmt_int
+    );
+
+    // エラーハンドラが設定されていれば状態を記録して戻り、そうでなければエラーを報告して終了する
+    result.push_str(
+        "define internal void @rt_error(i64 %code, i64 %line, i8* %loc, i8* %msg) {\nentry:\n",
+    );
+    if traps_errors {
+        result.push_str(
+            "    %handler = load i32, i32* @err_handler
+    %no_handler = icmp eq i32 %handler, 0
+    %in_handler = load i32, i32* @in_handler
+    %nested = icmp ne i32 %in_handler, 0
+    %uncaught = or i1 %no_handler, %nested
+    br i1 %uncaught, label %report, label %catch
+catch:
+    store i64 %code, i64* @err_code
+    store i64 %line, i64* @err_line
+    %cur = load i32, i32* @cur_stmt
+    store i32 %cur, i32* @resume_addr
+    %next = add i32 %cur, 1
+    store i32 %next, i32* @resume_next_addr
+    store i32 1, i32* @in_handler
+    store i32 %handler, i32* @pc
+    store i32 1, i32* @pending
+    ret void
+report:
+",
+        );
+    }
+    result.push_str(
+        format!(
+            "    call i32 @fflush(i8* null)
+    call i32 (i32, i8*, ...) @dprintf(i32 2, i8* {}, i8* %loc, i8* %msg)
+    call void @exit(i32 1)
+    unreachable
+}}
+
+",
+            fmt_error
+        )
+        .as_str(),
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{
+        check_golden, require_tool, run_command, run_x64, sample_ir, sample_names, TempDir,
+    };
+    use std::fs;
+
+    #[test]
+    fn samples_match_golden_files() {
+        for name in sample_names() {
+            let ll = gen_llvm(&sample_ir(&name)).unwrap();
+            check_golden("llvm", &format!("{}.ll", name), &ll);
+        }
+    }
+
+    /// ``llc`` と ``cc`` でコンパイルしたサンプルプログラムの出力が、x86-64 のバックエンドと一致するか
+    #[test]
+    #[ignore = "requires llc and cc"]
+    fn samples_behave_like_x64_backend() {
+        let dir = TempDir::new("llvm");
+        for name in sample_names() {
+            let ir = sample_ir(&name);
+            let ll_path = dir.join(&format!("{}.ll", name));
+            let obj_path = dir.join(&format!("{}.o", name));
+            let bin_path = dir.join(&format!("{}.bin", name));
+            fs::write(&ll_path, gen_llvm(&ir).unwrap()).unwrap();
+            let (ll_path, obj_path) = (ll_path.to_str().unwrap(), obj_path.to_str().unwrap());
+            require_tool(
+                "llc",
+                &[
+                    "-filetype=obj",
+                    "-relocation-model=pic",
+                    "-o",
+                    obj_path,
+                    ll_path,
+                ],
+            );
+            let libs: Vec<String> = ir
+                .linked_libs()
+                .iter()
+                .map(|lib| format!("-l{}", lib))
+                .collect();
+            let mut args = vec!["-o", bin_path.to_str().unwrap(), obj_path];
+            args.extend(libs.iter().map(String::as_str));
+            require_tool("cc", &args);
+
+            let actual = run_command(&bin_path, &[]).unwrap();
+            if let Some(expected) = run_x64(sample_ir(&name), &name, &dir) {
+                assert_eq!(actual, expected, "{}", name);
+            }
+        }
+    }
+}
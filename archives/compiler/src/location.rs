@@ -1,8 +1,8 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// ソースコード上の1文字の位置を指す
-#[derive(Clone, Copy, Serialize)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Point {
     line: i32,
     column: i32,
@@ -13,6 +13,16 @@ impl Point {
         Point { line, column }
     }
 
+    /// 行番号 (0 始まり)
+    pub fn line(&self) -> i32 {
+        self.line
+    }
+
+    /// 列番号 (0 始まり)
+    pub fn column(&self) -> i32 {
+        self.column
+    }
+
     fn stringify(&self) -> String {
         format!("{}:{}", self.line + 1, self.column + 1)
     }
@@ -31,7 +41,7 @@ impl fmt::Display for Point {
 }
 
 /// ソースコード上の位置 ( ``Point`` 2つで表される範囲) を指す
-#[derive(Clone, Copy, Serialize)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Location {
     pub start: Point,
     pub end: Point,
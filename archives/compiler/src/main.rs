@@ -1,69 +1,441 @@
 extern crate compiler;
 
-use compiler::{compile, get_io_info, term_color::red_bold, IOInfo, Options};
+use compiler::{
+    compile, diagnostic::Diagnostic, emit_ast, emit_ir, emit_tokens, get_io_info, run,
+    term_color::stderr_supports_color, AsmSyntax, CompileOutput, CrateType, Emit, ErrorFormat,
+    IOInfo, InputFormat, Options, Target,
+};
 use std::{
+    ffi::OsStr,
     fs,
+    io::{self, BufWriter, ErrorKind},
+    path::{Path, PathBuf},
     process::{self, Command},
 };
 
 fn main() {
     let opts: Options = Options::parse();
-
-    if opts.verbose {
-        println!("Target: {}", opts.target)
-    }
+    let reporter = Reporter::new(&opts);
 
     let IOInfo {
-        input: input_info,
+        input: mut input_info,
         output: output_info,
-    } = get_io_info(opts.input).unwrap_or_else(|msg| exit_failure(&msg));
+    } = get_io_info(&opts.input, opts.out_dir.as_ref().map(PathBuf::from))
+        .unwrap_or_else(|msg| reporter.exit_failure(&msg));
+    if opts.from_ast {
+        input_info.format = InputFormat::Ast;
+    }
 
-    let content = fs::read_to_string(input_info.src_path)
-        .unwrap_or_else(|_| exit_failure("Failed to read the source file"));
+    let content = fs::read_to_string(&input_info.src_path)
+        .unwrap_or_else(|_| reporter.exit_failure("Failed to read the source file"));
+    // JSON で表された抽象構文木の位置は BASIC のソースコード上の位置なので、入力ファイルの行は示さない
+    let src = match input_info.format {
+        InputFormat::Ast => None,
+        _ => Some(content.as_str()),
+    };
 
-    let asm_output = compile(&content).unwrap_or_else(|msg| exit_failure(&msg));
+    // run サブコマンドでは、内蔵のインタプリタで実行してその終了コードで終了する
+    if let Some(limits) = opts.run {
+        if opts.verbose {
+            println!("Optimization level: {}", opts.opt_level);
+        }
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let code = run(
+            &content,
+            input_info.format,
+            opts.opt_level,
+            limits,
+            &mut out,
+            &mut io::stderr(),
+        )
+        .unwrap_or_else(|diags| reporter.exit_with(&diags, &input_info.src_path, src));
+        process::exit(code);
+    }
 
-    fs::write(&output_info.asm_path, asm_output).unwrap_or_else(|err| {
-        exit_failure(&format!(
-            "{}\nError occurs while outputting assembly program",
-            err
-        ))
-    });
+    if opts.verbose {
+        println!("Target: {}", opts.target);
+        println!("Crate type: {}", opts.crate_type);
+        println!("Assembly syntax: {}", opts.asm_syntax);
+        println!("Optimization level: {}", opts.opt_level);
+    }
 
-    let status = Command::new("nasm")
-        .args(&["-f", "elf64", output_info.asm_path.to_str().unwrap()])
-        .status()
-        .unwrap_or_else(|err| {
-            exit_failure(&format!(
-                "{}\nUnable to find `nasm`, perhaps install NASM and set PATH",
+    if let Some(out_dir) = &opts.out_dir {
+        fs::create_dir_all(out_dir).unwrap_or_else(|err| {
+            reporter.exit_failure(&format!(
+                "{}\nError occurs while creating output directory",
                 err
             ))
         });
+    }
+
+    // --emit が指定された場合は、指定された中間生成物を出力して、そのうち最後の段階で終える
+    let emits = |stages: &[Emit]| {
+        opts.emit.is_empty() || opts.emit.iter().any(|emit| stages.contains(emit))
+    };
+    let emit_asm = emits(&[Emit::Asm, Emit::Obj, Emit::Bin]);
+    let emit_obj = emits(&[Emit::Obj, Emit::Bin]);
+    let emit_bin = emits(&[Emit::Bin]);
+
+    if opts.emit.contains(&Emit::Asm) && opts.target == Target::Bytecode {
+        reporter.exit_failure("`--emit=asm` is not supported for the `bytecode` target");
+    }
+    if opts.emit.contains(&Emit::Obj)
+        && matches!(
+            opts.target,
+            Target::Dotnet | Target::Wasm32Wasi | Target::I386Flat | Target::Bytecode
+        )
+    {
+        reporter.exit_failure(&format!(
+            "`--emit=obj` is not supported for the `{}` target",
+            opts.target
+        ));
+    }
 
-    if !status.success() {
-        exit_failure("Error occurs while executing `nasm`");
+    for emit in opts.emit.iter() {
+        match emit {
+            Emit::Tokens => {
+                let tokens = emit_tokens(&content, input_info.format)
+                    .unwrap_or_else(|diags| reporter.exit_with(&diags, &input_info.src_path, src));
+                fs::write(&output_info.tokens_path, tokens).unwrap_or_else(|err| {
+                    reporter.exit_failure(&format!("{}\nError occurs while outputting tokens", err))
+                });
+            }
+            Emit::Ast => {
+                let ast = emit_ast(&content, input_info.format)
+                    .unwrap_or_else(|diags| reporter.exit_with(&diags, &input_info.src_path, src));
+                fs::write(&output_info.ast_path, ast).unwrap_or_else(|err| {
+                    reporter.exit_failure(&format!("{}\nError occurs while outputting AST", err))
+                });
+            }
+            Emit::Ir => {
+                let ir = emit_ir(&content, input_info.format, opts.crate_type, opts.opt_level)
+                    .unwrap_or_else(|diags| reporter.exit_with(&diags, &input_info.src_path, src));
+                fs::write(&output_info.ir_path, ir).unwrap_or_else(|err| {
+                    reporter.exit_failure(&format!(
+                        "{}\nError occurs while outputting intermediate representation",
+                        err
+                    ))
+                });
+            }
+            Emit::Asm | Emit::Obj | Emit::Bin => {}
+        }
+    }
+    if !emit_asm {
+        return;
     }
 
-    let status = Command::new("ld")
-        .args(&[
-            "-o",
-            output_info.bin_path.to_str().unwrap(),
-            output_info.obj_path.to_str().unwrap(),
-        ])
-        .status()
-        .unwrap_or_else(|err| {
-            exit_failure(&format!(
-                "{}\nUnable to find `ld`, perhaps install Linker and set PATH",
-                err
-            ))
-        });
+    let CompileOutput {
+        asm: asm_output,
+        binary,
+        libs,
+        header,
+        opt_stats,
+    } = compile(
+        &content,
+        input_info.format,
+        &input_info.name,
+        &opts.compile_config(),
+    )
+    .unwrap_or_else(|diags| reporter.exit_with(&diags, &input_info.src_path, src));
+
+    if opts.verbose {
+        for stats in opt_stats.iter() {
+            println!("Pass `{}`: {} change(s)", stats.name, stats.changes);
+        }
+    }
+
+    match opts.target {
+        Target::Wasm32Wasi => {
+            write_output(
+                &reporter,
+                &output_info.wat_path,
+                asm_output,
+                "WebAssembly text",
+            );
+            if emit_bin {
+                write_output(
+                    &reporter,
+                    &output_info.wasm_path,
+                    binary.unwrap(),
+                    "WebAssembly module",
+                );
+            }
+        }
+        Target::Bytecode => {
+            write_output(
+                &reporter,
+                &output_info.basc_path,
+                binary.unwrap(),
+                "bytecode",
+            );
+        }
+        Target::I386Flat => {
+            write_output(
+                &reporter,
+                &output_info.asm_path,
+                asm_output,
+                "assembly program",
+            );
+            if emit_bin {
+                write_output(
+                    &reporter,
+                    &output_info.bin_path,
+                    binary.unwrap(),
+                    "flat binary",
+                );
+            }
+        }
+        Target::Llvm => {
+            write_output(&reporter, &output_info.ll_path, asm_output, "LLVM IR");
+            if !emit_obj {
+                return;
+            }
+
+            // llc がインストールされていなければ、LLVM IR の出力のみで終える
+            let llc_args = [
+                "-filetype=obj",
+                "-relocation-model=pic",
+                "-o",
+                output_info.obj_path.to_str().unwrap(),
+                output_info.ll_path.to_str().unwrap(),
+            ];
+            if !execute(&reporter, "llc", &llc_args) {
+                eprintln!("`llc` is not found, so only LLVM IR is generated");
+                return;
+            }
+            if emit_bin {
+                let mut args = vec![
+                    "-o".to_owned(),
+                    output_info.bin_path.to_str().unwrap().to_owned(),
+                    output_info.obj_path.to_str().unwrap().to_owned(),
+                ];
+                args.extend(libs.iter().map(|lib| format!("-l{}", lib)));
+                execute_required(&reporter, "cc", &args, "install C compiler");
+            }
+        }
+        Target::C => {
+            write_output(&reporter, &output_info.c_path, asm_output, "C source");
+            if !emit_obj {
+                return;
+            }
+
+            // C コンパイラがインストールされていなければ、C のソースコードの出力のみで終える
+            let mut args = vec!["-std=c99".to_owned()];
+            if emit_bin {
+                args.extend([
+                    "-o".to_owned(),
+                    output_info.bin_path.to_str().unwrap().to_owned(),
+                    output_info.c_path.to_str().unwrap().to_owned(),
+                ]);
+                args.extend(libs.iter().map(|lib| format!("-l{}", lib)));
+            } else {
+                args.extend([
+                    "-c".to_owned(),
+                    "-o".to_owned(),
+                    output_info.obj_path.to_str().unwrap().to_owned(),
+                    output_info.c_path.to_str().unwrap().to_owned(),
+                ]);
+            }
+            if !execute(&reporter, "cc", &args) {
+                eprintln!("`cc` is not found, so only C source is generated");
+            }
+        }
+        Target::Dotnet => {
+            write_output(&reporter, &output_info.il_path, asm_output, "CIL assembly");
+            if emit_bin {
+                // 入力ファイルと同じディレクトリに `.exe` が出力される
+                execute_required(
+                    &reporter,
+                    "ilasm",
+                    &[output_info.il_path.to_str().unwrap()],
+                    "install it",
+                );
+            }
+        }
+        Target::X64Linux | Target::X64Darwin => {
+            write_output(
+                &reporter,
+                &output_info.asm_path,
+                asm_output,
+                "assembly program",
+            );
+            if !emit_obj {
+                return;
+            }
 
-    if !status.success() {
-        exit_failure("Error occurs while executing `ld`");
+            // 内蔵のアセンブラで実行可能ファイルを生成した場合は、NASM と ld を使わない
+            if let Some(binary) = binary {
+                write_executable(&output_info.bin_path, &binary).unwrap_or_else(|err| {
+                    reporter.exit_failure(&format!(
+                        "{}\nError occurs while outputting executable",
+                        err
+                    ))
+                });
+                return;
+            }
+
+            match opts.asm_syntax {
+                AsmSyntax::Intel => execute_required(
+                    &reporter,
+                    "nasm",
+                    &["-f", "elf64", output_info.asm_path.to_str().unwrap()],
+                    "install NASM",
+                ),
+                AsmSyntax::Att => execute_required(
+                    &reporter,
+                    "as",
+                    &[
+                        "-o",
+                        output_info.obj_path.to_str().unwrap(),
+                        output_info.asm_path.to_str().unwrap(),
+                    ],
+                    "install GNU Binutils",
+                ),
+            }
+            if !emit_bin {
+                return;
+            }
+
+            if opts.crate_type == CrateType::StaticLib {
+                execute_required(
+                    &reporter,
+                    "ar",
+                    &[
+                        "rcs",
+                        output_info.lib_path.to_str().unwrap(),
+                        output_info.obj_path.to_str().unwrap(),
+                    ],
+                    "install GNU Binutils",
+                );
+                write_output(
+                    &reporter,
+                    &output_info.header_path,
+                    header.unwrap(),
+                    "C header",
+                );
+            } else if libs.is_empty() {
+                execute_required(
+                    &reporter,
+                    "ld",
+                    &[
+                        "-o",
+                        output_info.bin_path.to_str().unwrap(),
+                        output_info.obj_path.to_str().unwrap(),
+                    ],
+                    "install Linker",
+                );
+            } else {
+                // 外部ライブラリを呼び出す場合は、C コンパイラ経由で libc などと動的リンクする
+                let mut args = vec![
+                    "-nostartfiles".to_owned(),
+                    "-no-pie".to_owned(),
+                    "-o".to_owned(),
+                    output_info.bin_path.to_str().unwrap().to_owned(),
+                    output_info.obj_path.to_str().unwrap().to_owned(),
+                ];
+                args.extend(libs.iter().map(|lib| format!("-l{}", lib)));
+                execute_required(&reporter, "cc", &args, "install C compiler");
+            }
+        }
+    }
+}
+
+/// 生成物をファイルに書き込み、失敗した場合は ``what`` を示して終了する
+fn write_output<C: AsRef<[u8]>>(reporter: &Reporter, path: &Path, contents: C, what: &str) {
+    fs::write(path, contents).unwrap_or_else(|err| {
+        reporter.exit_failure(&format!("{}\nError occurs while outputting {}", err, what))
+    });
+}
+
+/// 外部のコマンドを実行し、失敗した場合は終了する
+///
+/// コマンドが見つからない場合は ``false`` を返す
+fn execute<S: AsRef<OsStr>>(reporter: &Reporter, program: &str, args: &[S]) -> bool {
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => true,
+        Ok(_) => reporter.exit_failure(&format!("Error occurs while executing `{}`", program)),
+        Err(err) if err.kind() == ErrorKind::NotFound => false,
+        Err(err) => reporter.exit_failure(&format!(
+            "{}\nError occurs while executing `{}`",
+            err, program
+        )),
+    }
+}
+
+/// 外部のコマンドを実行し、見つからない場合は ``hint`` を示して終了する
+fn execute_required<S: AsRef<OsStr>>(reporter: &Reporter, program: &str, args: &[S], hint: &str) {
+    if !execute(reporter, program, args) {
+        reporter.exit_failure(&format!(
+            "Unable to find `{}`, perhaps {} and set PATH",
+            program, hint
+        ));
     }
 }
 
-fn exit_failure(msg: &str) -> ! {
-    eprintln!("{}", red_bold(msg));
-    process::exit(1);
+#[cfg(unix)]
+fn write_executable(path: &Path, binary: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::write(path, binary)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn write_executable(path: &Path, binary: &[u8]) -> io::Result<()> {
+    fs::write(path, binary)
+}
+
+/// 診断を ``--error-format`` で指定された形式で標準エラー出力に出力して、終了コード 1 で終了する
+#[derive(Clone, Copy)]
+struct Reporter {
+    error_format: ErrorFormat,
+    /// 出力するエラーの最大個数 (0 の場合は制限しない)
+    max_errors: usize,
+}
+
+impl Reporter {
+    fn new(opts: &Options) -> Self {
+        Reporter {
+            error_format: opts.error_format,
+            max_errors: opts.max_errors,
+        }
+    }
+
+    fn exit_failure(&self, msg: &str) -> ! {
+        self.exit_with(&[Diagnostic::from(msg.to_owned())], Path::new(""), None)
+    }
+
+    /// ``--max-errors`` を超える分は出力せず、省略した個数のみを報告する
+    fn exit_with(&self, diags: &[Diagnostic], path: &Path, src: Option<&str>) -> ! {
+        let num_shown = if self.max_errors == 0 {
+            diags.len()
+        } else {
+            diags.len().min(self.max_errors)
+        };
+        let mut shown: Vec<Diagnostic> = diags[..num_shown].to_vec();
+        if num_shown < diags.len() {
+            let num_omitted = diags.len() - num_shown;
+            shown.push(Diagnostic::from(format!(
+                "{} more error{} not shown (use `--max-errors` to change the limit)",
+                num_omitted,
+                if num_omitted == 1 { "" } else { "s" }
+            )));
+        }
+
+        let color = stderr_supports_color();
+        for (i, diag) in shown.iter().enumerate() {
+            match self.error_format {
+                ErrorFormat::Human => {
+                    // rustc と同様に、診断の間を空行で区切る
+                    if i > 0 {
+                        eprintln!();
+                    }
+                    eprint!("{}", diag.render(&path.display().to_string(), src, color));
+                }
+                ErrorFormat::Json => eprintln!("{}", diag.to_json()),
+            }
+        }
+        process::exit(1);
+    }
 }
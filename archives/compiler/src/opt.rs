@@ -0,0 +1,885 @@
+use super::ast::Type;
+use super::ir::{Ir, IrInst};
+use super::OptLevel;
+use std::collections::{HashMap, HashSet};
+
+/// 最適化パス (変更した箇所の個数を返す)
+struct Pass {
+    name: &'static str,
+    run: fn(&mut Ir) -> usize,
+}
+
+/// ``-O1`` で実行するパス
+static O1_PASSES: [Pass; 3] = [
+    Pass {
+        name: "string-pool-dedup",
+        run: dedup_string_pool,
+    },
+    Pass {
+        name: "constant-propagation",
+        run: propagate_constants,
+    },
+    Pass {
+        name: "copy-propagation",
+        run: propagate_copies,
+    },
+];
+
+/// ``-O2`` で ``-O1`` のパスに加えて実行するパス
+static O2_PASSES: [Pass; 2] = [
+    Pass {
+        name: "dead-store-elimination",
+        run: eliminate_dead_stores,
+    },
+    Pass {
+        name: "unused-global-elimination",
+        run: eliminate_unused_globals,
+    },
+];
+
+/// ``-O2`` でパスの列を繰り返す最大の回数
+const MAX_ITERATIONS: usize = 8;
+
+/// 最適化パスごとの統計情報
+pub struct PassStats {
+    pub name: &'static str,
+    /// 変更した箇所の個数
+    pub changes: usize,
+}
+
+/// 最適化レベルに応じたパスを中間表現に適用する
+///
+/// ``-O2`` では、変更がなくなるまでパスの列を繰り返す
+pub fn optimize(ir: &mut Ir, level: OptLevel) -> Vec<PassStats> {
+    let passes: Vec<&Pass> = match level {
+        OptLevel::O0 => Vec::new(),
+        OptLevel::O1 => O1_PASSES.iter().collect(),
+        OptLevel::O2 => O1_PASSES.iter().chain(O2_PASSES.iter()).collect(),
+    };
+    let iterations = match level {
+        OptLevel::O2 => MAX_ITERATIONS,
+        _ => 1,
+    };
+    run_passes(ir, &passes, iterations)
+}
+
+/// パスの列を、変更がなくなるか ``iterations`` 回に達するまで繰り返す
+fn run_passes(ir: &mut Ir, passes: &[&Pass], iterations: usize) -> Vec<PassStats> {
+    let mut stats: Vec<PassStats> = passes
+        .iter()
+        .map(|pass| PassStats {
+            name: pass.name,
+            changes: 0,
+        })
+        .collect();
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (pass, stat) in passes.iter().zip(stats.iter_mut()) {
+            let changes = (pass.run)(ir);
+            stat.changes += changes;
+            changed |= changes > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+    stats
+}
+
+/// 変数の格納場所
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Var {
+    Global(i32),
+    Local(i32),
+}
+
+impl Var {
+    /// 変数を読み込む命令であれば、読み込む変数を取得する
+    fn loaded_by(inst: &IrInst) -> Option<Self> {
+        match inst {
+            IrInst::GetGlobal(index) => Some(Var::Global(*index)),
+            IrInst::GetLocal(index) => Some(Var::Local(*index)),
+            _ => None,
+        }
+    }
+
+    /// 変数に代入する命令であれば、代入先の変数を取得する
+    fn stored_by(inst: &IrInst) -> Option<Self> {
+        match inst {
+            IrInst::SetGlobal(index) => Some(Var::Global(*index)),
+            IrInst::SetLocal(index) => Some(Var::Local(*index)),
+            _ => None,
+        }
+    }
+
+    fn get_inst(self) -> IrInst {
+        match self {
+            Var::Global(index) => IrInst::GetGlobal(index),
+            Var::Local(index) => IrInst::GetLocal(index),
+        }
+    }
+}
+
+/// 伝播の際に追跡する値
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+    Unknown,
+    Int(i64),
+    /// 文字列プールの文字列
+    Str(i32),
+    /// 変数の現在の値と同じ値
+    Copy(Var),
+}
+
+/// 手続きの引数の個数と、戻り値を持つかどうか
+struct Signatures {
+    externs: Vec<(usize, bool)>,
+    procs: Vec<(usize, bool)>,
+}
+
+impl Signatures {
+    fn new(ir: &Ir) -> Self {
+        Signatures {
+            externs: ir
+                .externs
+                .iter()
+                .map(|ext| (ext.params.len(), ext.ret.is_some()))
+                .collect(),
+            procs: ir
+                .procs
+                .iter()
+                .map(|proc| (proc.params.len(), proc.ret.is_some()))
+                .collect(),
+        }
+    }
+}
+
+/// ``RESUME`` があれば、トップレベルの文の開始位置に他の位置から移ってくる可能性がある
+fn has_resume(ir: &Ir) -> bool {
+    ir.insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::Resume(_, _)))
+}
+
+/// ``ON ERROR GOTO`` でエラーを捕捉する可能性があるかどうか
+fn traps_errors(ir: &Ir) -> bool {
+    ir.insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_))))
+}
+
+fn propagate_constants(ir: &mut Ir) -> usize {
+    propagate(ir, true)
+}
+
+fn propagate_copies(ir: &mut Ir) -> usize {
+    propagate(ir, false)
+}
+
+/// 変数の値を命令列の先頭から追跡し、定数 ( ``constants`` が ``true`` の場合) または
+/// 他の変数のコピー ( ``false`` の場合) である変数の読み込みを置き換える
+///
+/// 定数の伝播では、型が定まっている値に対する型の検査も取り除く
+fn propagate(ir: &mut Ir, constants: bool) -> usize {
+    let sigs = Signatures::new(ir);
+    let resets_at_stmt = has_resume(ir);
+    let mut changes = propagate_insts(&mut ir.insts, &sigs, resets_at_stmt, constants);
+    for proc in ir.procs.iter_mut() {
+        changes += propagate_insts(&mut proc.insts, &sigs, false, constants);
+    }
+    changes
+}
+
+fn propagate_insts(
+    insts: &mut Vec<IrInst>,
+    sigs: &Signatures,
+    resets_at_stmt: bool,
+    constants: bool,
+) -> usize {
+    let mut vars = HashMap::<Var, Value>::new();
+    let mut stack = Vec::<Value>::new();
+    let mut keep = vec![true; insts.len()];
+    let mut changes = 0;
+
+    // 変数の値が変わったので、その変数のコピーとして追跡している値を無効にする
+    fn invalidate(vars: &mut HashMap<Var, Value>, stack: &mut [Value], pred: impl Fn(Var) -> bool) {
+        for value in vars.values_mut().chain(stack.iter_mut()) {
+            if matches!(value, Value::Copy(var) if pred(*var)) {
+                *value = Value::Unknown;
+            }
+        }
+    }
+
+    for (i, inst) in insts.iter_mut().enumerate() {
+        match inst {
+            IrInst::GetStaticStr(index) => stack.push(Value::Str(*index)),
+            IrInst::GetImmInt(value) => stack.push(Value::Int(*value)),
+            IrInst::GetGlobal(_) | IrInst::GetLocal(_) => {
+                let var = Var::loaded_by(inst).unwrap();
+                let value = vars.get(&var).copied().unwrap_or(Value::Unknown);
+                match value {
+                    Value::Int(value) if constants => {
+                        *inst = IrInst::GetImmInt(value);
+                        changes += 1;
+                    }
+                    Value::Str(index) if constants => {
+                        *inst = IrInst::GetStaticStr(index);
+                        changes += 1;
+                    }
+                    Value::Copy(src) if !constants => {
+                        *inst = src.get_inst();
+                        changes += 1;
+                    }
+                    _ => {}
+                }
+                stack.push(match value {
+                    Value::Unknown => Value::Copy(var),
+                    value => value,
+                });
+            }
+            IrInst::SetGlobal(_) | IrInst::SetLocal(_) => {
+                let var = Var::stored_by(inst).unwrap();
+                let value = stack.pop().unwrap_or(Value::Unknown);
+                invalidate(&mut vars, &mut stack, |v| v == var);
+                let value = if value == Value::Copy(var) {
+                    Value::Unknown
+                } else {
+                    value
+                };
+                vars.insert(var, value);
+            }
+            IrInst::AssertType(ty, _) => {
+                let satisfied = matches!(
+                    (ty, stack.last()),
+                    (Type::Integer, Some(Value::Int(_))) | (Type::String, Some(Value::Str(_)))
+                );
+                if constants && satisfied {
+                    keep[i] = false;
+                    changes += 1;
+                }
+            }
+            IrInst::CallExtern(index) => {
+                let (num_params, has_ret) = sigs.externs[*index as usize];
+                stack.truncate(stack.len().saturating_sub(num_params));
+                if has_ret {
+                    stack.push(Value::Unknown);
+                }
+            }
+            IrInst::CallProc(index, _) => {
+                // BASIC で定義された手続きはグローバル変数を変更しうる
+                vars.retain(|var, _| !matches!(var, Var::Global(_)));
+                invalidate(&mut vars, &mut stack, |var| matches!(var, Var::Global(_)));
+                let (num_params, has_ret) = sigs.procs[*index as usize];
+                stack.truncate(stack.len().saturating_sub(num_params));
+                if has_ret {
+                    stack.push(Value::Unknown);
+                }
+            }
+            IrInst::Pop | IrInst::Print => {
+                stack.pop();
+            }
+            IrInst::GetErrCode | IrInst::GetErrLine => stack.push(Value::Unknown),
+            IrInst::OnErrorGoto(_) => {}
+            // 他の位置から移ってくる可能性のある位置と、後続の命令に実行が進まない命令
+            IrInst::BeginStmt(_) => {
+                if resets_at_stmt {
+                    vars.clear();
+                    stack.clear();
+                }
+            }
+            IrInst::Label(_) | IrInst::Resume(_, _) | IrInst::End | IrInst::RaiseError(_) => {
+                vars.clear();
+                stack.clear();
+            }
+        }
+    }
+
+    let mut keep = keep.into_iter();
+    insts.retain(|_| keep.next().unwrap());
+    changes
+}
+
+/// 生存している変数の集合
+#[derive(Default)]
+struct Liveness {
+    /// すべてのグローバル変数が生存しているかどうか
+    all_globals: bool,
+    vars: HashSet<Var>,
+}
+
+impl Liveness {
+    fn is_live(&self, var: Var) -> bool {
+        (self.all_globals && matches!(var, Var::Global(_))) || self.vars.contains(&var)
+    }
+}
+
+/// 後で読み込まれることのない変数への代入を取り除く
+fn eliminate_dead_stores(ir: &mut Ir) -> usize {
+    let traps = traps_errors(ir);
+    let mut changes = eliminate_dead_stores_in(&mut ir.insts, Liveness::default(), traps);
+    for proc in ir.procs.iter_mut() {
+        // 手続きから戻った後は、戻り値とグローバル変数を読み込む可能性がある
+        let mut live = Liveness {
+            all_globals: true,
+            ..Liveness::default()
+        };
+        live.vars.extend(proc.ret_slot().map(Var::Local));
+        changes += eliminate_dead_stores_in(&mut proc.insts, live, true);
+    }
+    changes
+}
+
+/// 命令列を末尾から走査して不要な代入を取り除く
+///
+/// ``live`` は命令列の末尾で生存している変数、 ``traps`` はエラー発生時にエラーハンドラに移る可能性があるかどうか
+fn eliminate_dead_stores_in(insts: &mut Vec<IrInst>, mut live: Liveness, traps: bool) -> usize {
+    let mut changes = 0;
+    for inst in insts.iter_mut().rev() {
+        match inst {
+            IrInst::GetGlobal(_) | IrInst::GetLocal(_) => {
+                live.vars.insert(Var::loaded_by(inst).unwrap());
+            }
+            IrInst::SetGlobal(_) | IrInst::SetLocal(_) => {
+                let var = Var::stored_by(inst).unwrap();
+                if !live.is_live(var) {
+                    *inst = IrInst::Pop;
+                    changes += 1;
+                }
+                live.vars.remove(&var);
+            }
+            // 呼び出した手続きはグローバル変数を読み込みうる
+            IrInst::CallProc(_, _) => live.all_globals = true,
+            // エラーハンドラや再開する文はグローバル変数を読み込みうる
+            IrInst::AssertType(_, _) if traps => live.all_globals = true,
+            IrInst::Resume(_, _) => live.all_globals = true,
+            IrInst::RaiseError(_) if traps => live.all_globals = true,
+            IrInst::RaiseError(_) | IrInst::End => live = Liveness::default(),
+            _ => {}
+        }
+    }
+    remove_unused_pushes(insts);
+    changes
+}
+
+/// 副作用なしにスタックに値を積む命令の直後の ``Pop`` を、その命令ごと取り除く
+fn remove_unused_pushes(insts: &mut Vec<IrInst>) {
+    let mut result = Vec::with_capacity(insts.len());
+    for inst in insts.drain(..) {
+        let is_pure_push = matches!(
+            result.last(),
+            Some(
+                IrInst::GetStaticStr(_)
+                    | IrInst::GetImmInt(_)
+                    | IrInst::GetGlobal(_)
+                    | IrInst::GetLocal(_)
+                    | IrInst::GetErrCode
+                    | IrInst::GetErrLine
+            )
+        );
+        if matches!(inst, IrInst::Pop) && is_pure_push {
+            result.pop();
+        } else {
+            result.push(inst);
+        }
+    }
+    *insts = result;
+}
+
+/// 中間表現のすべての命令列
+fn all_insts_mut(ir: &mut Ir) -> impl Iterator<Item = &mut IrInst> {
+    ir.insts
+        .iter_mut()
+        .chain(ir.procs.iter_mut().flat_map(|proc| proc.insts.iter_mut()))
+}
+
+/// 読み込まれることのないグローバル変数への代入を取り除き、使われないグローバル変数を詰める
+fn eliminate_unused_globals(ir: &mut Ir) -> usize {
+    let mut read = HashSet::<i32>::new();
+    for inst in all_insts_mut(ir) {
+        if let IrInst::GetGlobal(index) = inst {
+            read.insert(*index);
+        }
+    }
+
+    for inst in all_insts_mut(ir) {
+        if matches!(inst, IrInst::SetGlobal(index) if !read.contains(index)) {
+            *inst = IrInst::Pop;
+        }
+    }
+    remove_unused_pushes(&mut ir.insts);
+    for proc in ir.procs.iter_mut() {
+        remove_unused_pushes(&mut proc.insts);
+    }
+
+    // 読み込まれるグローバル変数のみを、元の順番のまま番号を付け直す
+    let mut new_indices = HashMap::<i32, i32>::new();
+    for index in 0..ir.num_globals {
+        if read.contains(&index) {
+            new_indices.insert(index, new_indices.len() as i32);
+        }
+    }
+    for inst in all_insts_mut(ir) {
+        if let IrInst::GetGlobal(index) | IrInst::SetGlobal(index) = inst {
+            *index = new_indices[index];
+        }
+    }
+
+    let changes = ir.num_globals as usize - new_indices.len();
+    ir.num_globals = new_indices.len() as i32;
+    changes
+}
+
+/// 文字列プールの重複する文字列と使われない文字列を取り除く
+fn dedup_string_pool(ir: &mut Ir) -> usize {
+    let mut used = HashSet::<i32>::new();
+    for inst in all_insts_mut(ir) {
+        if let IrInst::GetStaticStr(index) = inst {
+            used.insert(*index);
+        }
+    }
+
+    let mut pool = Vec::<String>::new();
+    let mut new_indices = HashMap::<i32, i32>::new();
+    for (index, str) in ir.string_pool.iter().enumerate() {
+        if !used.contains(&(index as i32)) {
+            continue;
+        }
+        let new_index = match pool.iter().position(|s| s == str) {
+            Some(position) => position,
+            None => {
+                pool.push(str.clone());
+                pool.len() - 1
+            }
+        };
+        new_indices.insert(index as i32, new_index as i32);
+    }
+
+    for inst in all_insts_mut(ir) {
+        if let IrInst::GetStaticStr(index) = inst {
+            *index = new_indices[index];
+        }
+    }
+
+    let changes = ir.string_pool.len() - pool.len();
+    ir.string_pool = pool;
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{sample_ir, sample_names};
+    use crate::ir_text::{parse_ir, print_ir};
+
+    /// テキスト形式の中間表現に ``pass`` を適用し、変更した箇所の個数と結果を返す
+    fn apply(pass: fn(&mut Ir) -> usize, src: &str) -> (usize, String) {
+        let mut ir = parse_ir(src).unwrap();
+        let changes = pass(&mut ir);
+        (changes, print_ir(&ir))
+    }
+
+    /// テキスト形式の中間表現を ``print_ir`` と同じ書式にする
+    fn normalize(src: &str) -> String {
+        print_ir(&parse_ir(src).unwrap())
+    }
+
+    #[test]
+    fn dedup_strings() {
+        let src = "string 0 \"a\"
+string 1 \"b\"
+string 2 \"a\"
+string 3 \"unused\"
+main {
+    get_static_str 2
+    print
+    get_static_str 1
+    print
+    get_static_str 0
+    print
+}
+";
+        let expected = "string 0 \"a\"
+string 1 \"b\"
+main {
+    get_static_str 0
+    print
+    get_static_str 1
+    print
+    get_static_str 0
+    print
+}
+";
+        assert_eq!(apply(dedup_string_pool, src), (2, normalize(expected)));
+        assert_eq!(apply(dedup_string_pool, expected), (0, normalize(expected)));
+    }
+
+    #[test]
+    fn propagate_constants_and_remove_type_checks() {
+        let src = "globals 2
+string 0 \"s\"
+main {
+    get_imm_int 5
+    set_global 0
+    get_static_str 0
+    set_global 1
+    get_global 0
+    assert_type integer @1:1-1:2
+    print
+    get_global 1
+    assert_type string @1:1-1:2
+    print
+    get_global 1
+    assert_type integer @1:1-1:2
+    print
+}
+";
+        // 型が一致しない検査は、実行時にエラーを報告するため残す
+        let expected = "globals 2
+string 0 \"s\"
+main {
+    get_imm_int 5
+    set_global 0
+    get_static_str 0
+    set_global 1
+    get_imm_int 5
+    print
+    get_static_str 0
+    print
+    get_static_str 0
+    assert_type integer @1:1-1:2
+    print
+}
+";
+        assert_eq!(apply(propagate_constants, src), (5, normalize(expected)));
+    }
+
+    #[test]
+    fn constants_do_not_cross_calls_and_labels() {
+        let src = "globals 1
+proc 0 P(n: integer) -> integer locals 3 {
+    get_imm_int 1
+    set_local 2
+    get_imm_int 2
+    set_global 0
+    get_imm_int 0
+    call_proc 0 @1:1-1:2
+    pop
+    get_local 2
+    get_global 0
+    pop
+    set_local 1
+}
+main {
+    get_imm_int 1
+    set_global 0
+    label 0
+    get_global 0
+    print
+}
+";
+        // 手続きの呼び出しはグローバル変数のみ、ラベルはすべての変数の値を不明にする
+        let expected = "globals 1
+proc 0 P(n: integer) -> integer locals 3 {
+    get_imm_int 1
+    set_local 2
+    get_imm_int 2
+    set_global 0
+    get_imm_int 0
+    call_proc 0 @1:1-1:2
+    pop
+    get_imm_int 1
+    get_global 0
+    pop
+    set_local 1
+}
+main {
+    get_imm_int 1
+    set_global 0
+    label 0
+    get_global 0
+    print
+}
+";
+        assert_eq!(apply(propagate_constants, src), (1, normalize(expected)));
+    }
+
+    #[test]
+    fn constants_do_not_cross_statements_with_resume() {
+        let src = "globals 1
+main {
+    begin_stmt 0
+    on_error_goto 0
+    begin_stmt 1
+    get_imm_int 1
+    set_global 0
+    begin_stmt 2
+    get_global 0
+    print
+    end
+    label 0
+    resume_next @1:1-1:2
+}
+";
+        // RESUME NEXT で文の先頭に移ってくる可能性があるため、文を越えて伝播しない
+        assert_eq!(apply(propagate_constants, src), (0, normalize(src)));
+
+        let without_resume = src.replace("resume_next @1:1-1:2", "end");
+        let (changes, _) = apply(propagate_constants, &without_resume);
+        assert_eq!(changes, 1);
+    }
+
+    #[test]
+    fn propagate_copies_until_source_changes() {
+        let src = "globals 3
+main {
+    get_err_code
+    set_global 0
+    get_global 0
+    set_global 1
+    get_global 1
+    print
+    get_imm_int 3
+    set_global 0
+    get_global 1
+    print
+}
+";
+        let expected = "globals 3
+main {
+    get_err_code
+    set_global 0
+    get_global 0
+    set_global 1
+    get_global 0
+    print
+    get_imm_int 3
+    set_global 0
+    get_global 1
+    print
+}
+";
+        assert_eq!(apply(propagate_copies, src), (1, normalize(expected)));
+    }
+
+    #[test]
+    fn eliminate_overwritten_stores() {
+        let src = "globals 1
+main {
+    get_imm_int 1
+    set_global 0
+    get_imm_int 2
+    set_global 0
+    get_global 0
+    print
+    get_imm_int 3
+    set_global 0
+}
+";
+        let expected = "globals 1
+main {
+    get_imm_int 2
+    set_global 0
+    get_global 0
+    print
+}
+";
+        assert_eq!(apply(eliminate_dead_stores, src), (2, normalize(expected)));
+    }
+
+    #[test]
+    fn keep_stores_read_by_error_handler() {
+        // 型の検査でエラーハンドラに移り、そこでグローバル変数を読み込む可能性がある
+        let src = "globals 1
+string 0 \"s\"
+main {
+    on_error_goto 0
+    get_imm_int 1
+    set_global 0
+    get_static_str 0
+    assert_type integer @1:1-1:2
+    print
+    get_imm_int 2
+    set_global 0
+    end
+    label 0
+    get_global 0
+    print
+    end
+}
+";
+        // 最初の代入は残し、プログラムの終了の直前の代入のみを取り除く
+        let expected = src.replace("    get_imm_int 2\n    set_global 0\n", "");
+        assert_eq!(apply(eliminate_dead_stores, src), (1, normalize(&expected)));
+
+        // エラーを捕捉しなければ、エラーの発生後に実行は進まない
+        let without_handler = src.replace("    on_error_goto 0\n", "");
+        let (changes, _) = apply(eliminate_dead_stores, &without_handler);
+        assert_eq!(changes, 2);
+    }
+
+    #[test]
+    fn keep_stores_before_resume() {
+        let src = "globals 1
+main {
+    begin_stmt 0
+    on_error_goto 0
+    begin_stmt 1
+    get_imm_int 1
+    set_global 0
+    begin_stmt 2
+    get_global 0
+    print
+    end
+    label 0
+    get_imm_int 2
+    set_global 0
+    resume @1:1-1:2
+}
+";
+        assert_eq!(apply(eliminate_dead_stores, src), (0, normalize(src)));
+    }
+
+    #[test]
+    fn keep_global_and_return_stores_in_procs() {
+        let src = "globals 1
+proc 0 F(n: integer) -> integer locals 3 {
+    get_local 0
+    set_local 2
+    get_imm_int 7
+    set_global 0
+    get_local 0
+    set_local 1
+}
+main {
+    get_global 0
+    print
+}
+";
+        let expected = "globals 1
+proc 0 F(n: integer) -> integer locals 3 {
+    get_imm_int 7
+    set_global 0
+    get_local 0
+    set_local 1
+}
+main {
+    get_global 0
+    print
+}
+";
+        assert_eq!(apply(eliminate_dead_stores, src), (1, normalize(expected)));
+    }
+
+    #[test]
+    fn eliminate_and_renumber_globals() {
+        let src = "globals 3
+proc 0 P() locals 0 {
+    get_imm_int 1
+    set_global 0
+    get_global 2
+    print
+}
+main {
+    get_imm_int 2
+    set_global 1
+    get_imm_int 3
+    set_global 2
+    call_proc 0 @1:1-1:2
+}
+";
+        let expected = "globals 1
+proc 0 P() locals 0 {
+    get_global 0
+    print
+}
+main {
+    get_imm_int 3
+    set_global 0
+    call_proc 0 @1:1-1:2
+}
+";
+        assert_eq!(
+            apply(eliminate_unused_globals, src),
+            (2, normalize(expected))
+        );
+    }
+
+    /// ``length`` 個のグローバル変数に順に値をコピーしていくだけの、何も出力しないプログラム
+    fn copy_chain(length: i32) -> Ir {
+        let mut src = format!("globals {}\nmain {{\n    get_err_code\n", length);
+        for index in 0..length {
+            src.push_str(&format!("    set_global {}\n", index));
+            if index + 1 < length {
+                src.push_str(&format!("    get_global {}\n", index));
+            }
+        }
+        src.push_str("}\n");
+        parse_ir(&src).unwrap()
+    }
+
+    fn total_changes(stats: &[PassStats]) -> usize {
+        stats.iter().map(|stat| stat.changes).sum()
+    }
+
+    #[test]
+    fn o2_repeats_passes_until_fixpoint() {
+        let mut ir = copy_chain(4);
+        let stats = optimize(&mut ir, OptLevel::O2);
+        assert!(total_changes(&stats) > 0);
+        assert_eq!(print_ir(&ir), normalize("main {\n}\n"));
+        assert_eq!(total_changes(&optimize(&mut ir, OptLevel::O2)), 0);
+
+        for name in sample_names() {
+            let mut ir = sample_ir(&name);
+            optimize(&mut ir, OptLevel::O2);
+            let stats = optimize(&mut ir, OptLevel::O2);
+            assert_eq!(total_changes(&stats), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn o2_stops_after_max_iterations() {
+        // 毎回変更を報告するパスは、最大の回数で打ち切られる
+        let pass = Pass {
+            name: "always-changes",
+            run: |ir| {
+                ir.num_globals += 1;
+                1
+            },
+        };
+        let mut ir = copy_chain(1);
+        let stats = run_passes(&mut ir, &[&pass], MAX_ITERATIONS);
+        assert_eq!(stats[0].changes, MAX_ITERATIONS);
+        assert_eq!(ir.num_globals as usize, 1 + MAX_ITERATIONS);
+    }
+
+    #[test]
+    fn levels_select_passes() {
+        let names = |level| {
+            let mut ir = copy_chain(1);
+            optimize(&mut ir, level)
+                .iter()
+                .map(|stat| stat.name)
+                .collect::<Vec<_>>()
+        };
+        assert!(names(OptLevel::O0).is_empty());
+        assert_eq!(
+            names(OptLevel::O1),
+            [
+                "string-pool-dedup",
+                "constant-propagation",
+                "copy-propagation"
+            ]
+        );
+        assert_eq!(
+            names(OptLevel::O2),
+            [
+                "string-pool-dedup",
+                "constant-propagation",
+                "copy-propagation",
+                "dead-store-elimination",
+                "unused-global-elimination"
+            ]
+        );
+    }
+}
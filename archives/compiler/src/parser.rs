@@ -1,120 +1,211 @@
-use super::ast::{ExprAst, StmtAst};
-use super::location::Locatable;
-use super::term_color::red_bold;
+use super::ast::{
+    ExprAst, ExternDeclAst, ParamAst, ProcDefAst, ResumeTarget, StmtAst, Type, TypeAst,
+};
+use super::diagnostic::Diagnostic;
+use super::location::{Locatable, Location};
 use super::token::{Identifier, Token};
-use once_cell::sync::Lazy;
 
-/// シンタックスエラーのメッセージのプレフィックス
-static SYNTAX_ERROR: Lazy<String> = Lazy::new(|| red_bold("Syntax error:"));
+/// 手続きの仮引数リストと戻り値の型
+type Signature = (Vec<ParamAst>, Option<TypeAst>);
 
 /// 予約語リスト
-static RESERVED_WORDS: [&str; 2] = ["PRINT", "VAR"];
+pub static RESERVED_WORDS: [&str; 17] = [
+    "AS", "DECLARE", "END", "ERL", "ERR", "ERROR", "FUNCTION", "GOTO", "INTEGER", "LIB", "NEXT",
+    "ON", "PRINT", "RESUME", "STRING", "SUB", "VAR",
+];
+
+/// 式の中で参照できる組み込みの変数
+static BUILTIN_VARS: [&str; 2] = ["ERL", "ERR"];
 
 /// トークン列を元に抽象構文木を生成する
-pub fn parse(tokens: &[Token]) -> Result<Vec<StmtAst>, String> {
+///
+/// 構文エラーが見つかった場合は、次の改行まで読み飛ばして解析を続け、見つかったすべてのエラーを返す
+pub fn parse(tokens: &[Token]) -> Result<Vec<StmtAst>, Vec<Diagnostic>> {
+    let (stmts, errors) = parse_partial(tokens);
+    if errors.is_empty() {
+        Ok(stmts)
+    } else {
+        Err(errors)
+    }
+}
+
+/// 構文エラーのある文を除いた抽象構文木と、見つかったすべてのエラーを返す
+///
+/// 書きかけのソースコードも解析する必要のある、エディタの支援 (Language Server) で用いる
+pub fn parse_partial(tokens: &[Token]) -> (Vec<StmtAst>, Vec<Diagnostic>) {
+    let mut errors = Vec::new();
+    let (stmts, _) = parse_block(tokens, None, &mut errors);
+    (stmts, errors)
+}
+
+/// 1つの文をパースした結果
+enum Parsed<'a> {
+    /// 文と、それに続くトークン列
+    Stmt(StmtAst, &'a [Token]),
+    /// エラーを報告して読み飛ばした後に続くトークン列
+    Skipped(&'a [Token]),
+    /// ``END <end_kind>`` に続くトークン列
+    End(&'a [Token]),
+}
+
+/// 文の列をパースする
+///
+/// ``end_kind`` が指定されている場合は ``END <end_kind>`` までを手続きの本体として読み進める。
+/// エラーのある文は ``errors`` に追加して、次の改行から解析を再開する
+fn parse_block<'a>(
+    tokens: &'a [Token],
+    end_kind: Option<&str>,
+    errors: &mut Vec<Diagnostic>,
+) -> (Vec<StmtAst>, &'a [Token]) {
     let mut tokens = tokens;
     let mut stmts = Vec::<StmtAst>::new();
 
     loop {
-        loop {
-            match tokens.first() {
-                Some(Token::LineBreak(_)) => {
-                    tokens = &tokens[1..];
-                    continue;
-                }
-                Some(_) => {
-                    break;
-                }
-                None => {
-                    return Ok(stmts);
-                }
+        while let Some(Token::LineBreak(_)) = tokens.first() {
+            tokens = &tokens[1..];
+        }
+        if tokens.is_empty() {
+            if let Some(kind) = end_kind {
+                errors.push(unexpected(&format!("`END {}`", kind), None));
             }
+            return (stmts, tokens);
         }
 
-        match tokens.first() {
-            Some(Token::Ident(ident)) if ident.name == "VAR" => match tokens.get(1) {
-                Some(Token::Ident(var_ident)) => {
-                    validate_var_ident(&var_ident)?;
-
-                    match tokens.get(2) {
-                        Some(Token::Equal(_)) => {
-                            let (expr, rest) = parse_expr(&tokens[3..])?;
-                            stmts.push(StmtAst::VarDecl(var_ident.clone(), expr));
-                            expect_eol(&rest)?;
-                            tokens = rest;
-                        }
-                        Some(token) => {
-                            return Err(format!(
-                                "{} ({}) `=` expected but {:?} found",
-                                SYNTAX_ERROR.as_str(),
-                                token.locate(),
-                                token
-                            ));
-                        }
-                        None => {
-                            return Err(format!(
-                                "{} `=` expected but [EOF] found",
-                                SYNTAX_ERROR.as_str()
-                            ));
-                        }
-                    }
+        match parse_stmt(tokens, end_kind, errors) {
+            Ok(Parsed::Stmt(stmt, rest)) => {
+                stmts.push(stmt);
+                tokens = rest;
+            }
+            Ok(Parsed::Skipped(rest)) => {
+                tokens = rest;
+            }
+            Ok(Parsed::End(rest)) => {
+                return (stmts, rest);
+            }
+            Err(diag) => {
+                errors.push(diag);
+                tokens = skip_line(tokens);
+            }
+        }
+    }
+}
+
+/// 改行または空でないトークン列の先頭から、1つの文をパースする
+fn parse_stmt<'a>(
+    tokens: &'a [Token],
+    end_kind: Option<&str>,
+    errors: &mut Vec<Diagnostic>,
+) -> Result<Parsed<'a>, Diagnostic> {
+    match tokens.first() {
+        Some(Token::Ident(ident)) if ident.name == "END" => match (end_kind, tokens.get(1)) {
+            (_, None) | (_, Some(Token::LineBreak(_))) => {
+                Ok(Parsed::Stmt(StmtAst::End(ident.locate()), &tokens[1..]))
+            }
+            (Some(kind), Some(Token::Ident(kind_ident))) if kind_ident.name == kind => {
+                let rest = &tokens[2..];
+                // 後ろに余分なトークンがあっても、手続きの終わりとして扱う
+                if let Err(diag) = expect_eol(rest) {
+                    errors.push(diag);
+                    return Ok(Parsed::End(skip_line(rest)));
                 }
-                Some(Token::LineBreak(line_break)) => {
-                    return Err(format!(
-                        "{} ({}) Identifier expected but [EOL] found",
-                        SYNTAX_ERROR.as_str(),
-                        line_break.locate()
-                    ));
+                Ok(Parsed::End(rest))
+            }
+            (Some(kind), token) => Err(unexpected(&format!("`{}`", kind), token)),
+            (None, _) => Err(
+                Diagnostic::error("E0102", "`END` without `SUB` or `FUNCTION`")
+                    .with_label(ident.locate(), "no procedure to end")
+                    .with_help("use `END` alone to end the program"),
+            ),
+        },
+        Some(Token::Ident(ident)) if ident.name == "ON" => {
+            let rest = expect_keyword(&tokens[1..], "ERROR")?;
+            let rest = expect_keyword(rest, "GOTO")?;
+            let handler = match rest.first() {
+                Some(Token::IntLit(int_lit)) if int_lit.value == 0 => None,
+                Some(Token::Ident(label)) => {
+                    validate_var_ident(label)?;
+                    Some(label.clone())
                 }
-                Some(token) => {
-                    return Err(format!(
-                        "{} ({}) Identifier expected but {:?} found",
-                        SYNTAX_ERROR.as_str(),
-                        token.locate(),
-                        token
-                    ));
+                token => return Err(unexpected("Label or `0`", token)),
+            };
+            let rest = &rest[1..];
+            expect_eol(rest)?;
+            Ok(Parsed::Stmt(
+                StmtAst::OnErrorGoto(ident.locate(), handler),
+                rest,
+            ))
+        }
+        Some(Token::Ident(ident)) if ident.name == "RESUME" => {
+            let (target, rest) = match tokens.get(1) {
+                Some(Token::Ident(next)) if next.name == "NEXT" => {
+                    (ResumeTarget::Next, &tokens[2..])
                 }
-                None => {
-                    return Err(format!(
-                        "{} Identifier expected but [EOF] found",
-                        SYNTAX_ERROR.as_str()
-                    ));
+                _ => (ResumeTarget::Retry, &tokens[1..]),
+            };
+            expect_eol(rest)?;
+            Ok(Parsed::Stmt(StmtAst::Resume(ident.locate(), target), rest))
+        }
+        Some(Token::Ident(ident)) if ident.name == "ERROR" => {
+            let (expr, rest) = parse_expr(&tokens[1..])?;
+            expect_eol(rest)?;
+            Ok(Parsed::Stmt(StmtAst::Error(ident.locate(), expr), rest))
+        }
+        Some(Token::Ident(ident)) if matches!(tokens.get(1), Some(Token::Colon(_))) => {
+            // ラベルの後には、改行せずに文を続けてもよい
+            validate_var_ident(ident)?;
+            Ok(Parsed::Stmt(StmtAst::Label(ident.clone()), &tokens[2..]))
+        }
+        Some(Token::Ident(ident)) if ident.name == "SUB" || ident.name == "FUNCTION" => Ok(
+            parse_proc_def(ident.name == "FUNCTION", &tokens[1..], errors),
+        ),
+        Some(Token::Ident(ident)) if ident.name == "VAR" => match tokens.get(1) {
+            Some(Token::Ident(var_ident)) => {
+                validate_var_ident(var_ident)?;
+
+                match tokens.get(2) {
+                    Some(Token::Equal(_)) => {
+                        let (expr, rest) = parse_expr(&tokens[3..])?;
+                        expect_eol(rest)?;
+                        Ok(Parsed::Stmt(
+                            StmtAst::VarDecl(var_ident.clone(), expr),
+                            rest,
+                        ))
+                    }
+                    token => Err(unexpected("`=`", token)),
                 }
-            },
-            Some(Token::Ident(ident)) => {
-                // 先頭のトークンが識別子なら、代入文と手続き呼び出しの2通りが想定される
-                let (ast, rest) = parse_proc_call(ident, &tokens[1..])
-                    .or_else(|_| parse_var_assign(ident, &tokens[1..]))?;
-                stmts.push(ast);
-                expect_eol(&rest)?;
-                tokens = rest;
-            }
-            Some(head) => {
-                println!("{:?}", head);
-                return Err(format!(
-                    "{} ({}) Identifier expected but {:?} found",
-                    SYNTAX_ERROR.as_str(),
-                    head.locate(),
-                    head
-                ));
-            }
-            None => {
-                unreachable!();
             }
+            token => Err(unexpected("Identifier", token)),
+        },
+        Some(Token::Ident(ident)) if ident.name == "DECLARE" => {
+            let (ast, rest) = parse_extern_decl(&tokens[1..])?;
+            expect_eol(rest)?;
+            Ok(Parsed::Stmt(ast, rest))
         }
+        Some(Token::Ident(ident)) => {
+            // 先頭のトークンが識別子なら、代入文と手続き呼び出しの2通りが想定される
+            let (ast, rest) = parse_proc_call(ident, &tokens[1..])
+                .or_else(|_| parse_var_assign(ident, &tokens[1..]))?;
+            expect_eol(rest)?;
+            Ok(Parsed::Stmt(ast, rest))
+        }
+        head => Err(unexpected("Identifier", head)),
     }
 }
 
-fn expect_eol(tokens: &[Token]) -> Result<(), String> {
+/// 次の改行 (またはトークン列の終わり) まで読み飛ばす
+fn skip_line(tokens: &[Token]) -> &[Token] {
+    let pos = tokens
+        .iter()
+        .position(|token| matches!(token, Token::LineBreak(_)))
+        .unwrap_or(tokens.len());
+    &tokens[pos..]
+}
+
+fn expect_eol(tokens: &[Token]) -> Result<(), Diagnostic> {
     if let Some(head) = tokens.first() {
         match head {
             Token::LineBreak(_) => Ok(()),
-            _ => Err(format!(
-                "{} ({}) [EOL] or [EOF] expected but {:?} found",
-                SYNTAX_ERROR.as_str(),
-                head.locate(),
-                head
-            )),
+            _ => Err(unexpected("[EOL] or [EOF]", Some(head))),
         }
     } else {
         Ok(())
@@ -124,46 +215,42 @@ fn expect_eol(tokens: &[Token]) -> Result<(), String> {
 fn parse_var_assign<'a>(
     ident: &Identifier,
     tokens: &'a [Token],
-) -> Result<(StmtAst, &'a [Token]), String> {
-    validate_var_ident(&ident)?;
+) -> Result<(StmtAst, &'a [Token]), Diagnostic> {
+    validate_var_ident(ident)?;
 
     match tokens.first() {
         Some(Token::Equal(_)) => {
             let (expr_ast, rest) = parse_expr(&tokens[1..])?;
             Ok((StmtAst::VarAssign(ident.clone(), expr_ast), rest))
         }
-        Some(token) => Err(format!(
-            "{} ({}) `=` expected but {:?} found",
-            SYNTAX_ERROR.as_str(),
-            token.locate(),
-            token
-        )),
-        None => Err(format!(
-            "{} ({}) `=` expected but [EOF] found",
-            SYNTAX_ERROR.as_str(),
-            ident.locate().end
-        )),
+        Some(token) => Err(unexpected("`=`", Some(token))),
+        None => {
+            let end = ident.locate().end;
+            Err(unexpected("`=`", None).with_label(Location { start: end, end }, "expected `=`"))
+        }
     }
 }
 
 fn parse_proc_call<'a>(
     ident: &Identifier,
     tokens: &'a [Token],
-) -> Result<(StmtAst, &'a [Token]), String> {
+) -> Result<(StmtAst, &'a [Token]), Diagnostic> {
     let (args, rest) = parse_argument_list(tokens)?;
     Ok((StmtAst::ProcCall((*ident).clone(), args), rest))
 }
 
-fn parse_argument_list(tokens: &[Token]) -> Result<(Vec<ExprAst>, &[Token]), String> {
+fn parse_argument_list(tokens: &[Token]) -> Result<(Vec<ExprAst>, &[Token]), Diagnostic> {
     let mut args = Vec::<ExprAst>::new();
+
+    // 引数を取らない手続き呼び出し
+    if let None | Some(Token::LineBreak(_)) = tokens.first() {
+        return Ok((args, tokens));
+    }
+
     let (first_arg, mut remaining_tokens) = parse_expr(tokens)?;
     args.push(first_arg);
-    loop {
-        if let Some(Token::Comma { .. }) = remaining_tokens.first() {
-            remaining_tokens = &remaining_tokens[1..];
-        } else {
-            break;
-        }
+    while let Some(Token::Comma { .. }) = remaining_tokens.first() {
+        remaining_tokens = &remaining_tokens[1..];
         match parse_expr(remaining_tokens) {
             Ok((arg, rest)) => {
                 args.push(arg);
@@ -177,36 +264,286 @@ fn parse_argument_list(tokens: &[Token]) -> Result<(Vec<ExprAst>, &[Token]), Str
     Ok((args, remaining_tokens))
 }
 
-fn parse_expr(tokens: &[Token]) -> Result<(ExprAst, &[Token]), String> {
+fn parse_expr(tokens: &[Token]) -> Result<(ExprAst, &[Token]), Diagnostic> {
     match tokens.first() {
         Some(Token::StrLit(str_lit)) => Ok((ExprAst::StrLit(str_lit.clone()), &tokens[1..])),
+        Some(Token::IntLit(int_lit)) => Ok((ExprAst::IntLit(int_lit.clone()), &tokens[1..])),
+        Some(Token::Ident(ident)) if BUILTIN_VARS.contains(&ident.name.as_str()) => {
+            Ok((ExprAst::Ident(ident.clone()), &tokens[1..]))
+        }
         Some(Token::Ident(ident)) => {
-            validate_var_ident(&ident)?;
+            validate_var_ident(ident)?;
+
+            if let Some(Token::LParen(_)) = tokens.get(1) {
+                let (args, rest) = parse_paren_list(&tokens[2..], parse_expr)?;
+                return Ok((ExprAst::Call(ident.clone(), args), rest));
+            }
 
             Ok((ExprAst::Ident(ident.clone()), &tokens[1..]))
         }
-        Some(token) => Err(format!(
-            "{} ({}) Expression expected but {:?} found",
-            SYNTAX_ERROR.as_str(),
-            token.locate(),
-            token
-        )),
-        None => Err(format!(
-            "{} Expression expected but [EOF] found",
-            SYNTAX_ERROR.as_str()
-        )),
+        Some(Token::LParen(_)) => {
+            let (expr, rest) = parse_expr(&tokens[1..])?;
+            match rest.first() {
+                Some(Token::RParen(_)) => Ok((expr, &rest[1..])),
+                token => Err(unexpected("`)`", token)),
+            }
+        }
+        token => Err(unexpected("Expression", token)),
     }
 }
 
-fn validate_var_ident(var_ident: &Identifier) -> Result<(), String> {
+fn validate_var_ident(var_ident: &Identifier) -> Result<(), Diagnostic> {
     if RESERVED_WORDS.contains(&var_ident.name.as_str()) {
-        Err(format!(
-            "{} ({}) `{}` is not allowed as a variable name",
-            SYNTAX_ERROR.as_str(),
-            var_ident.locate(),
-            var_ident.name
-        ))
+        Err(Diagnostic::error(
+            "E0103",
+            format!("`{}` is not allowed as a variable name", var_ident.name),
+        )
+        .with_label(var_ident.locate(), "reserved word"))
     } else {
         Ok(())
     }
 }
+
+/// ``DECLARE`` に続く外部手続きの宣言をパースする
+fn parse_extern_decl(tokens: &[Token]) -> Result<(StmtAst, &[Token]), Diagnostic> {
+    let is_function = match tokens.first() {
+        Some(Token::Ident(kind)) if kind.name == "FUNCTION" => true,
+        Some(Token::Ident(kind)) if kind.name == "SUB" => false,
+        token => return Err(unexpected("`FUNCTION` or `SUB`", token)),
+    };
+
+    let name = parse_proc_name(&tokens[1..])?;
+
+    let rest = expect_keyword(&tokens[2..], "LIB")?;
+
+    let lib = match rest.first() {
+        Some(Token::StrLit(lib)) => lib.clone(),
+        token => return Err(unexpected("Library name", token)),
+    };
+
+    let ((params, ret), rest) = parse_signature(is_function, &rest[1..])?;
+
+    let decl = ExternDeclAst {
+        name,
+        lib,
+        params,
+        ret,
+    };
+
+    Ok((StmtAst::ExternDecl(decl), rest))
+}
+
+/// ``SUB`` / ``FUNCTION`` に続く手続きの定義を ``END SUB`` / ``END FUNCTION`` までパースする
+///
+/// 名前や仮引数リストにエラーがあっても、本体のエラーも報告できるように ``END`` まで読み進める
+fn parse_proc_def<'a>(
+    is_function: bool,
+    tokens: &'a [Token],
+    errors: &mut Vec<Diagnostic>,
+) -> Parsed<'a> {
+    let end_kind = if is_function { "FUNCTION" } else { "SUB" };
+    let header = parse_proc_name(tokens).and_then(|name| {
+        let (signature, rest) = parse_signature(is_function, &tokens[1..])?;
+        expect_eol(rest)?;
+        Ok((name, signature, rest))
+    });
+
+    match header {
+        Ok((name, (params, ret), rest)) => {
+            let (body, rest) = parse_block(rest, Some(end_kind), errors);
+            let def = ProcDefAst {
+                name,
+                params,
+                ret,
+                body,
+            };
+            Parsed::Stmt(StmtAst::ProcDef(def), rest)
+        }
+        Err(diag) => {
+            errors.push(diag);
+            let (_, rest) = parse_block(skip_line(tokens), Some(end_kind), errors);
+            Parsed::Skipped(rest)
+        }
+    }
+}
+
+fn parse_proc_name(tokens: &[Token]) -> Result<Identifier, Diagnostic> {
+    match tokens.first() {
+        Some(Token::Ident(name)) => {
+            validate_var_ident(name)?;
+            Ok(name.clone())
+        }
+        token => Err(unexpected("Identifier", token)),
+    }
+}
+
+/// ``(param AS type, ...) [AS type]`` の形式の仮引数リストと戻り値の型をパースする
+fn parse_signature(
+    is_function: bool,
+    tokens: &[Token],
+) -> Result<(Signature, &[Token]), Diagnostic> {
+    let rest = match tokens.first() {
+        Some(Token::LParen(_)) => &tokens[1..],
+        token => return Err(unexpected("`(`", token)),
+    };
+
+    let (params, rest) = parse_paren_list(rest, parse_param)?;
+
+    if is_function {
+        let rest = expect_keyword(rest, "AS")?;
+        let (ty, rest) = parse_type(rest)?;
+        Ok(((params, Some(ty)), rest))
+    } else {
+        Ok(((params, None), rest))
+    }
+}
+
+/// ``name AS type`` の形式の仮引数をパースする
+fn parse_param(tokens: &[Token]) -> Result<(ParamAst, &[Token]), Diagnostic> {
+    match tokens.first() {
+        Some(Token::Ident(name)) => {
+            validate_var_ident(name)?;
+            let rest = expect_keyword(&tokens[1..], "AS")?;
+            let (ty, rest) = parse_type(rest)?;
+            Ok((
+                ParamAst {
+                    name: name.clone(),
+                    ty,
+                },
+                rest,
+            ))
+        }
+        token => Err(unexpected("Parameter name", token)),
+    }
+}
+
+fn parse_type(tokens: &[Token]) -> Result<(TypeAst, &[Token]), Diagnostic> {
+    match tokens.first() {
+        Some(Token::Ident(ident)) if ident.name == "STRING" || ident.name == "INTEGER" => {
+            let ty = if ident.name == "STRING" {
+                Type::String
+            } else {
+                Type::Integer
+            };
+            let type_ast = TypeAst {
+                ty,
+                location: ident.locate(),
+            };
+            Ok((type_ast, &tokens[1..]))
+        }
+        token => Err(unexpected("`STRING` or `INTEGER`", token)),
+    }
+}
+
+/// 開き括弧の直後から、カンマ区切りの要素の列を閉じ括弧までパースする
+fn parse_paren_list<T, F>(tokens: &[Token], parse_elem: F) -> Result<(Vec<T>, &[Token]), Diagnostic>
+where
+    F: Fn(&[Token]) -> Result<(T, &[Token]), Diagnostic>,
+{
+    let mut elems = Vec::<T>::new();
+
+    if let Some(Token::RParen(_)) = tokens.first() {
+        return Ok((elems, &tokens[1..]));
+    }
+
+    let mut remaining_tokens = tokens;
+    loop {
+        let (elem, rest) = parse_elem(remaining_tokens)?;
+        elems.push(elem);
+        match rest.first() {
+            Some(Token::Comma(_)) => {
+                remaining_tokens = &rest[1..];
+            }
+            Some(Token::RParen(_)) => {
+                return Ok((elems, &rest[1..]));
+            }
+            token => {
+                return Err(unexpected("`,` or `)`", token));
+            }
+        }
+    }
+}
+
+fn expect_keyword<'a>(tokens: &'a [Token], keyword: &str) -> Result<&'a [Token], Diagnostic> {
+    match tokens.first() {
+        Some(Token::Ident(ident)) if ident.name == keyword => Ok(&tokens[1..]),
+        token => Err(unexpected(&format!("`{}`", keyword), token)),
+    }
+}
+
+/// 期待していないトークンが現れたときのエラーを生成する
+fn unexpected(expected: &str, found: Option<&Token>) -> Diagnostic {
+    match found {
+        Some(token) => Diagnostic::error(
+            "E0101",
+            format!("{} expected but {} found", expected, token),
+        )
+        .with_label(token.locate(), format!("expected {}", expected)),
+        None => Diagnostic::error("E0101", format!("{} expected but [EOF] found", expected)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    /// 解析できた文の個数と、エラーを1行の形式で返す
+    fn parse_src(src: &str) -> (usize, Vec<String>) {
+        let (stmts, errors) = parse_partial(&tokenize(src).unwrap());
+        (
+            stmts.len(),
+            errors.iter().map(|diag| diag.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn resync_at_line_break() {
+        let src = "VAR x = = 1
+PRINT x
+VAR = 2
+PRINT 3
+";
+        // エラーのある行だけを読み飛ばし、後続の文は解析される
+        assert_eq!(
+            parse_src(src),
+            (
+                2,
+                vec![
+                    "error[E0101] (1:9-1:9): Expression expected but `=` found".to_owned(),
+                    "error[E0101] (3:5-3:5): Identifier expected but `=` found".to_owned(),
+                ]
+            )
+        );
+        assert!(parse(&tokenize(src).unwrap()).is_err());
+    }
+
+    #[test]
+    fn missing_end_sub() {
+        let src = "SUB F ()
+  PRINT 1
+";
+        assert_eq!(
+            parse_src(src),
+            (
+                1,
+                vec!["error[E0101]: `END SUB` expected but [EOF] found".to_owned()]
+            )
+        );
+    }
+
+    #[test]
+    fn stray_end_sub() {
+        let src = "PRINT 1
+END SUB
+PRINT 2
+";
+        assert_eq!(
+            parse_src(src),
+            (
+                2,
+                vec!["error[E0102] (2:1-2:3): `END` without `SUB` or `FUNCTION`".to_owned()]
+            )
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use super::asm::{
+    Cond, Imm,
+    Instruction::{self, *},
+    Memory, Operand,
+    Operands::{self, *},
+    PopDst,
+    Register::{self, *},
+    TextSection, TextSectionItem,
+};
+
+/// 書き換え規則が一致したときの、置き換える項目の個数と置き換え後の項目
+type Rewrite = Option<(usize, Vec<TextSectionItem>)>;
+
+/// テキストセクションに覗き穴最適化を適用し、削除した命令の数を返す
+///
+/// 規則はいずれも命令を減らすため、どの規則も一致しなくなるまで繰り返し適用する
+pub fn peephole(txt: &mut TextSection) -> usize {
+    let items = txt.items_mut();
+    let before = count_insts(items);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < items.len() {
+            if let Some((len, replacement)) = rewrite(&items[i..]) {
+                items.splice(i..i + len, replacement);
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    before - count_insts(items)
+}
+
+fn count_insts(items: &[TextSectionItem]) -> usize {
+    items
+        .iter()
+        .filter(|item| matches!(item, TextSectionItem::Instruction { .. }))
+        .count()
+}
+
+fn rewrite(items: &[TextSectionItem]) -> Rewrite {
+    remove_unreachable(items)
+        .or_else(|| remove_jump_to_next(items))
+        .or_else(|| remove_redundant_type_check(items))
+        .or_else(|| remove_discarded_push(items))
+        .or_else(|| fold_push_pop(items))
+        .or_else(|| hoist_pop(items))
+}
+
+fn inst_at(items: &[TextSectionItem], i: usize) -> Option<&Instruction> {
+    match items.get(i) {
+        Some(TextSectionItem::Instruction { inst, .. }) => Some(inst),
+        _ => None,
+    }
+}
+
+fn comment_at(items: &[TextSectionItem], i: usize) -> Option<String> {
+    match items.get(i) {
+        Some(TextSectionItem::Instruction { comment, .. }) => comment.clone(),
+        _ => None,
+    }
+}
+
+fn item(inst: Instruction, comment: Option<String>) -> TextSectionItem {
+    TextSectionItem::Instruction { inst, comment }
+}
+
+/// 無条件ジャンプ・``ret`` の直後にあり、ラベルの付いていない命令を削除する
+fn remove_unreachable(items: &[TextSectionItem]) -> Rewrite {
+    match inst_at(items, 0)? {
+        Jmp(_) | JmpMem(_) | Ret => {
+            inst_at(items, 1)?;
+            Some((
+                2,
+                vec![item(inst_at(items, 0)?.clone(), comment_at(items, 0))],
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// 直後のラベルへのジャンプを削除する
+fn remove_jump_to_next(items: &[TextSectionItem]) -> Rewrite {
+    let target = match inst_at(items, 0)? {
+        Jmp(target) | Jcc(_, target) => target,
+        _ => return None,
+    };
+    for next in items[1..].iter() {
+        match next {
+            TextSectionItem::Label(label) if label == target => return Some((1, Vec::new())),
+            // ローカルラベルはその前の非ローカルラベルの下にあるため、非ローカルラベルを越えて探さない
+            TextSectionItem::Label(label) if !target.starts_with('.') || label.starts_with('.') => {
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// ``push TAG; cmp qword[rsp], TAG; jnz ...`` の型検査は必ず成功するため、比較とジャンプを削除する
+fn remove_redundant_type_check(items: &[TextSectionItem]) -> Rewrite {
+    match (inst_at(items, 0)?, inst_at(items, 1)?, inst_at(items, 2)?) {
+        (Push(Operand::Imm(pushed)), Cmp(MemImm(mem, expected)), Jcc(Cond::Nz | Cond::Ne, _))
+            if *mem == Memory::base(Rsp, 0) && pushed == expected =>
+        {
+            Some((
+                3,
+                vec![item(inst_at(items, 0)?.clone(), comment_at(items, 0))],
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// ``push X; add rsp, N`` は積んだ値をすぐに捨てるため、``add rsp, N - 8`` にする
+fn remove_discarded_push(items: &[TextSectionItem]) -> Rewrite {
+    match (inst_at(items, 0)?, inst_at(items, 1)?) {
+        (Push(_), Add(RegImm(Rsp, Imm::Int(size)))) if *size >= 8 => {
+            if *size == 8 {
+                Some((2, Vec::new()))
+            } else {
+                let inst = Add(RegImm(Rsp, Imm::Int(size - 8)));
+                Some((2, vec![item(inst, comment_at(items, 1))]))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// ``push X; pop Y`` を ``mov Y, X`` にする (X と Y が同じ場合は削除する)
+fn fold_push_pop(items: &[TextSectionItem]) -> Rewrite {
+    match (inst_at(items, 0)?, inst_at(items, 1)?) {
+        (Push(src), Pop(dst)) if *src == Operand::from(dst.clone()) => Some((2, Vec::new())),
+        (Push(src), Pop(dst)) => {
+            let operands = mov_operands(dst, src)?;
+            Some((2, vec![item(Mov(operands), comment_at(items, 1))]))
+        }
+        _ => None,
+    }
+}
+
+/// ``push X; I; pop Y`` の ``I`` がスタックと ``Y`` に触れない場合、``mov Y, X; I`` にする
+///
+/// ``push A; push B; pop C; pop D`` は ``fold_push_pop`` と合わせて ``mov D, A; mov C, B`` になる
+fn hoist_pop(items: &[TextSectionItem]) -> Rewrite {
+    match (inst_at(items, 0)?, inst_at(items, 1)?, inst_at(items, 2)?) {
+        (Push(src), middle, Pop(dst)) if is_independent(middle, dst) => {
+            let operands = mov_operands(dst, src)?;
+            Some((
+                3,
+                vec![
+                    item(Mov(operands), comment_at(items, 2)),
+                    item(middle.clone(), comment_at(items, 1)),
+                ],
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// ``mov dst, src`` のオペランドの組み合わせ (x86-64 で表せない場合は ``None``)
+fn mov_operands(dst: &PopDst, src: &Operand) -> Option<Operands> {
+    match (dst, src) {
+        (PopDst::Reg(dst), Operand::Reg(src)) => Some(RegReg(*dst, *src)),
+        (PopDst::Reg(dst), Operand::Mem(src)) => Some(RegMem(*dst, src.clone())),
+        (PopDst::Reg(dst), Operand::Imm(src)) => Some(RegImm(*dst, src.clone())),
+        (PopDst::Mem(dst), Operand::Reg(src)) => Some(MemReg(dst.clone(), *src)),
+        (PopDst::Mem(dst), Operand::Imm(src)) => Some(MemImm(dst.clone(), src.clone())),
+        (PopDst::Mem(_), Operand::Mem(_)) => None,
+    }
+}
+
+/// 命令が明示的に参照するレジスタとメモリ (暗黙にレジスタ・スタック・制御の流れに関わる命令の場合は ``None``)
+fn explicit_operands(inst: &Instruction) -> Option<(Vec<Register>, Vec<&Memory>)> {
+    match inst {
+        Mov(operands) | Add(operands) | Sub(operands) | Cmp(operands) | Xor(operands)
+        | And(operands) => Some(match operands {
+            RegReg(dst, src) => (vec![*dst, *src], Vec::new()),
+            RegMem(reg, mem) | MemReg(mem, reg) | MemReg8(mem, reg) => (vec![*reg], vec![mem]),
+            RegImm(reg, _) | Reg8Imm(reg, _) => (vec![*reg], Vec::new()),
+            MemImm(mem, _) | MemImm8(mem, _) => (Vec::new(), vec![mem]),
+        }),
+        Test(reg1, reg2) | Xchg(reg1, reg2) => Some((vec![*reg1, *reg2], Vec::new())),
+        Lea(reg, _) => Some((vec![*reg], Vec::new())),
+        Inc(reg) | Dec(reg) | Neg(reg) => Some((vec![*reg], Vec::new())),
+        _ => None,
+    }
+}
+
+fn address_registers(mem: &Memory) -> impl Iterator<Item = Register> + '_ {
+    mem.base.iter().chain(mem.index.iter()).copied()
+}
+
+/// ``inst`` と ``dst`` への書き込みの順序を入れ替えても結果が変わらないか
+fn is_independent(inst: &Instruction, dst: &PopDst) -> bool {
+    let (mut regs, mems) = match explicit_operands(inst) {
+        Some(operands) => operands,
+        None => return false,
+    };
+    if let Lea(_, mem) = inst {
+        regs.extend(address_registers(mem));
+    }
+    for mem in mems.iter() {
+        regs.extend(address_registers(mem));
+    }
+    if regs.contains(&Rsp) {
+        return false;
+    }
+
+    match dst {
+        PopDst::Reg(reg) => !regs.contains(reg),
+        PopDst::Mem(dst) => {
+            address_registers(dst).all(|reg| reg != Rsp && !regs.contains(&reg))
+                && mems.iter().all(|mem| is_disjoint(mem, dst))
+        }
+    }
+}
+
+/// ラベルからの相対位置で表された 2 つの 8 バイトの領域が重ならないか
+fn is_disjoint(mem1: &Memory, mem2: &Memory) -> bool {
+    if mem1.base.is_some() || mem1.index.is_some() || mem2.base.is_some() || mem2.index.is_some() {
+        return false;
+    }
+    match (&mem1.label, &mem2.label) {
+        (Some(label1), Some(label2)) => label1 != label2 || (mem1.disp - mem2.disp).abs() >= 8,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::Constant;
+    use super::*;
+
+    fn run(items: Vec<TextSectionItem>) -> (Vec<String>, usize) {
+        let mut txt = TextSection::default();
+        txt.items_mut().extend(items);
+        let eliminated = peephole(&mut txt);
+        let result = txt
+            .items()
+            .iter()
+            .map(|item| match item {
+                TextSectionItem::Label(label) => format!("{}:", label),
+                TextSectionItem::Instruction { inst, .. } => format!("{:?}", inst),
+            })
+            .collect();
+        (result, eliminated)
+    }
+
+    fn insts(insts: Vec<Instruction>) -> Vec<TextSectionItem> {
+        insts.into_iter().map(|inst| item(inst, None)).collect()
+    }
+
+    fn debug(insts: Vec<Instruction>) -> Vec<String> {
+        insts.iter().map(|inst| format!("{:?}", inst)).collect()
+    }
+
+    fn tag(constant: Constant) -> Imm {
+        Imm::Const(constant)
+    }
+
+    #[test]
+    fn push_pop_becomes_mov() {
+        let (result, eliminated) = run(insts(vec![
+            Push(Imm::Label("str0".to_owned()).into()),
+            Pop(Rdi.into()),
+        ]));
+        assert_eq!(
+            result,
+            debug(vec![Mov(RegImm(Rdi, Imm::Label("str0".to_owned())))])
+        );
+        assert_eq!(eliminated, 1);
+    }
+
+    #[test]
+    fn push_pop_of_same_operand_is_removed() {
+        let (result, eliminated) = run(insts(vec![Push(Rax.into()), Pop(Rax.into())]));
+        assert!(result.is_empty());
+        assert_eq!(eliminated, 2);
+    }
+
+    #[test]
+    fn push_pop_between_memory_is_kept() {
+        let items = vec![
+            Push(Memory::label("globals", 0).into()),
+            Pop(Memory::label("globals", 16).into()),
+        ];
+        let (result, eliminated) = run(insts(items.clone()));
+        assert_eq!(result, debug(items));
+        assert_eq!(eliminated, 0);
+    }
+
+    #[test]
+    fn nested_push_pop_becomes_movs() {
+        let (result, eliminated) = run(insts(vec![
+            Push(Memory::label("globals", 0).into()),
+            Push(Memory::label("globals", 8).into()),
+            Pop(Rsi.into()),
+            Pop(Rdi.into()),
+        ]));
+        assert_eq!(
+            result,
+            debug(vec![
+                Mov(RegMem(Rdi, Memory::label("globals", 0))),
+                Mov(RegMem(Rsi, Memory::label("globals", 8))),
+            ])
+        );
+        assert_eq!(eliminated, 2);
+    }
+
+    #[test]
+    fn pop_is_not_hoisted_over_dependent_instruction() {
+        let items = vec![
+            Push(Rax.into()),
+            Mov(RegImm(Rdi, Imm::Int(1))),
+            Pop(Rdi.into()),
+        ];
+        let (result, _) = run(insts(items.clone()));
+        assert_eq!(result, debug(items));
+
+        let items = vec![
+            Push(Rax.into()),
+            Mov(MemImm(Memory::base(Rbp, -8), Imm::Int(1))),
+            Pop(Memory::label("globals", 0).into()),
+        ];
+        let (result, _) = run(insts(items.clone()));
+        assert_eq!(result, debug(items));
+    }
+
+    #[test]
+    fn discarded_push_is_removed() {
+        let (result, eliminated) = run(insts(vec![
+            Push(Rax.into()),
+            Push(tag(Constant::TypeInt).into()),
+            Add(RegImm(Rsp, Imm::Int(16))),
+        ]));
+        assert!(result.is_empty());
+        assert_eq!(eliminated, 3);
+    }
+
+    #[test]
+    fn redundant_type_check_is_removed() {
+        let (result, eliminated) = run(insts(vec![
+            Push(Imm::Label("str0".to_owned()).into()),
+            Push(tag(Constant::TypeStr).into()),
+            Cmp(MemImm(Memory::base(Rsp, 0), tag(Constant::TypeStr))),
+            Jcc(Cond::Nz, "rt_error0".to_owned()),
+            Add(RegImm(Rsp, Imm::Int(8))),
+            Pop(Rdi.into()),
+        ]));
+        assert_eq!(
+            result,
+            debug(vec![Mov(RegImm(Rdi, Imm::Label("str0".to_owned())))])
+        );
+        assert_eq!(eliminated, 5);
+    }
+
+    #[test]
+    fn failing_type_check_is_kept() {
+        let items = vec![
+            Push(tag(Constant::TypeInt).into()),
+            Cmp(MemImm(Memory::base(Rsp, 0), tag(Constant::TypeStr))),
+            Jcc(Cond::Nz, "rt_error0".to_owned()),
+        ];
+        let (result, _) = run(insts(items.clone()));
+        assert_eq!(result, debug(items));
+    }
+
+    #[test]
+    fn jump_to_next_label_is_removed() {
+        let mut items = insts(vec![Jcc(Cond::E, "b".to_owned())]);
+        items.push(TextSectionItem::Label("a".to_owned()));
+        items.push(TextSectionItem::Label("b".to_owned()));
+        items.push(item(Ret, None));
+        let (result, eliminated) = run(items);
+        assert_eq!(result, vec!["a:", "b:", "Ret"]);
+        assert_eq!(eliminated, 1);
+    }
+
+    #[test]
+    fn local_jump_is_not_resolved_across_scopes() {
+        let mut items = insts(vec![Jmp(".end".to_owned())]);
+        items.push(TextSectionItem::Label("f".to_owned()));
+        items.push(TextSectionItem::Label(".end".to_owned()));
+        let (_, eliminated) = run(items);
+        assert_eq!(eliminated, 0);
+    }
+
+    #[test]
+    fn unreachable_instructions_are_removed() {
+        let mut items = insts(vec![
+            Jmp("program_exit".to_owned()),
+            Push(Rax.into()),
+            Call("f".to_owned()),
+        ]);
+        items.push(TextSectionItem::Label("stmt1".to_owned()));
+        items.push(item(Ret, None));
+        let (result, eliminated) = run(items);
+        assert_eq!(result, vec!["Jmp(\"program_exit\")", "stmt1:", "Ret"]);
+        assert_eq!(eliminated, 2);
+    }
+}
@@ -0,0 +1,276 @@
+use super::asm::Register::{self, *};
+use super::vcode::{VCode, VInst, VReg, VValue};
+
+/// System V AMD64 ABI で整数引数を渡すレジスタ
+pub static ARG_REGISTERS: [Register; 6] = [Rdi, Rsi, Rdx, Rcx, R8, R9];
+
+/// 呼び出し先保存レジスタ (手続きの呼び出しをまたいで値を保持できる)
+static CALLEE_SAVED: [Register; 5] = [Rbx, R12, R13, R14, R15];
+
+/// 割り当てに用いるすべてのレジスタ (呼び出し元保存レジスタを、引数に使われにくいものから優先する)
+///
+/// r11 はスタック上の領域どうしの転送などで一時的に用いるため、割り当てない
+static ALLOCATABLE: [Register; 13] = [
+    Rax, R10, R9, R8, Rcx, Rdx, Rsi, Rdi, Rbx, R12, R13, R14, R15,
+];
+
+/// 仮想レジスタの割り当て先
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Loc {
+    Reg(Register),
+    /// スタック上の 8 バイトの領域の番号
+    Spill(usize),
+}
+
+/// レジスタ割り当ての結果
+pub struct Allocation {
+    locs: Vec<Option<Loc>>,
+    /// スタック上に確保する領域の個数
+    pub num_spill_slots: usize,
+}
+
+impl Allocation {
+    pub fn loc(&self, reg: VReg) -> Loc {
+        self.locs[reg.0].unwrap()
+    }
+
+    /// 割り当てに用いた呼び出し先保存レジスタ (手続きの先頭で退避する必要がある)
+    pub fn used_callee_saved(&self) -> Vec<Register> {
+        CALLEE_SAVED
+            .iter()
+            .copied()
+            .filter(|reg| self.locs.contains(&Some(Loc::Reg(*reg))))
+            .collect()
+    }
+}
+
+/// 仮想レジスタの生存区間 (命令の位置で表す)
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    /// 区間の途中に手続きの呼び出しを含むかどうか
+    crosses_call: bool,
+    /// 引数や戻り値として受け渡されるため、割り当てると転送が不要になるレジスタ
+    hint: Option<Register>,
+}
+
+/// 割り当て中のレジスタ
+struct Active {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    reg: Register,
+}
+
+/// 命令列が直線的であることを利用して、生存区間を線形走査してレジスタを割り当てる
+///
+/// 手続きの呼び出しをまたぐ値には呼び出し先保存レジスタのみを割り当てる。
+/// レジスタが足りない場合は、最も遠くまで生存する値をスタックに退避 (スピル) する
+pub fn allocate(code: &VCode) -> Allocation {
+    let intervals = build_intervals(code);
+    let mut locs = vec![None; code.num_vregs];
+    let mut num_spill_slots = 0;
+    let mut active = Vec::<Active>::new();
+
+    for interval in intervals.iter() {
+        // 同じ命令で代入されたものを除き、この命令までに生存区間が終わったレジスタを解放する
+        active.retain(|a| a.end > interval.start || a.start == interval.start);
+
+        let candidates: &[Register] = if interval.crosses_call {
+            &CALLEE_SAVED
+        } else {
+            &ALLOCATABLE
+        };
+
+        let is_free = |reg: &Register| active.iter().all(|a| a.reg != *reg);
+        let free = interval
+            .hint
+            .filter(|reg| candidates.contains(reg) && is_free(reg))
+            .or_else(|| candidates.iter().copied().find(is_free));
+        if let Some(reg) = free {
+            locs[interval.vreg.0] = Some(Loc::Reg(reg));
+            active.push(Active {
+                vreg: interval.vreg,
+                start: interval.start,
+                end: interval.end,
+                reg,
+            });
+            continue;
+        }
+
+        let victim = active
+            .iter_mut()
+            .filter(|a| candidates.contains(&a.reg))
+            .max_by_key(|a| a.end);
+        match victim {
+            Some(victim) if victim.end > interval.end => {
+                locs[victim.vreg.0] = Some(Loc::Spill(num_spill_slots));
+                locs[interval.vreg.0] = Some(Loc::Reg(victim.reg));
+                victim.vreg = interval.vreg;
+                victim.start = interval.start;
+                victim.end = interval.end;
+            }
+            _ => {
+                locs[interval.vreg.0] = Some(Loc::Spill(num_spill_slots));
+            }
+        }
+        num_spill_slots += 1;
+    }
+
+    Allocation {
+        locs,
+        num_spill_slots,
+    }
+}
+
+/// 代入された位置の順に並べた生存区間を求める
+fn build_intervals(code: &VCode) -> Vec<Interval> {
+    let mut starts = vec![None; code.num_vregs];
+    let mut ends = vec![0; code.num_vregs];
+    let mut calls = Vec::new();
+    let mut hints = vec![None; code.num_vregs];
+
+    for (pos, inst) in code.insts.iter().enumerate() {
+        for (reg, hint) in register_hints(inst) {
+            hints[reg.0].get_or_insert(hint);
+        }
+        for reg in inst.uses() {
+            ends[reg.0] = pos;
+        }
+        for reg in inst.defs() {
+            starts[reg.0] = Some(pos);
+            ends[reg.0] = ends[reg.0].max(pos);
+        }
+        if inst.is_call() {
+            calls.push(pos);
+        }
+    }
+
+    let mut intervals: Vec<Interval> = starts
+        .iter()
+        .enumerate()
+        .filter_map(|(i, start)| {
+            start.map(|start| Interval {
+                vreg: VReg(i),
+                start,
+                end: ends[i],
+                crosses_call: calls.iter().any(|pos| start < *pos && *pos < ends[i]),
+                hint: hints[i],
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.vreg));
+    intervals
+}
+
+/// 命令が特定のレジスタで受け渡す仮想レジスタ
+fn register_hints(inst: &VInst) -> Vec<(VReg, Register)> {
+    let regs = |values: Vec<&VValue>, regs: &[Register]| -> Vec<(VReg, Register)> {
+        values
+            .into_iter()
+            .zip(regs.iter())
+            .filter_map(|(value, reg)| match value {
+                VValue::Reg(vreg) => Some((*vreg, *reg)),
+                VValue::Imm(_) => None,
+            })
+            .collect()
+    };
+    match inst {
+        VInst::Params(params) => params.iter().copied().zip(ARG_REGISTERS).collect(),
+        VInst::Call { args, ret, .. } => {
+            let mut hints = regs(args.iter().collect(), &ARG_REGISTERS);
+            hints.extend(ret.map(|ret| (ret, Rax)));
+            hints
+        }
+        VInst::Print(value, tag) => regs(vec![value, tag], &[Rdi, Rsi]),
+        VInst::RaiseError(value, _) => regs(vec![value], &[Rcx]),
+        VInst::Return(Some(value)) => regs(vec![value], &[Rax]),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Imm;
+    use crate::vcode::Place;
+
+    fn imm(reg: usize) -> VInst {
+        VInst::Imm(VReg(reg), Imm::Int(reg as i64))
+    }
+
+    fn call(args: Vec<VValue>, ret: Option<VReg>) -> VInst {
+        VInst::Call {
+            name: "f".to_owned(),
+            args,
+            ret,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn value_live_across_call_gets_callee_saved_register() {
+        let code = VCode {
+            insts: vec![
+                imm(0),
+                imm(1),
+                VInst::Store(Place::GlobalValue(1), VValue::Reg(VReg(1))),
+                call(Vec::new(), None),
+                VInst::Store(Place::GlobalValue(0), VValue::Reg(VReg(0))),
+            ],
+            num_vregs: 2,
+        };
+        let alloc = allocate(&code);
+        assert_eq!(alloc.loc(VReg(0)), Loc::Reg(Rbx));
+        assert_eq!(alloc.loc(VReg(1)), Loc::Reg(Rax));
+        assert_eq!(alloc.used_callee_saved(), vec![Rbx]);
+        assert_eq!(alloc.num_spill_slots, 0);
+    }
+
+    #[test]
+    fn register_pressure_forces_spill() {
+        let num_vregs = ALLOCATABLE.len() + 1;
+        let mut insts: Vec<VInst> = (0..num_vregs).map(imm).collect();
+        insts.extend(
+            (0..num_vregs)
+                .map(|i| VInst::Store(Place::GlobalValue(i as i32), VValue::Reg(VReg(i)))),
+        );
+        let alloc = allocate(&VCode { insts, num_vregs });
+
+        assert_eq!(alloc.num_spill_slots, 1);
+        let mut regs: Vec<Register> = (0..num_vregs)
+            .filter_map(|i| match alloc.loc(VReg(i)) {
+                Loc::Reg(reg) => Some(reg),
+                Loc::Spill(_) => None,
+            })
+            .collect();
+        assert_eq!(regs.len(), ALLOCATABLE.len());
+        regs.sort_by_key(|reg| reg.name());
+        regs.dedup();
+        assert_eq!(regs.len(), ALLOCATABLE.len());
+    }
+
+    #[test]
+    fn def_and_use_at_same_position_share_register() {
+        // 7 番目の引数はレジスタで渡されないため、最初に空いている rax に割り当てられ、
+        // 同じ命令で代入される戻り値も rax を使える
+        let mut insts: Vec<VInst> = (0..7).map(imm).collect();
+        insts.push(call(
+            (0..7).map(|i| VValue::Reg(VReg(i))).collect(),
+            Some(VReg(7)),
+        ));
+        insts.push(VInst::Return(Some(VValue::Reg(VReg(7)))));
+        let alloc = allocate(&VCode {
+            insts,
+            num_vregs: 8,
+        });
+
+        for (i, reg) in ARG_REGISTERS.iter().enumerate() {
+            assert_eq!(alloc.loc(VReg(i)), Loc::Reg(*reg));
+        }
+        assert_eq!(alloc.loc(VReg(6)), Loc::Reg(Rax));
+        assert_eq!(alloc.loc(VReg(7)), Loc::Reg(Rax));
+        assert_eq!(alloc.num_spill_slots, 0);
+    }
+}
@@ -1,32 +1,125 @@
-use super::ast::{ExprAst, StmtAst};
-use super::ir::{Ir, IrInst};
-use super::location::Locatable;
-use super::term_color::red_bold;
+use super::ast::{ExprAst, ExternDeclAst, ParamAst, ProcDefAst, StmtAst, Type};
+use super::diagnostic::Diagnostic;
+use super::ir::{ExternProc, Ir, IrInst, Proc};
+use super::location::{Locatable, Location};
 use super::token::Identifier;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-
-/// セマンティックエラーのメッセージのプレフィックス
-static SEMANTIC_ERROR: Lazy<String> = Lazy::new(|| red_bold("Semantic error:"));
+use super::CrateType;
+use std::{collections::HashMap, mem};
 
 /// 中間表現を生成する際に扱う状態
-#[derive(Clone, Default)]
+#[derive(Default)]
 struct Context {
     var_mappings: HashMap<String, i32>,
+    proc_mappings: HashMap<String, Callee>,
+    /// 手続きの名前から、その宣言の位置への対応
+    proc_locations: HashMap<String, Location>,
+    /// ラベルの名前から、ラベルの番号とその位置への対応
+    label_mappings: HashMap<String, (i32, Location)>,
+    /// これまでに解析したトップレベルの文の個数
+    num_stmts: i32,
+    /// 手続きの本体を解析している間のみ存在する、ローカルな状態
+    local: Option<LocalContext>,
+    /// これまでに見つかったエラー (エラーのある文を読み飛ばして解析を続ける)
+    errors: Vec<Diagnostic>,
 }
 
+/// 手続きの本体を解析する際に扱う状態
+#[derive(Default)]
+struct LocalContext {
+    var_mappings: HashMap<String, i32>,
+    num_locals: i32,
+    /// ``FUNCTION`` の戻り値 (関数名, ローカル変数の番号, 型)
+    ret: Option<(String, i32, Type)>,
+}
+
+/// 呼び出し先の手続き
+#[derive(Clone, Copy)]
+enum Callee {
+    Extern(i32),
+    Proc(i32),
+}
+
+/// 変数の格納場所
+enum Var {
+    Global(i32),
+    Local(i32),
+}
+
+/// System V AMD64 ABI でレジスタ渡しできる引数の最大個数
+pub const MAX_PARAMS: usize = 6;
+
 /// 抽象構文木を意味解析して、中間表現を生成する
-pub fn sem_analysis(stmts: &[StmtAst]) -> Result<Ir, String> {
+///
+/// エラーのある文は読み飛ばして解析を続け、見つかったすべてのエラーを返す
+pub fn sem_analysis(stmts: &[StmtAst], crate_type: CrateType) -> Result<Ir, Vec<Diagnostic>> {
     let mut context = Context::default();
     let mut ir = Ir::default();
 
+    // 前方参照できるように、ラベルを先に集めておく
     for stmt in stmts.iter() {
-        match stmt {
-            StmtAst::ProcCall(proc, args) => {
-                analyze_proc_call(proc, args, &mut ir, &mut context)?;
+        if let StmtAst::Label(label) = stmt {
+            if let Some((_, prev_location)) = context.label_mappings.get(&label.name) {
+                let diag = Diagnostic::error(
+                    "E0201",
+                    format!("Label `{}` is already defined", label.name),
+                )
+                .with_label(label.locate(), "redefined here")
+                .with_secondary_label(*prev_location, "first defined here");
+                context.errors.push(diag);
+                continue;
             }
-            StmtAst::VarDecl(var_ident, init_expr) => {
-                analyze_expr(init_expr, &mut ir, &mut context)?;
+            let label_index = context.label_mappings.len() as i32;
+            context
+                .label_mappings
+                .insert(label.name.clone(), (label_index, label.locate()));
+        }
+    }
+
+    for stmt in stmts.iter() {
+        let is_decl = matches!(stmt, StmtAst::ExternDecl(_) | StmtAst::ProcDef(_));
+
+        if crate_type == CrateType::StaticLib && !is_decl {
+            let diag = Diagnostic::error(
+                "E0202",
+                "Only declarations are allowed at the top level of a library",
+            )
+            .with_label(stmt.locate(), "not a declaration")
+            .with_note("a `staticlib` crate only exports its procedures");
+            context.errors.push(diag);
+            continue;
+        }
+
+        if !is_decl {
+            ir.insts.push(IrInst::BeginStmt(context.num_stmts));
+            context.num_stmts += 1;
+        }
+
+        if let Err(diag) = analyze_stmt(stmt, &mut ir, &mut context) {
+            context.errors.push(diag);
+        }
+    }
+
+    if context.errors.is_empty() {
+        Ok(ir)
+    } else {
+        Err(context.errors)
+    }
+}
+
+fn analyze_stmt(stmt: &StmtAst, ir: &mut Ir, context: &mut Context) -> Result<(), Diagnostic> {
+    match stmt {
+        StmtAst::ProcCall(proc, args) => {
+            analyze_proc_call(proc, args, ir, context)?;
+        }
+        StmtAst::VarDecl(var_ident, init_expr) => {
+            // 初期化式にエラーがあっても、後続の文で未宣言のエラーが出ないように変数は登録する
+            let result = analyze_expr(init_expr, ir, context);
+            if let Some(local) = &mut context.local {
+                let var_index = local.num_locals;
+                local.var_mappings.insert(var_ident.name.clone(), var_index);
+                ir.insts.push(IrInst::SetLocal(var_index));
+                local.num_locals += 1;
+            } else {
                 let var_index = ir.num_globals;
                 context
                     .var_mappings
@@ -34,88 +127,459 @@ pub fn sem_analysis(stmts: &[StmtAst]) -> Result<Ir, String> {
                 ir.insts.push(IrInst::SetGlobal(var_index));
                 ir.num_globals += 1;
             }
-            StmtAst::VarAssign(var_ident, expr) => {
-                let current_context = context.clone();
-                if let Some(var_index) = current_context.var_mappings.get(&var_ident.name) {
-                    analyze_expr(expr, &mut ir, &mut context)?;
-                    ir.insts.push(IrInst::SetGlobal(*var_index));
-                } else {
-                    return Err(format!(
-                        "{} ({}) `{}` is not declared",
-                        SEMANTIC_ERROR.as_str(),
-                        var_ident.locate(),
+            result?;
+        }
+        StmtAst::ExternDecl(decl) => {
+            analyze_extern_decl(decl, ir, context)?;
+        }
+        StmtAst::ProcDef(def) => {
+            analyze_proc_def(def, ir, context)?;
+        }
+        StmtAst::Label(label) => {
+            expect_top_level(stmt, context)?;
+            ir.insts
+                .push(IrInst::Label(context.label_mappings[&label.name].0));
+        }
+        StmtAst::OnErrorGoto(_, handler) => {
+            expect_top_level(stmt, context)?;
+            let label_index = match handler {
+                Some(label) => Some(
+                    context
+                        .label_mappings
+                        .get(&label.name)
+                        .ok_or_else(|| {
+                            Diagnostic::error(
+                                "E0203",
+                                format!("Label `{}` is not defined", label.name),
+                            )
+                            .with_label(label.locate(), "undefined label")
+                        })?
+                        .0,
+                ),
+                None => None,
+            };
+            ir.insts.push(IrInst::OnErrorGoto(label_index));
+        }
+        StmtAst::Resume(location, target) => {
+            expect_top_level(stmt, context)?;
+            ir.insts.push(IrInst::Resume(*target, *location));
+        }
+        StmtAst::Error(location, expr) => {
+            analyze_expr(expr, ir, context)?;
+            ir.insts
+                .push(IrInst::AssertType(Type::Integer, expr.locate()));
+            ir.insts.push(IrInst::RaiseError(*location));
+        }
+        StmtAst::End(_) => {
+            expect_top_level(stmt, context)?;
+            ir.insts.push(IrInst::End);
+        }
+        StmtAst::VarAssign(var_ident, expr) => {
+            // FUNCTION の本体で関数名に代入すると、戻り値が設定される
+            if let Some((_, ret_index, ret_ty)) = context
+                .local
+                .as_ref()
+                .and_then(|local| local.ret.clone())
+                .filter(|(name, _, _)| *name == var_ident.name)
+            {
+                analyze_expr(expr, ir, context)?;
+                ir.insts.push(IrInst::AssertType(ret_ty, expr.locate()));
+                ir.insts.push(IrInst::SetLocal(ret_index));
+                return Ok(());
+            }
+
+            match resolve_var(&var_ident.name, context) {
+                Some(Var::Global(var_index)) => {
+                    analyze_expr(expr, ir, context)?;
+                    ir.insts.push(IrInst::SetGlobal(var_index));
+                }
+                Some(Var::Local(var_index)) => {
+                    analyze_expr(expr, ir, context)?;
+                    ir.insts.push(IrInst::SetLocal(var_index));
+                }
+                None => {
+                    return Err(Diagnostic::error(
+                        "E0204",
+                        format!("`{}` is not declared", var_ident.name),
+                    )
+                    .with_label(var_ident.locate(), "undeclared variable")
+                    .with_help(format!(
+                        "declare it with `VAR {} = ...` before assigning",
                         var_ident.name
-                    ));
+                    )));
                 }
             }
         }
     }
 
-    Ok(ir)
+    Ok(())
 }
 
-fn analyze_proc_call(
-    proc: &Identifier,
-    args: &[ExprAst],
+/// 手続きの本体で使えない文でないことを検査する
+fn expect_top_level(stmt: &StmtAst, context: &Context) -> Result<(), Diagnostic> {
+    if context.local.is_some() {
+        Err(
+            Diagnostic::error("E0205", "This statement is only allowed at the top level")
+                .with_label(stmt.locate(), "inside a procedure"),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+/// 変数名を解決する (ローカル変数はグローバル変数より優先される)
+fn resolve_var(name: &str, context: &Context) -> Option<Var> {
+    let local_var = context
+        .local
+        .as_ref()
+        .and_then(|local| local.var_mappings.get(name));
+
+    if let Some(var_index) = local_var {
+        Some(Var::Local(*var_index))
+    } else {
+        context
+            .var_mappings
+            .get(name)
+            .map(|var_index| Var::Global(*var_index))
+    }
+}
+
+/// 手続きの名前と仮引数を検査する
+///
+/// 仮引数が多すぎる場合は、呼び出し側で未定義のエラーが続かないようにエラーを記録するのみで、手続きは登録させる
+fn check_proc_signature(
+    name: &Identifier,
+    params: &[ParamAst],
+    context: &mut Context,
+) -> Result<(), Diagnostic> {
+    if name.name == "PRINT" || context.proc_mappings.contains_key(&name.name) {
+        let diag = Diagnostic::error("E0206", format!("`{}` is already defined", name.name))
+            .with_label(name.locate(), "redefined here");
+        return Err(match context.proc_locations.get(&name.name) {
+            Some(prev_location) => diag.with_secondary_label(*prev_location, "first defined here"),
+            None => diag.with_note(format!("`{}` is a built-in procedure", name.name)),
+        });
+    }
+
+    if params.len() > MAX_PARAMS {
+        context.errors.push(
+            Diagnostic::error(
+                "E0207",
+                format!("Procedures can take at most {} arguments", MAX_PARAMS),
+            )
+            .with_label(params[MAX_PARAMS].name.locate(), "too many parameters")
+            .with_note("arguments are passed in registers"),
+        );
+    }
+
+    Ok(())
+}
+
+fn analyze_extern_decl(
+    decl: &ExternDeclAst,
     ir: &mut Ir,
     context: &mut Context,
-) -> Result<(), String> {
-    for arg in args {
-        analyze_expr(arg, ir, context)?;
+) -> Result<(), Diagnostic> {
+    check_proc_signature(&decl.name, &decl.params, context)?;
+
+    let ext = ExternProc {
+        name: decl.name.name.clone(),
+        lib: decl.lib.value.clone(),
+        params: decl.params.iter().map(|param| param.ty.ty).collect(),
+        ret: decl.ret.as_ref().map(|ret| ret.ty),
+    };
+    context
+        .proc_mappings
+        .insert(ext.name.clone(), Callee::Extern(ir.externs.len() as i32));
+    context
+        .proc_locations
+        .insert(ext.name.clone(), decl.name.locate());
+    ir.externs.push(ext);
+
+    Ok(())
+}
+
+fn analyze_proc_def(
+    def: &ProcDefAst,
+    ir: &mut Ir,
+    context: &mut Context,
+) -> Result<(), Diagnostic> {
+    if context.local.is_some() {
+        return Err(Diagnostic::error(
+            "E0208",
+            "Procedures cannot be defined inside another procedure",
+        )
+        .with_label(def.name.locate(), "nested procedure"));
     }
 
-    if proc.name == "PRINT" {
-        let num_args = args.len();
-
-        if num_args > 1 {
-            return Err(format!(
-                "{} ({}-{}) Expected 1 argument, found {}",
-                SEMANTIC_ERROR.as_str(),
-                args[0].locate().start,
-                args[num_args - 1].locate().end,
-                num_args
-            ));
-        } else if num_args == 0 {
-            return Err(format!(
-                "{} ({}) Expected 1 argument, found 0",
-                SEMANTIC_ERROR.as_str(),
-                proc.locate()
-            ));
+    check_proc_signature(&def.name, &def.params, context)?;
+
+    let proc_index = ir.procs.len();
+    let proc = Proc {
+        name: def.name.name.clone(),
+        param_names: def.params.iter().map(|p| p.name.name.clone()).collect(),
+        params: def.params.iter().map(|p| p.ty.ty).collect(),
+        ret: def.ret.as_ref().map(|ret| ret.ty),
+        num_locals: 0,
+        insts: Vec::new(),
+    };
+
+    // 再帰呼び出しできるように、本体を解析する前に登録しておく
+    context
+        .proc_mappings
+        .insert(proc.name.clone(), Callee::Proc(proc_index as i32));
+    context
+        .proc_locations
+        .insert(proc.name.clone(), def.name.locate());
+
+    let mut local = LocalContext::default();
+    for param in def.params.iter() {
+        local
+            .var_mappings
+            .insert(param.name.name.clone(), local.num_locals);
+        local.num_locals += 1;
+    }
+    if let (Some(ret_slot), Some(ret_ty)) = (proc.ret_slot(), proc.ret) {
+        local.ret = Some((proc.name.clone(), ret_slot, ret_ty));
+        local.num_locals += 1;
+    }
+
+    ir.procs.push(proc);
+    context.local = Some(local);
+    let main_insts = mem::take(&mut ir.insts);
+
+    // 戻り値を型に応じた既定値で初期化する
+    if let Some((_, ret_slot, ret_ty)) = context.local.as_ref().and_then(|l| l.ret.clone()) {
+        match ret_ty {
+            Type::String => {
+                ir.string_pool.push(String::new());
+                ir.insts
+                    .push(IrInst::GetStaticStr(ir.string_pool.len() as i32 - 1));
+            }
+            Type::Integer => {
+                ir.insts.push(IrInst::GetImmInt(0));
+            }
         }
+        ir.insts.push(IrInst::SetLocal(ret_slot));
+    }
+
+    for stmt in def.body.iter() {
+        if let Err(diag) = analyze_stmt(stmt, ir, context) {
+            context.errors.push(diag);
+        }
+    }
+
+    let local = context.local.take().unwrap();
+    let proc = &mut ir.procs[proc_index];
+    proc.num_locals = local.num_locals;
+    proc.insts = mem::replace(&mut ir.insts, main_insts);
 
+    Ok(())
+}
+
+fn analyze_proc_call(
+    proc: &Identifier,
+    args: &[ExprAst],
+    ir: &mut Ir,
+    context: &mut Context,
+) -> Result<(), Diagnostic> {
+    if proc.name == "PRINT" {
+        check_num_args(proc, args, 1)?;
+        analyze_expr(&args[0], ir, context)?;
         ir.insts.push(IrInst::Print);
 
+        Ok(())
+    } else if let Some(callee) = context.proc_mappings.get(&proc.name).copied() {
+        let has_ret = analyze_call(callee, proc, args, ir, context)?;
+
+        // 手続き呼び出し文では戻り値を使わない
+        if has_ret {
+            ir.insts.push(IrInst::Pop);
+        }
+
         Ok(())
     } else {
-        Err(format!(
-            "{} ({}) `{}` is not defined",
-            SEMANTIC_ERROR.as_str(),
-            proc.locate(),
-            proc.name
-        ))
+        Err(not_defined(proc))
     }
 }
 
-fn analyze_expr(expr_ast: &ExprAst, ir: &mut Ir, context: &mut Context) -> Result<(), String> {
+/// 手続きを呼び出す命令を生成し、戻り値を持つかどうかを返す
+fn analyze_call(
+    callee: Callee,
+    proc: &Identifier,
+    args: &[ExprAst],
+    ir: &mut Ir,
+    context: &mut Context,
+) -> Result<bool, Diagnostic> {
+    let (params, has_ret) = match callee {
+        Callee::Extern(index) => {
+            let ext = &ir.externs[index as usize];
+            (ext.params.clone(), ext.ret.is_some())
+        }
+        Callee::Proc(index) => {
+            let proc = &ir.procs[index as usize];
+            (proc.params.clone(), proc.ret.is_some())
+        }
+    };
+
+    check_num_args(proc, args, params.len()).map_err(|diag| {
+        match context.proc_locations.get(&proc.name) {
+            Some(location) => diag.with_secondary_label(*location, "defined here"),
+            None => diag,
+        }
+    })?;
+
+    for (arg, param_ty) in args.iter().zip(params) {
+        analyze_expr(arg, ir, context)?;
+        ir.insts.push(IrInst::AssertType(param_ty, arg.locate()));
+    }
+
+    ir.insts.push(match callee {
+        Callee::Extern(index) => IrInst::CallExtern(index),
+        Callee::Proc(index) => IrInst::CallProc(index, proc.locate()),
+    });
+
+    Ok(has_ret)
+}
+
+fn check_num_args(proc: &Identifier, args: &[ExprAst], expected: usize) -> Result<(), Diagnostic> {
+    let num_args = args.len();
+
+    if num_args == expected {
+        return Ok(());
+    }
+
+    let location = if num_args == 0 {
+        proc.locate()
+    } else {
+        Location {
+            start: args[0].locate().start,
+            end: args[num_args - 1].locate().end,
+        }
+    };
+    Err(Diagnostic::error(
+        "E0210",
+        format!("Expected {} argument(s), found {}", expected, num_args),
+    )
+    .with_label(
+        location,
+        format!("`{}` takes {} argument(s)", proc.name, expected),
+    ))
+}
+
+fn analyze_expr(expr_ast: &ExprAst, ir: &mut Ir, context: &mut Context) -> Result<(), Diagnostic> {
     match expr_ast {
-        ExprAst::Ident(ident) => {
-            if let Some(var_index) = context.var_mappings.get(&ident.name) {
-                ir.insts.push(IrInst::GetGlobal(*var_index));
-            } else {
-                return Err(format!(
-                    "{} ({}) `{}` is not defined",
-                    SEMANTIC_ERROR.as_str(),
-                    ident.locate(),
-                    ident.name
-                ));
-            }
+        ExprAst::Ident(ident) if ident.name == "ERR" => {
+            ir.insts.push(IrInst::GetErrCode);
+        }
+        ExprAst::Ident(ident) if ident.name == "ERL" => {
+            ir.insts.push(IrInst::GetErrLine);
         }
+        ExprAst::Ident(ident) => match resolve_var(&ident.name, context) {
+            Some(Var::Global(var_index)) => {
+                ir.insts.push(IrInst::GetGlobal(var_index));
+            }
+            Some(Var::Local(var_index)) => {
+                ir.insts.push(IrInst::GetLocal(var_index));
+            }
+            None => {
+                return Err(not_defined(ident));
+            }
+        },
         ExprAst::StrLit(str_lit) => {
             ir.string_pool.push(str_lit.value.clone());
             ir.insts
                 .push(IrInst::GetStaticStr(ir.string_pool.len() as i32 - 1));
         }
+        ExprAst::IntLit(int_lit) => {
+            ir.insts.push(IrInst::GetImmInt(int_lit.value));
+        }
+        ExprAst::Call(func, args) => {
+            let callee = context
+                .proc_mappings
+                .get(&func.name)
+                .copied()
+                .ok_or_else(|| not_defined(func))?;
+
+            if !analyze_call(callee, func, args, ir, context)? {
+                let diag = Diagnostic::error(
+                    "E0211",
+                    format!("`{}` is a SUB and does not return a value", func.name),
+                )
+                .with_label(func.locate(), "used as a value");
+                return Err(match context.proc_locations.get(&func.name) {
+                    Some(location) => {
+                        diag.with_secondary_label(*location, "declared as a SUB here")
+                    }
+                    None => diag,
+                }
+                .with_help("declare it as a FUNCTION to return a value"));
+            }
+        }
     }
 
     Ok(())
 }
+
+/// 定義されていない変数や手続きを参照したときのエラー
+fn not_defined(ident: &Identifier) -> Diagnostic {
+    Diagnostic::error("E0209", format!("`{}` is not defined", ident.name))
+        .with_label(ident.locate(), "not found in this scope")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    fn errors_of(src: &str) -> Vec<String> {
+        let ast = parse(&tokenize(src).unwrap()).unwrap();
+        match sem_analysis(&ast, CrateType::Bin) {
+            Ok(_) => panic!("the program is accepted"),
+            Err(errors) => errors.iter().map(|diag| diag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn too_many_params_without_cascading_errors() {
+        let src = "DECLARE SUB Ext LIB \"c\" (a AS INTEGER, b AS INTEGER, c AS INTEGER, d AS INTEGER, e AS INTEGER, f AS INTEGER, g AS INTEGER)
+SUB F (a AS INTEGER, b AS INTEGER, c AS INTEGER, d AS INTEGER, e AS INTEGER, f AS INTEGER, g AS INTEGER)
+  PRINT g
+END SUB
+Ext 1, 2, 3, 4, 5, 6, 7
+F 1, 2, 3, 4, 5, 6, 7
+F 1
+";
+        // 呼び出しは解析され、引数の個数の誤りは報告される
+        assert_eq!(
+            errors_of(src),
+            vec![
+                "error[E0207] (1:110-1:110): Procedures can take at most 6 arguments",
+                "error[E0207] (2:92-2:92): Procedures can take at most 6 arguments",
+                "error[E0210] (7:3-7:3): Expected 7 argument(s), found 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_undefined_variables() {
+        let src = "PRINT a
+VAR b = c
+PRINT b
+SUB F ()
+  PRINT d
+END SUB
+PRINT e
+";
+        // 初期化式にエラーのある ``b`` は宣言されたものとして扱い、後続の文でエラーにしない
+        assert_eq!(
+            errors_of(src),
+            vec![
+                "error[E0209] (1:7-1:7): `a` is not defined",
+                "error[E0209] (2:9-2:9): `c` is not defined",
+                "error[E0209] (5:9-5:9): `d` is not defined",
+                "error[E0209] (7:7-7:7): `e` is not defined",
+            ]
+        );
+    }
+}
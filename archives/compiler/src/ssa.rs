@@ -0,0 +1,1073 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// SSA 形式の値 (型タグを含む BASIC の値を表し、関数ごとに番号が振られる)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Value(pub usize);
+
+/// 基本ブロックの番号 (``BlockId(0)`` が入口のブロックになる)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct BlockId(pub usize);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block{}", self.0)
+    }
+}
+
+/// 制御フローグラフと SSA 形式による中間表現
+///
+/// ローカル変数とスタックマシンのスタックは SSA 値に置き換えられる。
+/// グローバル変数は手続きやエラーハンドラからも読み書きされるため、メモリ上の値として ``LoadGlobal`` / ``StoreGlobal`` で扱う
+#[derive(Debug)]
+pub struct Module {
+    /// トップレベルの命令列
+    pub main: Function,
+    /// BASIC で定義された手続き (``Ir::procs`` と同じ順序)
+    pub procs: Vec<Function>,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub blocks: Vec<Block>,
+    /// SSA 値の個数
+    pub num_values: usize,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    /// ブロックの先頭にあるトップレベルの文の番号 ( ``RESUME`` で実行を再開する位置になる)
+    pub stmt: Option<i32>,
+    /// ブロックの先頭にあるラベルの番号
+    pub label: Option<i32>,
+    pub phis: Vec<Phi>,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+    /// ランタイムエラーが発生したときに移りうるブロック (エラーハンドラの先頭)
+    pub exception_successors: Vec<BlockId>,
+}
+
+impl Block {
+    /// 後続のブロック (ランタイムエラーによるものを含む)
+    pub fn successors(&self) -> Vec<BlockId> {
+        let mut succs = self.terminator.successors();
+        for succ in self.exception_successors.iter() {
+            if !succs.contains(succ) {
+                succs.push(*succ);
+            }
+        }
+        succs
+    }
+}
+
+/// 合流点で、どの先行ブロックから来たかによって値を選ぶ φ 関数
+#[derive(Debug)]
+pub struct Phi {
+    pub result: Value,
+    /// 先行ブロックとそこから来たときの値
+    pub args: Vec<(BlockId, Value)>,
+}
+
+#[derive(Debug)]
+pub struct Inst {
+    pub result: Option<Value>,
+    pub kind: InstKind,
+}
+
+#[derive(Debug)]
+pub enum InstKind {
+    /// 文字列プールの文字列
+    StaticStr(i32),
+    /// 整数の即値
+    Int(i64),
+    /// 代入される前のローカル変数の値
+    Undef,
+    /// 手続きの引数
+    Param(i32),
+    LoadGlobal(i32),
+    StoreGlobal(i32, Value),
+    /// 値が指定した型であることを検査する (失敗した場合はランタイムエラーを発生させる)
+    AssertType(Value, Type, Location),
+    /// 外部ライブラリの手続きを呼び出す (戻り値があれば結果になる)
+    CallExtern(i32, Vec<Value>),
+    /// BASIC で定義された手続きを呼び出す (戻り値があれば結果になる)
+    CallProc(i32, Vec<Value>, Location),
+    Print(Value),
+    OnErrorGoto(Option<i32>),
+    GetErrCode,
+    GetErrLine,
+}
+
+impl InstKind {
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            InstKind::StoreGlobal(_, value)
+            | InstKind::AssertType(value, _, _)
+            | InstKind::Print(value) => vec![*value],
+            InstKind::CallExtern(_, args) | InstKind::CallProc(_, args, _) => args.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            InstKind::StoreGlobal(_, value)
+            | InstKind::AssertType(value, _, _)
+            | InstKind::Print(value) => vec![value],
+            InstKind::CallExtern(_, args) | InstKind::CallProc(_, args, _) => {
+                args.iter_mut().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// ランタイムエラーを発生させうるかどうか
+    pub fn may_trap(&self) -> bool {
+        matches!(self, InstKind::AssertType(..) | InstKind::CallProc(..))
+    }
+}
+
+#[derive(Debug)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// エラーハンドラから実行を再開する (再開先は実行時に決まるため、すべての文の先頭が後続になる)
+    Resume(ResumeTarget, Location, Vec<BlockId>),
+    /// 整数をエラー番号としてランタイムエラーを発生させる
+    Raise(Value, Location),
+    /// プログラムを終了する
+    Exit,
+    /// 手続きから戻る
+    Return(Option<Value>),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Resume(_, _, targets) => targets.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::Raise(value, _) | Terminator::Return(Some(value)) => vec![*value],
+            _ => Vec::new(),
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Raise(value, _) | Terminator::Return(Some(value)) => vec![value],
+            _ => Vec::new(),
+        }
+    }
+
+    /// ランタイムエラーを発生させうるかどうか
+    pub fn may_trap(&self) -> bool {
+        matches!(self, Terminator::Resume(..) | Terminator::Raise(..))
+    }
+}
+
+impl Function {
+    /// 各ブロックの先行ブロック
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.successors() {
+                preds[succ.0].push(BlockId(i));
+            }
+        }
+        preds
+    }
+}
+
+/// 基本ブロックに分割した、中間表現の命令列の範囲
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+/// 命令列を基本ブロックに分割する
+///
+/// 文の先頭とラベルでブロックを開始し、制御を移す命令でブロックを終える
+fn split_blocks(insts: &[IrInst]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for (i, inst) in insts.iter().enumerate() {
+        if matches!(inst, IrInst::BeginStmt(_) | IrInst::Label(_)) && i > start {
+            spans.push(Span { start, end: i });
+            start = i;
+        }
+        if matches!(
+            inst,
+            IrInst::End | IrInst::Resume(_, _) | IrInst::RaiseError(_)
+        ) {
+            spans.push(Span { start, end: i + 1 });
+            start = i + 1;
+        }
+    }
+    if start < insts.len() || spans.is_empty() {
+        spans.push(Span {
+            start,
+            end: insts.len(),
+        });
+    }
+    spans
+}
+
+/// 中間表現を制御フローグラフと SSA 形式の中間表現に変換する
+pub fn build_ssa(ir: &Ir) -> Module {
+    Module {
+        main: build_function(ir, "<main>", &ir.insts, None),
+        procs: ir
+            .procs
+            .iter()
+            .map(|proc| build_function(ir, &proc.name, &proc.insts, Some(proc)))
+            .collect(),
+    }
+}
+
+fn build_function(ir: &Ir, name: &str, insts: &[IrInst], proc: Option<&Proc>) -> Function {
+    let spans = split_blocks(insts);
+
+    // トップレベルでは、最後の文の次の位置 ( RESUME NEXT で移る先) にプログラムを終了するブロックを置く
+    let num_blocks = spans.len() + proc.map_or(1, |_| 0);
+    let mut blocks: Vec<Block> = (0..num_blocks)
+        .map(|i| Block {
+            stmt: None,
+            label: None,
+            phis: Vec::new(),
+            insts: Vec::new(),
+            terminator: if i + 1 < num_blocks {
+                Terminator::Jump(BlockId(i + 1))
+            } else if proc.is_some() {
+                Terminator::Return(None)
+            } else {
+                Terminator::Exit
+            },
+            exception_successors: Vec::new(),
+        })
+        .collect();
+    for (block, span) in blocks.iter_mut().zip(spans.iter()) {
+        match insts.get(span.start) {
+            Some(IrInst::BeginStmt(index)) => block.stmt = Some(*index),
+            Some(IrInst::Label(index)) => block.label = Some(*index),
+            _ => {}
+        }
+    }
+    if proc.is_none() {
+        let num_stmts = insts
+            .iter()
+            .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+            .count();
+        blocks.last_mut().unwrap().stmt = Some(num_stmts as i32);
+    }
+
+    // 分岐先を決めるため、制御を移す命令による終端をあらかじめ設定しておく
+    let resume_targets: Vec<BlockId> = blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| block.stmt.is_some())
+        .map(|(i, _)| BlockId(i))
+        .collect();
+    let handler_labels: HashSet<i32> = insts
+        .iter()
+        .filter_map(|inst| match inst {
+            IrInst::OnErrorGoto(label) => *label,
+            _ => None,
+        })
+        .collect();
+    let handlers: Vec<BlockId> = blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| {
+            block
+                .label
+                .is_some_and(|label| handler_labels.contains(&label))
+        })
+        .map(|(i, _)| BlockId(i))
+        .collect();
+    for (block, span) in blocks.iter_mut().zip(spans.iter()) {
+        let span_insts = &insts[span.start..span.end];
+        match span_insts.last() {
+            Some(IrInst::End) => block.terminator = Terminator::Exit,
+            Some(IrInst::Resume(target, location)) => {
+                block.terminator = Terminator::Resume(*target, *location, resume_targets.clone())
+            }
+            _ => {}
+        }
+        let may_trap = span_insts.iter().any(|inst| {
+            matches!(
+                inst,
+                IrInst::AssertType(_, _)
+                    | IrInst::CallProc(_, _)
+                    | IrInst::Resume(_, _)
+                    | IrInst::RaiseError(_)
+            )
+        });
+        if may_trap {
+            block.exception_successors = handlers.clone();
+        }
+    }
+
+    let func = Function {
+        name: name.to_owned(),
+        blocks,
+        num_values: 0,
+    };
+    let preds = func.predecessors();
+    let mut succs = vec![Vec::new(); num_blocks];
+    for (i, block_preds) in preds.iter().enumerate() {
+        for pred in block_preds.iter() {
+            succs[pred.0].push(BlockId(i));
+        }
+    }
+    let mut builder = Builder {
+        ir,
+        func,
+        current_defs: HashMap::new(),
+        sealed: vec![false; num_blocks],
+        unfilled_preds: preds.iter().map(|block_preds| block_preds.len()).collect(),
+        preds,
+        succs,
+        incomplete_phis: vec![Vec::new(); num_blocks],
+        replacements: HashMap::new(),
+    };
+
+    for i in 0..num_blocks {
+        if builder.unfilled_preds[i] == 0 {
+            builder.seal(BlockId(i));
+        }
+    }
+    for i in 0..num_blocks {
+        let block = BlockId(i);
+        if i == 0 {
+            if let Some(proc) = proc {
+                for index in 0..proc.params.len() {
+                    let value = builder.push_inst(block, InstKind::Param(index as i32));
+                    builder.write_variable(index as i32, block, value);
+                }
+            }
+        }
+        let span_insts = spans
+            .get(i)
+            .map_or(&[][..], |span| &insts[span.start..span.end]);
+        builder.fill_block(block, span_insts);
+        if let (Some(proc), Terminator::Return(_)) = (proc, &builder.func.blocks[i].terminator) {
+            let ret = proc
+                .ret_slot()
+                .map(|slot| builder.read_variable(slot, block));
+            builder.func.blocks[i].terminator = Terminator::Return(ret);
+        }
+        builder.mark_filled(block);
+    }
+    builder.finish()
+}
+
+/// Braun らの手法 (Simple and Efficient Construction of Static Single Assignment Form) で SSA 形式を構築する
+///
+/// ブロックは番号順に命令を埋めていき、すべての先行ブロックを埋め終えたブロックを封印 (seal) する。
+/// 封印前のブロックで変数を読み出した場合は、オペランドが未確定の φ 関数を置いておき、封印時に確定させる
+struct Builder<'a> {
+    ir: &'a Ir,
+    func: Function,
+    preds: Vec<Vec<BlockId>>,
+    succs: Vec<Vec<BlockId>>,
+    /// 各ブロックの末尾におけるローカル変数の値
+    current_defs: HashMap<(i32, BlockId), Value>,
+    sealed: Vec<bool>,
+    /// 各ブロックの先行ブロックのうち、まだ命令を埋めていないものの個数
+    unfilled_preds: Vec<usize>,
+    /// 封印前のブロックに置いた φ 関数 (変数の番号, φ 関数の値)
+    incomplete_phis: Vec<Vec<(i32, Value)>>,
+    /// 自明な φ 関数を取り除いたときの置き換え先
+    replacements: HashMap<Value, Value>,
+}
+
+impl Builder<'_> {
+    fn new_value(&mut self) -> Value {
+        self.func.num_values += 1;
+        Value(self.func.num_values - 1)
+    }
+
+    fn push_inst(&mut self, block: BlockId, kind: InstKind) -> Value {
+        let value = self.new_value();
+        self.func.blocks[block.0].insts.push(Inst {
+            result: Some(value),
+            kind,
+        });
+        value
+    }
+
+    fn push_void_inst(&mut self, block: BlockId, kind: InstKind) {
+        self.func.blocks[block.0]
+            .insts
+            .push(Inst { result: None, kind });
+    }
+
+    fn fill_block(&mut self, block: BlockId, insts: &[IrInst]) {
+        // 文の境界ではスタックは空になっている
+        let mut stack = Vec::<Value>::new();
+        for inst in insts.iter() {
+            match inst {
+                IrInst::GetStaticStr(index) => {
+                    stack.push(self.push_inst(block, InstKind::StaticStr(*index)));
+                }
+                IrInst::GetImmInt(value) => {
+                    stack.push(self.push_inst(block, InstKind::Int(*value)));
+                }
+                IrInst::GetGlobal(index) => {
+                    stack.push(self.push_inst(block, InstKind::LoadGlobal(*index)));
+                }
+                IrInst::SetGlobal(index) => {
+                    let value = stack.pop().unwrap();
+                    self.push_void_inst(block, InstKind::StoreGlobal(*index, value));
+                }
+                IrInst::GetLocal(index) => {
+                    stack.push(self.read_variable(*index, block));
+                }
+                IrInst::SetLocal(index) => {
+                    let value = stack.pop().unwrap();
+                    self.write_variable(*index, block, value);
+                }
+                IrInst::AssertType(ty, location) => {
+                    let value = *stack.last().unwrap();
+                    self.push_void_inst(block, InstKind::AssertType(value, *ty, *location));
+                }
+                IrInst::CallExtern(index) => {
+                    let ext = &self.ir.externs[*index as usize];
+                    let args = stack.split_off(stack.len() - ext.params.len());
+                    let kind = InstKind::CallExtern(*index, args);
+                    if ext.ret.is_some() {
+                        stack.push(self.push_inst(block, kind));
+                    } else {
+                        self.push_void_inst(block, kind);
+                    }
+                }
+                IrInst::CallProc(index, location) => {
+                    let proc = &self.ir.procs[*index as usize];
+                    let args = stack.split_off(stack.len() - proc.params.len());
+                    let kind = InstKind::CallProc(*index, args, *location);
+                    if proc.ret.is_some() {
+                        stack.push(self.push_inst(block, kind));
+                    } else {
+                        self.push_void_inst(block, kind);
+                    }
+                }
+                IrInst::Pop => {
+                    stack.pop();
+                }
+                IrInst::Print => {
+                    let value = stack.pop().unwrap();
+                    self.push_void_inst(block, InstKind::Print(value));
+                }
+                IrInst::BeginStmt(_) | IrInst::Label(_) | IrInst::End | IrInst::Resume(_, _) => {}
+                IrInst::OnErrorGoto(label) => {
+                    self.push_void_inst(block, InstKind::OnErrorGoto(*label));
+                }
+                IrInst::RaiseError(location) => {
+                    let value = stack.pop().unwrap();
+                    self.func.blocks[block.0].terminator = Terminator::Raise(value, *location);
+                }
+                IrInst::GetErrCode => {
+                    stack.push(self.push_inst(block, InstKind::GetErrCode));
+                }
+                IrInst::GetErrLine => {
+                    stack.push(self.push_inst(block, InstKind::GetErrLine));
+                }
+            }
+        }
+    }
+
+    fn write_variable(&mut self, var: i32, block: BlockId, value: Value) {
+        self.current_defs.insert((var, block), value);
+    }
+
+    fn read_variable(&mut self, var: i32, block: BlockId) -> Value {
+        if let Some(value) = self.current_defs.get(&(var, block)) {
+            return *value;
+        }
+
+        let preds = self.preds[block.0].clone();
+        let value = if !self.sealed[block.0] {
+            let phi = self.new_phi(block);
+            self.incomplete_phis[block.0].push((var, phi));
+            phi
+        } else if preds.len() == 1 {
+            self.read_variable(var, preds[0])
+        } else if preds.is_empty() {
+            self.new_undef(block)
+        } else {
+            // 循環している場合に備えて、オペランドを求める前に φ 関数を変数の値としておく
+            let phi = self.new_phi(block);
+            self.write_variable(var, block, phi);
+            self.add_phi_operands(var, phi, block)
+        };
+        self.write_variable(var, block, value);
+        value
+    }
+
+    fn new_phi(&mut self, block: BlockId) -> Value {
+        let result = self.new_value();
+        self.func.blocks[block.0].phis.push(Phi {
+            result,
+            args: Vec::new(),
+        });
+        result
+    }
+
+    fn new_undef(&mut self, block: BlockId) -> Value {
+        let result = self.new_value();
+        self.func.blocks[block.0].insts.insert(
+            0,
+            Inst {
+                result: Some(result),
+                kind: InstKind::Undef,
+            },
+        );
+        result
+    }
+
+    fn add_phi_operands(&mut self, var: i32, phi: Value, block: BlockId) -> Value {
+        for pred in self.preds[block.0].clone() {
+            let value = self.read_variable(var, pred);
+            self.phi_mut(block, phi).args.push((pred, value));
+        }
+        self.try_remove_trivial_phi(phi, block)
+    }
+
+    fn phi_mut(&mut self, block: BlockId, phi: Value) -> &mut Phi {
+        self.func.blocks[block.0]
+            .phis
+            .iter_mut()
+            .find(|p| p.result == phi)
+            .unwrap()
+    }
+
+    fn resolve(&self, mut value: Value) -> Value {
+        while let Some(replacement) = self.replacements.get(&value) {
+            value = *replacement;
+        }
+        value
+    }
+
+    /// すべてのオペランドが同じ値 (または自身) である φ 関数を取り除き、その値に置き換える
+    fn try_remove_trivial_phi(&mut self, phi: Value, block: BlockId) -> Value {
+        let mut same = None;
+        for (_, arg) in self.phi_mut(block, phi).args.clone() {
+            let arg = self.resolve(arg);
+            if Some(arg) == same || arg == phi {
+                continue;
+            }
+            if same.is_some() {
+                return phi;
+            }
+            same = Some(arg);
+        }
+        let same = match same {
+            Some(same) => same,
+            // 到達できないブロックか、自身のみを参照している
+            None => self.new_undef(block),
+        };
+        self.func.blocks[block.0].phis.retain(|p| p.result != phi);
+        self.replacements.insert(phi, same);
+        same
+    }
+
+    /// ブロックを埋め終えたことを記録し、すべての先行ブロックを埋め終えた後続ブロックを封印する
+    fn mark_filled(&mut self, block: BlockId) {
+        for succ in self.succs[block.0].clone() {
+            self.unfilled_preds[succ.0] -= 1;
+            if self.unfilled_preds[succ.0] == 0 {
+                self.seal(succ);
+            }
+        }
+    }
+
+    fn seal(&mut self, block: BlockId) {
+        self.sealed[block.0] = true;
+        for (var, phi) in std::mem::take(&mut self.incomplete_phis[block.0]) {
+            self.add_phi_operands(var, phi, block);
+        }
+    }
+
+    /// 取り除いた φ 関数への参照を置き換え、置き換えによって自明になった φ 関数がなくなるまで取り除く
+    fn finish(mut self) -> Function {
+        loop {
+            let trivial = self.func.blocks.iter().enumerate().find_map(|(i, block)| {
+                block
+                    .phis
+                    .iter()
+                    .find(|phi| {
+                        let mut args = phi
+                            .args
+                            .iter()
+                            .map(|(_, arg)| self.resolve(*arg))
+                            .filter(|arg| *arg != phi.result);
+                        let first = args.next();
+                        args.all(|arg| Some(arg) == first)
+                    })
+                    .map(|phi| (phi.result, BlockId(i)))
+            });
+            match trivial {
+                Some((phi, block)) => {
+                    self.try_remove_trivial_phi(phi, block);
+                }
+                None => break,
+            }
+        }
+
+        let replacements = std::mem::take(&mut self.replacements);
+        let resolve = |value: &mut Value| {
+            while let Some(replacement) = replacements.get(value) {
+                *value = *replacement;
+            }
+        };
+        for block in self.func.blocks.iter_mut() {
+            for phi in block.phis.iter_mut() {
+                for (_, arg) in phi.args.iter_mut() {
+                    resolve(arg);
+                }
+            }
+            for inst in block.insts.iter_mut() {
+                for operand in inst.kind.operands_mut() {
+                    resolve(operand);
+                }
+            }
+            for operand in block.terminator.operands_mut() {
+                resolve(operand);
+            }
+        }
+        self.func
+    }
+}
+
+/// 支配木 (各ブロックの直接支配ブロック)
+pub struct DomTree {
+    /// 直接支配ブロック (入口のブロックは自身、到達できないブロックは ``None``)
+    idoms: Vec<Option<BlockId>>,
+    /// 支配木における子
+    children: Vec<Vec<BlockId>>,
+    /// 支配木を深さ優先で辿ったときの、各ブロックに入った順番と出た順番
+    ///
+    /// ``a`` が ``b`` を支配するのは、 ``b`` の区間が ``a`` の区間に含まれる場合に限る
+    preorder: Vec<usize>,
+    postorder: Vec<usize>,
+}
+
+impl DomTree {
+    /// 直接支配ブロック (入口のブロックと到達できないブロックは ``None``)
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idoms[block.0].filter(|idom| *idom != block)
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idoms[block.0].is_some()
+    }
+
+    /// ``a`` が ``b`` を支配するかどうか (すべてのブロックは自身を支配する)
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        self.is_reachable(a)
+            && self.is_reachable(b)
+            && self.preorder[a.0] <= self.preorder[b.0]
+            && self.postorder[b.0] <= self.postorder[a.0]
+    }
+
+    /// 支配木における子 (直接支配するブロック)
+    pub fn children(&self, block: BlockId) -> Vec<BlockId> {
+        self.children[block.0].clone()
+    }
+}
+
+/// Cooper らの反復アルゴリズム (A Simple, Fast Dominance Algorithm) で支配木を求める
+pub fn dominators(func: &Function) -> DomTree {
+    let num_blocks = func.blocks.len();
+    let preds = func.predecessors();
+
+    // 逆後順 (reverse postorder) と、その中での各ブロックの位置
+    let mut postorder = Vec::new();
+    let mut visited = vec![false; num_blocks];
+    let mut stack = vec![(BlockId(0), 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.pop() {
+        let succs = func.blocks[block.0].successors();
+        if next < succs.len() {
+            stack.push((block, next + 1));
+            let succ = succs[next];
+            if !visited[succ.0] {
+                visited[succ.0] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            postorder.push(block);
+        }
+    }
+    let mut order = vec![usize::MAX; num_blocks];
+    for (i, block) in postorder.iter().enumerate() {
+        order[block.0] = i;
+    }
+
+    let mut idoms = vec![None; num_blocks];
+    idoms[0] = Some(BlockId(0));
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in postorder.iter().rev().skip(1) {
+            let mut new_idom = None;
+            for pred in preds[block.0].iter() {
+                if idoms[pred.0].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => *pred,
+                    Some(other) => intersect(&idoms, &order, *pred, other),
+                });
+            }
+            if new_idom.is_some() && idoms[block.0] != new_idom {
+                idoms[block.0] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    let mut children = vec![Vec::new(); num_blocks];
+    for (i, idom) in idoms.iter().enumerate() {
+        match idom {
+            Some(idom) if idom.0 != i => children[idom.0].push(BlockId(i)),
+            _ => {}
+        }
+    }
+
+    // 支配木を深さ優先で辿り、入った順番と出た順番を振る
+    let mut preorder = vec![usize::MAX; num_blocks];
+    let mut postorder = vec![usize::MAX; num_blocks];
+    let (mut num_entered, mut num_exited) = (0, 0);
+    let mut stack = vec![(BlockId(0), 0)];
+    preorder[0] = 0;
+    num_entered += 1;
+    while let Some((block, next)) = stack.pop() {
+        if let Some(child) = children[block.0].get(next) {
+            stack.push((block, next + 1));
+            preorder[child.0] = num_entered;
+            num_entered += 1;
+            stack.push((*child, 0));
+        } else {
+            postorder[block.0] = num_exited;
+            num_exited += 1;
+        }
+    }
+
+    DomTree {
+        idoms,
+        children,
+        preorder,
+        postorder,
+    }
+}
+
+fn intersect(
+    idoms: &[Option<BlockId>],
+    order: &[usize],
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while order[a.0] < order[b.0] {
+            a = idoms[a.0].unwrap();
+        }
+        while order[b.0] < order[a.0] {
+            b = idoms[b.0].unwrap();
+        }
+    }
+    a
+}
+
+/// SSA 形式の中間表現が正しい形をしているか検査する
+///
+/// - 分岐先のブロックが存在する
+/// - 各値はちょうど 1 度だけ定義される
+/// - φ 関数は先行ブロックごとに 1 つのオペランドを持つ
+/// - 到達できるブロックでは、値の定義がその使用を支配する
+pub fn verify(func: &Function) -> Result<(), String> {
+    let error = |msg: String| Err(format!("Invalid SSA in `{}`: {}", func.name, msg));
+
+    if func.blocks.is_empty() {
+        return error("no entry block".to_owned());
+    }
+    for (i, block) in func.blocks.iter().enumerate() {
+        for succ in block.successors() {
+            if succ.0 >= func.blocks.len() {
+                return error(format!("block{} jumps to undefined {}", i, succ));
+            }
+        }
+    }
+
+    // 各値を定義したブロックと、ブロック内の位置 (φ 関数は 0、命令は 1 から)
+    let mut defs = HashMap::<Value, (BlockId, usize)>::new();
+    for (i, block) in func.blocks.iter().enumerate() {
+        let results = block.phis.iter().map(|phi| (phi.result, 0)).chain(
+            block
+                .insts
+                .iter()
+                .enumerate()
+                .filter_map(|(j, inst)| inst.result.map(|result| (result, j + 1))),
+        );
+        for (value, pos) in results {
+            if value.0 >= func.num_values {
+                return error(format!("{} is out of range", value));
+            }
+            if defs.insert(value, (BlockId(i), pos)).is_some() {
+                return error(format!("{} is defined more than once", value));
+            }
+        }
+    }
+
+    let preds = func.predecessors();
+    let dom_tree = dominators(func);
+    let check_use = |value: Value, block: BlockId, pos: usize| -> Result<(), String> {
+        let (def_block, def_pos) = match defs.get(&value) {
+            Some(def) => *def,
+            None => return Err(format!("{} is used in {} but never defined", value, block)),
+        };
+        if !dom_tree.is_reachable(block) {
+            return Ok(());
+        }
+        let dominates = if def_block == block {
+            def_pos < pos
+        } else {
+            dom_tree.dominates(def_block, block)
+        };
+        if dominates {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} is used in {} but its definition does not dominate the use",
+                value, block
+            ))
+        }
+    };
+
+    for (i, block) in func.blocks.iter().enumerate() {
+        let id = BlockId(i);
+        for phi in block.phis.iter() {
+            let mut phi_preds: Vec<BlockId> = phi.args.iter().map(|(pred, _)| *pred).collect();
+            let mut block_preds = preds[i].clone();
+            phi_preds.sort();
+            block_preds.sort();
+            if phi_preds != block_preds {
+                return error(format!(
+                    "φ {} in {} does not have one operand per predecessor",
+                    phi.result, id
+                ));
+            }
+            // φ 関数のオペランドは、対応する先行ブロックの末尾で使用される
+            for (pred, arg) in phi.args.iter() {
+                check_use(*arg, *pred, usize::MAX).or_else(error)?;
+            }
+        }
+        for (j, inst) in block.insts.iter().enumerate() {
+            for operand in inst.kind.operands() {
+                check_use(operand, id, j + 1).or_else(error)?;
+            }
+        }
+        for operand in block.terminator.operands() {
+            check_use(operand, id, usize::MAX).or_else(error)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "function {}", self.name)?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            write!(f, "{}:", BlockId(i))?;
+            if let Some(stmt) = block.stmt {
+                write!(f, "  ; stmt{}", stmt)?;
+            }
+            if let Some(label) = block.label {
+                write!(f, "  ; user_label{}", label)?;
+            }
+            writeln!(f)?;
+            for phi in block.phis.iter() {
+                let args: Vec<String> = phi
+                    .args
+                    .iter()
+                    .map(|(pred, value)| format!("[{}, {}]", pred, value))
+                    .collect();
+                writeln!(f, "    {} = phi {}", phi.result, args.join(", "))?;
+            }
+            for inst in block.insts.iter() {
+                write!(f, "    ")?;
+                if let Some(result) = inst.result {
+                    write!(f, "{} = ", result)?;
+                }
+                let operands: Vec<String> =
+                    inst.kind.operands().iter().map(|v| v.to_string()).collect();
+                let operands = operands.join(", ");
+                match &inst.kind {
+                    InstKind::StaticStr(index) => writeln!(f, "str {}", index)?,
+                    InstKind::Int(value) => writeln!(f, "int {}", value)?,
+                    InstKind::Undef => writeln!(f, "undef")?,
+                    InstKind::Param(index) => writeln!(f, "param {}", index)?,
+                    InstKind::LoadGlobal(index) => writeln!(f, "load_global {}", index)?,
+                    InstKind::StoreGlobal(index, _) => {
+                        writeln!(f, "store_global {}, {}", index, operands)?
+                    }
+                    InstKind::AssertType(_, ty, _) => {
+                        writeln!(f, "assert_type {}, {:?}", operands, ty)?
+                    }
+                    InstKind::CallExtern(index, _) => {
+                        writeln!(f, "call_extern {}({})", index, operands)?
+                    }
+                    InstKind::CallProc(index, _, _) => {
+                        writeln!(f, "call_proc {}({})", index, operands)?
+                    }
+                    InstKind::Print(_) => writeln!(f, "print {}", operands)?,
+                    InstKind::OnErrorGoto(Some(label)) => {
+                        writeln!(f, "on_error_goto user_label{}", label)?
+                    }
+                    InstKind::OnErrorGoto(None) => writeln!(f, "on_error_goto 0")?,
+                    InstKind::GetErrCode => writeln!(f, "err_code")?,
+                    InstKind::GetErrLine => writeln!(f, "err_line")?,
+                }
+            }
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump {}", target)?,
+                Terminator::Resume(target, _, _) => writeln!(f, "    resume {:?}", target)?,
+                Terminator::Raise(value, _) => writeln!(f, "    raise {}", value)?,
+                Terminator::Exit => writeln!(f, "    exit")?,
+                Terminator::Return(Some(value)) => writeln!(f, "    return {}", value)?,
+                Terminator::Return(None) => writeln!(f, "    return")?,
+            }
+            if !block.exception_successors.is_empty() {
+                let handlers: Vec<String> = block
+                    .exception_successors
+                    .iter()
+                    .map(|handler| handler.to_string())
+                    .collect();
+                writeln!(f, "    ; on error: {}", handlers.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::Point;
+
+    fn block(insts: Vec<Inst>, terminator: Terminator) -> Block {
+        Block {
+            stmt: None,
+            label: None,
+            phis: Vec::new(),
+            insts,
+            terminator,
+            exception_successors: Vec::new(),
+        }
+    }
+
+    fn int(result: usize) -> Inst {
+        Inst {
+            result: Some(Value(result)),
+            kind: InstKind::Int(result as i64),
+        }
+    }
+
+    fn print(value: usize) -> Inst {
+        Inst {
+            result: None,
+            kind: InstKind::Print(Value(value)),
+        }
+    }
+
+    /// block0 から block1 と block2 に分岐し、block3 で合流する関数 (block4 には到達できない)
+    fn diamond(join: Block) -> Function {
+        let location = Location {
+            start: Point::new(0, 0),
+            end: Point::new(0, 0),
+        };
+        Function {
+            name: "diamond".to_owned(),
+            blocks: vec![
+                block(
+                    Vec::new(),
+                    Terminator::Resume(ResumeTarget::Next, location, vec![BlockId(1), BlockId(2)]),
+                ),
+                block(vec![int(0)], Terminator::Jump(BlockId(3))),
+                block(vec![int(1)], Terminator::Jump(BlockId(3))),
+                join,
+                block(Vec::new(), Terminator::Exit),
+            ],
+            num_values: 3,
+        }
+    }
+
+    #[test]
+    fn dominator_tree_of_diamond() {
+        let func = diamond(block(Vec::new(), Terminator::Exit));
+        let dom_tree = dominators(&func);
+
+        assert_eq!(dom_tree.idom(BlockId(0)), None);
+        for i in 1..=3 {
+            assert_eq!(dom_tree.idom(BlockId(i)), Some(BlockId(0)));
+            assert!(dom_tree.dominates(BlockId(0), BlockId(i)));
+            assert!(dom_tree.dominates(BlockId(i), BlockId(i)));
+        }
+        assert_eq!(
+            dom_tree.children(BlockId(0)),
+            vec![BlockId(1), BlockId(2), BlockId(3)]
+        );
+        assert!(!dom_tree.dominates(BlockId(1), BlockId(3)));
+        assert!(!dom_tree.dominates(BlockId(3), BlockId(1)));
+        assert!(!dom_tree.dominates(BlockId(1), BlockId(2)));
+
+        assert!(!dom_tree.is_reachable(BlockId(4)));
+        assert!(!dom_tree.dominates(BlockId(0), BlockId(4)));
+        assert!(!dom_tree.dominates(BlockId(4), BlockId(3)));
+    }
+
+    #[test]
+    fn verify_accepts_phi_at_join() {
+        let mut join = block(vec![print(2)], Terminator::Exit);
+        join.phis.push(Phi {
+            result: Value(2),
+            args: vec![(BlockId(1), Value(0)), (BlockId(2), Value(1))],
+        });
+        assert_eq!(verify(&diamond(join)), Ok(()));
+    }
+
+    #[test]
+    fn verify_rejects_use_not_dominated_by_definition() {
+        let func = diamond(block(vec![print(0)], Terminator::Exit));
+        let err = verify(&func).unwrap_err();
+        assert!(err.contains("does not dominate"), "{}", err);
+    }
+
+    #[test]
+    fn verify_rejects_wrong_number_of_phi_operands() {
+        let mut join = block(vec![print(2)], Terminator::Exit);
+        join.phis.push(Phi {
+            result: Value(2),
+            args: vec![(BlockId(1), Value(0))],
+        });
+        let err = verify(&diamond(join)).unwrap_err();
+        assert!(err.contains("one operand per predecessor"), "{}", err);
+    }
+}
@@ -0,0 +1,330 @@
+use super::ast::{ExprAst, ProcDefAst, StmtAst, Type};
+use super::location::{Locatable, Location, Point};
+use std::collections::HashMap;
+
+/// 変数の種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// トップレベルで宣言された変数
+    Global,
+    /// 手続きの本体で宣言された変数
+    Local,
+    /// 手続きの仮引数
+    Param,
+    /// ``FUNCTION`` の戻り値 (関数名への代入で設定される)
+    Return,
+}
+
+/// 変数の宣言と、それを参照している箇所
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// 宣言された位置
+    pub definition: Location,
+    /// 宣言以外で参照または代入されている位置
+    pub references: Vec<Location>,
+    /// 代入される値から推論した型 (推論できない場合や、異なる型の値が代入される場合は ``None`` )
+    pub ty: Option<Type>,
+    /// 宣言された手続きの番号 ( ``SymbolTable::scopes`` の添字、トップレベルの変数は ``None`` )
+    pub scope: Option<usize>,
+}
+
+/// 手続きの本体の範囲
+#[derive(Clone, Debug)]
+pub struct Scope {
+    pub name: String,
+    pub start: Point,
+    /// 次のトップレベルの文が始まる行の先頭 (最後の文の場合は ``None`` )
+    pub end: Option<Point>,
+}
+
+/// ソースコード中のすべての変数
+#[derive(Default)]
+pub struct SymbolTable {
+    /// 宣言された順に並べた変数
+    pub symbols: Vec<Symbol>,
+    pub scopes: Vec<Scope>,
+}
+
+impl SymbolTable {
+    /// 指定した位置にある識別子 (宣言または参照) が指す変数
+    ///
+    /// 識別子の直後の位置も、その識別子を指すものとして扱う
+    pub fn symbol_at(&self, point: Point) -> Option<&Symbol> {
+        let locations = || {
+            self.symbols.iter().flat_map(|symbol| {
+                std::iter::once(&symbol.definition)
+                    .chain(symbol.references.iter())
+                    .map(move |location| (symbol, location))
+            })
+        };
+        locations()
+            .find(|(_, location)| contains(location, point, 0))
+            .or_else(|| locations().find(|(_, location)| contains(location, point, 1)))
+            .map(|(symbol, _)| symbol)
+    }
+
+    /// 指定した位置で参照できる変数を名前順に返す
+    ///
+    /// 同じ名前の変数が複数ある場合は、ローカル変数と後から宣言された変数を優先する
+    pub fn visible_at(&self, point: Point) -> Vec<&Symbol> {
+        let scope = self.scopes.iter().position(|scope| {
+            before(scope.start, point) && scope.end.is_none_or(|end| before(point, end))
+        });
+
+        let mut visible = HashMap::<&str, &Symbol>::new();
+        for symbol in self
+            .symbols
+            .iter()
+            .filter(|symbol| before(symbol.definition.start, point))
+        {
+            if symbol.scope.is_none() {
+                visible.insert(&symbol.name, symbol);
+            }
+        }
+        for symbol in self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.scope.is_some() && symbol.scope == scope)
+        {
+            // 仮引数と戻り値は手続きの先頭で宣言されるため、常に参照できる
+            if symbol.kind != SymbolKind::Local || before(symbol.definition.start, point) {
+                visible.insert(&symbol.name, symbol);
+            }
+        }
+
+        let mut visible: Vec<&Symbol> = visible.into_values().collect();
+        visible.sort_by(|a, b| a.name.cmp(&b.name));
+        visible
+    }
+}
+
+/// ``a`` が ``b`` より前の位置か
+fn before(a: Point, b: Point) -> bool {
+    (a.line(), a.column()) < (b.line(), b.column())
+}
+
+/// 1行に収まる範囲 ``location`` が、終わりを ``margin`` 文字延ばして ``point`` を含むか
+fn contains(location: &Location, point: Point, margin: i32) -> bool {
+    location.start.line() == point.line()
+        && location.start.column() <= point.column()
+        && point.column() <= location.end.column() + margin
+}
+
+/// 変数を集める際に扱う状態
+#[derive(Default)]
+struct Context {
+    table: SymbolTable,
+    /// 各変数に代入される値の型
+    assigned: Vec<Vec<Option<Type>>>,
+    var_mappings: HashMap<String, usize>,
+    /// 手続きの本体を解析している間のみ存在する、ローカル変数の対応
+    local_mappings: Option<HashMap<String, usize>>,
+    /// 解析している手続きの番号
+    scope: Option<usize>,
+    /// 解析している ``FUNCTION`` の戻り値 (関数名, 変数の番号)
+    ret: Option<(String, usize)>,
+    /// 手続きの名前から、戻り値の型への対応 ( ``SUB`` の場合は ``None`` )
+    proc_rets: HashMap<String, Option<Type>>,
+}
+
+/// 抽象構文木から変数の宣言と参照を集め、型を推論する
+///
+/// 意味解析と同じ規則で名前を解決し、解決できない名前は無視する
+pub fn collect_symbols(stmts: &[StmtAst]) -> SymbolTable {
+    let mut context = Context::default();
+
+    for (i, stmt) in stmts.iter().enumerate() {
+        match stmt {
+            StmtAst::ProcDef(def) => {
+                let end = stmts
+                    .get(i + 1)
+                    .map(|next| Point::new(next.locate().start.line(), 0));
+                collect_proc_def(def, end, &mut context);
+            }
+            _ => collect_stmt(stmt, &mut context),
+        }
+    }
+
+    let mut table = context.table;
+    for (symbol, types) in table.symbols.iter_mut().zip(context.assigned) {
+        // 代入文では右辺を先に解析するため、参照をソースコード上の順に並べ直す
+        symbol
+            .references
+            .sort_by_key(|location| (location.start.line(), location.start.column()));
+        symbol.ty = match types.split_first() {
+            Some((first, rest)) if rest.iter().all(|ty| ty == first) => *first,
+            _ => None,
+        };
+    }
+    table
+}
+
+fn collect_proc_def(def: &ProcDefAst, end: Option<Point>, context: &mut Context) {
+    // 再帰呼び出しの戻り値の型も推論できるように、本体を解析する前に登録しておく
+    context
+        .proc_rets
+        .insert(def.name.name.clone(), def.ret.as_ref().map(|ret| ret.ty));
+
+    let scope = context.table.scopes.len();
+    context.table.scopes.push(Scope {
+        name: def.name.name.clone(),
+        start: def.name.location.start,
+        end,
+    });
+
+    let mut local_mappings = HashMap::new();
+    for param in def.params.iter() {
+        let index = declare(
+            &param.name.name,
+            SymbolKind::Param,
+            param.name.location,
+            Some(param.ty.ty),
+            Some(scope),
+            context,
+        );
+        local_mappings.insert(param.name.name.clone(), index);
+    }
+    let mut outer_ret = context.ret.take();
+    if let Some(ret) = &def.ret {
+        let index = declare(
+            &def.name.name,
+            SymbolKind::Return,
+            def.name.location,
+            Some(ret.ty),
+            Some(scope),
+            context,
+        );
+        outer_ret = context.ret.replace((def.name.name.clone(), index));
+    }
+
+    let outer_mappings = context.local_mappings.replace(local_mappings);
+    let outer_scope = context.scope.replace(scope);
+    for stmt in def.body.iter() {
+        collect_stmt(stmt, context);
+    }
+    context.local_mappings = outer_mappings;
+    context.scope = outer_scope;
+    context.ret = outer_ret;
+}
+
+fn collect_stmt(stmt: &StmtAst, context: &mut Context) {
+    match stmt {
+        StmtAst::VarDecl(var_ident, init_expr) => {
+            let ty = collect_expr(init_expr, context);
+            let scope = context.scope;
+            let kind = if scope.is_some() {
+                SymbolKind::Local
+            } else {
+                SymbolKind::Global
+            };
+            let index = declare(
+                &var_ident.name,
+                kind,
+                var_ident.location,
+                ty,
+                scope,
+                context,
+            );
+            match &mut context.local_mappings {
+                Some(local_mappings) => local_mappings.insert(var_ident.name.clone(), index),
+                None => context.var_mappings.insert(var_ident.name.clone(), index),
+            };
+        }
+        StmtAst::VarAssign(var_ident, expr) => {
+            let ty = collect_expr(expr, context);
+            // 戻り値の型は宣言で決まるため、代入される値の型は記録しない
+            if let Some((_, index)) = context
+                .ret
+                .as_ref()
+                .filter(|(name, _)| *name == var_ident.name)
+            {
+                context.table.symbols[*index]
+                    .references
+                    .push(var_ident.location);
+            } else if let Some(index) = resolve_var(&var_ident.name, context) {
+                context.table.symbols[index]
+                    .references
+                    .push(var_ident.location);
+                context.assigned[index].push(ty);
+            }
+        }
+        StmtAst::ProcCall(_, args) => {
+            for arg in args.iter() {
+                collect_expr(arg, context);
+            }
+        }
+        StmtAst::ExternDecl(decl) => {
+            context
+                .proc_rets
+                .insert(decl.name.name.clone(), decl.ret.as_ref().map(|ret| ret.ty));
+        }
+        StmtAst::ProcDef(def) => {
+            // 手続きの中の手続きの定義は意味解析でエラーになるが、本体の変数は集めておく
+            collect_proc_def(def, None, context);
+        }
+        StmtAst::Error(_, expr) => {
+            collect_expr(expr, context);
+        }
+        StmtAst::Label(_)
+        | StmtAst::OnErrorGoto(_, _)
+        | StmtAst::Resume(_, _)
+        | StmtAst::End(_) => {}
+    }
+}
+
+/// 式に含まれる変数の参照を集め、式の型を推論する
+fn collect_expr(expr: &ExprAst, context: &mut Context) -> Option<Type> {
+    match expr {
+        ExprAst::Ident(ident) if ident.name == "ERR" || ident.name == "ERL" => Some(Type::Integer),
+        ExprAst::Ident(ident) => {
+            let index = resolve_var(&ident.name, context)?;
+            context.table.symbols[index].references.push(ident.location);
+            let types = &context.assigned[index];
+            match types.split_first() {
+                Some((first, rest)) if rest.iter().all(|ty| ty == first) => *first,
+                _ => None,
+            }
+        }
+        ExprAst::StrLit(_) => Some(Type::String),
+        ExprAst::IntLit(_) => Some(Type::Integer),
+        ExprAst::Call(func, args) => {
+            for arg in args.iter() {
+                collect_expr(arg, context);
+            }
+            context.proc_rets.get(&func.name).copied().flatten()
+        }
+    }
+}
+
+/// 変数を登録して、その番号を返す
+fn declare(
+    name: &str,
+    kind: SymbolKind,
+    definition: Location,
+    ty: Option<Type>,
+    scope: Option<usize>,
+    context: &mut Context,
+) -> usize {
+    context.table.symbols.push(Symbol {
+        name: name.to_owned(),
+        kind,
+        definition,
+        references: Vec::new(),
+        ty: None,
+        scope,
+    });
+    context.assigned.push(vec![ty]);
+    context.table.symbols.len() - 1
+}
+
+/// 変数名を解決する (ローカル変数はグローバル変数より優先される)
+fn resolve_var(name: &str, context: &Context) -> Option<usize> {
+    context
+        .local_mappings
+        .as_ref()
+        .and_then(|local_mappings| local_mappings.get(name))
+        .or_else(|| context.var_mappings.get(name))
+        .copied()
+}
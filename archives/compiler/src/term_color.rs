@@ -1,4 +1,32 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
 /// テキストを赤色・太文字にする
 pub fn red_bold(text: &str) -> String {
     format!("\x1b[31m\x1b[1m{}\x1b[m", text)
 }
+
+/// テキストを黄色・太文字にする
+pub fn yellow_bold(text: &str) -> String {
+    format!("\x1b[33m\x1b[1m{}\x1b[m", text)
+}
+
+/// テキストを青色・太文字にする
+pub fn blue_bold(text: &str) -> String {
+    format!("\x1b[34m\x1b[1m{}\x1b[m", text)
+}
+
+/// テキストを太文字にする
+pub fn bold(text: &str) -> String {
+    format!("\x1b[1m{}\x1b[m", text)
+}
+
+/// 標準エラー出力に色を付けて出力するか判定する
+///
+/// 環境変数 ``NO_COLOR`` が空でない値に設定されている場合と、標準エラー出力が端末でない場合は色を付けない
+pub fn stderr_supports_color() -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && io::stderr().is_terminal()
+}
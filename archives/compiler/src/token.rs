@@ -1,8 +1,8 @@
 use super::location::{Locatable, Location, Point};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Identifier {
     pub name: String,
     pub location: Location,
@@ -20,7 +20,7 @@ impl Locatable for Identifier {
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct StringLiteral {
     pub value: String,
     pub location: Location,
@@ -38,6 +38,25 @@ impl Locatable for StringLiteral {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IntegerLiteral {
+    pub value: i64,
+    pub location: Location,
+}
+
+impl fmt::Debug for IntegerLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{:?}", self.value, self.location)
+    }
+}
+
+impl Locatable for IntegerLiteral {
+    fn locate(&self) -> Location {
+        self.location
+    }
+}
+
+#[derive(Serialize)]
 pub struct Comma {
     pub loc: Point,
 }
@@ -57,6 +76,7 @@ impl Locatable for Comma {
     }
 }
 
+#[derive(Serialize)]
 pub struct Equal {
     pub loc: Point,
 }
@@ -76,6 +96,67 @@ impl Locatable for Equal {
     }
 }
 
+#[derive(Serialize)]
+pub struct Colon {
+    pub loc: Point,
+}
+
+impl fmt::Debug for Colon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`:`@{:?}", self.loc)
+    }
+}
+
+impl Locatable for Colon {
+    fn locate(&self) -> Location {
+        Location {
+            start: self.loc,
+            end: self.loc,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LParen {
+    pub loc: Point,
+}
+
+impl fmt::Debug for LParen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`(`@{:?}", self.loc)
+    }
+}
+
+impl Locatable for LParen {
+    fn locate(&self) -> Location {
+        Location {
+            start: self.loc,
+            end: self.loc,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RParen {
+    pub loc: Point,
+}
+
+impl fmt::Debug for RParen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`)`@{:?}", self.loc)
+    }
+}
+
+impl Locatable for RParen {
+    fn locate(&self) -> Location {
+        Location {
+            start: self.loc,
+            end: self.loc,
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct LineBreak {
     pub loc: Point,
 }
@@ -95,14 +176,19 @@ impl Locatable for LineBreak {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum Token {
     /// 識別子
     Ident(Identifier),
     /// 文字列リテラル
     StrLit(StringLiteral),
+    /// 整数リテラル
+    IntLit(IntegerLiteral),
     Comma(Comma),
     Equal(Equal),
+    Colon(Colon),
+    LParen(LParen),
+    RParen(RParen),
     LineBreak(LineBreak),
 }
 
@@ -111,9 +197,30 @@ impl Locatable for Token {
         match self {
             Token::Ident(ident) => ident.locate(),
             Token::StrLit(str_lit) => str_lit.locate(),
+            Token::IntLit(int_lit) => int_lit.locate(),
             Token::Comma(comma) => comma.locate(),
             Token::Equal(equal) => equal.locate(),
+            Token::Colon(colon) => colon.locate(),
+            Token::LParen(lparen) => lparen.locate(),
+            Token::RParen(rparen) => rparen.locate(),
             Token::LineBreak(line_break) => line_break.locate(),
         }
     }
 }
+
+/// エラーメッセージに用いる、位置を含めないトークンの表記
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident.name),
+            Token::StrLit(str_lit) => write!(f, "\"{}\"", str_lit.value),
+            Token::IntLit(int_lit) => write!(f, "`{}`", int_lit.value),
+            Token::Comma(_) => write!(f, "`,`"),
+            Token::Equal(_) => write!(f, "`=`"),
+            Token::Colon(_) => write!(f, "`:`"),
+            Token::LParen(_) => write!(f, "`(`"),
+            Token::RParen(_) => write!(f, "`)`"),
+            Token::LineBreak(_) => write!(f, "[EOL]"),
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use super::diagnostic::Diagnostic;
 use super::location::{Location, Point};
-use super::term_color::red_bold;
-use super::token::{Comma, Equal, Identifier, LineBreak, StringLiteral, Token};
-use once_cell::sync::Lazy;
+use super::token::{
+    Colon, Comma, Equal, Identifier, IntegerLiteral, LParen, LineBreak, RParen, StringLiteral,
+    Token,
+};
 
 #[derive(PartialEq, Eq)]
 enum TokenizerState {
@@ -22,10 +24,8 @@ struct StrLitState {
     acc: String,
 }
 
-static TOKENIZATION_ERROR: Lazy<String> = Lazy::new(|| red_bold("Tokenization error:"));
-
 /// ソースコード文字列を元にトークン列を生成する
-pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+pub fn tokenize(src: &str) -> Result<Vec<Token>, Diagnostic> {
     let len = src.len();
 
     let mut tokens = Vec::<Token>::new();
@@ -35,15 +35,10 @@ pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
 
     for (i, c) in src.chars().enumerate() {
         if c == '\n' {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1);
-
-            if let TokenizerState::StringLiteral(_) = state {
-                return Err(format!(
-                    "{} ({}:{}) Unexpected [EOL] in string literal",
-                    TOKENIZATION_ERROR.as_str(),
-                    line_number,
-                    column_number
-                ));
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
+
+            if let TokenizerState::StringLiteral(StrLitState { start, .. }) = state {
+                return Err(unterminated_str_lit(line_number, start, column_number - 1));
             }
 
             tokens.push(Token::LineBreak(LineBreak {
@@ -79,26 +74,44 @@ pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
         }
 
         if c.is_whitespace() {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
         } else if c == '"' {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1);
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
 
             state = TokenizerState::StringLiteral(StrLitState {
                 start: column_number,
                 acc: String::new(),
             });
         } else if c == ',' {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1);
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
 
             tokens.push(Token::Comma(Comma {
                 loc: Point::new(line_number, column_number),
             }));
         } else if c == '=' {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1);
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
 
             tokens.push(Token::Equal(Equal {
                 loc: Point::new(line_number, column_number),
             }));
+        } else if c == ':' {
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
+
+            tokens.push(Token::Colon(Colon {
+                loc: Point::new(line_number, column_number),
+            }));
+        } else if c == '(' {
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
+
+            tokens.push(Token::LParen(LParen {
+                loc: Point::new(line_number, column_number),
+            }));
+        } else if c == ')' {
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number - 1)?;
+
+            tokens.push(Token::RParen(RParen {
+                loc: Point::new(line_number, column_number),
+            }));
         } else if let TokenizerState::Identifier(IdentState { start, ref acc }) = state {
             let acc = format!("{}{}", acc, c);
             state = TokenizerState::Identifier(IdentState { start, acc })
@@ -111,15 +124,10 @@ pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
         }
 
         if i == len - 1 {
-            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number);
-
-            if let TokenizerState::StringLiteral(_) = state {
-                return Err(format!(
-                    "{} ({}:{}) Unexpected end of line",
-                    TOKENIZATION_ERROR.as_str(),
-                    line_number,
-                    column_number
-                ));
+            try_tokenizing_ident(&mut tokens, &mut state, line_number, column_number)?;
+
+            if let TokenizerState::StringLiteral(StrLitState { start, .. }) = state {
+                return Err(unterminated_str_lit(line_number, start, column_number));
             }
         }
 
@@ -129,21 +137,46 @@ pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
     Ok(tokens)
 }
 
+/// 読み進めている識別子があれば、識別子または整数リテラルとしてトークン列に追加する
 fn try_tokenizing_ident(
     tokens: &mut Vec<Token>,
     state: &mut TokenizerState,
     line: i32,
     column: i32,
-) {
+) -> Result<(), Diagnostic> {
     if let TokenizerState::Identifier(IdentState { start, ref acc }) = state {
         let location = Location {
             start: Point::new(line, *start),
             end: Point::new(line, column),
         };
-        tokens.push(Token::Ident(Identifier {
-            name: acc.clone(),
-            location,
-        }));
+
+        if acc.chars().all(|c| c.is_ascii_digit()) {
+            let value = acc.parse::<i64>().map_err(|_| {
+                Diagnostic::error("E0002", format!("Integer literal `{}` is too large", acc))
+                    .with_label(location, "does not fit in INTEGER")
+                    .with_note(format!("the largest INTEGER is {}", i64::MAX))
+            })?;
+            tokens.push(Token::IntLit(IntegerLiteral { value, location }));
+        } else {
+            tokens.push(Token::Ident(Identifier {
+                name: acc.clone(),
+                location,
+            }));
+        }
+
         *state = TokenizerState::Ready;
     }
+
+    Ok(())
+}
+
+/// 行末またはファイルの末尾までに閉じられなかった文字列リテラルのエラー
+fn unterminated_str_lit(line: i32, start: i32, end: i32) -> Diagnostic {
+    let location = Location {
+        start: Point::new(line, start),
+        end: Point::new(line, end),
+    };
+    Diagnostic::error("E0001", "Unterminated string literal")
+        .with_label(location, "missing closing `\"`")
+        .with_note("string literals cannot span multiple lines")
 }
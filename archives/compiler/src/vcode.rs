@@ -0,0 +1,304 @@
+use super::asm::{Constant, Imm};
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use std::convert::TryFrom;
+
+/// 仮想レジスタ (レジスタ割り当てによって物理レジスタかスタック上の領域に対応付けられる)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct VReg(pub usize);
+
+/// 命令のオペランド
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VValue {
+    Reg(VReg),
+    /// 32 ビットに収まる即値 (文字列のアドレスや型タグを含む)
+    Imm(Imm),
+}
+
+/// レジスタに割り当てられず、常にメモリに置かれる値
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Place {
+    GlobalValue(i32),
+    GlobalTag(i32),
+    ErrCode,
+    ErrLine,
+}
+
+/// 仮想レジスタを用いる命令
+///
+/// 仮想レジスタへの代入は 1 度のみで、スタックマシンのスタックとローカル変数はすべて仮想レジスタに置き換えられる
+#[derive(Debug)]
+pub enum VInst {
+    /// 手続きの先頭で、レジスタで渡された引数を受け取る
+    Params(Vec<VReg>),
+    /// 即値を代入する
+    Imm(VReg, Imm),
+    /// メモリ上の値を読み込む
+    Load(VReg, Place),
+    /// メモリに値を書き込む
+    Store(Place, VValue),
+    /// 型タグを検査する
+    AssertTag(VValue, Type, Location),
+    /// 手続きを呼び出す ( ``location`` は BASIC で定義された手続きの呼び出し箇所)
+    Call {
+        name: String,
+        args: Vec<VValue>,
+        ret: Option<VReg>,
+        location: Option<Location>,
+    },
+    /// 値と型タグを受け取って値を表示する
+    Print(VValue, VValue),
+    BeginStmt(i32),
+    Label(i32),
+    OnErrorGoto(Option<i32>),
+    Resume(ResumeTarget, Location),
+    /// 整数をエラー番号としてランタイムエラーを発生させる
+    RaiseError(VValue, Location),
+    End,
+    /// 手続きから戻る
+    Return(Option<VValue>),
+}
+
+impl VInst {
+    /// 命令が読み出す仮想レジスタ
+    pub fn uses(&self) -> Vec<VReg> {
+        let values: Vec<&VValue> = match self {
+            VInst::Store(_, value)
+            | VInst::AssertTag(value, _, _)
+            | VInst::RaiseError(value, _)
+            | VInst::Return(Some(value)) => vec![value],
+            VInst::Call { args, .. } => args.iter().collect(),
+            VInst::Print(value, tag) => vec![value, tag],
+            _ => Vec::new(),
+        };
+        values
+            .into_iter()
+            .filter_map(|value| match value {
+                VValue::Reg(reg) => Some(*reg),
+                VValue::Imm(_) => None,
+            })
+            .collect()
+    }
+
+    /// 命令が代入する仮想レジスタ
+    pub fn defs(&self) -> Vec<VReg> {
+        match self {
+            VInst::Params(regs) => regs.clone(),
+            VInst::Imm(reg, _) | VInst::Load(reg, _) => vec![*reg],
+            VInst::Call { ret: Some(reg), .. } => vec![*reg],
+            _ => Vec::new(),
+        }
+    }
+
+    /// 呼び出し元保存レジスタを破壊するかどうか
+    pub fn is_call(&self) -> bool {
+        matches!(self, VInst::Call { .. } | VInst::Print(_, _))
+    }
+}
+
+/// 仮想レジスタを用いる命令列
+pub struct VCode {
+    pub insts: Vec<VInst>,
+    /// 仮想レジスタの個数
+    pub num_vregs: usize,
+}
+
+/// スタックマシンのスタックに積まれる値 (値と型タグの組)
+type StackEntry = (VValue, VValue);
+
+struct Lowering<'a> {
+    ir: &'a Ir,
+    insts: Vec<VInst>,
+    num_vregs: usize,
+    stack: Vec<StackEntry>,
+    /// ローカル変数の現在の値
+    locals: Vec<Option<StackEntry>>,
+}
+
+impl Lowering<'_> {
+    fn new_vreg(&mut self) -> VReg {
+        self.num_vregs += 1;
+        VReg(self.num_vregs - 1)
+    }
+
+    fn pop(&mut self) -> StackEntry {
+        self.stack.pop().unwrap()
+    }
+
+    fn push_call_result(&mut self, ret: Option<Type>) -> Option<VReg> {
+        ret.map(|ty| {
+            let reg = self.new_vreg();
+            self.stack.push((VValue::Reg(reg), type_tag(ty)));
+            reg
+        })
+    }
+
+    fn lower(&mut self, insts: &[IrInst]) {
+        for ir_inst in insts.iter() {
+            match ir_inst {
+                IrInst::GetStaticStr(index) => {
+                    let value = VValue::Imm(Imm::Label(format!("str{}", index)));
+                    self.stack.push((value, type_tag(Type::String)));
+                }
+                IrInst::GetImmInt(value) => {
+                    let value = if i32::try_from(*value).is_ok() {
+                        VValue::Imm(Imm::Int(*value))
+                    } else {
+                        let reg = self.new_vreg();
+                        self.insts.push(VInst::Imm(reg, Imm::Int(*value)));
+                        VValue::Reg(reg)
+                    };
+                    self.stack.push((value, type_tag(Type::Integer)));
+                }
+                IrInst::GetGlobal(index) => {
+                    let value = self.new_vreg();
+                    let tag = self.new_vreg();
+                    self.insts
+                        .push(VInst::Load(value, Place::GlobalValue(*index)));
+                    self.insts.push(VInst::Load(tag, Place::GlobalTag(*index)));
+                    self.stack.push((VValue::Reg(value), VValue::Reg(tag)));
+                }
+                IrInst::SetGlobal(index) => {
+                    let (value, tag) = self.pop();
+                    self.insts.push(VInst::Store(Place::GlobalTag(*index), tag));
+                    self.insts
+                        .push(VInst::Store(Place::GlobalValue(*index), value));
+                }
+                IrInst::GetLocal(index) => {
+                    // 代入前に読み出された場合は、型の検査に失敗する値とする
+                    let entry = self.locals[*index as usize]
+                        .clone()
+                        .unwrap_or((VValue::Imm(Imm::Int(0)), VValue::Imm(Imm::Int(0))));
+                    self.stack.push(entry);
+                }
+                IrInst::SetLocal(index) => {
+                    let entry = self.pop();
+                    self.locals[*index as usize] = Some(entry);
+                }
+                IrInst::AssertType(ty, location) => {
+                    let (_, tag) = self.stack.last().unwrap().clone();
+                    self.insts.push(VInst::AssertTag(tag, *ty, *location));
+                }
+                IrInst::CallExtern(index) => {
+                    let ext = &self.ir.externs[*index as usize];
+                    let args = self.pop_args(ext.params.len());
+                    let ret = self.push_call_result(ext.ret);
+                    self.insts.push(VInst::Call {
+                        name: ext.name.clone(),
+                        args,
+                        ret,
+                        location: None,
+                    });
+                }
+                IrInst::CallProc(index, location) => {
+                    let proc = &self.ir.procs[*index as usize];
+                    let args = self.pop_args(proc.params.len());
+                    let ret = self.push_call_result(proc.ret);
+                    self.insts.push(VInst::Call {
+                        name: proc.name.clone(),
+                        args,
+                        ret,
+                        location: Some(*location),
+                    });
+                }
+                IrInst::Pop => {
+                    self.pop();
+                }
+                IrInst::Print => {
+                    let (value, tag) = self.pop();
+                    self.insts.push(VInst::Print(value, tag));
+                }
+                IrInst::BeginStmt(index) => {
+                    self.insts.push(VInst::BeginStmt(*index));
+                }
+                IrInst::Label(index) => {
+                    self.insts.push(VInst::Label(*index));
+                }
+                IrInst::OnErrorGoto(label) => {
+                    self.insts.push(VInst::OnErrorGoto(*label));
+                }
+                IrInst::Resume(target, location) => {
+                    self.insts.push(VInst::Resume(*target, *location));
+                }
+                IrInst::RaiseError(location) => {
+                    let (value, _) = self.pop();
+                    self.insts.push(VInst::RaiseError(value, *location));
+                }
+                IrInst::GetErrCode => {
+                    let value = self.new_vreg();
+                    self.insts.push(VInst::Load(value, Place::ErrCode));
+                    self.stack
+                        .push((VValue::Reg(value), type_tag(Type::Integer)));
+                }
+                IrInst::GetErrLine => {
+                    let value = self.new_vreg();
+                    self.insts.push(VInst::Load(value, Place::ErrLine));
+                    self.stack
+                        .push((VValue::Reg(value), type_tag(Type::Integer)));
+                }
+                IrInst::End => {
+                    self.insts.push(VInst::End);
+                }
+            }
+        }
+    }
+
+    /// 引数の値をスタックからポップする (型タグは呼び出し先に渡さない)
+    fn pop_args(&mut self, num_params: usize) -> Vec<VValue> {
+        let args = self.stack.split_off(self.stack.len() - num_params);
+        args.into_iter().map(|(value, _)| value).collect()
+    }
+}
+
+fn type_tag(ty: Type) -> VValue {
+    VValue::Imm(Imm::Const(match ty {
+        Type::String => Constant::TypeStr,
+        Type::Integer => Constant::TypeInt,
+    }))
+}
+
+/// トップレベルの命令列を仮想レジスタを用いる命令列に変換する
+pub fn lower_main(ir: &Ir) -> VCode {
+    let mut lowering = Lowering {
+        ir,
+        insts: Vec::new(),
+        num_vregs: 0,
+        stack: Vec::new(),
+        locals: Vec::new(),
+    };
+    lowering.lower(&ir.insts);
+    VCode {
+        insts: lowering.insts,
+        num_vregs: lowering.num_vregs,
+    }
+}
+
+/// 手続きの命令列を仮想レジスタを用いる命令列に変換する
+pub fn lower_proc(ir: &Ir, proc: &Proc) -> VCode {
+    let mut lowering = Lowering {
+        ir,
+        insts: Vec::new(),
+        num_vregs: 0,
+        stack: Vec::new(),
+        locals: vec![None; proc.num_locals as usize],
+    };
+
+    let params: Vec<VReg> = proc.params.iter().map(|_| lowering.new_vreg()).collect();
+    for (i, (reg, ty)) in params.iter().zip(proc.params.iter()).enumerate() {
+        lowering.locals[i] = Some((VValue::Reg(*reg), type_tag(*ty)));
+    }
+    lowering.insts.push(VInst::Params(params));
+
+    lowering.lower(&proc.insts);
+
+    let ret = proc
+        .ret_slot()
+        .and_then(|slot| lowering.locals[slot as usize].clone())
+        .map(|(value, _)| value);
+    lowering.insts.push(VInst::Return(ret));
+    VCode {
+        insts: lowering.insts,
+        num_vregs: lowering.num_vregs,
+    }
+}
@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+
+/// 値の型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+/// 命令 (関数・ローカル変数・グローバル変数・ラベルは名前で参照する)
+#[derive(Clone, Debug)]
+pub enum WasmInst {
+    Block(Option<String>),
+    Loop(Option<String>),
+    If,
+    End,
+    Br(String),
+    BrIf(String),
+    /// 分岐先の一覧と既定の分岐先
+    BrTable(Vec<String>, String),
+    Return,
+    Call(String),
+    Drop,
+    LocalGet(String),
+    LocalSet(String),
+    LocalTee(String),
+    GlobalGet(String),
+    GlobalSet(String),
+    I32Load8U,
+    I32Store,
+    I32Store8,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Ne,
+    I32Add,
+    I32Sub,
+    I32Or,
+    I64Ne,
+    I64LtS,
+    I64Sub,
+    I64DivU,
+    I64RemU,
+    I32WrapI64,
+    I64ExtendI32U,
+}
+
+/// 外部から取り込む関数
+pub struct Import {
+    pub module: String,
+    pub field: String,
+    pub name: String,
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// グローバル変数 (すべて可変で、0 で初期化される)
+pub struct Global {
+    pub name: String,
+    pub ty: ValType,
+}
+
+/// モジュール内で定義される関数
+pub struct Func {
+    pub name: String,
+    /// 外部に公開する名前
+    pub export: Option<String>,
+    pub params: Vec<(String, ValType)>,
+    pub results: Vec<ValType>,
+    pub locals: Vec<(String, ValType)>,
+    pub body: Vec<WasmInst>,
+}
+
+/// WebAssembly モジュールの内部表現
+#[derive(Default)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub globals: Vec<Global>,
+    pub funcs: Vec<Func>,
+    /// 線形メモリに配置するデータ (開始アドレスとバイト列)
+    pub data: (u32, Vec<u8>),
+}
+
+impl Module {
+    /// テキスト形式 (``.wat``) に変換する
+    pub fn stringify(&self) -> String {
+        let mut result = String::from("(module\n");
+
+        for import in self.imports.iter() {
+            result.push_str(
+                format!(
+                    "  (import \"{}\" \"{}\" (func ${}{}))\n",
+                    import.module,
+                    import.field,
+                    import.name,
+                    signature(&import.params, &import.results)
+                )
+                .as_str(),
+            );
+        }
+
+        result.push_str("  (memory 1)\n");
+        result.push_str("  (export \"memory\" (memory 0))\n");
+
+        for global in self.globals.iter() {
+            result.push_str(
+                format!(
+                    "  (global ${} (mut {}) ({}.const 0))\n",
+                    global.name,
+                    global.ty.name(),
+                    global.ty.name()
+                )
+                .as_str(),
+            );
+        }
+
+        let (offset, bytes) = &self.data;
+        result
+            .push_str(format!("  (data (i32.const {}) \"{}\")\n", offset, escape(bytes)).as_str());
+
+        for func in self.funcs.iter() {
+            result.push_str(format!("  (func ${}", func.name).as_str());
+            if let Some(export) = &func.export {
+                result.push_str(format!(" (export \"{}\")", export).as_str());
+            }
+            for (name, ty) in func.params.iter() {
+                result.push_str(format!(" (param ${} {})", name, ty.name()).as_str());
+            }
+            for ty in func.results.iter() {
+                result.push_str(format!(" (result {})", ty.name()).as_str());
+            }
+            result.push('\n');
+            for (name, ty) in func.locals.iter() {
+                result.push_str(format!("    (local ${} {})\n", name, ty.name()).as_str());
+            }
+
+            let mut indent = 2;
+            for inst in func.body.iter() {
+                if let WasmInst::End = inst {
+                    indent -= 1;
+                }
+                result.push_str(format!("{}{}\n", "  ".repeat(indent), inst_text(inst)).as_str());
+                if let WasmInst::Block(_) | WasmInst::Loop(_) | WasmInst::If = inst {
+                    indent += 1;
+                }
+            }
+            result.push_str("  )\n");
+        }
+
+        result.push_str(")\n");
+        result
+    }
+
+    /// バイナリ形式 (``.wasm``) に変換する
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut types = Vec::<(Vec<ValType>, Vec<ValType>)>::new();
+        let mut type_index = |params: Vec<ValType>, results: Vec<ValType>| {
+            let sig = (params, results);
+            match types.iter().position(|t| *t == sig) {
+                Some(index) => index as u32,
+                None => {
+                    types.push(sig);
+                    types.len() as u32 - 1
+                }
+            }
+        };
+
+        let import_types = self
+            .imports
+            .iter()
+            .map(|import| type_index(import.params.clone(), import.results.clone()))
+            .collect::<Vec<_>>();
+        let func_types = self
+            .funcs
+            .iter()
+            .map(|func| {
+                let params = func.params.iter().map(|(_, ty)| *ty).collect();
+                type_index(params, func.results.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let func_indices = self
+            .imports
+            .iter()
+            .map(|import| import.name.as_str())
+            .chain(self.funcs.iter().map(|func| func.name.as_str()))
+            .enumerate()
+            .map(|(i, name)| (name, i as u32))
+            .collect::<HashMap<_, _>>();
+        let global_indices = self
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, global)| (global.name.as_str(), i as u32))
+            .collect::<HashMap<_, _>>();
+
+        let mut result = b"\0asm".to_vec();
+        result.extend([1, 0, 0, 0]);
+
+        // type section
+        let mut section = Vec::new();
+        leb_u32(&mut section, types.len() as u32);
+        for (params, results) in types.iter() {
+            section.push(0x60);
+            val_types(&mut section, params);
+            val_types(&mut section, results);
+        }
+        push_section(&mut result, 1, section);
+
+        // import section
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.imports.len() as u32);
+        for (import, type_index) in self.imports.iter().zip(import_types) {
+            name(&mut section, &import.module);
+            name(&mut section, &import.field);
+            section.push(0x00);
+            leb_u32(&mut section, type_index);
+        }
+        push_section(&mut result, 2, section);
+
+        // function section
+        let mut section = Vec::new();
+        leb_u32(&mut section, func_types.len() as u32);
+        for type_index in func_types {
+            leb_u32(&mut section, type_index);
+        }
+        push_section(&mut result, 3, section);
+
+        // memory section
+        push_section(&mut result, 5, vec![1, 0x00, 1]);
+
+        // global section
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.globals.len() as u32);
+        for global in self.globals.iter() {
+            section.push(global.ty.code());
+            section.push(0x01);
+            section.push(match global.ty {
+                ValType::I32 => 0x41,
+                ValType::I64 => 0x42,
+            });
+            section.push(0);
+            section.push(0x0b);
+        }
+        push_section(&mut result, 6, section);
+
+        // export section
+        let exports = self
+            .funcs
+            .iter()
+            .filter_map(|func| func.export.as_ref().map(|export| (export, &func.name)))
+            .collect::<Vec<_>>();
+        let mut section = Vec::new();
+        leb_u32(&mut section, exports.len() as u32 + 1);
+        name(&mut section, "memory");
+        section.extend([0x02, 0]);
+        for (export, func_name) in exports {
+            name(&mut section, export);
+            section.push(0x00);
+            leb_u32(&mut section, func_indices[func_name.as_str()]);
+        }
+        push_section(&mut result, 7, section);
+
+        // code section
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.funcs.len() as u32);
+        for func in self.funcs.iter() {
+            let body = encode_func(func, &func_indices, &global_indices)?;
+            leb_u32(&mut section, body.len() as u32);
+            section.extend(body);
+        }
+        push_section(&mut result, 10, section);
+
+        // data section
+        let (offset, bytes) = &self.data;
+        let mut section = vec![1, 0x00, 0x41];
+        leb_i64(&mut section, *offset as i64);
+        section.push(0x0b);
+        leb_u32(&mut section, bytes.len() as u32);
+        section.extend(bytes);
+        push_section(&mut result, 11, section);
+
+        Ok(result)
+    }
+}
+
+/// 関数の本体をエンコードする
+fn encode_func(
+    func: &Func,
+    func_indices: &HashMap<&str, u32>,
+    global_indices: &HashMap<&str, u32>,
+) -> Result<Vec<u8>, String> {
+    let local_indices = func
+        .params
+        .iter()
+        .chain(func.locals.iter())
+        .enumerate()
+        .map(|(i, (name, _))| (name.as_str(), i as u32))
+        .collect::<HashMap<_, _>>();
+
+    let lookup = |table: &HashMap<&str, u32>, kind: &str, name: &str| {
+        table
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("Unknown {} `{}` in `{}`", kind, name, func.name))
+    };
+
+    // 構造化制御命令のラベル (分岐先の相対的な深さを求めるのに用いる)
+    let mut labels = Vec::<Option<&str>>::new();
+    let depth = |labels: &Vec<Option<&str>>, name: &str| {
+        labels
+            .iter()
+            .rev()
+            .position(|label| *label == Some(name))
+            .map(|d| d as u32)
+            .ok_or_else(|| format!("Unknown label `{}` in `{}`", name, func.name))
+    };
+
+    let mut body = Vec::new();
+
+    // 連続する同じ型のローカル変数をまとめて宣言する
+    let mut groups = Vec::<(u32, ValType)>::new();
+    for (_, ty) in func.locals.iter() {
+        match groups.last_mut() {
+            Some((count, last_ty)) if last_ty == ty => *count += 1,
+            _ => groups.push((1, *ty)),
+        }
+    }
+    leb_u32(&mut body, groups.len() as u32);
+    for (count, ty) in groups {
+        leb_u32(&mut body, count);
+        body.push(ty.code());
+    }
+
+    for inst in func.body.iter() {
+        match inst {
+            WasmInst::Block(label) => {
+                body.extend([0x02, 0x40]);
+                labels.push(label.as_deref());
+            }
+            WasmInst::Loop(label) => {
+                body.extend([0x03, 0x40]);
+                labels.push(label.as_deref());
+            }
+            WasmInst::If => {
+                body.extend([0x04, 0x40]);
+                labels.push(None);
+            }
+            WasmInst::End => {
+                body.push(0x0b);
+                labels.pop();
+            }
+            WasmInst::Br(label) => {
+                body.push(0x0c);
+                leb_u32(&mut body, depth(&labels, label)?);
+            }
+            WasmInst::BrIf(label) => {
+                body.push(0x0d);
+                leb_u32(&mut body, depth(&labels, label)?);
+            }
+            WasmInst::BrTable(targets, default) => {
+                body.push(0x0e);
+                leb_u32(&mut body, targets.len() as u32);
+                for target in targets.iter() {
+                    leb_u32(&mut body, depth(&labels, target)?);
+                }
+                leb_u32(&mut body, depth(&labels, default)?);
+            }
+            WasmInst::Return => body.push(0x0f),
+            WasmInst::Call(name) => {
+                body.push(0x10);
+                leb_u32(&mut body, lookup(func_indices, "function", name)?);
+            }
+            WasmInst::Drop => body.push(0x1a),
+            WasmInst::LocalGet(name) => {
+                body.push(0x20);
+                leb_u32(&mut body, lookup(&local_indices, "local", name)?);
+            }
+            WasmInst::LocalSet(name) => {
+                body.push(0x21);
+                leb_u32(&mut body, lookup(&local_indices, "local", name)?);
+            }
+            WasmInst::LocalTee(name) => {
+                body.push(0x22);
+                leb_u32(&mut body, lookup(&local_indices, "local", name)?);
+            }
+            WasmInst::GlobalGet(name) => {
+                body.push(0x23);
+                leb_u32(&mut body, lookup(global_indices, "global", name)?);
+            }
+            WasmInst::GlobalSet(name) => {
+                body.push(0x24);
+                leb_u32(&mut body, lookup(global_indices, "global", name)?);
+            }
+            // memarg (アラインメントの log2, オフセット)
+            WasmInst::I32Load8U => body.extend([0x2d, 0, 0]),
+            WasmInst::I32Store => body.extend([0x36, 2, 0]),
+            WasmInst::I32Store8 => body.extend([0x3a, 0, 0]),
+            WasmInst::I32Const(value) => {
+                body.push(0x41);
+                leb_i64(&mut body, *value as i64);
+            }
+            WasmInst::I64Const(value) => {
+                body.push(0x42);
+                leb_i64(&mut body, *value);
+            }
+            WasmInst::I32Eqz => body.push(0x45),
+            WasmInst::I32Ne => body.push(0x47),
+            WasmInst::I32Add => body.push(0x6a),
+            WasmInst::I32Sub => body.push(0x6b),
+            WasmInst::I32Or => body.push(0x72),
+            WasmInst::I64Ne => body.push(0x52),
+            WasmInst::I64LtS => body.push(0x53),
+            WasmInst::I64Sub => body.push(0x7d),
+            WasmInst::I64DivU => body.push(0x80),
+            WasmInst::I64RemU => body.push(0x82),
+            WasmInst::I32WrapI64 => body.push(0xa7),
+            WasmInst::I64ExtendI32U => body.push(0xad),
+        }
+    }
+
+    body.push(0x0b);
+    Ok(body)
+}
+
+/// 命令のテキスト表現
+fn inst_text(inst: &WasmInst) -> String {
+    let label = |label: &Option<String>| match label {
+        Some(label) => format!(" ${}", label),
+        None => String::new(),
+    };
+
+    match inst {
+        WasmInst::Block(l) => format!("block{}", label(l)),
+        WasmInst::Loop(l) => format!("loop{}", label(l)),
+        WasmInst::If => "if".to_owned(),
+        WasmInst::End => "end".to_owned(),
+        WasmInst::Br(l) => format!("br ${}", l),
+        WasmInst::BrIf(l) => format!("br_if ${}", l),
+        WasmInst::BrTable(targets, default) => {
+            let targets = targets
+                .iter()
+                .chain(std::iter::once(default))
+                .map(|l| format!("${}", l))
+                .collect::<Vec<_>>();
+            format!("br_table {}", targets.join(" "))
+        }
+        WasmInst::Return => "return".to_owned(),
+        WasmInst::Call(name) => format!("call ${}", name),
+        WasmInst::Drop => "drop".to_owned(),
+        WasmInst::LocalGet(name) => format!("local.get ${}", name),
+        WasmInst::LocalSet(name) => format!("local.set ${}", name),
+        WasmInst::LocalTee(name) => format!("local.tee ${}", name),
+        WasmInst::GlobalGet(name) => format!("global.get ${}", name),
+        WasmInst::GlobalSet(name) => format!("global.set ${}", name),
+        WasmInst::I32Load8U => "i32.load8_u".to_owned(),
+        WasmInst::I32Store => "i32.store".to_owned(),
+        WasmInst::I32Store8 => "i32.store8".to_owned(),
+        WasmInst::I32Const(value) => format!("i32.const {}", value),
+        WasmInst::I64Const(value) => format!("i64.const {}", value),
+        WasmInst::I32Eqz => "i32.eqz".to_owned(),
+        WasmInst::I32Ne => "i32.ne".to_owned(),
+        WasmInst::I32Add => "i32.add".to_owned(),
+        WasmInst::I32Sub => "i32.sub".to_owned(),
+        WasmInst::I32Or => "i32.or".to_owned(),
+        WasmInst::I64Ne => "i64.ne".to_owned(),
+        WasmInst::I64LtS => "i64.lt_s".to_owned(),
+        WasmInst::I64Sub => "i64.sub".to_owned(),
+        WasmInst::I64DivU => "i64.div_u".to_owned(),
+        WasmInst::I64RemU => "i64.rem_u".to_owned(),
+        WasmInst::I32WrapI64 => "i32.wrap_i64".to_owned(),
+        WasmInst::I64ExtendI32U => "i64.extend_i32_u".to_owned(),
+    }
+}
+
+/// 関数型のテキスト表現
+fn signature(params: &[ValType], results: &[ValType]) -> String {
+    let mut result = String::new();
+    for ty in params.iter() {
+        result.push_str(format!(" (param {})", ty.name()).as_str());
+    }
+    for ty in results.iter() {
+        result.push_str(format!(" (result {})", ty.name()).as_str());
+    }
+    result
+}
+
+/// 文字列リテラルとして使えるようにバイト列をエスケープする
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'"' | b'\\' => format!("\\{}", b as char),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => format!("\\{:02x}", b),
+        })
+        .collect()
+}
+
+fn push_section(result: &mut Vec<u8>, id: u8, section: Vec<u8>) {
+    result.push(id);
+    leb_u32(result, section.len() as u32);
+    result.extend(section);
+}
+
+fn val_types(result: &mut Vec<u8>, types: &[ValType]) {
+    leb_u32(result, types.len() as u32);
+    result.extend(types.iter().map(|ty| ty.code()));
+}
+
+fn name(result: &mut Vec<u8>, name: &str) {
+    leb_u32(result, name.len() as u32);
+    result.extend(name.as_bytes());
+}
+
+/// 符号なし LEB128
+fn leb_u32(result: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            result.push(byte);
+            break;
+        }
+        result.push(byte | 0x80);
+    }
+}
+
+/// 符号付き LEB128
+fn leb_i64(result: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            result.push(byte);
+            break;
+        }
+        result.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_bytes(value: u32) -> Vec<u8> {
+        let mut result = Vec::new();
+        leb_u32(&mut result, value);
+        result
+    }
+
+    fn i64_bytes(value: i64) -> Vec<u8> {
+        let mut result = Vec::new();
+        leb_i64(&mut result, value);
+        result
+    }
+
+    #[test]
+    fn unsigned_leb128() {
+        assert_eq!(u32_bytes(0), [0x00]);
+        assert_eq!(u32_bytes(127), [0x7f]);
+        assert_eq!(u32_bytes(128), [0x80, 0x01]);
+        assert_eq!(u32_bytes(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(u32_bytes(u32::MAX), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn signed_leb128() {
+        assert_eq!(i64_bytes(0), [0x00]);
+        assert_eq!(i64_bytes(-1), [0x7f]);
+        assert_eq!(i64_bytes(63), [0x3f]);
+        assert_eq!(i64_bytes(64), [0xc0, 0x00]);
+        assert_eq!(i64_bytes(-64), [0x40]);
+        assert_eq!(i64_bytes(-65), [0xbf, 0x7f]);
+        assert_eq!(i64_bytes(-123456), [0xc0, 0xbb, 0x78]);
+        assert_eq!(
+            i64_bytes(i64::MAX),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]
+        );
+        assert_eq!(
+            i64_bytes(i64::MIN),
+            [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]
+        );
+    }
+
+    #[test]
+    fn encode_module() {
+        let module = Module {
+            imports: vec![Import {
+                module: "env".to_string(),
+                field: "print".to_string(),
+                name: "print".to_string(),
+                params: vec![ValType::I64],
+                results: vec![],
+            }],
+            globals: vec![Global {
+                name: "g".to_string(),
+                ty: ValType::I32,
+            }],
+            funcs: vec![Func {
+                name: "f".to_string(),
+                export: Some("f".to_string()),
+                params: vec![],
+                results: vec![ValType::I32],
+                locals: vec![("x".to_string(), ValType::I32)],
+                body: vec![
+                    WasmInst::I64Const(-65),
+                    WasmInst::Call("print".to_string()),
+                    WasmInst::GlobalGet("g".to_string()),
+                ],
+            }],
+            data: (16, b"hi".to_vec()),
+        };
+
+        #[rustfmt::skip]
+        let expected = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // type: (i64) -> (), () -> (i32)
+            0x01, 0x09, 0x02, 0x60, 0x01, 0x7e, 0x00, 0x60, 0x00, 0x01, 0x7f,
+            // import: env.print
+            0x02, 0x0d, 0x01, 0x03, b'e', b'n', b'v', 0x05, b'p', b'r', b'i', b'n', b't', 0x00, 0x00,
+            // function
+            0x03, 0x02, 0x01, 0x01,
+            // memory
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            // global
+            0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x00, 0x0b,
+            // export: memory, f
+            0x07, 0x0e, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+            0x01, b'f', 0x00, 0x01,
+            // code
+            0x0a, 0x0d, 0x01, 0x0b, 0x01, 0x01, 0x7f, 0x42, 0xbf, 0x7f, 0x10, 0x00, 0x23, 0x00, 0x0b,
+            // data
+            0x0b, 0x08, 0x01, 0x00, 0x41, 0x10, 0x0b, 0x02, b'h', b'i',
+        ];
+        assert_eq!(module.encode().unwrap(), expected);
+    }
+
+    #[test]
+    fn reject_unknown_reference() {
+        let module = Module {
+            funcs: vec![Func {
+                name: "f".to_string(),
+                export: None,
+                params: vec![],
+                results: vec![],
+                locals: vec![],
+                body: vec![WasmInst::Br("missing".to_string())],
+            }],
+            ..Module::default()
+        };
+        assert_eq!(
+            module.encode().unwrap_err(),
+            "Unknown label `missing` in `f`"
+        );
+    }
+}
@@ -0,0 +1,788 @@
+use super::ast::{ResumeTarget, Type};
+use super::ir::{Ir, IrInst, Proc};
+use super::location::Location;
+use super::sem_analysis::MAX_PARAMS;
+use super::wasm::{Func, Global, Import, Module, ValType, WasmInst as I};
+
+/// WASI のモジュール名
+static WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// ランタイムが使用するため、手続きの名前として使えない関数名
+static RUNTIME_FUNCS: [&str; 9] = [
+    "_start",
+    "fd_write",
+    "proc_exit",
+    "rt_strlen",
+    "rt_write",
+    "rt_write_str",
+    "rt_print_int",
+    "rt_print_value",
+    "rt_error",
+];
+
+static TYPE_STR: i32 = 1;
+static TYPE_INT: i32 = 2;
+
+// 線形メモリの配置
+/// fd_write に渡す iovec (iov_base, iov_len)
+static IOVEC: i32 = 0;
+/// fd_write が書き込んだバイト数の格納場所
+static NWRITTEN: i32 = 8;
+/// 整数を10進数の文字列に変換するためのバッファの末尾
+static INT_BUF_END: i32 = 48;
+/// データセグメントの開始アドレス
+static DATA_START: u32 = 64;
+
+/// ランタイムが出力するメッセージ
+static MESSAGES: [(&str, &str); 7] = [
+    ("err_prefix", "Runtime error at "),
+    ("err_sep", ": "),
+    ("err_newline", "\n"),
+    ("msg_expected_str", "type mismatch (expected STRING)"),
+    ("msg_expected_int", "type mismatch (expected INTEGER)"),
+    ("msg_resume_without_error", "RESUME without error"),
+    ("msg_error_stmt", "error raised by ERROR statement"),
+];
+
+/// 線形メモリに配置するデータ
+struct DataSegment {
+    bytes: Vec<u8>,
+}
+
+impl DataSegment {
+    /// NUL 終端文字列を追加し、そのアドレスを返す
+    fn push_str(&mut self, s: &str) -> i32 {
+        let addr = DATA_START as i32 + self.bytes.len() as i32;
+        self.bytes.extend(s.as_bytes());
+        self.bytes.push(0);
+        addr
+    }
+}
+
+/// 関数本体の生成に用いる状態
+struct FuncContext<'a> {
+    ir: &'a Ir,
+    /// 文字列プールの各文字列のアドレス
+    str_addrs: &'a [i32],
+    /// ランタイムのエラーメッセージのアドレス
+    msg_addrs: &'a [i32],
+    data: &'a mut DataSegment,
+    body: Vec<I>,
+    /// ``ON ERROR GOTO`` でエラーを捕捉するかどうか
+    traps_errors: bool,
+    /// 生成中の関数が BASIC で定義された手続きの場合、その戻り値の有無
+    proc_ret: Option<bool>,
+    /// トップレベルの文の個数
+    num_stmts: i32,
+}
+
+/// 中間表現から WASI 向けの WebAssembly モジュールを生成する
+///
+/// 値はスタック上で (``i64`` の値, ``i32`` の型タグ) の組として表され、
+/// 文字列の値は線形メモリ上の NUL 終端文字列のアドレスとなる
+pub fn gen_wasm(ir: &Ir) -> Result<Module, String> {
+    for name in ir
+        .procs
+        .iter()
+        .map(|proc| &proc.name)
+        .chain(ir.externs.iter().map(|ext| &ext.name))
+    {
+        if RUNTIME_FUNCS.contains(&name.as_str()) {
+            return Err(format!(
+                "`{}` conflicts with a function used by the runtime",
+                name
+            ));
+        }
+    }
+
+    let traps_errors = ir
+        .insts
+        .iter()
+        .any(|inst| matches!(inst, IrInst::OnErrorGoto(Some(_))));
+
+    let mut data = DataSegment { bytes: Vec::new() };
+    let str_addrs = ir
+        .string_pool
+        .iter()
+        .map(|s| data.push_str(s))
+        .collect::<Vec<_>>();
+    let msg_addrs = MESSAGES
+        .iter()
+        .map(|(_, s)| data.push_str(s))
+        .collect::<Vec<_>>();
+
+    let mut module = Module::default();
+
+    module.imports.push(Import {
+        module: WASI_MODULE.to_owned(),
+        field: "fd_write".to_owned(),
+        name: "fd_write".to_owned(),
+        params: vec![ValType::I32; 4],
+        results: vec![ValType::I32],
+    });
+    module.imports.push(Import {
+        module: WASI_MODULE.to_owned(),
+        field: "proc_exit".to_owned(),
+        name: "proc_exit".to_owned(),
+        params: vec![ValType::I32],
+        results: vec![],
+    });
+
+    // 外部ライブラリの手続きは、ライブラリ名のモジュールから取り込む
+    for ext in ir.externs.iter() {
+        module.imports.push(Import {
+            module: ext.lib.clone(),
+            field: ext.name.clone(),
+            name: ext.name.clone(),
+            params: ext.params.iter().map(|ty| extern_type(*ty)).collect(),
+            results: ext.ret.iter().map(|ty| extern_type(*ty)).collect(),
+        });
+    }
+
+    // グローバル変数 1 つにつき、値と型タグの 2 つのグローバル変数を用意する
+    for i in 0..ir.num_globals {
+        module.globals.push(Global {
+            name: format!("g{}", i),
+            ty: ValType::I64,
+        });
+        module.globals.push(Global {
+            name: format!("g{}_tag", i),
+            ty: ValType::I32,
+        });
+    }
+
+    // ON ERROR GOTO によるエラー処理の状態
+    for (name, ty) in [
+        ("err_code", ValType::I64),
+        ("err_line", ValType::I64),
+        ("err_handler", ValType::I32),
+        ("in_handler", ValType::I32),
+        ("cur_stmt", ValType::I32),
+        ("resume_addr", ValType::I32),
+        ("resume_next_addr", ValType::I32),
+        ("pc", ValType::I32),
+        ("pending", ValType::I32),
+    ] {
+        module.globals.push(Global {
+            name: name.to_owned(),
+            ty,
+        });
+    }
+
+    let num_stmts = ir
+        .insts
+        .iter()
+        .filter(|inst| matches!(inst, IrInst::BeginStmt(_)))
+        .count() as i32;
+
+    let mut ctx = FuncContext {
+        ir,
+        str_addrs: &str_addrs,
+        msg_addrs: &msg_addrs,
+        data: &mut data,
+        body: Vec::new(),
+        traps_errors,
+        proc_ret: None,
+        num_stmts,
+    };
+
+    gen_main(&mut ctx);
+    module.funcs.push(Func {
+        name: "_start".to_owned(),
+        export: Some("_start".to_owned()),
+        params: vec![],
+        results: vec![],
+        locals: scratch_locals(),
+        body: std::mem::take(&mut ctx.body),
+    });
+
+    for proc in ir.procs.iter() {
+        module.funcs.push(gen_proc(proc, &mut ctx));
+    }
+
+    module.funcs.extend(runtime_funcs(&msg_addrs, traps_errors));
+    module.data = (DATA_START, data.bytes);
+
+    Ok(module)
+}
+
+/// エントリポイント ``_start`` の本体を生成する
+///
+/// エラーを捕捉する場合は、文とラベルの位置ごとにブロックを入れ子にしたループで囲み、
+/// ``pc`` の値に従って ``br_table`` でその位置へ分岐できるようにする
+fn gen_main(ctx: &mut FuncContext) {
+    let ir = ctx.ir;
+
+    if !ctx.traps_errors {
+        ctx.body.push(I::Block(Some("exit".to_owned())));
+        gen_insts(&ir.insts, ctx);
+        ctx.body.push(I::End);
+        return;
+    }
+
+    // 分岐先の位置 (命令列での出現順)
+    let mut positions = ir
+        .insts
+        .iter()
+        .filter_map(|inst| match inst {
+            IrInst::BeginStmt(index) => Some(format!("stmt{}", index)),
+            IrInst::Label(index) => Some(format!("user_label{}", index)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    positions.push(format!("stmt{}", ctx.num_stmts));
+
+    // pc の値と分岐先の対応 (文の番号, 最後の文の次の位置, ラベルの番号の順)
+    let num_labels = positions.len() as i32 - ctx.num_stmts - 1;
+    let targets = (0..=ctx.num_stmts)
+        .map(|i| format!("stmt{}", i))
+        .chain((0..num_labels).map(|i| format!("user_label{}", i)))
+        .collect::<Vec<_>>();
+
+    ctx.body.push(I::Loop(Some("dispatch".to_owned())));
+    ctx.body.push(I::Block(Some("exit".to_owned())));
+    for position in positions.iter().rev() {
+        ctx.body.push(I::Block(Some(position.clone())));
+    }
+    ctx.body.push(I::GlobalGet("pc".to_owned()));
+    ctx.body.push(I::BrTable(targets, "exit".to_owned()));
+
+    // 位置ごとにブロックを閉じ、その位置から次の位置までの命令列を生成する
+    let mut rest = &ir.insts[..];
+    while !rest.is_empty() {
+        let len = rest[1..]
+            .iter()
+            .position(|inst| matches!(inst, IrInst::BeginStmt(_) | IrInst::Label(_)))
+            .map_or(rest.len(), |i| i + 1);
+        ctx.body.push(I::End);
+        gen_insts(&rest[..len], ctx);
+        rest = &rest[len..];
+    }
+    ctx.body.push(I::End); // 最後の文の次の位置
+    ctx.body.push(I::End); // exit
+    ctx.body.push(I::End); // dispatch
+}
+
+/// BASIC で定義された手続きを関数として生成する
+///
+/// 引数と戻り値は ``i64`` の値のみで受け渡しする (型タグは呼び出し側で検査・付与する)
+fn gen_proc(proc: &Proc, ctx: &mut FuncContext) -> Func {
+    ctx.proc_ret = Some(proc.ret.is_some());
+
+    // 引数の型タグを設定する
+    for (i, ty) in proc.params.iter().enumerate() {
+        ctx.body.push(I::I32Const(type_tag(*ty)));
+        ctx.body.push(I::LocalSet(format!("t{}", i)));
+    }
+
+    gen_insts(&proc.insts, ctx);
+
+    if let Some(ret_slot) = proc.ret_slot() {
+        ctx.body.push(I::LocalGet(format!("v{}", ret_slot)));
+    }
+
+    let num_params = proc.params.len() as i32;
+    let mut locals = (num_params..proc.num_locals)
+        .map(|i| (format!("v{}", i), ValType::I64))
+        .collect::<Vec<_>>();
+    locals.extend((0..proc.num_locals).map(|i| (format!("t{}", i), ValType::I32)));
+    locals.extend(scratch_locals());
+
+    ctx.proc_ret = None;
+
+    Func {
+        name: proc.name.clone(),
+        export: None,
+        params: (0..num_params)
+            .map(|i| (format!("v{}", i), ValType::I64))
+            .collect(),
+        results: proc.ret.iter().map(|_| ValType::I64).collect(),
+        locals,
+        body: std::mem::take(&mut ctx.body),
+    }
+}
+
+/// 命令列を生成する
+fn gen_insts(insts: &[IrInst], ctx: &mut FuncContext) {
+    for ir_inst in insts.iter() {
+        match ir_inst {
+            IrInst::GetStaticStr(index) => {
+                let addr = ctx.str_addrs[*index as usize];
+                ctx.body.push(I::I64Const(addr as i64));
+                ctx.body.push(I::I32Const(TYPE_STR));
+            }
+            IrInst::GetImmInt(value) => {
+                ctx.body.push(I::I64Const(*value));
+                ctx.body.push(I::I32Const(TYPE_INT));
+            }
+            IrInst::GetGlobal(index) => {
+                ctx.body.push(I::GlobalGet(format!("g{}", index)));
+                ctx.body.push(I::GlobalGet(format!("g{}_tag", index)));
+            }
+            IrInst::SetGlobal(index) => {
+                ctx.body.push(I::GlobalSet(format!("g{}_tag", index)));
+                ctx.body.push(I::GlobalSet(format!("g{}", index)));
+            }
+            IrInst::GetLocal(index) => {
+                ctx.body.push(I::LocalGet(format!("v{}", index)));
+                ctx.body.push(I::LocalGet(format!("t{}", index)));
+            }
+            IrInst::SetLocal(index) => {
+                ctx.body.push(I::LocalSet(format!("t{}", index)));
+                ctx.body.push(I::LocalSet(format!("v{}", index)));
+            }
+            IrInst::AssertType(ty, location) => {
+                let message = match ty {
+                    Type::String => "msg_expected_str",
+                    Type::Integer => "msg_expected_int",
+                };
+                ctx.body.push(I::LocalTee("tag".to_owned()));
+                ctx.body.push(I::I32Const(type_tag(*ty)));
+                ctx.body.push(I::I32Ne);
+                ctx.body.push(I::If);
+                ctx.body.push(I::I64Const(13));
+                gen_runtime_error(location, message, ctx);
+                ctx.body.push(I::End);
+                ctx.body.push(I::LocalGet("tag".to_owned()));
+            }
+            IrInst::CallExtern(index) => {
+                let ext = &ctx.ir.externs[*index as usize];
+                pop_args(ext.params.len(), ctx);
+                for (i, ty) in ext.params.iter().enumerate() {
+                    ctx.body.push(I::LocalGet(format!("a{}", i)));
+                    if *ty == Type::String {
+                        ctx.body.push(I::I32WrapI64);
+                    }
+                }
+                ctx.body.push(I::Call(ext.name.clone()));
+                if let Some(ret) = ext.ret {
+                    if ret == Type::String {
+                        ctx.body.push(I::I64ExtendI32U);
+                    }
+                    ctx.body.push(I::I32Const(type_tag(ret)));
+                }
+            }
+            IrInst::CallProc(index, _) => {
+                let proc = &ctx.ir.procs[*index as usize];
+                pop_args(proc.params.len(), ctx);
+                for i in 0..proc.params.len() {
+                    ctx.body.push(I::LocalGet(format!("a{}", i)));
+                }
+                ctx.body.push(I::Call(proc.name.clone()));
+                if let Some(ret) = proc.ret {
+                    ctx.body.push(I::I32Const(type_tag(ret)));
+                }
+                // 呼び出し先でエラーが捕捉された
+                if ctx.traps_errors {
+                    ctx.body.push(I::GlobalGet("pending".to_owned()));
+                    ctx.body.push(I::If);
+                    gen_goto_handler(ctx);
+                    ctx.body.push(I::End);
+                }
+            }
+            IrInst::Pop => {
+                ctx.body.push(I::Drop);
+                ctx.body.push(I::Drop);
+            }
+            IrInst::Print => {
+                ctx.body.push(I::Call("rt_print_value".to_owned()));
+            }
+            IrInst::BeginStmt(index) => {
+                if ctx.traps_errors {
+                    ctx.body.push(I::I32Const(*index));
+                    ctx.body.push(I::GlobalSet("cur_stmt".to_owned()));
+                }
+            }
+            IrInst::Label(_) => {}
+            IrInst::OnErrorGoto(handler) => {
+                // 0 はエラーハンドラが設定されていないことを表す
+                let pc = handler.map_or(0, |index| ctx.num_stmts + 1 + index);
+                ctx.body.push(I::I32Const(pc));
+                ctx.body.push(I::GlobalSet("err_handler".to_owned()));
+            }
+            IrInst::Resume(target, location) => {
+                ctx.body.push(I::GlobalGet("in_handler".to_owned()));
+                ctx.body.push(I::I32Eqz);
+                ctx.body.push(I::If);
+                ctx.body.push(I::I64Const(20));
+                gen_runtime_error(location, "msg_resume_without_error", ctx);
+                ctx.body.push(I::End);
+
+                // エラーを捕捉しない場合は、 RESUME は常にエラーになる
+                if ctx.traps_errors {
+                    ctx.body.push(I::I32Const(0));
+                    ctx.body.push(I::GlobalSet("in_handler".to_owned()));
+                    ctx.body.push(I::I64Const(0));
+                    ctx.body.push(I::GlobalSet("err_code".to_owned()));
+                    ctx.body.push(I::I64Const(0));
+                    ctx.body.push(I::GlobalSet("err_line".to_owned()));
+                    ctx.body.push(I::GlobalGet(
+                        match target {
+                            ResumeTarget::Retry => "resume_addr",
+                            ResumeTarget::Next => "resume_next_addr",
+                        }
+                        .to_owned(),
+                    ));
+                    ctx.body.push(I::GlobalSet("pc".to_owned()));
+                    ctx.body.push(I::Br("dispatch".to_owned()));
+                }
+            }
+            IrInst::RaiseError(location) => {
+                ctx.body.push(I::Drop);
+                gen_runtime_error(location, "msg_error_stmt", ctx);
+            }
+            IrInst::GetErrCode => {
+                ctx.body.push(I::GlobalGet("err_code".to_owned()));
+                ctx.body.push(I::I32Const(TYPE_INT));
+            }
+            IrInst::GetErrLine => {
+                ctx.body.push(I::GlobalGet("err_line".to_owned()));
+                ctx.body.push(I::I32Const(TYPE_INT));
+            }
+            IrInst::End => {
+                ctx.body.push(I::Br("exit".to_owned()));
+            }
+        }
+    }
+}
+
+/// スタックに積まれたエラー番号と、位置とメッセージを渡して ``rt_error`` を呼び出す
+///
+/// エラーが捕捉された場合はエラーハンドラへ移る
+fn gen_runtime_error(location: &Location, message: &str, ctx: &mut FuncContext) {
+    let loc_addr = ctx.data.push_str(&location.start.to_string());
+    let msg_index = MESSAGES
+        .iter()
+        .position(|(name, _)| *name == message)
+        .unwrap();
+    ctx.body.push(I::I64Const(location.start.line() as i64 + 1));
+    ctx.body.push(I::I32Const(loc_addr));
+    ctx.body.push(I::I32Const(ctx.msg_addrs[msg_index]));
+    ctx.body.push(I::Call("rt_error".to_owned()));
+
+    if ctx.traps_errors {
+        gen_goto_handler(ctx);
+    }
+}
+
+/// エラーが捕捉されたとき、エラーハンドラへ移る (手続きの中では呼び出し元に戻る)
+fn gen_goto_handler(ctx: &mut FuncContext) {
+    match ctx.proc_ret {
+        Some(has_ret) => {
+            if has_ret {
+                ctx.body.push(I::I64Const(0));
+            }
+            ctx.body.push(I::Return);
+        }
+        None => {
+            ctx.body.push(I::I32Const(0));
+            ctx.body.push(I::GlobalSet("pending".to_owned()));
+            ctx.body.push(I::Br("dispatch".to_owned()));
+        }
+    }
+}
+
+/// スタックに積まれた引数の型タグを捨てて、値を一時変数に退避する
+fn pop_args(num_params: usize, ctx: &mut FuncContext) {
+    for i in (0..num_params).rev() {
+        ctx.body.push(I::Drop);
+        ctx.body.push(I::LocalSet(format!("a{}", i)));
+    }
+}
+
+/// 各関数で用いる一時変数
+fn scratch_locals() -> Vec<(String, ValType)> {
+    let mut locals = vec![("tag".to_owned(), ValType::I32)];
+    locals.extend((0..MAX_PARAMS).map(|i| (format!("a{}", i), ValType::I64)));
+    locals
+}
+
+/// 型タグの値
+fn type_tag(ty: Type) -> i32 {
+    match ty {
+        Type::String => TYPE_STR,
+        Type::Integer => TYPE_INT,
+    }
+}
+
+/// 外部ライブラリの手続きの引数・戻り値の型 (文字列は線形メモリのアドレスとして渡す)
+fn extern_type(ty: Type) -> ValType {
+    match ty {
+        Type::String => ValType::I32,
+        Type::Integer => ValType::I64,
+    }
+}
+
+/// ランタイムの関数を生成する
+fn runtime_funcs(msg_addrs: &[i32], traps_errors: bool) -> Vec<Func> {
+    let local = |name: &str| I::LocalGet(name.to_owned());
+    let set = |name: &str| I::LocalSet(name.to_owned());
+    let param = |name: &str, ty: ValType| (name.to_owned(), ty);
+    let msg = |name: &str| {
+        let index = MESSAGES.iter().position(|(n, _)| *n == name).unwrap();
+        I::I32Const(msg_addrs[index])
+    };
+
+    // rt_strlen (NUL 終端文字列の長さ)
+    let strlen = Func {
+        name: "rt_strlen".to_owned(),
+        export: None,
+        params: vec![param("ptr", ValType::I32)],
+        results: vec![ValType::I32],
+        locals: vec![param("len", ValType::I32)],
+        body: vec![
+            I::Block(Some("done".to_owned())),
+            I::Loop(Some("next".to_owned())),
+            local("ptr"),
+            local("len"),
+            I::I32Add,
+            I::I32Load8U,
+            I::I32Eqz,
+            I::BrIf("done".to_owned()),
+            local("len"),
+            I::I32Const(1),
+            I::I32Add,
+            set("len"),
+            I::Br("next".to_owned()),
+            I::End,
+            I::End,
+            local("len"),
+        ],
+    };
+
+    // rt_write (fd にバイト列を書き込む)
+    let write = Func {
+        name: "rt_write".to_owned(),
+        export: None,
+        params: vec![
+            param("fd", ValType::I32),
+            param("ptr", ValType::I32),
+            param("len", ValType::I32),
+        ],
+        results: vec![],
+        locals: vec![],
+        body: vec![
+            I::I32Const(IOVEC),
+            local("ptr"),
+            I::I32Store,
+            I::I32Const(IOVEC + 4),
+            local("len"),
+            I::I32Store,
+            local("fd"),
+            I::I32Const(IOVEC),
+            I::I32Const(1),
+            I::I32Const(NWRITTEN),
+            I::Call("fd_write".to_owned()),
+            I::Drop,
+        ],
+    };
+
+    // rt_write_str (fd に NUL 終端文字列を書き込む)
+    let write_str = Func {
+        name: "rt_write_str".to_owned(),
+        export: None,
+        params: vec![param("fd", ValType::I32), param("ptr", ValType::I32)],
+        results: vec![],
+        locals: vec![],
+        body: vec![
+            local("fd"),
+            local("ptr"),
+            local("ptr"),
+            I::Call("rt_strlen".to_owned()),
+            I::Call("rt_write".to_owned()),
+        ],
+    };
+
+    // rt_print_int (バッファに下の桁から10進数の文字を詰めていく)
+    let print_int = Func {
+        name: "rt_print_int".to_owned(),
+        export: None,
+        params: vec![param("value", ValType::I64)],
+        results: vec![],
+        locals: vec![param("ptr", ValType::I32), param("neg", ValType::I32)],
+        body: vec![
+            I::I32Const(INT_BUF_END),
+            set("ptr"),
+            local("value"),
+            I::I64Const(0),
+            I::I64LtS,
+            I::LocalTee("neg".to_owned()),
+            I::If,
+            I::I64Const(0),
+            local("value"),
+            I::I64Sub,
+            set("value"),
+            I::End,
+            I::Loop(Some("digit".to_owned())),
+            local("ptr"),
+            I::I32Const(1),
+            I::I32Sub,
+            I::LocalTee("ptr".to_owned()),
+            local("value"),
+            I::I64Const(10),
+            I::I64RemU,
+            I::I32WrapI64,
+            I::I32Const(b'0' as i32),
+            I::I32Add,
+            I::I32Store8,
+            local("value"),
+            I::I64Const(10),
+            I::I64DivU,
+            I::LocalTee("value".to_owned()),
+            I::I64Const(0),
+            I::I64Ne,
+            I::BrIf("digit".to_owned()),
+            I::End,
+            local("neg"),
+            I::If,
+            local("ptr"),
+            I::I32Const(1),
+            I::I32Sub,
+            I::LocalTee("ptr".to_owned()),
+            I::I32Const(b'-' as i32),
+            I::I32Store8,
+            I::End,
+            I::I32Const(1),
+            local("ptr"),
+            I::I32Const(INT_BUF_END),
+            local("ptr"),
+            I::I32Sub,
+            I::Call("rt_write".to_owned()),
+        ],
+    };
+
+    // rt_print_value (型タグに応じて値を出力する)
+    let print_value = Func {
+        name: "rt_print_value".to_owned(),
+        export: None,
+        params: vec![param("value", ValType::I64), param("tag", ValType::I32)],
+        results: vec![],
+        locals: vec![],
+        body: vec![
+            local("tag"),
+            I::I32Const(TYPE_STR),
+            I::I32Ne,
+            I::If,
+            local("value"),
+            I::Call("rt_print_int".to_owned()),
+            I::Return,
+            I::End,
+            I::I32Const(1),
+            local("value"),
+            I::I32WrapI64,
+            I::Call("rt_write_str".to_owned()),
+        ],
+    };
+
+    // rt_error (エラーハンドラが設定されていれば状態を記録して戻り、そうでなければエラーを報告して終了する)
+    let mut error_body = Vec::new();
+    if traps_errors {
+        error_body.extend([
+            I::GlobalGet("err_handler".to_owned()),
+            I::I32Eqz,
+            I::GlobalGet("in_handler".to_owned()),
+            I::I32Or,
+            I::I32Eqz,
+            I::If,
+            local("code"),
+            I::GlobalSet("err_code".to_owned()),
+            local("line"),
+            I::GlobalSet("err_line".to_owned()),
+            I::GlobalGet("cur_stmt".to_owned()),
+            I::GlobalSet("resume_addr".to_owned()),
+            I::GlobalGet("cur_stmt".to_owned()),
+            I::I32Const(1),
+            I::I32Add,
+            I::GlobalSet("resume_next_addr".to_owned()),
+            I::I32Const(1),
+            I::GlobalSet("in_handler".to_owned()),
+            I::GlobalGet("err_handler".to_owned()),
+            I::GlobalSet("pc".to_owned()),
+            I::I32Const(1),
+            I::GlobalSet("pending".to_owned()),
+            I::Return,
+            I::End,
+        ]);
+    }
+    for part in [
+        msg("err_prefix"),
+        local("loc"),
+        msg("err_sep"),
+        local("msg"),
+        msg("err_newline"),
+    ] {
+        error_body.push(I::I32Const(2));
+        error_body.push(part);
+        error_body.push(I::Call("rt_write_str".to_owned()));
+    }
+    error_body.push(I::I32Const(1));
+    error_body.push(I::Call("proc_exit".to_owned()));
+
+    let error = Func {
+        name: "rt_error".to_owned(),
+        export: None,
+        params: vec![
+            param("code", ValType::I64),
+            param("line", ValType::I64),
+            param("loc", ValType::I32),
+            param("msg", ValType::I32),
+        ],
+        results: vec![],
+        locals: vec![],
+        body: error_body,
+    };
+
+    vec![strlen, write, write_str, print_int, print_value, error]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{check_golden, sample_ir, sample_names};
+
+    /// 符号なし LEB128 を読み取り、値と次の位置を返す
+    fn read_leb_u32(bytes: &[u8], mut pos: usize) -> (u32, usize) {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[pos];
+            pos += 1;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return (value, pos);
+            }
+            shift += 7;
+        }
+    }
+
+    #[test]
+    fn samples_match_golden_files() {
+        for name in sample_names() {
+            let module = gen_wasm(&sample_ir(&name)).unwrap();
+            check_golden("wasm", &format!("{}.wat", name), &module.stringify());
+        }
+    }
+
+    #[test]
+    fn samples_encode_well_formed_sections() {
+        for name in sample_names() {
+            let bytes = gen_wasm(&sample_ir(&name)).unwrap().encode().unwrap();
+            assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0", "{}", name);
+
+            let mut ids = Vec::new();
+            let mut pos = 8;
+            while pos < bytes.len() {
+                ids.push(bytes[pos]);
+                let (size, start) = read_leb_u32(&bytes, pos + 1);
+                pos = start + size as usize;
+            }
+            assert_eq!(
+                pos,
+                bytes.len(),
+                "{}: section size overruns the module",
+                name
+            );
+            assert_eq!(ids, [1, 2, 3, 5, 6, 7, 10, 11], "{}", name);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! コマンドラインからの ``--emit`` と ``--out-dir`` の扱い
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+};
+
+static HELLO: &str = "PRINT \"Hello\"\n";
+
+/// 削除されるときに中身ごと削除される一時ディレクトリ
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// テストごとに異なる ``name`` を与える
+    fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("basic-cli-{}-{}", name, process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, file_name: &str) -> PathBuf {
+        self.0.join(file_name)
+    }
+
+    /// ディレクトリ内のファイル名を名前順に返す
+    fn files(&self) -> Vec<String> {
+        list_files(&self.0)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+fn list_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+fn compiler(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// コンパイラを実行し、成功したことを確かめる
+fn compile(args: &[&str]) {
+    let output = compiler(args);
+    assert!(
+        output.status.success(),
+        "compiler {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// コンパイラを実行し、失敗して標準エラー出力に ``expected`` を含むことを確かめる
+fn compile_fails(args: &[&str], expected: &str) {
+    let output = compiler(args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "compiler {:?} succeeded", args);
+    assert!(
+        stderr.contains(expected),
+        "{:?} does not contain {:?}",
+        stderr,
+        expected
+    );
+}
+
+#[test]
+fn emit_writes_next_to_input() {
+    let dir = TempDir::new("next-to-input");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+
+    compile(&["--emit=tokens", src.to_str().unwrap()]);
+    assert_eq!(dir.files(), ["prog.bas", "prog.tokens.json"]);
+}
+
+#[test]
+fn multiple_emit_kinds_stop_at_last() {
+    let dir = TempDir::new("multiple");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+
+    compile(&["--emit=tokens,ast,ir", src.to_str().unwrap()]);
+    assert_eq!(
+        dir.files(),
+        ["prog.ast.json", "prog.bas", "prog.bir", "prog.tokens.json"]
+    );
+
+    // 最後の段階が asm であれば、オブジェクトファイルと実行可能ファイルは出力しない
+    compile(&["--emit", "ir", "--emit", "asm", src.to_str().unwrap()]);
+    assert!(dir.join("prog.s").exists());
+    assert!(!dir.join("prog.o").exists());
+    assert!(!dir.join("prog.bin").exists());
+}
+
+#[test]
+fn emit_names_outputs_after_ast_input() {
+    // `*.ast.json` から読み込んだ場合も `*` を元に出力ファイル名を決める
+    let dir = TempDir::new("ast-input");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+    compile(&["--emit=ast", src.to_str().unwrap()]);
+    fs::remove_file(&src).unwrap();
+
+    let ast = dir.join("prog.ast.json");
+    compile(&["--emit=ir", ast.to_str().unwrap()]);
+    assert_eq!(dir.files(), ["prog.ast.json", "prog.bir"]);
+}
+
+#[test]
+fn out_dir_is_created() {
+    let dir = TempDir::new("out-dir");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+    let out_dir = dir.join("missing").join("nested");
+
+    compile(&[
+        "--emit=ir,asm",
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+        src.to_str().unwrap(),
+    ]);
+    assert_eq!(list_files(&out_dir), ["prog.bir", "prog.s"]);
+    assert_eq!(dir.files(), ["missing", "prog.bas"]);
+}
+
+/// ``--emit`` がなければ、組み込みのアセンブラで実行可能ファイルまで出力する
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn out_dir_without_emit() {
+    let dir = TempDir::new("out-dir-bin");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+    let out_dir = dir.join("out");
+
+    compile(&["-o", out_dir.to_str().unwrap(), src.to_str().unwrap()]);
+    assert_eq!(list_files(&out_dir), ["prog.bin", "prog.s"]);
+    let output = Command::new(out_dir.join("prog.bin")).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hello");
+}
+
+#[test]
+fn out_dir_is_a_file() {
+    let dir = TempDir::new("out-dir-file");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+    let out_dir = dir.join("file");
+    fs::write(&out_dir, "").unwrap();
+
+    compile_fails(
+        &[
+            "--emit=ir",
+            "-o",
+            out_dir.to_str().unwrap(),
+            src.to_str().unwrap(),
+        ],
+        "Error occurs while creating output directory",
+    );
+}
+
+#[test]
+fn unsupported_emit_kinds() {
+    let dir = TempDir::new("unsupported");
+    let src = dir.join("prog.bas");
+    fs::write(&src, HELLO).unwrap();
+    let src = src.to_str().unwrap();
+
+    compile_fails(
+        &["--emit=asm", "--target", "bytecode", src],
+        "`--emit=asm` is not supported for the `bytecode` target",
+    );
+    compile_fails(
+        &["--emit=obj", "--target", "wasm32-wasi", src],
+        "`--emit=obj` is not supported for the `wasm32-wasi` target",
+    );
+    compile_fails(&["--emit=exe", src], "exe");
+    assert_eq!(dir.files(), ["prog.bas"]);
+}
@@ -1,4 +1,19 @@
-use std::{collections::HashMap, convert::TryFrom};
+mod modrm;
+
+use modrm::ModRM;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+/// 1 文字を出力するシリアルポート (COM1) の番号
+pub const SERIAL_PORT: u16 = 0x03f8;
+
+const CARRY_FLAG: u32 = 1;
+const ZERO_FLAG: u32 = 1 << 6;
+const SIGN_FLAG: u32 = 1 << 7;
+const OVERFLOW_FLAG: u32 = 1 << 11;
 
 type NativeInst = fn(&mut Emulator) -> ();
 
@@ -24,17 +39,31 @@ pub struct Emulator {
     eflags: u32,
     /// メモリ (バイト列)
     memory: Vec<u8>,
+    /// シリアルポートに出力されたバイト列 ( ``None`` の場合は標準出力に書き込む)
+    serial_output: Option<Vec<u8>>,
 }
 
 impl Emulator {
     pub fn new(size: usize, eip: u32, esp: u32) -> Self {
-        let mut emu = Emulator::default();
-        emu.eip = eip;
-        emu.esp = esp;
+        let mut emu = Emulator {
+            eip,
+            esp,
+            ..Default::default()
+        };
         emu.memory.resize_with(size, Default::default);
         emu
     }
 
+    /// シリアルポートへの出力を標準出力に書き込まず、 ``serial_output`` で取り出せるようにする
+    pub fn capture_serial_output(&mut self) {
+        self.serial_output = Some(Vec::new());
+    }
+
+    /// ``capture_serial_output`` を呼んでからシリアルポートに出力されたバイト列
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial_output.as_deref().unwrap_or_default()
+    }
+
     pub fn dump_registers(&self) {
         println!("EAX = {}", self.eax);
         println!("ECX = {}", self.ecx);
@@ -60,6 +89,167 @@ impl Emulator {
         self.memory[(self.eip + displacement) as usize] as i8
     }
 
+    fn read_code_u32(&self, displacement: u32) -> u32 {
+        self.read_memory_u32(self.eip + displacement)
+    }
+
+    fn read_code_i32(&self, displacement: u32) -> i32 {
+        self.read_code_u32(displacement) as i32
+    }
+
+    fn read_memory_u8(&self, address: u32) -> u8 {
+        self.memory[address as usize]
+    }
+
+    /// リトルエンディアンで 32 ビット値を読み込む
+    fn read_memory_u32(&self, address: u32) -> u32 {
+        let address = address as usize;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.memory[address..address + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// リトルエンディアンで 32 ビット値を書き込む
+    fn write_memory_u32(&mut self, address: u32, value: u32) {
+        let address = address as usize;
+        self.memory[address..address + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// 番号 (0: EAX, 1: ECX, 2: EDX, 3: EBX, 4: ESP, 5: EBP, 6: ESI, 7: EDI) でレジスタを読み込む
+    fn get_register32(&self, index: u8) -> u32 {
+        match index {
+            0 => self.eax,
+            1 => self.ecx,
+            2 => self.edx,
+            3 => self.ebx,
+            4 => self.esp,
+            5 => self.ebp,
+            6 => self.esi,
+            7 => self.edi,
+            _ => panic!("Invalid register number: {}", index),
+        }
+    }
+
+    fn set_register32(&mut self, index: u8, value: u32) {
+        match index {
+            0 => self.eax = value,
+            1 => self.ecx = value,
+            2 => self.edx = value,
+            3 => self.ebx = value,
+            4 => self.esp = value,
+            5 => self.ebp = value,
+            6 => self.esi = value,
+            7 => self.edi = value,
+            _ => panic!("Invalid register number: {}", index),
+        }
+    }
+
+    /// 番号 (0: AL, 1: CL, 2: DL, 3: BL, 4: AH, 5: CH, 6: DH, 7: BH) で 8 ビットレジスタを読み込む
+    fn get_register8(&self, index: u8) -> u8 {
+        if index < 4 {
+            self.get_register32(index) as u8
+        } else {
+            (self.get_register32(index - 4) >> 8) as u8
+        }
+    }
+
+    fn set_register8(&mut self, index: u8, value: u8) {
+        if index < 4 {
+            let reg = self.get_register32(index);
+            self.set_register32(index, (reg & !0xff) | value as u32);
+        } else {
+            let reg = self.get_register32(index - 4);
+            self.set_register32(index - 4, (reg & !0xff00) | ((value as u32) << 8));
+        }
+    }
+
+    fn push32(&mut self, value: u32) {
+        self.esp -= 4;
+        self.write_memory_u32(self.esp, value);
+    }
+
+    fn pop32(&mut self) -> u32 {
+        let value = self.read_memory_u32(self.esp);
+        self.esp += 4;
+        value
+    }
+
+    fn set_flag(&mut self, flag: u32, is_set: bool) {
+        if is_set {
+            self.eflags |= flag;
+        } else {
+            self.eflags &= !flag;
+        }
+    }
+
+    fn is_set(&self, flag: u32) -> bool {
+        self.eflags & flag != 0
+    }
+
+    /// 加算 ``lhs + rhs`` の結果に従って EFLAGS を更新する
+    fn update_eflags_add(&mut self, lhs: u32, rhs: u32) {
+        let (result, carry) = lhs.overflowing_add(rhs);
+        let overflow = (lhs as i32).overflowing_add(rhs as i32).1;
+        self.set_flag(CARRY_FLAG, carry);
+        self.set_flag(ZERO_FLAG, result == 0);
+        self.set_flag(SIGN_FLAG, result >> 31 != 0);
+        self.set_flag(OVERFLOW_FLAG, overflow);
+    }
+
+    /// 減算 ``lhs - rhs`` の結果に従って EFLAGS を更新する
+    fn update_eflags_sub(&mut self, lhs: u32, rhs: u32) {
+        let (result, borrow) = lhs.overflowing_sub(rhs);
+        let overflow = (lhs as i32).overflowing_sub(rhs as i32).1;
+        self.set_flag(CARRY_FLAG, borrow);
+        self.set_flag(ZERO_FLAG, result == 0);
+        self.set_flag(SIGN_FLAG, result >> 31 != 0);
+        self.set_flag(OVERFLOW_FLAG, overflow);
+    }
+
+    /// 8 ビットの減算 ``lhs - rhs`` の結果に従って EFLAGS を更新する
+    fn update_eflags_sub8(&mut self, lhs: u8, rhs: u8) {
+        let (result, borrow) = lhs.overflowing_sub(rhs);
+        let overflow = (lhs as i8).overflowing_sub(rhs as i8).1;
+        self.set_flag(CARRY_FLAG, borrow);
+        self.set_flag(ZERO_FLAG, result == 0);
+        self.set_flag(SIGN_FLAG, result >> 7 != 0);
+        self.set_flag(OVERFLOW_FLAG, overflow);
+    }
+
+    /// ``inc`` / ``dec`` の結果に従って EFLAGS を更新する (CF は変更しない)
+    fn update_eflags_inc_dec(&mut self, value: u32, delta: i32) {
+        let (result, overflow) = (value as i32).overflowing_add(delta);
+        self.set_flag(ZERO_FLAG, result == 0);
+        self.set_flag(SIGN_FLAG, result < 0);
+        self.set_flag(OVERFLOW_FLAG, overflow);
+    }
+
+    /// 論理演算の結果に従って EFLAGS を更新する (CF と OF はクリアする)
+    fn update_eflags_logical(&mut self, result: u32) {
+        self.set_flag(CARRY_FLAG, false);
+        self.set_flag(ZERO_FLAG, result == 0);
+        self.set_flag(SIGN_FLAG, result >> 31 != 0);
+        self.set_flag(OVERFLOW_FLAG, false);
+    }
+
+    /// I/O ポートに 1 バイトを出力する
+    fn io_out8(&mut self, port: u16, value: u8) {
+        if port == SERIAL_PORT {
+            if let Some(output) = self.serial_output.as_mut() {
+                output.push(value);
+                return;
+            }
+            let mut stdout = io::stdout();
+            stdout.write_all(&[value]).unwrap();
+            stdout.flush().unwrap();
+        }
+    }
+
+    /// 相対アドレスで分岐する
+    fn jump_relative(&mut self, diff: i32) {
+        self.eip = self.eip.wrapping_add(diff as u32);
+    }
+
     pub fn store_bytes(&mut self, content: &[u8]) {
         for (i, b) in content.iter().enumerate() {
             self.memory[i] = *b;
@@ -83,12 +273,6 @@ impl Emulator {
 pub fn get_inst_table() -> InstTable {
     let mut insts = InstTable::default();
 
-    insts.member.insert(0xff, |emu| {
-        println!("inc");
-        // TODO: implement `inc` instruction
-        emu.eip += 3;
-    });
-
     insts.member.insert(0xeb, |emu| {
         println!("short_jmp");
         let diff = emu.read_code_i8(1);
@@ -98,10 +282,520 @@ pub fn get_inst_table() -> InstTable {
         } else if diff < 0 {
             emu.eip = emu
                 .eip
-                .checked_sub(u32::try_from(diff * (-1)).unwrap())
+                .checked_sub(u32::try_from(-diff).unwrap())
                 .expect("Failed to jump");
         }
     });
 
+    // add/sub/cmp r/m32, imm32
+    insts.member.insert(0x81, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let imm = emu.read_code_u32(0);
+        emu.eip += 4;
+        let value = modrm.get_rm32(emu);
+        match modrm.reg {
+            0 => {
+                modrm.set_rm32(emu, value.wrapping_add(imm));
+                emu.update_eflags_add(value, imm);
+            }
+            5 => {
+                modrm.set_rm32(emu, value.wrapping_sub(imm));
+                emu.update_eflags_sub(value, imm);
+            }
+            7 => emu.update_eflags_sub(value, imm),
+            reg => panic!("Unknown instruction: 81 /{}", reg),
+        }
+    });
+
+    // xor r/m32, r32
+    insts.member.insert(0x31, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let result = modrm.get_rm32(emu) ^ emu.get_register32(modrm.reg);
+        modrm.set_rm32(emu, result);
+        emu.update_eflags_logical(result);
+    });
+
+    // cmp al, imm8
+    insts.member.insert(0x3c, |emu| {
+        let imm = emu.read_code_u8(1);
+        emu.eip += 2;
+        let al = emu.get_register8(0);
+        emu.update_eflags_sub8(al, imm);
+    });
+
+    // inc r32
+    for code in 0x40..=0x47 {
+        insts.member.insert(code, |emu| {
+            let reg = emu.read_code_u8(0) - 0x40;
+            emu.eip += 1;
+            let value = emu.get_register32(reg);
+            emu.set_register32(reg, value.wrapping_add(1));
+            emu.update_eflags_inc_dec(value, 1);
+        });
+    }
+
+    // dec r32
+    for code in 0x48..=0x4f {
+        insts.member.insert(code, |emu| {
+            let reg = emu.read_code_u8(0) - 0x48;
+            emu.eip += 1;
+            let value = emu.get_register32(reg);
+            emu.set_register32(reg, value.wrapping_sub(1));
+            emu.update_eflags_inc_dec(value, -1);
+        });
+    }
+
+    // push r32
+    for code in 0x50..=0x57 {
+        insts.member.insert(code, |emu| {
+            let reg = emu.read_code_u8(0) - 0x50;
+            emu.eip += 1;
+            let value = emu.get_register32(reg);
+            emu.push32(value);
+        });
+    }
+
+    // pop r32
+    for code in 0x58..=0x5f {
+        insts.member.insert(code, |emu| {
+            let reg = emu.read_code_u8(0) - 0x58;
+            emu.eip += 1;
+            let value = emu.pop32();
+            emu.set_register32(reg, value);
+        });
+    }
+
+    // push imm32
+    insts.member.insert(0x68, |emu| {
+        let imm = emu.read_code_u32(1);
+        emu.eip += 5;
+        emu.push32(imm);
+    });
+
+    // mov r/m32, r32
+    insts.member.insert(0x89, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let value = emu.get_register32(modrm.reg);
+        modrm.set_rm32(emu, value);
+    });
+
+    // mov r8, r/m8
+    insts.member.insert(0x8a, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let value = modrm.get_rm8(emu);
+        emu.set_register8(modrm.reg, value);
+    });
+
+    // mov r32, r/m32
+    insts.member.insert(0x8b, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let value = modrm.get_rm32(emu);
+        emu.set_register32(modrm.reg, value);
+    });
+
+    // mov r32, imm32
+    for code in 0xb8..=0xbf {
+        insts.member.insert(code, |emu| {
+            let reg = emu.read_code_u8(0) - 0xb8;
+            let imm = emu.read_code_u32(1);
+            emu.eip += 5;
+            emu.set_register32(reg, imm);
+        });
+    }
+
+    // ret
+    insts.member.insert(0xc3, |emu| {
+        emu.eip = emu.pop32();
+    });
+
+    // mov r/m32, imm32
+    insts.member.insert(0xc7, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let imm = emu.read_code_u32(0);
+        emu.eip += 4;
+        modrm.set_rm32(emu, imm);
+    });
+
+    // call rel32
+    insts.member.insert(0xe8, |emu| {
+        let diff = emu.read_code_i32(1);
+        emu.eip += 5;
+        emu.push32(emu.eip);
+        emu.jump_relative(diff);
+    });
+
+    // jmp rel32
+    insts.member.insert(0xe9, |emu| {
+        let diff = emu.read_code_i32(1);
+        emu.eip += 5;
+        emu.jump_relative(diff);
+    });
+
+    // out dx, al
+    insts.member.insert(0xee, |emu| {
+        emu.eip += 1;
+        let port = emu.get_register32(2) as u16;
+        let value = emu.get_register8(0);
+        emu.io_out8(port, value);
+    });
+
+    // neg r/m32, div r/m32
+    insts.member.insert(0xf7, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        let value = modrm.get_rm32(emu);
+        match modrm.reg {
+            3 => {
+                let result = 0u32.wrapping_sub(value);
+                modrm.set_rm32(emu, result);
+                emu.update_eflags_sub(0, value);
+            }
+            6 => {
+                if value == 0 {
+                    panic!("Division by zero");
+                }
+                let dividend = ((emu.edx as u64) << 32) | emu.eax as u64;
+                let quotient = dividend / value as u64;
+                emu.eax = u32::try_from(quotient).expect("Division overflow");
+                emu.edx = (dividend % value as u64) as u32;
+            }
+            reg => panic!("Unknown instruction: f7 /{}", reg),
+        }
+    });
+
+    // inc r/m32, jmp r/m32
+    insts.member.insert(0xff, |emu| {
+        emu.eip += 1;
+        let modrm = ModRM::parse(emu);
+        match modrm.reg {
+            0 => {
+                let value = modrm.get_rm32(emu);
+                modrm.set_rm32(emu, value.wrapping_add(1));
+                emu.update_eflags_inc_dec(value, 1);
+            }
+            4 => emu.eip = modrm.get_rm32(emu),
+            reg => panic!("Unknown instruction: ff /{}", reg),
+        }
+    });
+
+    // 2 バイトのオペコード (条件分岐 jcc rel32)
+    insts.member.insert(0x0f, |emu| {
+        let code = emu.read_code_u8(1);
+        let diff = emu.read_code_i32(2);
+        emu.eip += 6;
+        let carry = emu.is_set(CARRY_FLAG);
+        let zero = emu.is_set(ZERO_FLAG);
+        let sign_ne_overflow = emu.is_set(SIGN_FLAG) != emu.is_set(OVERFLOW_FLAG);
+        // 下位ビットが 1 の条件は、その前の偶数の条件の否定
+        let cond = match code & 0xfe {
+            0x80 => emu.is_set(OVERFLOW_FLAG),
+            0x82 => carry,
+            0x84 => zero,
+            0x86 => carry || zero,
+            0x88 => emu.is_set(SIGN_FLAG),
+            0x8c => sign_ne_overflow,
+            0x8e => zero || sign_ne_overflow,
+            _ => panic!("Unknown instruction: 0f {:02x}", code),
+        };
+        let taken = cond != (code & 1 != 0);
+        if taken {
+            emu.jump_relative(diff);
+        }
+    });
+
     insts
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 命令を置く位置
+    const CODE_START: u32 = 0x10;
+
+    fn emulator() -> Emulator {
+        Emulator::new(0x400, CODE_START, 0x400)
+    }
+
+    /// ``eip`` の位置に ``code`` を置き、1 命令だけ実行する
+    fn step(emu: &mut Emulator, code: &[u8]) {
+        let start = emu.eip as usize;
+        emu.memory[start..start + code.len()].copy_from_slice(code);
+        let inst = get_inst_table().member[&code[0]];
+        inst(emu);
+    }
+
+    /// 命令を実行し、 ``eip`` が命令の直後に進むことを確認する
+    fn exec(emu: &mut Emulator, code: &[u8]) {
+        let next = emu.eip + code.len() as u32;
+        step(emu, code);
+        assert_eq!(emu.eip, next, "{:02x?}", code);
+    }
+
+    /// セットされているフラグ (C: CF, Z: ZF, S: SF, O: OF)
+    fn flags(emu: &Emulator) -> String {
+        [
+            (CARRY_FLAG, 'C'),
+            (ZERO_FLAG, 'Z'),
+            (SIGN_FLAG, 'S'),
+            (OVERFLOW_FLAG, 'O'),
+        ]
+        .iter()
+        .filter(|(flag, _)| emu.is_set(*flag))
+        .map(|(_, c)| c)
+        .collect()
+    }
+
+    /// ``eax`` に ``value`` を入れて命令を実行し、 ``eax`` とフラグを返す
+    fn exec_eax(value: u32, code: &[u8]) -> (u32, String) {
+        let mut emu = emulator();
+        emu.eax = value;
+        exec(&mut emu, code);
+        (emu.eax, flags(&emu))
+    }
+
+    fn with_imm32(prefix: &[u8], imm: u32) -> Vec<u8> {
+        let mut code = prefix.to_vec();
+        code.extend(imm.to_le_bytes());
+        code
+    }
+
+    #[test]
+    fn add_imm32() {
+        let add = |lhs, rhs| exec_eax(lhs, &with_imm32(&[0x81, 0xc0], rhs));
+        assert_eq!(add(1, 2), (3, "".to_string()));
+        assert_eq!(add(0xffff_ffff, 1), (0, "CZ".to_string()));
+        assert_eq!(add(0x7fff_ffff, 1), (0x8000_0000, "SO".to_string()));
+        assert_eq!(add(0x8000_0000, 0x8000_0000), (0, "CZO".to_string()));
+        assert_eq!(add(0xffff_fffe, 1), (0xffff_ffff, "S".to_string()));
+    }
+
+    #[test]
+    fn sub_imm32() {
+        let sub = |lhs, rhs| exec_eax(lhs, &with_imm32(&[0x81, 0xe8], rhs));
+        assert_eq!(sub(5, 3), (2, "".to_string()));
+        assert_eq!(sub(3, 3), (0, "Z".to_string()));
+        assert_eq!(sub(0, 1), (0xffff_ffff, "CS".to_string()));
+        assert_eq!(sub(0x8000_0000, 1), (0x7fff_ffff, "O".to_string()));
+        assert_eq!(
+            sub(0x7fff_ffff, 0xffff_ffff),
+            (0x8000_0000, "CSO".to_string())
+        );
+    }
+
+    #[test]
+    fn cmp_imm32_keeps_operand() {
+        let cmp = |lhs, rhs| exec_eax(lhs, &with_imm32(&[0x81, 0xf8], rhs));
+        assert_eq!(cmp(5, 5), (5, "Z".to_string()));
+        assert_eq!(cmp(1, 2), (1, "CS".to_string()));
+        assert_eq!(cmp(0x8000_0000, 1), (0x8000_0000, "O".to_string()));
+    }
+
+    #[test]
+    fn cmp_al_imm8() {
+        let cmp = |eax, imm| exec_eax(eax, &[0x3c, imm]).1;
+        assert_eq!(cmp(0x1234_5601, 1), "Z");
+        assert_eq!(cmp(1, 2), "CS");
+        assert_eq!(cmp(0x80, 1), "O");
+        assert_eq!(cmp(0x7f, 0xff), "CSO");
+        assert_eq!(cmp(0xff, 0x01), "S");
+    }
+
+    #[test]
+    fn inc_and_dec_keep_carry_flag() {
+        assert_eq!(exec_eax(1, &[0x40]), (2, "".to_string()));
+        assert_eq!(exec_eax(0xffff_ffff, &[0x40]), (0, "Z".to_string()));
+        assert_eq!(
+            exec_eax(0x7fff_ffff, &[0x40]),
+            (0x8000_0000, "SO".to_string())
+        );
+        assert_eq!(exec_eax(1, &[0x48]), (0, "Z".to_string()));
+        assert_eq!(exec_eax(0, &[0x48]), (0xffff_ffff, "S".to_string()));
+        assert_eq!(
+            exec_eax(0x8000_0000, &[0x48]),
+            (0x7fff_ffff, "O".to_string())
+        );
+
+        let mut emu = emulator();
+        emu.set_flag(CARRY_FLAG, true);
+        emu.edi = 0xffff_ffff;
+        exec(&mut emu, &[0x47]);
+        assert_eq!((emu.edi, flags(&emu)), (0, "CZ".to_string()));
+        exec(&mut emu, &[0x4f]);
+        assert_eq!((emu.edi, flags(&emu)), (0xffff_ffff, "CS".to_string()));
+    }
+
+    #[test]
+    fn inc_rm32() {
+        // inc dword [ebp-4]
+        let mut emu = emulator();
+        emu.ebp = 0x100;
+        emu.write_memory_u32(0xfc, 41);
+        exec(&mut emu, &[0xff, 0x45, 0xfc]);
+        assert_eq!(emu.read_memory_u32(0xfc), 42);
+        assert_eq!(flags(&emu), "");
+
+        // inc ecx
+        let mut emu = emulator();
+        emu.set_flag(CARRY_FLAG, true);
+        emu.ecx = 0x7fff_ffff;
+        exec(&mut emu, &[0xff, 0xc1]);
+        assert_eq!((emu.ecx, flags(&emu)), (0x8000_0000, "CSO".to_string()));
+    }
+
+    #[test]
+    fn jmp_rm32() {
+        let mut emu = emulator();
+        emu.eax = 0x123;
+        step(&mut emu, &[0xff, 0xe0]);
+        assert_eq!(emu.eip, 0x123);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown instruction: ff /7")]
+    fn unknown_ff_extension() {
+        step(&mut emulator(), &[0xff, 0xf8]);
+    }
+
+    #[test]
+    fn neg_rm32() {
+        assert_eq!(
+            exec_eax(5, &[0xf7, 0xd8]),
+            (5u32.wrapping_neg(), "CS".to_string())
+        );
+        assert_eq!(exec_eax(0, &[0xf7, 0xd8]), (0, "Z".to_string()));
+        assert_eq!(
+            exec_eax(0x8000_0000, &[0xf7, 0xd8]),
+            (0x8000_0000, "CSO".to_string())
+        );
+    }
+
+    #[test]
+    fn div_rm32() {
+        let mut emu = emulator();
+        emu.edx = 1;
+        emu.eax = 1;
+        emu.ecx = 2;
+        exec(&mut emu, &[0xf7, 0xf1]);
+        assert_eq!((emu.eax, emu.edx), (0x8000_0000, 1));
+    }
+
+    #[test]
+    fn xor_clears_carry_and_overflow() {
+        let mut emu = emulator();
+        emu.eflags = CARRY_FLAG | OVERFLOW_FLAG;
+        emu.eax = 0x1234;
+        exec(&mut emu, &[0x31, 0xc0]);
+        assert_eq!((emu.eax, flags(&emu)), (0, "Z".to_string()));
+    }
+
+    /// ``lhs`` と ``rhs`` を ``cmp`` で比較した後、 ``0f code`` の条件分岐が分岐するかどうか
+    fn jcc_taken(lhs: u32, rhs: u32, code: u8) -> bool {
+        let mut emu = emulator();
+        emu.eax = lhs;
+        exec(&mut emu, &with_imm32(&[0x81, 0xf8], rhs));
+        let next = emu.eip + 6;
+        step(&mut emu, &with_imm32(&[0x0f, code], 0x20));
+        if emu.eip == next + 0x20 {
+            true
+        } else {
+            assert_eq!(emu.eip, next);
+            false
+        }
+    }
+
+    #[test]
+    fn jcc_conditions() {
+        // (cmp の左辺, 右辺, 0f 80 〜 0f 8f (jp / jnp を除く) のうち分岐するもの)
+        let cases: [(u32, u32, &[u8]); 5] = [
+            (5, 5, &[0x81, 0x83, 0x84, 0x86, 0x89, 0x8d, 0x8e]),
+            (0xffff_ffff, 1, &[0x81, 0x83, 0x85, 0x87, 0x88, 0x8c, 0x8e]),
+            (1, 0xffff_ffff, &[0x81, 0x82, 0x85, 0x86, 0x89, 0x8d, 0x8f]),
+            (0x8000_0000, 1, &[0x80, 0x83, 0x85, 0x87, 0x89, 0x8c, 0x8e]),
+            (
+                0x7fff_ffff,
+                0xffff_ffff,
+                &[0x80, 0x82, 0x85, 0x86, 0x88, 0x8d, 0x8f],
+            ),
+        ];
+        for (lhs, rhs, taken) in cases.iter() {
+            for code in (0x80..=0x8f).filter(|code| !matches!(code, 0x8a | 0x8b)) {
+                assert_eq!(
+                    jcc_taken(*lhs, *rhs, code),
+                    taken.contains(&code),
+                    "cmp {:#x}, {:#x}; 0f {:02x}",
+                    lhs,
+                    rhs,
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn jcc_backward() {
+        let mut emu = emulator();
+        emu.set_flag(ZERO_FLAG, true);
+        step(&mut emu, &with_imm32(&[0x0f, 0x84], (-0x16i32) as u32));
+        assert_eq!(emu.eip, 0);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut emu = emulator();
+        step(&mut emu, &with_imm32(&[0xe8], 0x20));
+        assert_eq!(emu.eip, CODE_START + 5 + 0x20);
+        assert_eq!(emu.esp, 0x3fc);
+        assert_eq!(emu.read_memory_u32(0x3fc), CODE_START + 5);
+        step(&mut emu, &[0xc3]);
+        assert_eq!((emu.eip, emu.esp), (CODE_START + 5, 0x400));
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut emu = emulator();
+        exec(&mut emu, &with_imm32(&[0x68], 0xdead_beef));
+        exec(&mut emu, &[0x5b]);
+        assert_eq!((emu.ebx, emu.esp), (0xdead_beef, 0x400));
+        emu.esi = 7;
+        exec(&mut emu, &[0x56]);
+        assert_eq!(emu.read_memory_u32(emu.esp), 7);
+    }
+
+    #[test]
+    fn mov_forms() {
+        let mut emu = emulator();
+        // mov edx, 0x200
+        exec(&mut emu, &with_imm32(&[0xba], 0x200));
+        // mov dword [edx], 0x12345678
+        exec(&mut emu, &with_imm32(&[0xc7, 0x02], 0x1234_5678));
+        // mov ecx, [edx]
+        exec(&mut emu, &[0x8b, 0x0a]);
+        assert_eq!(emu.ecx, 0x1234_5678);
+        // mov [edx+4], ecx
+        exec(&mut emu, &[0x89, 0x4a, 0x04]);
+        assert_eq!(emu.read_memory_u32(0x204), 0x1234_5678);
+        // mov ah, [edx+1]
+        exec(&mut emu, &[0x8a, 0x62, 0x01]);
+        assert_eq!(emu.eax, 0x5600);
+    }
+
+    #[test]
+    fn out_writes_to_serial_port() {
+        let mut emu = emulator();
+        emu.capture_serial_output();
+        emu.edx = SERIAL_PORT as u32;
+        emu.eax = 0x4142;
+        exec(&mut emu, &[0xee]);
+        // シリアルポート以外への出力は無視する
+        emu.edx = 0x80;
+        exec(&mut emu, &[0xee]);
+        assert_eq!(emu.serial_output(), b"B");
+    }
+}
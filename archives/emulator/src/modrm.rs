@@ -0,0 +1,211 @@
+use super::Emulator;
+
+/// ModR/M バイト (と SIB バイト、変位) を解釈した結果
+pub struct ModRM {
+    /// ``mod`` フィールド (0 〜 3)
+    pub mod_: u8,
+    /// ``reg`` フィールド (レジスタ番号、またはオペコードの拡張)
+    pub reg: u8,
+    /// ``r/m`` フィールド
+    pub rm: u8,
+    /// SIB バイト ( ``r/m`` が 4 で ``mod`` が 3 でない場合のみ)
+    pub sib: u8,
+    /// 変位
+    pub disp: u32,
+}
+
+impl ModRM {
+    /// ``eip`` が指す ModR/M バイトを読み込み、変位の直後まで ``eip`` を進める
+    pub fn parse(emu: &mut Emulator) -> Self {
+        let code = emu.read_code_u8(0);
+        let mut modrm = ModRM {
+            mod_: (code & 0xc0) >> 6,
+            reg: (code & 0x38) >> 3,
+            rm: code & 0x07,
+            sib: 0,
+            disp: 0,
+        };
+        emu.eip += 1;
+
+        if modrm.mod_ != 3 && modrm.rm == 4 {
+            modrm.sib = emu.read_code_u8(0);
+            emu.eip += 1;
+        }
+
+        if (modrm.mod_ == 0 && modrm.rm == 5) || modrm.mod_ == 2 {
+            modrm.disp = emu.read_code_u32(0);
+            emu.eip += 4;
+        } else if modrm.mod_ == 1 {
+            modrm.disp = emu.read_code_i8(0) as u32;
+            emu.eip += 1;
+        }
+
+        modrm
+    }
+
+    /// メモリを指すオペランドの実効アドレスを計算する
+    pub fn effective_address(&self, emu: &Emulator) -> u32 {
+        match (self.mod_, self.rm) {
+            (3, _) => panic!("The operand is not a memory"),
+            (0, 5) => self.disp,
+            (_, 4) => {
+                // スケールとインデックスは使わず、ベースレジスタのみに対応する
+                if self.sib & 0x38 != 0x20 {
+                    panic!("Unsupported SIB byte: {:#04x}", self.sib);
+                }
+                let base = emu.get_register32(self.sib & 0x07);
+                base.wrapping_add(self.displacement())
+            }
+            (_, rm) => emu.get_register32(rm).wrapping_add(self.displacement()),
+        }
+    }
+
+    fn displacement(&self) -> u32 {
+        if self.mod_ == 0 {
+            0
+        } else {
+            self.disp
+        }
+    }
+
+    /// ``r/m`` が指す 32 ビット値を読み込む
+    pub fn get_rm32(&self, emu: &Emulator) -> u32 {
+        if self.mod_ == 3 {
+            emu.get_register32(self.rm)
+        } else {
+            emu.read_memory_u32(self.effective_address(emu))
+        }
+    }
+
+    /// ``r/m`` が指す場所に 32 ビット値を書き込む
+    pub fn set_rm32(&self, emu: &mut Emulator, value: u32) {
+        if self.mod_ == 3 {
+            emu.set_register32(self.rm, value);
+        } else {
+            let address = self.effective_address(emu);
+            emu.write_memory_u32(address, value);
+        }
+    }
+
+    /// ``r/m`` が指す 8 ビット値を読み込む
+    pub fn get_rm8(&self, emu: &Emulator) -> u8 {
+        if self.mod_ == 3 {
+            emu.get_register8(self.rm)
+        } else {
+            emu.read_memory_u8(self.effective_address(emu))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// アドレス 0 に ``code`` を置いて解釈し、結果と ``eip`` の進んだ量を返す
+    fn parse(emu: &mut Emulator, code: &[u8]) -> (ModRM, u32) {
+        emu.store_bytes(code);
+        emu.eip = 0;
+        let modrm = ModRM::parse(emu);
+        (modrm, emu.eip)
+    }
+
+    fn emulator() -> Emulator {
+        Emulator::new(0x400, 0, 0x400)
+    }
+
+    #[test]
+    fn register_operand() {
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0xd9]);
+        assert_eq!((modrm.mod_, modrm.reg, modrm.rm, len), (3, 3, 1, 1));
+
+        emu.ecx = 0x1234_5678;
+        assert_eq!(modrm.get_rm32(&emu), 0x1234_5678);
+        assert_eq!(modrm.get_rm8(&emu), 0x78);
+        modrm.set_rm32(&mut emu, 7);
+        assert_eq!(emu.ecx, 7);
+    }
+
+    #[test]
+    fn high_byte_register() {
+        // r/m = 4 (mod = 3) は 8 ビットでは AH、32 ビットでは ESP を指す
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0xc4]);
+        assert_eq!(len, 1);
+        emu.eax = 0xab00;
+        emu.esp = 0x300;
+        assert_eq!(modrm.get_rm8(&emu), 0xab);
+        assert_eq!(modrm.get_rm32(&emu), 0x300);
+    }
+
+    #[test]
+    fn register_indirect() {
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0x03]);
+        assert_eq!((modrm.mod_, modrm.rm, len), (0, 3, 1));
+        emu.ebx = 0x200;
+        assert_eq!(modrm.effective_address(&emu), 0x200);
+
+        modrm.set_rm32(&mut emu, 0xdead_beef);
+        assert_eq!(emu.read_memory_u32(0x200), 0xdead_beef);
+        assert_eq!(modrm.get_rm32(&emu), 0xdead_beef);
+        assert_eq!(modrm.get_rm8(&emu), 0xef);
+    }
+
+    #[test]
+    fn absolute_address() {
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0x05, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!((modrm.mod_, modrm.rm, len), (0, 5, 5));
+        assert_eq!(modrm.effective_address(&emu), 0x1234_5678);
+    }
+
+    #[test]
+    fn disp8_is_sign_extended() {
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0x45, 0xfc]);
+        assert_eq!((modrm.mod_, modrm.rm, len), (1, 5, 2));
+        emu.ebp = 0x100;
+        assert_eq!(modrm.effective_address(&emu), 0xfc);
+    }
+
+    #[test]
+    fn disp32() {
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0x86, 0x10, 0x01, 0x00, 0x00]);
+        assert_eq!((modrm.mod_, modrm.reg, modrm.rm, len), (2, 0, 6, 5));
+        emu.esi = 0x20;
+        assert_eq!(modrm.effective_address(&emu), 0x130);
+    }
+
+    #[test]
+    fn sib_base_register() {
+        // [esp+8]
+        let mut emu = emulator();
+        let (modrm, len) = parse(&mut emu, &[0x44, 0x24, 0x08]);
+        assert_eq!((modrm.mod_, modrm.rm, modrm.sib, len), (1, 4, 0x24, 3));
+        emu.esp = 0x300;
+        assert_eq!(modrm.effective_address(&emu), 0x308);
+
+        // [esp] (mod = 0 では変位を読まない)
+        let (modrm, len) = parse(&mut emu, &[0x04, 0x24]);
+        assert_eq!(len, 2);
+        assert_eq!(modrm.effective_address(&emu), 0x300);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported SIB byte: 0x88")]
+    fn sib_index_is_unsupported() {
+        let mut emu = emulator();
+        let (modrm, _) = parse(&mut emu, &[0x04, 0x88]);
+        modrm.effective_address(&emu);
+    }
+
+    #[test]
+    #[should_panic(expected = "The operand is not a memory")]
+    fn register_has_no_address() {
+        let mut emu = emulator();
+        let (modrm, _) = parse(&mut emu, &[0xc0]);
+        modrm.effective_address(&emu);
+    }
+}
@@ -0,0 +1,13 @@
+FUNCTION Greeting() AS STRING
+    Greeting = "Hello from BASIC"
+END FUNCTION
+
+FUNCTION Answer() AS INTEGER
+    Answer = 42
+END FUNCTION
+
+SUB PrintTwice(msg AS STRING, n AS INTEGER)
+    PRINT msg
+    PRINT msg
+    PRINT n
+END SUB
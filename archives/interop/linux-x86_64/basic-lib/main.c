@@ -0,0 +1,9 @@
+#include <stdio.h>
+#include "greet.h"
+
+int main() {
+    puts(Greeting());
+    printf("%ld\n", Answer());
+    PrintTwice("BASIC ", 3);
+    return 0;
+}
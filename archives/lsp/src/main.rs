@@ -0,0 +1,20 @@
+mod server;
+mod transport;
+
+use clap::{app_from_crate, Arg};
+use std::{io, process};
+
+fn main() {
+    app_from_crate!()
+        .arg(
+            Arg::new("stdio")
+                .long("stdio")
+                .about("Communicates over the standard input and output (always enabled)"),
+        )
+        .get_matches();
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let code = server::run(&mut stdin.lock(), &mut stdout.lock());
+    process::exit(code);
+}
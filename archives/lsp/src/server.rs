@@ -0,0 +1,587 @@
+use super::transport::{read_message, write_message};
+use compiler::{
+    ast::Type,
+    diagnostic::{Diagnostic, Severity},
+    location::{Location, Point},
+    parser::{parse_partial, RESERVED_WORDS},
+    sem_analysis::sem_analysis,
+    symbols::{collect_symbols, Symbol, SymbolKind, SymbolTable},
+    tokenizer::tokenize,
+    CrateType,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+/// JSON-RPC のエラーコード
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// ``TextDocumentSyncKind.Full`` (変更のたびにソースコード全体を受け取る)
+const SYNC_FULL: i64 = 1;
+
+/// ``CompletionItemKind``
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+
+/// リクエストを処理した結果 (失敗した場合は JSON-RPC のエラーコードとメッセージ)
+type RequestResult = Result<Value, (i64, String)>;
+
+/// エディタで開かれているソースコードの解析結果
+struct Document {
+    symbols: SymbolTable,
+}
+
+/// Language Server の状態
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    initialized: bool,
+    shutdown: bool,
+}
+
+impl Server {
+    /// クライアントから受け取ったメッセージを処理し、送り返すメッセージを ``out`` に追加する
+    ///
+    /// ``exit`` 通知を受け取った場合は、プロセスの終了コードを返す
+    pub fn handle(&mut self, message: &Value, out: &mut Vec<Value>) -> Option<i32> {
+        // クライアントからのレスポンスは、こちらからリクエストを送らないため無視する
+        let method = message.get("method").and_then(Value::as_str)?;
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        match message.get("id") {
+            Some(id) => {
+                let response = match self.handle_request(method, &params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, msg)) => error_response(id.clone(), code, &msg),
+                };
+                out.push(response);
+                None
+            }
+            None => self.handle_notification(method, &params, out),
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> RequestResult {
+        if method == "initialize" {
+            self.initialized = true;
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_FULL,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }));
+        }
+        if !self.initialized {
+            return Err((
+                SERVER_NOT_INITIALIZED,
+                "Server is not initialized".to_owned(),
+            ));
+        }
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "Server is shutting down".to_owned()));
+        }
+
+        match method {
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, symbol) = self.symbol_at(params)?;
+                Ok(symbol.map_or(Value::Null, |symbol| {
+                    location_json(&uri, &symbol.definition)
+                }))
+            }
+            "textDocument/references" => {
+                let (uri, symbol) = self.symbol_at(params)?;
+                let include_declaration = params
+                    .pointer("/context/includeDeclaration")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(true);
+                let locations: Vec<Value> = symbol.map_or(Vec::new(), |symbol| {
+                    include_declaration
+                        .then_some(&symbol.definition)
+                        .into_iter()
+                        .chain(symbol.references.iter())
+                        .map(|location| location_json(&uri, location))
+                        .collect()
+                });
+                Ok(Value::from(locations))
+            }
+            "textDocument/hover" => {
+                let (_, symbol) = self.symbol_at(params)?;
+                Ok(symbol.map_or(Value::Null, |symbol| {
+                    json!({
+                        "contents": { "kind": "markdown", "value": hover_text(symbol) },
+                    })
+                }))
+            }
+            "textDocument/completion" => {
+                let (uri, point) = text_document_position(params)?;
+                let document = self.document(&uri)?;
+                let keywords = RESERVED_WORDS
+                    .iter()
+                    .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }));
+                let vars = document
+                    .symbols
+                    .visible_at(point)
+                    .into_iter()
+                    .map(|symbol| {
+                        json!({
+                            "label": symbol.name,
+                            "kind": COMPLETION_VARIABLE,
+                            "detail": type_name(symbol.ty),
+                        })
+                    });
+                Ok(Value::from(vars.chain(keywords).collect::<Vec<Value>>()))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method `{}`", method))),
+        }
+    }
+
+    fn handle_notification(
+        &mut self,
+        method: &str,
+        params: &Value,
+        out: &mut Vec<Value>,
+    ) -> Option<i32> {
+        match method {
+            "exit" => return Some(if self.shutdown { 0 } else { 1 }),
+            "textDocument/didOpen" => {
+                if let (Some(uri), Some(text)) = (
+                    params.pointer("/textDocument/uri").and_then(Value::as_str),
+                    params.pointer("/textDocument/text").and_then(Value::as_str),
+                ) {
+                    self.update(uri, text, out);
+                }
+            }
+            "textDocument/didChange" => {
+                // 全体を送る設定なので、最後の変更がソースコード全体になる
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+                if let (Some(uri), Some(text)) = (
+                    params.pointer("/textDocument/uri").and_then(Value::as_str),
+                    text,
+                ) {
+                    self.update(uri, text, out);
+                }
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params.pointer("/textDocument/uri").and_then(Value::as_str) {
+                    self.documents.remove(uri);
+                    out.push(publish_diagnostics(uri, Vec::new()));
+                }
+            }
+            // ``initialized`` などの、応答の必要ない通知は無視する
+            _ => {}
+        }
+        None
+    }
+
+    /// ソースコードを解析し直して、診断を送る
+    fn update(&mut self, uri: &str, text: &str, out: &mut Vec<Value>) {
+        let (symbols, diags) = analyze(text);
+        // 位置を持たない診断 (``END SUB`` のないままソースコードが終わった場合など) は、ソースコードの末尾に示す
+        let end = end_of(text);
+        let diagnostics = diags
+            .iter()
+            .map(|diag| diagnostic_json(diag, uri, end))
+            .collect();
+        self.documents.insert(uri.to_owned(), Document { symbols });
+        out.push(publish_diagnostics(uri, diagnostics));
+    }
+
+    fn document(&self, uri: &str) -> Result<&Document, (i64, String)> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Document `{}` is not open", uri)))
+    }
+
+    /// ``TextDocumentPositionParams`` が指す変数
+    fn symbol_at(&self, params: &Value) -> Result<(String, Option<&Symbol>), (i64, String)> {
+        let (uri, point) = text_document_position(params)?;
+        let document = self.document(&uri)?;
+        Ok((uri, document.symbols.symbol_at(point)))
+    }
+}
+
+/// 標準入力などから受け取ったメッセージを、 ``exit`` 通知を受け取るか入力が終わるまで処理する
+///
+/// 戻り値はプロセスの終了コード ( ``shutdown`` リクエストの後に終了した場合のみ 0)
+pub fn run(reader: &mut dyn BufRead, writer: &mut dyn Write) -> i32 {
+    let mut server = Server::default();
+    loop {
+        let mut out = Vec::new();
+        let exit_code = match read_message(reader) {
+            Ok(Some(Ok(message))) => server.handle(&message, &mut out),
+            Ok(Some(Err(msg))) => {
+                out.push(error_response(Value::Null, PARSE_ERROR, &msg));
+                None
+            }
+            Ok(None) | Err(_) => Some(if server.shutdown { 0 } else { 1 }),
+        };
+        for message in out.iter() {
+            if write_message(writer, message).is_err() {
+                return 1;
+            }
+        }
+        if let Some(exit_code) = exit_code {
+            return exit_code;
+        }
+    }
+}
+
+/// ソースコードを解析して、変数の一覧と診断を返す
+///
+/// 構文エラーがあってもエラーのない文から変数を集める。意味解析はコンパイラと同じく、構文エラーがない場合のみ行う
+fn analyze(text: &str) -> (SymbolTable, Vec<Diagnostic>) {
+    let tokens = match tokenize(text) {
+        Ok(tokens) => tokens,
+        Err(diag) => return (SymbolTable::default(), vec![diag]),
+    };
+    let (stmts, errors) = parse_partial(&tokens);
+    let symbols = collect_symbols(&stmts);
+    if !errors.is_empty() {
+        return (symbols, errors);
+    }
+    let errors = sem_analysis(&stmts, CrateType::Bin)
+        .err()
+        .unwrap_or_default();
+    (symbols, errors)
+}
+
+/// ソースコードの末尾の位置
+fn end_of(text: &str) -> Point {
+    let line = text.lines().count().saturating_sub(1);
+    let column = text.lines().last().map_or(0, |last| last.chars().count());
+    Point::new(line as i32, column as i32)
+}
+
+fn text_document_position(params: &Value) -> Result<(String, Point), (i64, String)> {
+    let uri = params.pointer("/textDocument/uri").and_then(Value::as_str);
+    let line = params.pointer("/position/line").and_then(Value::as_i64);
+    let character = params
+        .pointer("/position/character")
+        .and_then(Value::as_i64);
+    match (uri, line, character) {
+        (Some(uri), Some(line), Some(character)) => {
+            Ok((uri.to_owned(), Point::new(line as i32, character as i32)))
+        }
+        _ => Err((INVALID_PARAMS, "Invalid text document position".to_owned())),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn position_json(point: Point) -> Value {
+    json!({ "line": point.line(), "character": point.column() })
+}
+
+/// コンパイラの位置 (終わりの文字を含む) を、LSP の範囲 (終わりの文字を含まない) に変換する
+fn range_json(location: &Location) -> Value {
+    let end = Point::new(location.end.line(), location.end.column() + 1);
+    json!({ "start": position_json(location.start), "end": position_json(end) })
+}
+
+fn location_json(uri: &str, location: &Location) -> Value {
+    json!({ "uri": uri, "range": range_json(location) })
+}
+
+/// コンパイラの診断を LSP の ``Diagnostic`` に変換する
+///
+/// 注釈と修正案はメッセージに続け、補足のラベルは ``relatedInformation`` にする
+fn diagnostic_json(diag: &Diagnostic, uri: &str, end: Point) -> Value {
+    let range = match diag.primary_location() {
+        Some(location) => range_json(&location),
+        None => json!({ "start": position_json(end), "end": position_json(end) }),
+    };
+    let mut message = diag.message.clone();
+    for note in diag.notes.iter() {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(help) = &diag.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    let related: Vec<Value> = diag
+        .labels
+        .iter()
+        .filter(|label| !label.primary)
+        .map(|label| {
+            json!({
+                "location": location_json(uri, &label.location),
+                "message": label.message,
+            })
+        })
+        .collect();
+
+    json!({
+        "range": range,
+        "severity": match diag.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "code": diag.code,
+        "source": "basic",
+        "message": message,
+        "relatedInformation": related,
+    })
+}
+
+fn type_name(ty: Option<Type>) -> Option<&'static str> {
+    ty.map(|ty| match ty {
+        Type::Integer => "INTEGER",
+        Type::String => "STRING",
+    })
+}
+
+/// ホバーで表示する、変数の宣言と推論した型
+fn hover_text(symbol: &Symbol) -> String {
+    let decl = match type_name(symbol.ty) {
+        Some(ty) => format!("{} AS {}", symbol.name, ty),
+        None => symbol.name.clone(),
+    };
+    let kind = match symbol.kind {
+        SymbolKind::Global => "global variable",
+        SymbolKind::Local => "local variable",
+        SymbolKind::Param => "parameter",
+        SymbolKind::Return => "return value",
+    };
+    let note = if symbol.ty.is_none() {
+        " (type unknown: not inferable or assigned values of different types)"
+    } else {
+        ""
+    };
+    format!("```basic\n{}\n```\n{}{}", decl, kind, note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.bas";
+
+    /// メッセージの列を1つのセッションとして処理し、サーバーが送ったメッセージと終了コードを返す
+    fn session(messages: &[Value]) -> (Vec<Value>, i32) {
+        let mut input = Vec::new();
+        for message in messages.iter() {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let code = run(&mut Cursor::new(input), &mut output);
+
+        let mut reader = Cursor::new(output);
+        let mut received = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            received.push(message.unwrap());
+        }
+        (received, code)
+    }
+
+    /// ``initialize`` から ``exit`` までの間に、ソースコードを開いてリクエストを送るセッション
+    fn open_and_request(src: &str, requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut messages = vec![
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": URI, "languageId": "basic", "version": 1, "text": src } },
+            }),
+        ];
+        for (i, (method, params)) in requests.iter().enumerate() {
+            messages
+                .push(json!({ "jsonrpc": "2.0", "id": i + 1, "method": method, "params": params }));
+        }
+        messages.push(json!({ "jsonrpc": "2.0", "id": 100, "method": "shutdown" }));
+        messages.push(json!({ "jsonrpc": "2.0", "method": "exit" }));
+
+        let (received, code) = session(&messages);
+        assert_eq!(code, 0);
+        received
+    }
+
+    fn position(line: i32, character: i32) -> Value {
+        json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+    }
+
+    fn result(received: &[Value], id: usize) -> &Value {
+        &received
+            .iter()
+            .find(|message| message["id"] == json!(id))
+            .unwrap()["result"]
+    }
+
+    const SRC: &str = "VAR a = 1
+VAR s = \"x\"
+FUNCTION Twice(n AS INTEGER) AS INTEGER
+    VAR t = n
+    Twice = t
+END FUNCTION
+a = Twice(a)
+PRINT s
+";
+
+    #[test]
+    fn exit_code_depends_on_shutdown() {
+        let initialize = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} });
+        let exit = json!({ "jsonrpc": "2.0", "method": "exit" });
+        let (received, code) = session(&[initialize.clone(), exit.clone()]);
+        assert_eq!(code, 1);
+        assert_eq!(
+            received[0]["result"]["capabilities"]["hoverProvider"],
+            json!(true)
+        );
+
+        let shutdown = json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" });
+        let (_, code) = session(&[initialize, shutdown, exit]);
+        assert_eq!(code, 0);
+    }
+
+    #[test]
+    fn rejects_unknown_requests() {
+        let (received, _) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": position(0, 0) }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/symbol", "params": {} }),
+        ]);
+        assert_eq!(received[0]["error"]["code"], json!(SERVER_NOT_INITIALIZED));
+        assert_eq!(received[2]["error"]["code"], json!(METHOD_NOT_FOUND));
+    }
+
+    #[test]
+    fn publishes_all_syntax_errors() {
+        let received = open_and_request("VAR a = )\nPRINT a\nPRINT 1 2\n", &[]);
+        let diagnostics = &received[1]["params"]["diagnostics"];
+        assert_eq!(
+            received[1]["method"],
+            json!("textDocument/publishDiagnostics")
+        );
+        assert_eq!(diagnostics.as_array().unwrap().len(), 2);
+        assert_eq!(diagnostics[0]["code"], json!("E0101"));
+        assert_eq!(
+            diagnostics[1]["range"]["start"],
+            json!({ "line": 2, "character": 8 })
+        );
+    }
+
+    #[test]
+    fn publishes_semantic_errors_with_related_information() {
+        let received = open_and_request("SUB Foo(x AS INTEGER)\nEND SUB\nFoo 1, 2\n", &[]);
+        let diagnostic = &received[1]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["code"], json!("E0210"));
+        assert_eq!(
+            diagnostic["relatedInformation"][0]["location"]["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+    }
+
+    #[test]
+    fn finds_definition_and_references() {
+        let mut references = position(0, 4);
+        references["context"] = json!({ "includeDeclaration": false });
+        let received = open_and_request(
+            SRC,
+            &[
+                ("textDocument/definition", position(6, 10)),
+                ("textDocument/references", references),
+                ("textDocument/definition", position(4, 12)),
+            ],
+        );
+
+        assert_eq!(
+            result(&received, 1)["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+        let starts: Vec<&Value> = result(&received, 2)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| &location["range"]["start"])
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                &json!({ "line": 6, "character": 0 }),
+                &json!({ "line": 6, "character": 10 })
+            ]
+        );
+        assert_eq!(
+            result(&received, 3)["range"]["start"],
+            json!({ "line": 3, "character": 8 })
+        );
+    }
+
+    #[test]
+    fn hovers_with_inferred_types() {
+        let received = open_and_request(
+            "VAR a = 1\nVAR b = a\nVAR c = \"x\"\nc = 2\nPRINT c\n",
+            &[
+                ("textDocument/hover", position(1, 4)),
+                ("textDocument/hover", position(4, 6)),
+                ("textDocument/hover", position(3, 2)),
+            ],
+        );
+        let hover = |id| result(&received, id)["contents"]["value"].as_str().unwrap();
+        assert!(hover(1).contains("b AS INTEGER"));
+        assert!(hover(2).contains("type unknown"));
+        assert_eq!(result(&received, 3), &Value::Null);
+    }
+
+    #[test]
+    fn completes_keywords_and_variables_in_scope() {
+        let received = open_and_request(
+            SRC,
+            &[
+                ("textDocument/completion", position(4, 4)),
+                ("textDocument/completion", position(7, 0)),
+            ],
+        );
+        let labels = |id| -> Vec<String> {
+            result(&received, id)
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_owned())
+                .collect()
+        };
+        let in_proc = labels(1);
+        let after_proc = labels(2);
+        for name in ["a", "s", "n", "t", "Twice", "VAR", "PRINT"].iter() {
+            assert!(in_proc.iter().any(|label| label == name), "{}", name);
+        }
+        assert!(after_proc.iter().any(|label| label == "a"));
+        assert!(!after_proc.iter().any(|label| label == "n" || label == "t"));
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::Value;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+/// 受け付けるメッセージの本文の最大の長さ (バイト数)
+///
+/// ``Content-Length`` はクライアントから送られる値なので、そのまま確保せずにこの長さで制限する
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// ``Content-Length`` ヘッダで区切られた JSON-RPC のメッセージを1つ読み込む
+///
+/// 入力が終わっている場合は ``None`` を返す。本文が JSON として正しくない場合は ``Some(Err(..))`` を返す。
+/// ``Content-Length`` が ``MAX_CONTENT_LENGTH`` を超える場合や、本文が途中で終わっている場合は ``Err`` を返す
+pub fn read_message(reader: &mut dyn BufRead) -> io::Result<Option<Result<Value, String>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing `Content-Length` header"))?;
+    if content_length > MAX_CONTENT_LENGTH {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "`Content-Length` {} exceeds the limit of {} bytes",
+                content_length, MAX_CONTENT_LENGTH
+            ),
+        ));
+    }
+    // 本文が実際に届いた分だけ確保する
+    let mut content = Vec::new();
+    reader
+        .take(content_length as u64)
+        .read_to_end(&mut content)?;
+    if content.len() < content_length {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "Message body ends after {} of {} bytes",
+                content.len(),
+                content_length
+            ),
+        ));
+    }
+    Ok(Some(
+        serde_json::from_slice(&content).map_err(|e| e.to_string()),
+    ))
+}
+
+/// ``Content-Length`` ヘッダを付けて、JSON-RPC のメッセージを書き込む
+pub fn write_message(writer: &mut dyn Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read(input: &str) -> io::Result<Option<Result<Value, String>>> {
+        read_message(&mut Cursor::new(input.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &serde_json::json!({"id": 1})).unwrap();
+        let message = read_message(&mut Cursor::new(buf)).unwrap().unwrap();
+        assert_eq!(message, Ok(serde_json::json!({"id": 1})));
+    }
+
+    #[test]
+    fn oversized_content_length() {
+        let err = read("Content-Length: 18446744073709551615\r\n\r\n{}").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", MAX_CONTENT_LENGTH + 1);
+        assert_eq!(read(&input).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_body() {
+        let err = read("Content-Length: 100\r\n\r\n{}").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn missing_content_length() {
+        let err = read("Content-Type: application/json\r\n\r\n{}").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,53 @@
+extern crate compiler;
+
+use clap::{app_from_crate, Arg};
+use compiler::{diagnostic::Diagnostic, run_bytecode, term_color::stderr_supports_color, Limits};
+use std::{
+    fs,
+    io::{self, BufWriter},
+    process,
+};
+
+fn main() {
+    let matches = app_from_crate!()
+        .arg(
+            Arg::new("INPUT")
+                .required(true)
+                .about("Bytecode file (.basc)"),
+        )
+        .arg(
+            Arg::new("max-steps")
+                .long("max-steps")
+                .takes_value(true)
+                .validator(|s| s.parse::<u64>())
+                .about("Stops after executing the given number of instructions"),
+        )
+        .arg(
+            Arg::new("max-memory")
+                .long("max-memory")
+                .takes_value(true)
+                .validator(|s| s.parse::<usize>())
+                .about("Stops when the stack and variables exceed the given number of bytes"),
+        )
+        .get_matches();
+
+    let limits = Limits {
+        max_steps: matches.value_of("max-steps").map(|s| s.parse().unwrap()),
+        max_memory: matches.value_of("max-memory").map(|s| s.parse().unwrap()),
+    };
+
+    let bytes = fs::read(matches.value_of("INPUT").unwrap())
+        .unwrap_or_else(|_| exit_failure("Failed to read the bytecode file"));
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let code = run_bytecode(&bytes, limits, &mut out, &mut io::stderr())
+        .unwrap_or_else(|msg| exit_failure(&msg));
+    process::exit(code);
+}
+
+fn exit_failure(msg: &str) -> ! {
+    let diag = Diagnostic::from(msg.to_owned());
+    eprint!("{}", diag.render("", None, stderr_supports_color()));
+    process::exit(1);
+}